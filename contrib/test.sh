@@ -1,6 +1,8 @@
 #!/bin/sh -ex
 
-FEATURES="compiler use-serde rand"
+FEATURES="compiler use-serde rand bc-ur slip132 generator ctv"
+# Features whose dependencies are not covered by the MSRV
+NON_MSRV_FEATURES="async rayon arbitrary bip39 proptest"
 
 # Use toolchain if explicitly specified
 if [ -n "$TOOLCHAIN" ]
@@ -39,6 +41,15 @@ do
     cargo test --verbose --features="$feature"
 done
 
+# Test the features not covered by the MSRV if told to
+if [ "$DO_NON_MSRV_FEATURES" = true ]
+then
+    for feature in ${NON_MSRV_FEATURES}
+    do
+        cargo test --verbose --features="$feature"
+    done
+fi
+
 # Also build and run each example to catch regressions
 cargo build --examples
 # run all examples
@@ -0,0 +1,301 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP 322 Message Signing
+//!
+//! Generic signed messages as defined in BIP 322, in their "full" form: the
+//! signature of a message for an address is the `to_sign` transaction,
+//! spending a virtual `to_spend` output paying to the address and committing
+//! to the message. Proofs are produced with the PSBT signer and finalizer of
+//! this crate, and verified with its interpreter.
+//!
+//! The full form of a proof is the consensus serialization of its `to_sign`
+//! transaction, which is usually encoded in base64. Proofs of funds, whose
+//! `to_sign` transaction has inputs besides the `to_spend` output, are not
+//! supported.
+//!
+
+use std::{error, fmt};
+
+use bitcoin::blockdata::{opcodes, script};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+
+use descriptor::{ConversionError, Descriptor, DescriptorPublicKey, DescriptorTrait, KeyMap};
+use {interpreter, psbt, ForEachKey, TranslatePk2};
+
+/// The tag of the hash of signed messages
+const MESSAGE_TAG: &'static [u8] = b"BIP0322-signed-message";
+
+/// An error signing or verifying a message
+#[derive(Debug)]
+pub enum Error {
+    /// The keys of the descriptor could not be derived, such as keys with
+    /// wildcards
+    Conversion(ConversionError),
+    /// The `to_sign` transaction could not be signed or finalized
+    Psbt(psbt::Error),
+    /// The proof is not a `to_sign` transaction of the message
+    InvalidProof(&'static str),
+    /// The witness of the proof does not satisfy the script pubkey
+    Interpreter(interpreter::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Conversion(ref e) => fmt::Display::fmt(e, f),
+            Error::Psbt(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidProof(reason) => write!(f, "invalid BIP 322 proof: {}", reason),
+            Error::Interpreter(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<ConversionError> for Error {
+    fn from(e: ConversionError) -> Error {
+        Error::Conversion(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<psbt::Error> for Error {
+    fn from(e: psbt::Error) -> Error {
+        Error::Psbt(e)
+    }
+}
+
+/// The tagged hash of `message` committed to by its `to_spend` transaction
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(MESSAGE_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message);
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual `to_spend` transaction of `message`, whose single output pays
+/// to `script_pubkey`
+pub fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: script::Builder::new()
+                .push_int(0)
+                .push_slice(&message_hash(message)[..])
+                .into_script(),
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The unsigned `to_sign` transaction spending the output of `to_spend`
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script::Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+/// Signs `message` for the address of `descriptor`, returning the signed
+/// `to_sign` transaction
+///
+/// The descriptor must not have wildcards: derive it at the index of the
+/// address first. The secret keys are looked up in `key_map`, and the
+/// transaction is signed and finalized as a PSBT would be, so that the
+/// descriptor must be satisfiable with signatures alone: the `to_sign`
+/// transaction has no timelocks.
+pub fn sign<C: secp256k1::Signing + secp256k1::Verification>(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    message: &[u8],
+    key_map: &KeyMap,
+    secp: &Secp256k1<C>,
+) -> Result<Transaction, Error> {
+    let derived = descriptor.translate_pk2(|pk| pk.derive_public_key(secp))?;
+    let to_spend = to_spend(&derived.script_pubkey(), message);
+
+    let mut psbt = Psbt::from_unsigned_tx(to_sign(&to_spend)).expect("unsigned transaction");
+    {
+        let input = &mut psbt.inputs[0];
+        psbt::set_scripts(input, &derived);
+        input.non_witness_utxo = Some(to_spend);
+
+        // The signer signs for the keys of the bip32 derivations
+        let mut keys = vec![];
+        descriptor.for_each_key(|key| {
+            keys.push(key.as_key().clone());
+            true
+        });
+        for key in keys {
+            let source = (key.master_fingerprint(), key.full_derivation_path());
+            input
+                .bip32_derivation
+                .insert(key.derive_public_key(secp)?, source);
+        }
+    }
+
+    psbt::sign(&mut psbt, key_map, secp)?;
+    psbt::finalize_owned_inputs(&mut psbt, &[derived], secp)?;
+    Ok(psbt::extract(&psbt, secp)?)
+}
+
+/// Verifies that `proof`, a signed `to_sign` transaction, proves that
+/// `message` was signed for the address of `script_pubkey`
+///
+/// The version, lock time and sequence of the proof are those it was
+/// signed with, so that proofs for scripts with timelocks can be verified.
+pub fn verify<C: secp256k1::Verification>(
+    script_pubkey: &Script,
+    message: &[u8],
+    proof: &Transaction,
+    secp: &Secp256k1<C>,
+) -> Result<(), Error> {
+    let to_spend = to_spend(script_pubkey, message);
+    if proof.input.len() != 1 {
+        return Err(Error::InvalidProof("proof of funds"));
+    }
+    let input = &proof.input[0];
+    if input.previous_output != OutPoint::new(to_spend.txid(), 0) {
+        return Err(Error::InvalidProof("not spending to_spend"));
+    }
+    if proof.output.len() != 1
+        || proof.output[0].value != 0
+        || proof.output[0].script_pubkey != to_sign(&to_spend).output[0].script_pubkey
+    {
+        return Err(Error::InvalidProof("output is not an empty OP_RETURN"));
+    }
+
+    let mut interpreter = interpreter::Interpreter::from_txdata(
+        script_pubkey,
+        &input.script_sig,
+        &input.witness,
+        proof.lock_time,
+        input.sequence,
+    )
+    .map_err(Error::Interpreter)?;
+    let vfyfn = interpreter.sighash_verify(secp, proof, 0, 0);
+    if let Some(error) = interpreter.iter(vfyfn).filter_map(Result::err).next() {
+        return Err(Error::Interpreter(error));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin;
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    #[test]
+    fn message_hash() {
+        assert_eq!(
+            super::message_hash(b""),
+            sha256::Hash::from_hex(
+                "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            super::message_hash(b"Hello World"),
+            sha256::Hash::from_hex(
+                "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn transactions() {
+        // Test vectors of BIP 322
+        let address =
+            bitcoin::Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let vectors = [
+            (
+                &b""[..],
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                &b"Hello World"[..],
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ];
+        for &(message, to_spend_txid, to_sign_txid) in &vectors {
+            let to_spend = super::to_spend(&address.script_pubkey(), message);
+            assert_eq!(to_spend.txid().to_string(), to_spend_txid);
+            assert_eq!(super::to_sign(&to_spend).txid().to_string(), to_sign_txid);
+        }
+    }
+
+    #[test]
+    fn sign_verify() {
+        let secp = Secp256k1::new();
+        let descriptors = [
+            "wpkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/1)",
+            "pkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/2)",
+            "sh(wsh(multi(2,tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/1/0,tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/1/1)))",
+        ];
+        for s in &descriptors {
+            let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, s).unwrap();
+            let spk = descriptor
+                .translate_pk2(|pk| pk.derive_public_key(&secp))
+                .unwrap()
+                .script_pubkey();
+
+            let proof = sign(&descriptor, b"Hello World", &key_map, &secp).unwrap();
+            verify(&spk, b"Hello World", &proof, &secp).unwrap();
+            assert!(verify(&spk, b"Hello world", &proof, &secp).is_err());
+
+            // Without all the keys
+            let mut partial = KeyMap::new();
+            for (pk, sk) in key_map.iter().skip(1) {
+                partial.insert(pk.clone(), sk.clone());
+            }
+            assert!(sign(&descriptor, b"Hello World", &partial, &secp).is_err());
+        }
+
+        let (wildcard, key_map) = Descriptor::parse_descriptor(&secp, "wpkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/*)").unwrap();
+        match sign(&wildcard, b"", &key_map, &secp) {
+            Err(Error::Conversion(ConversionError::Wildcard)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Watch-only Output Descriptors
+//!
+//! Implementation of the addr() and raw() descriptors, which describe an
+//! output by its address or scriptPubKey only. They can be used to watch
+//! outputs, but not to spend them.
+//!
+
+use std::cmp::Ordering;
+use std::{fmt, str::FromStr};
+
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::{self, Script};
+
+use expression;
+use policy::{semantic, Liftable};
+use {Error, MiniscriptKey, Satisfier, ToPublicKey};
+
+use super::{
+    checksum::{desc_checksum, verify_checksum},
+    key::networks_agree,
+    DescriptorTrait,
+};
+
+/// An addr() descriptor
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Addr {
+    /// underlying address
+    address: bitcoin::Address,
+}
+
+impl Addr {
+    /// Create a new addr() descriptor
+    pub fn new(address: bitcoin::Address) -> Self {
+        Addr { address: address }
+    }
+
+    /// Get a reference to the inner address
+    pub fn as_inner(&self) -> &bitcoin::Address {
+        &self.address
+    }
+
+    /// Get the inner address
+    pub fn into_inner(self) -> bitcoin::Address {
+        self.address
+    }
+}
+
+// Addresses are not ordered, order them by their string representation
+impl PartialOrd for Addr {
+    fn partial_cmp(&self, other: &Addr) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Addr {
+    fn cmp(&self, other: &Addr) -> Ordering {
+        self.address.to_string().cmp(&other.address.to_string())
+    }
+}
+
+impl fmt::Debug for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "addr({})", self.address)
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = format!("addr({})", self.address);
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for Addr {
+    fn lift(&self) -> Result<semantic::Policy<Pk>, Error> {
+        Err(Error::WatchOnlyDescriptor)
+    }
+}
+
+impl expression::FromTree for Addr {
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        if top.name == "addr" && top.args.len() == 1 {
+            expression::terminal(&top.args[0], |s| {
+                bitcoin::Address::from_str(s).map(Addr::new)
+            })
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing addr descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl FromStr for Addr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        expression::FromTree::from_tree(&top)
+    }
+}
+
+impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Addr {
+    fn sanity_check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Test networks share their base58 encoding, so an address parsed as a
+    // testnet one may be meant for any of them
+    fn address(&self, network: bitcoin::Network) -> Result<bitcoin::Address, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        if !networks_agree(self.address.network, network) {
+            return Err(Error::AddrNetworkMismatch(self.address.network, network));
+        }
+        Ok(bitcoin::Address {
+            network: network,
+            payload: self.address.payload.clone(),
+        })
+    }
+
+    fn script_pubkey(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        self.address.script_pubkey()
+    }
+
+    fn unsigned_script_sig(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        Script::new()
+    }
+
+    fn explicit_script(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        self.address.script_pubkey()
+    }
+
+    fn get_satisfaction<S>(&self, _satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        Pk: ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        Err(Error::WatchOnlyDescriptor)
+    }
+
+    fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        Err(Error::WatchOnlyDescriptor)
+    }
+
+    fn script_code(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        self.address.script_pubkey()
+    }
+}
+
+/// A raw() descriptor
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Raw {
+    /// underlying scriptPubKey
+    script: Script,
+}
+
+impl Raw {
+    /// Create a new raw() descriptor
+    pub fn new(script: Script) -> Self {
+        Raw { script: script }
+    }
+
+    /// Get a reference to the inner script
+    pub fn as_inner(&self) -> &Script {
+        &self.script
+    }
+
+    /// Get the inner script
+    pub fn into_inner(self) -> Script {
+        self.script
+    }
+}
+
+impl fmt::Debug for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "raw({})", self.script.to_hex())
+    }
+}
+
+impl fmt::Display for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = format!("raw({})", self.script.to_hex());
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for Raw {
+    fn lift(&self) -> Result<semantic::Policy<Pk>, Error> {
+        Err(Error::WatchOnlyDescriptor)
+    }
+}
+
+impl expression::FromTree for Raw {
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        if top.name == "raw" && top.args.len() == 1 {
+            expression::terminal(&top.args[0], |s| {
+                Vec::<u8>::from_hex(s).map(|bytes| Raw::new(Script::from(bytes)))
+            })
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing raw descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl FromStr for Raw {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        expression::FromTree::from_tree(&top)
+    }
+}
+
+impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Raw {
+    fn sanity_check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn address(&self, network: bitcoin::Network) -> Result<bitcoin::Address, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        bitcoin::Address::from_script(&self.script, network).ok_or(Error::RawDescriptorAddr)
+    }
+
+    fn script_pubkey(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        self.script.clone()
+    }
+
+    fn unsigned_script_sig(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        Script::new()
+    }
+
+    fn explicit_script(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        self.script.clone()
+    }
+
+    fn get_satisfaction<S>(&self, _satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        Pk: ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        Err(Error::WatchOnlyDescriptor)
+    }
+
+    fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        Err(Error::WatchOnlyDescriptor)
+    }
+
+    fn script_code(&self) -> Script
+    where
+        Pk: ToPublicKey,
+    {
+        self.script.clone()
+    }
+}
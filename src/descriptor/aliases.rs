@@ -0,0 +1,202 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Key Aliases
+//!
+//! Human-readable names standing for descriptor public keys, such as
+//! `or(pk(Alice),pk(Bob))`, to parse policies and descriptors written with
+//! them and to render policies and descriptors with them.
+//!
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use super::{Descriptor, DescriptorPublicKey};
+use policy::Concrete;
+use {Error, TranslatePk2};
+
+/// A table of aliases of descriptor public keys
+///
+/// Aliases are made of ASCII letters, digits and `_`, and must not be valid
+/// keys themselves, so that keys can be written either way.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyAliases {
+    keys: BTreeMap<String, DescriptorPublicKey>,
+}
+
+impl KeyAliases {
+    /// Creates an empty table
+    pub fn new() -> KeyAliases {
+        KeyAliases::default()
+    }
+
+    /// Makes `alias` stand for `key`. Fails if the alias is not well-formed,
+    /// or if the alias or the key already have an entry in the table.
+    pub fn insert(&mut self, alias: &str, key: DescriptorPublicKey) -> Result<(), Error> {
+        if alias.is_empty()
+            || !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            || DescriptorPublicKey::from_str(alias).is_ok()
+        {
+            return Err(Error::BadDescriptor(format!(
+                "invalid key alias '{}'",
+                alias
+            )));
+        }
+        if self.keys.contains_key(alias) {
+            return Err(Error::BadDescriptor(format!(
+                "key alias '{}' is already defined",
+                alias
+            )));
+        }
+        if let Some(other) = self.alias_of(&key) {
+            return Err(Error::BadDescriptor(format!(
+                "key {} already has the alias '{}'",
+                key, other
+            )));
+        }
+        self.keys.insert(alias.to_owned(), key);
+        Ok(())
+    }
+
+    /// The key `alias` stands for
+    pub fn get(&self, alias: &str) -> Option<&DescriptorPublicKey> {
+        self.keys.get(alias)
+    }
+
+    /// The alias of `key`
+    pub fn alias_of(&self, key: &DescriptorPublicKey) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|&(_, k)| k == key)
+            .map(|(alias, _)| alias.as_str())
+    }
+
+    /// The key an alias or a key written in full stands for
+    fn resolve(&self, s: &str) -> Result<DescriptorPublicKey, Error> {
+        match self.keys.get(s) {
+            Some(key) => Ok(key.clone()),
+            None => DescriptorPublicKey::from_str(s).map_err(|_| {
+                Error::BadDescriptor(format!("'{}' is neither a key alias nor a key", s))
+            }),
+        }
+    }
+
+    /// The alias of a key, or the key written in full if it has none
+    fn render(&self, key: &DescriptorPublicKey) -> String {
+        match self.alias_of(key) {
+            Some(alias) => alias.to_owned(),
+            None => key.to_string(),
+        }
+    }
+
+    /// Parses a policy whose keys are aliases from the table, or keys
+    /// written in full
+    pub fn parse_policy(&self, s: &str) -> Result<Concrete<DescriptorPublicKey>, Error> {
+        Concrete::<String>::from_str(s)?.translate_pk(|alias| self.resolve(alias))
+    }
+
+    /// Parses a descriptor whose keys are aliases from the table, or keys
+    /// written in full. A checksum, if any, is the checksum of `s`.
+    pub fn parse_descriptor(&self, s: &str) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        Descriptor::<String>::from_str(s)?.translate_pk2(|alias| self.resolve(alias))
+    }
+
+    /// Renders a policy with the aliases of its keys, so that
+    /// [KeyAliases::parse_policy] gives it back
+    pub fn policy_to_string(&self, policy: &Concrete<DescriptorPublicKey>) -> String {
+        policy
+            .translate_pk::<_, _, ()>(|key| Ok(self.render(key)))
+            .expect("infallible translation function")
+            .to_string()
+    }
+
+    /// Renders a descriptor with the aliases of its keys, so that
+    /// [KeyAliases::parse_descriptor] gives it back. The checksum is the
+    /// checksum of the rendered string.
+    pub fn descriptor_to_string(&self, descriptor: &Descriptor<DescriptorPublicKey>) -> String {
+        descriptor
+            .translate_pk2_infallible(|key| self.render(key))
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556";
+    const BOB: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn aliases() -> KeyAliases {
+        let mut aliases = KeyAliases::new();
+        aliases
+            .insert("Alice", DescriptorPublicKey::from_str(ALICE).unwrap())
+            .unwrap();
+        aliases
+            .insert("Bob", DescriptorPublicKey::from_str(BOB).unwrap())
+            .unwrap();
+        aliases
+    }
+
+    #[test]
+    fn policy_aliases() {
+        let aliases = aliases();
+        let policy = aliases.parse_policy("or(pk(Alice),pk(Bob))").unwrap();
+        assert_eq!(
+            policy,
+            Concrete::from_str(&format!("or(pk({}),pk({}))", ALICE, BOB)).unwrap()
+        );
+        assert_eq!(
+            aliases.policy_to_string(&policy),
+            "or(1@pk(Alice),1@pk(Bob))"
+        );
+
+        // Keys written in full are left as is
+        let policy = aliases
+            .parse_policy(&format!("and(pk(Alice),pk({}))", BOB))
+            .unwrap();
+        assert_eq!(aliases.policy_to_string(&policy), "and(pk(Alice),pk(Bob))");
+
+        assert!(aliases.parse_policy("pk(Carol)").is_err());
+    }
+
+    #[test]
+    fn descriptor_aliases() {
+        let aliases = aliases();
+        let descriptor = aliases.parse_descriptor("wsh(multi(1,Alice,Bob))").unwrap();
+        assert_eq!(
+            descriptor,
+            Descriptor::from_str(&format!("wsh(multi(1,{},{}))", ALICE, BOB)).unwrap()
+        );
+        let rendered = aliases.descriptor_to_string(&descriptor);
+        assert!(rendered.starts_with("wsh(multi(1,Alice,Bob))#"));
+        assert_eq!(aliases.parse_descriptor(&rendered).unwrap(), descriptor);
+    }
+
+    #[test]
+    fn invalid_aliases() {
+        let mut aliases = aliases();
+        let carol = DescriptorPublicKey::from_str(
+            "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+        )
+        .unwrap();
+        assert!(aliases.insert("", carol.clone()).is_err());
+        assert!(aliases.insert("Carol)", carol.clone()).is_err());
+        assert!(aliases.insert(BOB, carol.clone()).is_err());
+        assert!(aliases.insert("Alice", carol.clone()).is_err());
+        assert!(aliases
+            .insert("Alice2", DescriptorPublicKey::from_str(ALICE).unwrap())
+            .is_err());
+        assert!(aliases.insert("Carol", carol).is_ok());
+    }
+}
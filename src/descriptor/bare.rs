@@ -33,7 +33,7 @@ use {
 
 use super::{
     checksum::{desc_checksum, verify_checksum},
-    DescriptorTrait,
+    DescriptorTrait, SpendPathWeight,
 };
 
 /// Create a Bare Descriptor. That is descriptor that is
@@ -61,6 +61,22 @@ impl<Pk: MiniscriptKey> Bare<Pk> {
     pub fn as_inner(&self) -> &Miniscript<Pk, BareCtx> {
         &self.ms
     }
+
+    /// Computes an upper bound on the weight of a satisfying witness along
+    /// each spend path of the descriptor, see `Descriptor::satisfaction_weights`
+    pub fn satisfaction_weights(&self) -> Vec<SpendPathWeight<Pk>> {
+        self.ms
+            .satisfaction_paths()
+            .into_iter()
+            .map(|path| {
+                let scriptsig_len = path.max_scriptsig_size;
+                SpendPathWeight {
+                    conditions: path.conditions,
+                    weight: 4 * (varint_len(scriptsig_len) + scriptsig_len),
+                }
+            })
+            .collect()
+    }
 }
 
 impl<Pk: MiniscriptKey> fmt::Debug for Bare<Pk> {
@@ -179,6 +195,10 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Bare<Pk> {
     {
         self.ms.for_each_key(pred)
     }
+
+    fn key_path(&self, index: usize) -> Option<Vec<usize>> {
+        self.ms.key_path(index)
+    }
 }
 
 impl<P: MiniscriptKey, Q: MiniscriptKey> TranslatePk<P, Q> for Bare<P> {
@@ -5,6 +5,7 @@
 
 use std::iter::FromIterator;
 
+use expression;
 use Error;
 
 const INPUT_CHARSET: &str =  "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
@@ -91,17 +92,128 @@ pub(super) fn verify_checksum(s: &str) -> Result<&str, Error> {
     if let Some(checksum_str) = parts.next() {
         let expected_sum = desc_checksum(desc_str)?;
         if checksum_str != expected_sum {
-            return Err(Error::BadDescriptor(format!(
+            let msg = format!(
                 "Invalid checksum '{}', expected '{}'",
                 checksum_str, expected_sum
-            )));
+            );
+            return Err(match locate_error(desc_str, checksum_str, &expected_sum) {
+                Some((pos, ch)) => {
+                    expression::record_error(&s[pos..pos + 1]);
+                    Error::BadDescriptor(format!(
+                        "{}, did you mean '{}' at position {}?",
+                        msg, ch, pos
+                    ))
+                }
+                None => Error::BadDescriptor(msg),
+            });
         }
     }
     Ok(desc_str)
 }
+
+/// Locate a single substituted character in a descriptor `desc#checksum`
+/// whose checksum does not match `expected_sum`, the checksum of `desc`.
+///
+/// Returns the byte offset of the character in the full string along with its
+/// correction, or `None` if no single substitution (or more than one) makes
+/// the checksum valid again.
+///
+/// The checksum is linear in the symbols it is computed over, so the effect
+/// of each substitution on it is computed directly rather than by computing
+/// the checksum of every candidate: this takes time linear in the length of
+/// the descriptor.
+fn locate_error(desc: &str, checksum: &str, expected_sum: &str) -> Option<(usize, char)> {
+    // A single error in the checksum itself leaves the descriptor intact
+    if checksum.len() == expected_sum.len() {
+        let mut diffs = checksum
+            .chars()
+            .zip(expected_sum.chars())
+            .enumerate()
+            .filter(|&(_, (a, b))| a != b);
+        if let (Some((i, (_, ch))), None) = (diffs.next(), diffs.next()) {
+            return Some((desc.len() + 1 + i, ch));
+        }
+    }
+
+    // The difference between the checksum given and the expected one, which
+    // the substitution must cause
+    if checksum.len() != 8 || expected_sum.len() != 8 {
+        return None;
+    }
+    let mut target = 0;
+    for (a, b) in checksum.chars().zip(expected_sum.chars()) {
+        let a = CHECKSUM_CHARSET.find(a)? as u64;
+        let b = CHECKSUM_CHARSET.find(b).expect("checksum charset") as u64;
+        target = (target << 5) | (a ^ b);
+    }
+
+    // The steps of `desc_checksum` at which each character is fed, by its
+    // low bits and as part of the class of its group of three characters
+    let positions: Vec<u64> = desc
+        .chars()
+        .map(|ch| INPUT_CHARSET.find(ch).expect("checksum computed") as u64)
+        .collect();
+    let mut low_steps = Vec::with_capacity(positions.len());
+    let mut cls_steps = Vec::with_capacity(positions.len() / 3 + 1);
+    let mut steps = 0;
+    for i in 0..positions.len() {
+        low_steps.push(steps);
+        steps += 1;
+        if i % 3 == 2 || i + 1 == positions.len() {
+            cls_steps.push(steps);
+            steps += 1;
+        }
+    }
+    steps += 8;
+
+    // The effect of each bit of a symbol fed `k` steps before the end, for
+    // every `k`
+    let mut effects = Vec::with_capacity(steps);
+    let mut effect = [1, 2, 4, 8, 16];
+    for _ in 0..steps {
+        effects.push(effect);
+        for bit in effect.iter_mut() {
+            *bit = poly_mod(*bit, 0);
+        }
+    }
+    let effect_of = |symbol: u64, step: usize| {
+        let basis = &effects[steps - 1 - step];
+        (0..5)
+            .filter(|&bit| (symbol >> bit) & 1 == 1)
+            .fold(0, |acc, bit| acc ^ basis[bit])
+    };
+
+    let mut found = None;
+    for (pos, &original) in positions.iter().enumerate() {
+        let group = pos / 3;
+        let group_len = ::std::cmp::min(3, positions.len() - 3 * group);
+        let weight = 3u64.pow((group_len - 1 - pos % 3) as u32);
+        let cls = positions[3 * group..3 * group + group_len]
+            .iter()
+            .fold(0, |acc, p| acc * 3 + (p >> 5));
+        for (candidate, ch) in INPUT_CHARSET.chars().enumerate() {
+            let candidate = candidate as u64;
+            if candidate == original {
+                continue;
+            }
+            let new_cls = cls + weight * (candidate >> 5) - weight * (original >> 5);
+            let diff = effect_of((candidate ^ original) & 31, low_steps[pos])
+                ^ effect_of(cls ^ new_cls, cls_steps[group]);
+            if diff == target {
+                if found.is_some() {
+                    return None;
+                }
+                found = Some((pos, ch));
+            }
+        }
+    }
+    found
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use expression::{parse_with_span, Span};
     use std::str;
 
     macro_rules! check_expected {
@@ -150,4 +262,48 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn test_locate_checksum_error() {
+        let desc = "wpkh(tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/1/2/*)";
+        assert_eq!(
+            verify_checksum(&format!("{}#tqz0nc62", desc)).unwrap(),
+            desc
+        );
+
+        // Typo in the checksum
+        let (err, span) =
+            parse_with_span(&format!("{}#tqz0nc6z", desc), verify_checksum).unwrap_err();
+        let pos = desc.len() + 8;
+        assert_eq!(
+            span,
+            Some(Span {
+                start: pos,
+                end: pos + 1
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Invalid descriptor: Invalid checksum 'tqz0nc6z', expected 'tqz0nc62', did you mean '2' at position {}?",
+                pos
+            )
+        );
+
+        // Typo in the descriptor
+        let typo = desc.replace("/1/2/*", "/1/3/*");
+        let (err, span) =
+            parse_with_span(&format!("{}#tqz0nc62", typo), verify_checksum).unwrap_err();
+        let pos = desc.len() - 4;
+        assert_eq!(
+            span,
+            Some(Span {
+                start: pos,
+                end: pos + 1
+            })
+        );
+        assert!(err
+            .to_string()
+            .ends_with(&format!("did you mean '2' at position {}?", pos)));
+    }
 }
@@ -0,0 +1,435 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Compact Binary Encoding
+//!
+//! A binary encoding of descriptors, more compact than their string form, for
+//! QR codes and devices with little storage.
+//!
+//! The encoding is a tree of tag-length-value records. Every fragment is a
+//! record whose value is the concatenation of the records of its arguments,
+//! while keys, hashes and numbers are leaf records holding their binary
+//! serialization. Lengths are encoded as Bitcoin variable length integers.
+//! The descriptor checksum is not encoded, it is recomputed when decoding.
+//!
+
+use std::str::{self, FromStr};
+
+use bitcoin;
+use bitcoin::consensus::encode::{Decodable, Encodable, VarInt};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::util::{base58, bip32};
+
+use super::checksum::verify_checksum;
+use super::{Descriptor, DescriptorPublicKey, DescriptorSinglePub, DescriptorXKey, Wildcard};
+use expression;
+use {Error, MAX_RECURSION_DEPTH};
+
+/// A number, encoded little-endian in as few bytes as possible
+const TAG_NUMBER: u8 = 0x01;
+/// A hash or a script, encoded as its bytes
+const TAG_HASH: u8 = 0x02;
+/// A key, see `encode_key`
+const TAG_KEY: u8 = 0x03;
+/// Miniscript wrappers: their number, their characters and the wrapped record
+const TAG_WRAPPERS: u8 = 0x04;
+/// An address, encoded as its string
+const TAG_STRING: u8 = 0x05;
+/// A fragment, tagged `TAG_FRAGMENT` plus its index in `FRAGMENTS`
+const TAG_FRAGMENT: u8 = 0x10;
+
+/// The fragments of descriptors, new fragments must be appended to keep the
+/// tags of the existing ones. Extension fragments are not listed, as
+/// descriptors cannot hold them.
+const FRAGMENTS: [&str; 27] = [
+    "sh",
+    "wsh",
+    "pkh",
+    "wpkh",
+    "sortedmulti",
+    "pk",
+    "pk_k",
+    "pk_h",
+    "older",
+    "after",
+    "sha256",
+    "hash256",
+    "ripemd160",
+    "hash160",
+    "and_v",
+    "and_b",
+    "and_n",
+    "andor",
+    "or_b",
+    "or_c",
+    "or_d",
+    "or_i",
+    "thresh",
+    "multi",
+    "addr",
+    "raw",
+    "txtemplate",
+];
+
+// Flags of the first byte of an encoded key
+const KEY_ORIGIN: u8 = 0x01;
+const KEY_XPUB: u8 = 0x02;
+const KEY_WILDCARD: u8 = 0x04;
+const KEY_HARDENED_WILDCARD: u8 = 0x08;
+
+fn bad(msg: &str) -> Error {
+    Error::BadDescriptor(format!("invalid compact encoding: {}", msg))
+}
+
+/// Encode `desc` in the compact binary encoding
+pub(super) fn encode(desc: &Descriptor<DescriptorPublicKey>) -> Vec<u8> {
+    let desc = desc.to_string();
+    let desc = verify_checksum(&desc).expect("Descriptors display a valid checksum");
+    let tree = expression::Tree::from_str(desc).expect("Descriptors display a valid tree");
+    let mut ret = vec![];
+    encode_node(&tree, &mut ret);
+    ret
+}
+
+/// Decode a descriptor from the compact binary encoding
+pub(super) fn decode(mut bytes: &[u8]) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    let desc = decode_node(&mut bytes, 0)?;
+    if !bytes.is_empty() {
+        return Err(bad("trailing data"));
+    }
+    Descriptor::from_str(&desc)
+}
+
+fn write_record(tag: u8, value: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    VarInt(value.len() as u64)
+        .consensus_encode(&mut *out)
+        .expect("writing to a vec cannot fail");
+    out.extend_from_slice(value);
+}
+
+fn encode_node(node: &expression::Tree, out: &mut Vec<u8>) {
+    let (wrappers, name) = match node.name.find(':') {
+        Some(i) => (&node.name[..i], &node.name[i + 1..]),
+        None => ("", node.name),
+    };
+
+    let (tag, value) = if !node.args.is_empty() {
+        let index = FRAGMENTS
+            .iter()
+            .position(|fragment| *fragment == name)
+            .expect("Descriptors only display known fragments");
+        let mut value = vec![];
+        for arg in &node.args {
+            // Their arguments could be mistaken for numbers or hashes
+            match name {
+                "addr" => write_record(TAG_STRING, arg.name.as_bytes(), &mut value),
+                "raw" => {
+                    let script = Vec::<u8>::from_hex(arg.name).expect("Scripts display as hex");
+                    write_record(TAG_HASH, &script, &mut value);
+                }
+                _ => encode_node(arg, &mut value),
+            }
+        }
+        (TAG_FRAGMENT + index as u8, value)
+    } else if let Ok(mut n) = u32::from_str(name) {
+        // Also covers the `0` and `1` fragments, which display the same
+        let mut value = vec![];
+        while n > 0 {
+            value.push(n as u8);
+            n >>= 8;
+        }
+        (TAG_NUMBER, value)
+    } else if let Ok(key) = DescriptorPublicKey::from_str(name) {
+        (TAG_KEY, encode_key(&key))
+    } else {
+        let hash = Vec::<u8>::from_hex(name).expect("Descriptors only display hex hashes");
+        (TAG_HASH, hash)
+    };
+
+    if wrappers.is_empty() {
+        write_record(tag, &value, out);
+    } else {
+        let mut wrapped = vec![wrappers.len() as u8];
+        wrapped.extend_from_slice(wrappers.as_bytes());
+        write_record(tag, &value, &mut wrapped);
+        write_record(TAG_WRAPPERS, &wrapped, out);
+    }
+}
+
+// The flags byte, the origin if any, then the derivation path of extended keys
+// and the serialized key itself
+fn encode_key(key: &DescriptorPublicKey) -> Vec<u8> {
+    let mut ret = vec![0];
+    let origin = match *key {
+        DescriptorPublicKey::SinglePub(ref single) => &single.origin,
+        DescriptorPublicKey::XPub(ref xpub) => &xpub.origin,
+    };
+    if let Some((ref fingerprint, ref path)) = *origin {
+        ret[0] |= KEY_ORIGIN;
+        ret.extend_from_slice(&fingerprint[..]);
+        encode_path(path, &mut ret);
+    }
+
+    match *key {
+        DescriptorPublicKey::SinglePub(ref single) => ret.extend(single.key.to_bytes()),
+        DescriptorPublicKey::XPub(ref xpub) => {
+            ret[0] |= KEY_XPUB;
+            ret[0] |= match xpub.wildcard {
+                Wildcard::None => 0,
+                Wildcard::Unhardened => KEY_WILDCARD,
+                Wildcard::Hardened => KEY_HARDENED_WILDCARD,
+            };
+            encode_path(&xpub.derivation_path, &mut ret);
+            let xkey = base58::from_check(&xpub.xkey.to_string())
+                .expect("Extended keys display valid base58");
+            ret.extend(xkey);
+        }
+    }
+    ret
+}
+
+fn encode_path(path: &bip32::DerivationPath, out: &mut Vec<u8>) {
+    let children: Vec<u32> = path.into_iter().map(|child| u32::from(*child)).collect();
+    VarInt(children.len() as u64)
+        .consensus_encode(&mut *out)
+        .expect("writing to a vec cannot fail");
+    for mut child in children {
+        for _ in 0..4 {
+            out.push(child as u8);
+            child >>= 8;
+        }
+    }
+}
+
+fn read_bytes<'a>(cursor: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    let bytes: &'a [u8] = *cursor;
+    if len > bytes.len() {
+        return Err(bad("unexpected end of data"));
+    }
+    let (ret, rest) = bytes.split_at(len);
+    *cursor = rest;
+    Ok(ret)
+}
+
+fn read_varint(cursor: &mut &[u8]) -> Result<u64, Error> {
+    VarInt::consensus_decode(cursor)
+        .map(|n| n.0)
+        .map_err(|_| bad("invalid length"))
+}
+
+fn read_record<'a>(cursor: &mut &'a [u8]) -> Result<(u8, &'a [u8]), Error> {
+    let tag = read_bytes(cursor, 1)?[0];
+    let len = read_varint(cursor)?;
+    if len > cursor.len() as u64 {
+        return Err(bad("unexpected end of data"));
+    }
+    let value = read_bytes(cursor, len as usize)?;
+    Ok((tag, value))
+}
+
+fn decode_node(cursor: &mut &[u8], depth: u32) -> Result<String, Error> {
+    if depth >= MAX_RECURSION_DEPTH {
+        return Err(Error::MaxRecursiveDepthExceeded);
+    }
+
+    let (tag, mut value) = read_record(cursor)?;
+    match tag {
+        TAG_NUMBER => {
+            if value.len() > 4 || value.last() == Some(&0) {
+                return Err(bad("non-canonical number"));
+            }
+            let n = value
+                .iter()
+                .rev()
+                .fold(0u32, |n, byte| (n << 8) | u32::from(*byte));
+            Ok(n.to_string())
+        }
+        TAG_HASH => Ok(value.to_hex()),
+        TAG_STRING => {
+            if !value.iter().all(u8::is_ascii_alphanumeric) {
+                return Err(bad("invalid string"));
+            }
+            Ok(str::from_utf8(value)
+                .expect("ASCII is valid UTF-8")
+                .to_owned())
+        }
+        TAG_KEY => Ok(decode_key(value)?.to_string()),
+        TAG_WRAPPERS => {
+            let len = read_bytes(&mut value, 1)?[0];
+            let wrappers = read_bytes(&mut value, len as usize)?;
+            if wrappers.is_empty() || !wrappers.iter().all(u8::is_ascii_lowercase) {
+                return Err(bad("invalid wrappers"));
+            }
+            let wrappers = str::from_utf8(wrappers).expect("ASCII is valid UTF-8");
+            let sub = decode_node(&mut value, depth + 1)?;
+            if !value.is_empty() {
+                return Err(bad("trailing data in wrapper"));
+            }
+            Ok(format!("{}:{}", wrappers, sub))
+        }
+        tag if tag >= TAG_FRAGMENT && ((tag - TAG_FRAGMENT) as usize) < FRAGMENTS.len() => {
+            let mut args = vec![];
+            while !value.is_empty() {
+                args.push(decode_node(&mut value, depth + 1)?);
+            }
+            if args.is_empty() {
+                return Err(bad("fragment without arguments"));
+            }
+            Ok(format!(
+                "{}({})",
+                FRAGMENTS[(tag - TAG_FRAGMENT) as usize],
+                args.join(",")
+            ))
+        }
+        _ => Err(bad("unknown tag")),
+    }
+}
+
+fn decode_key(mut value: &[u8]) -> Result<DescriptorPublicKey, Error> {
+    let flags = read_bytes(&mut value, 1)?[0];
+    if flags & !(KEY_ORIGIN | KEY_XPUB | KEY_WILDCARD | KEY_HARDENED_WILDCARD) != 0 {
+        return Err(bad("unknown key flags"));
+    }
+
+    let origin = if flags & KEY_ORIGIN != 0 {
+        let fingerprint = bip32::Fingerprint::from(read_bytes(&mut value, 4)?);
+        Some((fingerprint, decode_path(&mut value)?))
+    } else {
+        None
+    };
+
+    let wildcard = match flags & (KEY_WILDCARD | KEY_HARDENED_WILDCARD) {
+        0 => Wildcard::None,
+        KEY_WILDCARD => Wildcard::Unhardened,
+        KEY_HARDENED_WILDCARD => Wildcard::Hardened,
+        _ => return Err(bad("invalid wildcard")),
+    };
+
+    if flags & KEY_XPUB != 0 {
+        let derivation_path = decode_path(&mut value)?;
+        let xkey = bip32::ExtendedPubKey::from_str(&base58::check_encode_slice(value))
+            .map_err(|_| bad("invalid extended key"))?;
+        Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+            origin,
+            xkey,
+            derivation_path,
+            wildcard,
+        }))
+    } else {
+        if wildcard != Wildcard::None {
+            return Err(bad("wildcard on a single key"));
+        }
+        let key = bitcoin::PublicKey::from_slice(value).map_err(|_| bad("invalid key"))?;
+        Ok(DescriptorPublicKey::SinglePub(DescriptorSinglePub {
+            origin,
+            key,
+        }))
+    }
+}
+
+fn decode_path(cursor: &mut &[u8]) -> Result<bip32::DerivationPath, Error> {
+    let len = read_varint(cursor)?;
+    if len > cursor.len() as u64 / 4 {
+        return Err(bad("unexpected end of data"));
+    }
+    let mut children = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        let child = read_bytes(cursor, 4)?
+            .iter()
+            .rev()
+            .fold(0u32, |n, byte| (n << 8) | u32::from(*byte));
+        children.push(bip32::ChildNumber::from(child));
+    }
+    Ok(bip32::DerivationPath::from(children))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(s: &str) -> Vec<u8> {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(s).unwrap();
+        let bytes = desc.to_compact_bytes();
+        assert!(bytes.len() < desc.to_string().len());
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_compact_bytes(&bytes).unwrap(),
+            desc
+        );
+        bytes
+    }
+
+    #[test]
+    fn compact_roundtrip() {
+        roundtrip("wpkh([2cbe2a6d/84'/1'/0']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/0/*)");
+        roundtrip("sh(wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))");
+        roundtrip("wsh(sortedmulti(2,tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/1/*h,tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK/1/*h))");
+        roundtrip("sh(wsh(or_d(pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),and_v(v:pkh(02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e),older(1000)))))");
+        roundtrip("addr(bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq)");
+        roundtrip("raw(0051)");
+        roundtrip("wsh(andor(pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),sha256(1111111111111111111111111111111111111111111111111111111111111111),and_v(v:pk(02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e),after(0))))");
+    }
+
+    #[test]
+    #[cfg(feature = "ctv")]
+    fn compact_roundtrip_txtemplate() {
+        let bytes = roundtrip("wsh(and_v(v:pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),txtemplate(1111111111111111111111111111111111111111111111111111111111111111)))");
+        // txtemplate, hash record of 32 bytes
+        assert_eq!(
+            &bytes[bytes.len() - 36..bytes.len() - 32],
+            &[TAG_FRAGMENT + 26, 34, TAG_HASH, 32][..]
+        );
+    }
+
+    #[test]
+    fn compact_encoding() {
+        let bytes =
+            roundtrip("pkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)");
+        // pkh, key record, no flags then the key
+        assert_eq!(&bytes[..5], &[TAG_FRAGMENT + 2, 36, TAG_KEY, 34, 0][..]);
+        assert_eq!(bytes.len(), 5 + 33);
+
+        let bytes = roundtrip("wsh(and_v(v:pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),older(1000)))");
+        // older, number record, 1000 little-endian
+        assert_eq!(
+            &bytes[bytes.len() - 6..],
+            &[TAG_FRAGMENT + 8, 4, TAG_NUMBER, 2, 0xe8, 0x03][..]
+        );
+    }
+
+    #[test]
+    fn compact_invalid() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "pkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)",
+        )
+        .unwrap();
+        let mut bytes = desc.to_compact_bytes();
+
+        assert!(Descriptor::<DescriptorPublicKey>::from_compact_bytes(&bytes[..10]).is_err());
+        bytes.push(0);
+        assert!(Descriptor::<DescriptorPublicKey>::from_compact_bytes(&bytes).is_err());
+        bytes.pop();
+        bytes[0] = 0xff;
+        assert!(Descriptor::<DescriptorPublicKey>::from_compact_bytes(&bytes).is_err());
+        // Number with a trailing zero byte
+        assert!(Descriptor::<DescriptorPublicKey>::from_compact_bytes(&[
+            TAG_FRAGMENT + 8,
+            4,
+            TAG_NUMBER,
+            2,
+            1,
+            0
+        ])
+        .is_err());
+    }
+}
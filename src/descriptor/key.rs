@@ -10,6 +10,8 @@ use bitcoin::{
     XpubIdentifier,
 };
 
+#[cfg(feature = "arbitrary")]
+use arbitrary::{self, Arbitrary, Unstructured};
 use MiniscriptKey;
 
 /// The MiniscriptKey corresponding to Descriptors. This can
@@ -32,7 +34,7 @@ pub struct DescriptorSinglePub {
 }
 
 /// A Single Descriptor Secret Key with optional origin information
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub struct DescriptorSinglePriv {
     /// Origin information
     pub origin: Option<bip32::KeySource>,
@@ -41,7 +43,7 @@ pub struct DescriptorSinglePriv {
 }
 
 /// A Secret Key that can be either a single key or an Xprv
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum DescriptorSecretKey {
     /// Single Secret Key
     SinglePriv(DescriptorSinglePriv),
@@ -49,6 +51,12 @@ pub enum DescriptorSecretKey {
     XPrv(DescriptorXKey<bip32::ExtendedPrivKey>),
 }
 
+/// Whether keys for the networks `a` and `b` are encoded the same way, that is
+/// both are mainnet or both are a test network
+pub(crate) fn networks_agree(a: bitcoin::Network, b: bitcoin::Network) -> bool {
+    (a == bitcoin::Network::Bitcoin) == (b == bitcoin::Network::Bitcoin)
+}
+
 impl fmt::Display for DescriptorSecretKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -319,6 +327,8 @@ impl FromStr for DescriptorPublicKey {
     }
 }
 
+serde_string_impl!(DescriptorPublicKey, "a descriptor public key");
+
 /// Descriptor key conversion error
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ConversionError {
@@ -483,6 +493,77 @@ impl FromStr for DescriptorSecretKey {
     }
 }
 
+serde_string_impl!(DescriptorSecretKey, "a descriptor secret key");
+
+/// An arbitrary derivation path of at most 4 steps, hardened or not
+#[cfg(feature = "arbitrary")]
+fn arbitrary_path(u: &mut Unstructured) -> arbitrary::Result<bip32::DerivationPath> {
+    let len = u.int_in_range(0..=4)?;
+    let mut path = Vec::with_capacity(len);
+    for _ in 0..len {
+        path.push(bip32::ChildNumber::from(u.arbitrary::<u32>()?));
+    }
+    Ok(path.into())
+}
+
+/// Mainnet keys, either single keys or xprvs with an arbitrary derivation
+/// path. Single keys are always compressed, so that they can be used in any
+/// descriptor.
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for DescriptorSecretKey {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let origin = if u.arbitrary()? {
+            let fingerprint = u.arbitrary::<[u8; 4]>()?;
+            Some((
+                bip32::Fingerprint::from(&fingerprint[..]),
+                arbitrary_path(u)?,
+            ))
+        } else {
+            None
+        };
+        let secret = u.arbitrary::<[u8; 32]>()?;
+        if u.arbitrary()? {
+            let key = secp256k1::SecretKey::from_slice(&secret)
+                .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+            Ok(DescriptorSecretKey::SinglePriv(DescriptorSinglePriv {
+                origin: origin,
+                key: bitcoin::PrivateKey {
+                    compressed: true,
+                    network: bitcoin::Network::Bitcoin,
+                    key: key,
+                },
+            }))
+        } else {
+            let xkey = bip32::ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &secret)
+                .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+            Ok(DescriptorSecretKey::XPrv(DescriptorXKey {
+                origin: origin,
+                xkey: xkey,
+                derivation_path: arbitrary_path(u)?,
+                wildcard: *u.choose(&[Wildcard::None, Wildcard::Unhardened, Wildcard::Hardened])?,
+            }))
+        }
+    }
+}
+
+/// The public keys of arbitrary secret keys, their hardened derivation steps
+/// applied. Xpubs never have a hardened wildcard, so that they can be derived.
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for DescriptorPublicKey {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let secp = Secp256k1::signing_only();
+        let mut pk = DescriptorSecretKey::arbitrary(u)?
+            .as_public(&secp)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        if let DescriptorPublicKey::XPub(ref mut xpub) = pk {
+            if xpub.wildcard == Wildcard::Hardened {
+                xpub.wildcard = Wildcard::Unhardened;
+            }
+        }
+        Ok(pk)
+    }
+}
+
 impl<K: InnerXKey> DescriptorXKey<K> {
     fn parse_xkey_origin(
         s: &str,
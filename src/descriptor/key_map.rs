@@ -0,0 +1,876 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Key Map
+//!
+//! Map from the public keys of a descriptor to their corresponding secret keys
+//!
+
+use std::collections::btree_map;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::FromIterator;
+use std::sync::Arc;
+use std::{error, fmt};
+
+use bitcoin;
+use bitcoin::secp256k1::{schnorrsig, Secp256k1, Signing};
+use bitcoin::util::bip32;
+use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::key::networks_agree;
+use super::{ConversionError, DescriptorPublicKey, DescriptorSecretKey, Wildcard};
+use psbt;
+use Error;
+
+/// A map of public key to secret key
+///
+/// This map is returned whenever a descriptor that contains secrets is parsed using
+/// [`Descriptor::parse_descriptor`], since the descriptor will always only contain
+/// public keys. This map allows looking up the corresponding secret key given a
+/// public key from the descriptor.
+///
+/// With the `serde` feature enabled, the map serializes as a map from the string
+/// form of each public key to the string form of its secret key, ordered by public
+/// key, so that it can be stored alongside the public descriptor.
+///
+/// [`Descriptor::parse_descriptor`]: ../enum.Descriptor.html#method.parse_descriptor
+#[derive(Debug, Default)]
+pub struct KeyMap {
+    map: BTreeMap<DescriptorPublicKey, DescriptorSecretKey>,
+    /// Public keys indexed by the fingerprints BIP32 key requests may use for
+    /// them
+    fingerprints: FingerprintIndex,
+}
+
+type FingerprintIndex = BTreeMap<bip32::Fingerprint, BTreeSet<DescriptorPublicKey>>;
+
+/// The fingerprints under which a BIP32 key request for `pk` can be made: the
+/// master fingerprint of the key, and the fingerprint of the extended key itself
+fn request_fingerprints(pk: &DescriptorPublicKey) -> Vec<bip32::Fingerprint> {
+    let mut fingerprints = vec![pk.master_fingerprint()];
+    if let DescriptorPublicKey::XPub(ref xpub) = *pk {
+        let xkey_fingerprint = xpub.xkey.fingerprint();
+        if xkey_fingerprint != fingerprints[0] {
+            fingerprints.push(xkey_fingerprint);
+        }
+    }
+    fingerprints
+}
+
+fn index(fingerprints: &mut FingerprintIndex, pk: &DescriptorPublicKey) {
+    for fingerprint in request_fingerprints(pk) {
+        fingerprints
+            .entry(fingerprint)
+            .or_insert_with(BTreeSet::new)
+            .insert(pk.clone());
+    }
+}
+
+impl KeyMap {
+    /// Creates a new, empty key map
+    pub fn new() -> KeyMap {
+        KeyMap {
+            map: BTreeMap::new(),
+            fingerprints: BTreeMap::new(),
+        }
+    }
+
+    fn unindex(&mut self, pk: &DescriptorPublicKey) {
+        for fingerprint in request_fingerprints(pk) {
+            let now_empty = match self.fingerprints.get_mut(&fingerprint) {
+                Some(pks) => {
+                    pks.remove(pk);
+                    pks.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.fingerprints.remove(&fingerprint);
+            }
+        }
+    }
+
+    /// Inserts a secret key into the map, keyed by its public key
+    ///
+    /// Returns the secret key previously associated with `pk`, if any.
+    pub fn insert(
+        &mut self,
+        pk: DescriptorPublicKey,
+        sk: DescriptorSecretKey,
+    ) -> Option<DescriptorSecretKey> {
+        index(&mut self.fingerprints, &pk);
+        self.map.insert(pk, sk)
+    }
+
+    /// Returns the secret key corresponding to `pk`, if any
+    pub fn get(&self, pk: &DescriptorPublicKey) -> Option<&DescriptorSecretKey> {
+        self.map.get(pk)
+    }
+
+    /// Returns a mutable reference to the secret key corresponding to `pk`, if any
+    pub fn get_mut(&mut self, pk: &DescriptorPublicKey) -> Option<&mut DescriptorSecretKey> {
+        self.map.get_mut(pk)
+    }
+
+    /// Removes the secret key corresponding to `pk` from the map, returning it
+    pub fn remove(&mut self, pk: &DescriptorPublicKey) -> Option<DescriptorSecretKey> {
+        let sk = self.map.remove(pk);
+        if sk.is_some() {
+            self.unindex(pk);
+        }
+        sk
+    }
+
+    /// Whether the map contains a secret key for `pk`
+    pub fn contains_key(&self, pk: &DescriptorPublicKey) -> bool {
+        self.map.contains_key(pk)
+    }
+
+    /// Gets the entry for `pk` for in-place manipulation
+    pub fn entry(&mut self, pk: DescriptorPublicKey) -> KeyMapEntry {
+        KeyMapEntry {
+            entry: self.map.entry(pk),
+            fingerprints: &mut self.fingerprints,
+        }
+    }
+
+    /// Iterates over the (public key, secret key) pairs in the map, ordered by public key
+    pub fn iter(&self) -> btree_map::Iter<DescriptorPublicKey, DescriptorSecretKey> {
+        self.map.iter()
+    }
+
+    /// Iterates over the public keys in the map, in order
+    pub fn keys(&self) -> btree_map::Keys<DescriptorPublicKey, DescriptorSecretKey> {
+        self.map.keys()
+    }
+
+    /// Iterates over the secret keys in the map, ordered by their public key
+    pub fn values(&self) -> btree_map::Values<DescriptorPublicKey, DescriptorSecretKey> {
+        self.map.values()
+    }
+
+    /// Computes the private key corresponding to a key derived from one of the
+    /// keys of the map, e.g. a key of a descriptor obtained with `derive`
+    ///
+    /// Extended keys are looked up by the master fingerprint and full derivation
+    /// path of `key`, and the matching extended private key is derived along that
+    /// path. Returns `Ok(None)` if no secret in the map corresponds to `key`, and
+    /// an error if `key` still has a wildcard or if the derivation fails.
+    pub fn derive_secret<C: Signing>(
+        &self,
+        key: &DescriptorPublicKey,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<bitcoin::PrivateKey>, DeriveSecretError> {
+        let key_request = match *key {
+            DescriptorPublicKey::SinglePub(ref single) => KeyRequest::Pubkey(single.key),
+            DescriptorPublicKey::XPub(ref xpub) => match xpub.wildcard {
+                Wildcard::Unhardened => return Err(ConversionError::Wildcard.into()),
+                Wildcard::Hardened => return Err(ConversionError::HardenedWildcard.into()),
+                Wildcard::None => {
+                    KeyRequest::Bip32((key.master_fingerprint(), key.full_derivation_path()))
+                }
+            },
+        };
+        self.get_key(&key_request, secp)
+            .map_err(DeriveSecretError::Bip32)
+    }
+
+    /// Signs every input of `psbt` with the keys of the map matching its bip32
+    /// derivations, see `psbt::sign`
+    ///
+    /// Returns the public keys which produced a signature for each input.
+    pub fn sign_psbt<C: Signing>(
+        &self,
+        psbt: &mut Psbt,
+        secp: &Secp256k1<C>,
+    ) -> Result<psbt::SigningKeys, psbt::Error> {
+        psbt::sign(psbt, self, secp)
+    }
+
+    /// Moves all the keys of `other` into this map
+    ///
+    /// Keys present in both maps must map to the same secret key. If any public
+    /// key is mapped to different secret keys, no key is moved and all the
+    /// conflicting public keys are returned in the error.
+    pub fn merge(&mut self, other: KeyMap) -> Result<(), KeyMapMergeError> {
+        let conflicts: Vec<DescriptorPublicKey> = other
+            .iter()
+            .filter(|&(pk, sk)| match self.get(pk) {
+                Some(existing) => existing != sk,
+                None => false,
+            })
+            .map(|(pk, _)| pk.clone())
+            .collect();
+
+        if !conflicts.is_empty() {
+            return Err(KeyMapMergeError { conflicts });
+        }
+        self.extend(other);
+        Ok(())
+    }
+
+    /// Removes all the keys from the map
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.fingerprints.clear();
+    }
+
+    /// The number of keys in the map
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Checks that all the secret keys of the map are for `network`
+    ///
+    /// Test networks share their key encoding, so keys for any of them are
+    /// accepted for another.
+    pub fn check_network(&self, network: bitcoin::Network) -> Result<(), Error> {
+        for sk in self.map.values() {
+            let key_network = match *sk {
+                DescriptorSecretKey::SinglePriv(ref single) => single.key.network,
+                DescriptorSecretKey::XPrv(ref xprv) => xprv.xkey.network,
+            };
+            if !networks_agree(key_network, network) {
+                return Err(Error::BadDescriptor(format!(
+                    "secret key for {} used on {}",
+                    key_network, network
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An entry of a [`KeyMap`], returned by [`KeyMap::entry`]
+///
+/// Secret keys are only added to the map through the entry when it is vacant
+/// and one of its `or_insert` methods is called.
+#[derive(Debug)]
+pub struct KeyMapEntry<'a> {
+    entry: btree_map::Entry<'a, DescriptorPublicKey, DescriptorSecretKey>,
+    fingerprints: &'a mut FingerprintIndex,
+}
+
+impl<'a> KeyMapEntry<'a> {
+    /// The public key of the entry
+    pub fn key(&self) -> &DescriptorPublicKey {
+        self.entry.key()
+    }
+
+    /// Inserts `default` if the entry is vacant, and returns the secret key of
+    /// the entry
+    pub fn or_insert(self, default: DescriptorSecretKey) -> &'a mut DescriptorSecretKey {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, and returns the
+    /// secret key of the entry
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut DescriptorSecretKey
+    where
+        F: FnOnce() -> DescriptorSecretKey,
+    {
+        let KeyMapEntry {
+            entry,
+            fingerprints,
+        } = self;
+        match entry {
+            btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            btree_map::Entry::Vacant(entry) => {
+                index(fingerprints, entry.key());
+                entry.insert(default())
+            }
+        }
+    }
+
+    /// Calls `f` on the secret key of the entry if it is occupied
+    pub fn and_modify<F>(self, f: F) -> KeyMapEntry<'a>
+    where
+        F: FnOnce(&mut DescriptorSecretKey),
+    {
+        KeyMapEntry {
+            entry: self.entry.and_modify(f),
+            fingerprints: self.fingerprints,
+        }
+    }
+}
+
+/// Error returned by [`KeyMap::merge`] when the maps contain different secret
+/// keys for the same public keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMapMergeError {
+    /// The public keys mapped to different secret keys in each map
+    pub conflicts: Vec<DescriptorPublicKey>,
+}
+
+impl fmt::Display for KeyMapMergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("conflicting secret keys for public keys")?;
+        for (i, pk) in self.conflicts.iter().enumerate() {
+            if i == 0 {
+                write!(f, " {}", pk)?;
+            } else {
+                write!(f, ", {}", pk)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for KeyMapMergeError {}
+
+/// Error returned by [`KeyMap::derive_secret`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeriveSecretError {
+    /// The key cannot be converted to a single key
+    Conversion(ConversionError),
+    /// The secret key could not be derived along the path of the key
+    Bip32(bip32::Error),
+}
+
+impl fmt::Display for DeriveSecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeriveSecretError::Conversion(ref e) => fmt::Display::fmt(e, f),
+            DeriveSecretError::Bip32(ref e) => write!(f, "secret key derivation: {}", e),
+        }
+    }
+}
+
+impl error::Error for DeriveSecretError {}
+
+#[doc(hidden)]
+impl From<ConversionError> for DeriveSecretError {
+    fn from(e: ConversionError) -> DeriveSecretError {
+        DeriveSecretError::Conversion(e)
+    }
+}
+
+impl IntoIterator for KeyMap {
+    type Item = (DescriptorPublicKey, DescriptorSecretKey);
+    type IntoIter = btree_map::IntoIter<DescriptorPublicKey, DescriptorSecretKey>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a KeyMap {
+    type Item = (&'a DescriptorPublicKey, &'a DescriptorSecretKey);
+    type IntoIter = btree_map::Iter<'a, DescriptorPublicKey, DescriptorSecretKey>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Extend<(DescriptorPublicKey, DescriptorSecretKey)> for KeyMap {
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = (DescriptorPublicKey, DescriptorSecretKey)>,
+    {
+        for (pk, sk) in iter {
+            self.insert(pk, sk);
+        }
+    }
+}
+
+impl FromIterator<(DescriptorPublicKey, DescriptorSecretKey)> for KeyMap {
+    fn from_iter<T>(iter: T) -> KeyMap
+    where
+        T: IntoIterator<Item = (DescriptorPublicKey, DescriptorSecretKey)>,
+    {
+        let mut map = KeyMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+/// A request for a private key, as made by a signer
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum KeyRequest {
+    /// Request the private key corresponding to an ECDSA public key
+    Pubkey(bitcoin::PublicKey),
+    /// Request the private key corresponding to an x-only public key
+    ///
+    /// Keys whose full public key only differs from the x-only key by the
+    /// parity of its y coordinate also match.
+    XOnlyPubkey(schnorrsig::PublicKey),
+    /// Request the private key at a BIP32 derivation path from a master key
+    Bip32(bip32::KeySource),
+}
+
+impl KeyRequest {
+    /// Whether the request is for the given public key
+    fn matches_pubkey(&self, pk: &bitcoin::PublicKey) -> bool {
+        match *self {
+            KeyRequest::Pubkey(ref req) => req == pk,
+            KeyRequest::XOnlyPubkey(ref req) => req.serialize()[..] == pk.key.serialize()[1..],
+            KeyRequest::Bip32(..) => false,
+        }
+    }
+}
+
+/// Trait describing sources of private keys, such as a [`KeyMap`]
+pub trait GetKey {
+    /// Error returned when a key source fails to look up a key
+    type Error;
+
+    /// Looks up the private key corresponding to `key_request`
+    ///
+    /// Returns `Ok(None)` if the key is not known to this source.
+    fn get_key<C: Signing>(
+        &self,
+        key_request: &KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<bitcoin::PrivateKey>, Self::Error>;
+
+    /// Chains this key source with `next`, which is only queried for the keys
+    /// this source does not know
+    fn chain<B: GetKey>(self, next: B) -> ChainedGetKey<Self, B>
+    where
+        Self: Sized,
+    {
+        ChainedGetKey::new(self, next)
+    }
+}
+
+impl<'a, T: GetKey> GetKey for &'a T {
+    type Error = T::Error;
+
+    fn get_key<C: Signing>(
+        &self,
+        key_request: &KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<bitcoin::PrivateKey>, T::Error> {
+        (**self).get_key(key_request, secp)
+    }
+}
+
+impl<T: GetKey> GetKey for Arc<T> {
+    type Error = T::Error;
+
+    fn get_key<C: Signing>(
+        &self,
+        key_request: &KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<bitcoin::PrivateKey>, T::Error> {
+        (**self).get_key(key_request, secp)
+    }
+}
+
+/// Key source querying two key sources in order
+///
+/// The second source is only queried if the first one does not know the
+/// requested key. Errors of either source are returned immediately. More
+/// sources can be queried by nesting chains, e.g. with [`GetKey::chain`].
+#[derive(Clone, Debug)]
+pub struct ChainedGetKey<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: GetKey, B: GetKey> ChainedGetKey<A, B> {
+    /// Creates a key source querying `first`, then `second`
+    pub fn new(first: A, second: B) -> ChainedGetKey<A, B> {
+        ChainedGetKey { first, second }
+    }
+
+    /// Splits the chain into its two key sources
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: GetKey, B: GetKey> GetKey for ChainedGetKey<A, B> {
+    type Error = ChainedGetKeyError<A::Error, B::Error>;
+
+    fn get_key<C: Signing>(
+        &self,
+        key_request: &KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<bitcoin::PrivateKey>, Self::Error> {
+        match self.first.get_key(key_request, secp) {
+            Ok(Some(key)) => Ok(Some(key)),
+            Ok(None) => self
+                .second
+                .get_key(key_request, secp)
+                .map_err(ChainedGetKeyError::Second),
+            Err(e) => Err(ChainedGetKeyError::First(e)),
+        }
+    }
+}
+
+/// Error of a [`ChainedGetKey`], coming from either of its key sources
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChainedGetKeyError<E1, E2> {
+    /// The first key source failed
+    First(E1),
+    /// The second key source failed
+    Second(E2),
+}
+
+impl<E1: fmt::Display, E2: fmt::Display> fmt::Display for ChainedGetKeyError<E1, E2> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChainedGetKeyError::First(ref e) => write!(f, "first key source: {}", e),
+            ChainedGetKeyError::Second(ref e) => write!(f, "second key source: {}", e),
+        }
+    }
+}
+
+impl<E1: error::Error, E2: error::Error> error::Error for ChainedGetKeyError<E1, E2> {}
+
+impl GetKey for KeyMap {
+    type Error = bip32::Error;
+
+    /// Looks up the private key corresponding to `key_request`
+    ///
+    /// Extended private keys are derived along their derivation path to match
+    /// public key requests, so keys with wildcards only match `Bip32` requests.
+    ///
+    /// `Bip32` requests are looked up by fingerprint, and only the keys whose
+    /// master fingerprint or own fingerprint (as it appears in the descriptor)
+    /// match the request are derived.
+    fn get_key<C: Signing>(
+        &self,
+        key_request: &KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<bitcoin::PrivateKey>, bip32::Error> {
+        if let KeyRequest::Bip32((fingerprint, _)) = *key_request {
+            if let Some(pks) = self.fingerprints.get(&fingerprint) {
+                for pk in pks {
+                    if let Some(sk) = self.map.get(pk) {
+                        if let Some(key) = secret_for_request(sk, key_request, secp)? {
+                            return Ok(Some(key));
+                        }
+                    }
+                }
+            }
+            return Ok(None);
+        }
+
+        for sk in self.values() {
+            if let Some(key) = secret_for_request(sk, key_request, secp)? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Returns the private key for `key_request` if it can be obtained from `sk`
+fn secret_for_request<C: Signing>(
+    sk: &DescriptorSecretKey,
+    key_request: &KeyRequest,
+    secp: &Secp256k1<C>,
+) -> Result<Option<bitcoin::PrivateKey>, bip32::Error> {
+    match (sk, key_request) {
+        (&DescriptorSecretKey::SinglePriv(ref single), &KeyRequest::Bip32(ref source)) => {
+            if single.origin.as_ref() == Some(source) {
+                Ok(Some(single.key))
+            } else {
+                Ok(None)
+            }
+        }
+        (&DescriptorSecretKey::SinglePriv(ref single), _) => {
+            if key_request.matches_pubkey(&single.key.public_key(secp)) {
+                Ok(Some(single.key))
+            } else {
+                Ok(None)
+            }
+        }
+        (&DescriptorSecretKey::XPrv(ref xprv), &KeyRequest::Bip32((fingerprint, ref path))) => {
+            if xprv.xkey.fingerprint(secp) == fingerprint {
+                let derived = xprv.xkey.derive_priv(secp, path)?;
+                return Ok(Some(derived.private_key));
+            }
+            if let Some((origin_fingerprint, ref origin_path)) = xprv.origin {
+                let origin_path: &[bip32::ChildNumber] = origin_path.as_ref();
+                let path: &[bip32::ChildNumber] = path.as_ref();
+                if origin_fingerprint == fingerprint
+                    && path.len() >= origin_path.len()
+                    && path[..origin_path.len()] == *origin_path
+                {
+                    let derived = xprv.xkey.derive_priv(secp, &&path[origin_path.len()..])?;
+                    return Ok(Some(derived.private_key));
+                }
+            }
+            Ok(None)
+        }
+        (&DescriptorSecretKey::XPrv(ref xprv), _) => {
+            if xprv.wildcard != Wildcard::None {
+                return Ok(None);
+            }
+            let derived = xprv.xkey.derive_priv(secp, &xprv.derivation_path)?;
+            if key_request.matches_pubkey(&derived.private_key.public_key(secp)) {
+                Ok(Some(derived.private_key))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for KeyMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for KeyMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KeyMap, D::Error> {
+        let map: BTreeMap<DescriptorPublicKey, DescriptorSecretKey> =
+            BTreeMap::deserialize(deserializer)?;
+        Ok(map.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChainedGetKey, ChainedGetKeyError, DeriveSecretError, GetKey, KeyMap, KeyMapMergeError,
+        KeyRequest,
+    };
+    use bitcoin::secp256k1::{self, schnorrsig};
+    use bitcoin::util::bip32;
+    use descriptor::{ConversionError, Descriptor, DescriptorPublicKey, DescriptorSecretKey};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use ForEachKey;
+
+    fn key_pair(s: &str) -> (DescriptorPublicKey, DescriptorSecretKey) {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let sk = DescriptorSecretKey::from_str(s).unwrap();
+        (sk.as_public(&secp).unwrap(), sk)
+    }
+
+    #[test]
+    fn map_api() {
+        let (pk_a, sk_a) = key_pair("tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/1'/2");
+        let (pk_b, sk_b) = key_pair("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA");
+
+        let mut key_map = KeyMap::new();
+        assert!(key_map.is_empty());
+        assert!(key_map.insert(pk_a.clone(), sk_a).is_none());
+        assert!(key_map.contains_key(&pk_a));
+        assert!(!key_map.contains_key(&pk_b));
+
+        key_map.entry(pk_b.clone()).or_insert(sk_b);
+        assert_eq!(key_map.len(), 2);
+        assert_eq!(key_map.keys().count(), 2);
+        assert_eq!(key_map.values().count(), 2);
+        assert!(key_map.iter().all(|(pk, _)| *pk == pk_a || *pk == pk_b));
+
+        assert!(key_map.remove(&pk_a).is_some());
+        assert!(key_map.remove(&pk_a).is_none());
+        assert_eq!(key_map.keys().collect::<Vec<_>>(), vec![&pk_b]);
+
+        let key_map: KeyMap = key_map.into_iter().collect();
+        assert_eq!(key_map.len(), 1);
+    }
+
+    #[test]
+    fn get_key() {
+        let secp = secp256k1::Secp256k1::new();
+        let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, "wpkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/44'/0'/0'/0/*)").unwrap();
+        let derived_pk = match descriptor.derive(7) {
+            Descriptor::Wpkh(wpkh) => wpkh.as_inner().derive_public_key(&secp).unwrap(),
+            _ => unreachable!(),
+        };
+
+        // Bip32 requests are resolved through the origin of the key
+        let request = KeyRequest::Bip32((
+            bip32::Fingerprint::from_str("2cbe2a6d").unwrap(),
+            bip32::DerivationPath::from_str("m/44'/0'/0'/0/7").unwrap(),
+        ));
+        let sk = key_map.get_key(&request, &secp).unwrap().unwrap();
+        assert_eq!(sk.public_key(&secp), derived_pk);
+
+        let request = KeyRequest::Bip32((
+            bip32::Fingerprint::from_str("deadbeef").unwrap(),
+            bip32::DerivationPath::from_str("m/44'/0'/0'/0/7").unwrap(),
+        ));
+        assert_eq!(key_map.get_key(&request, &secp).unwrap(), None);
+
+        // Wildcard keys cannot be matched from a public key alone
+        let request = KeyRequest::Pubkey(derived_pk);
+        assert_eq!(key_map.get_key(&request, &secp).unwrap(), None);
+
+        let (pk, sk) = key_pair("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA");
+        let mut key_map = KeyMap::new();
+        key_map.insert(pk, sk);
+        let secret = match *key_map.values().next().unwrap() {
+            DescriptorSecretKey::SinglePriv(ref single) => single.key,
+            _ => unreachable!(),
+        };
+        let public = secret.public_key(&secp);
+
+        let request = KeyRequest::Pubkey(public);
+        assert_eq!(key_map.get_key(&request, &secp).unwrap(), Some(secret));
+
+        // X-only requests match regardless of the parity of the full key
+        let xonly = schnorrsig::PublicKey::from_slice(&public.key.serialize()[1..]).unwrap();
+        let request = KeyRequest::XOnlyPubkey(xonly);
+        assert_eq!(key_map.get_key(&request, &secp).unwrap(), Some(secret));
+    }
+
+    /// Key source which fails on every request
+    struct FailingGetKey;
+
+    impl GetKey for FailingGetKey {
+        type Error = &'static str;
+
+        fn get_key<C: secp256k1::Signing>(
+            &self,
+            _: &KeyRequest,
+            _: &secp256k1::Secp256k1<C>,
+        ) -> Result<Option<bitcoin::PrivateKey>, &'static str> {
+            Err("unavailable")
+        }
+    }
+
+    #[test]
+    fn chained_get_key() {
+        let secp = secp256k1::Secp256k1::new();
+        let (pk_a, sk_a) = key_pair("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA");
+        let (pk_b, sk_b) = key_pair("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy");
+        let secret_b = match sk_b {
+            DescriptorSecretKey::SinglePriv(ref single) => single.key,
+            _ => unreachable!(),
+        };
+        let request_a = match pk_a {
+            DescriptorPublicKey::SinglePub(ref single) => KeyRequest::Pubkey(single.key),
+            _ => unreachable!(),
+        };
+        let request_b = KeyRequest::Pubkey(secret_b.public_key(&secp));
+
+        let key_map_a: KeyMap = vec![(pk_a, sk_a)].into_iter().collect();
+        let key_map_b: KeyMap = vec![(pk_b, sk_b)].into_iter().collect();
+        let key_map_b = Arc::new(key_map_b);
+
+        // References and `Arc`s are key sources
+        let chain = (&key_map_a).chain(key_map_b.clone());
+        assert!(chain.get_key(&request_a, &secp).unwrap().is_some());
+        assert_eq!(chain.get_key(&request_b, &secp).unwrap(), Some(secret_b));
+
+        // Later sources are not queried once a key is found
+        let chain = ChainedGetKey::new(&key_map_a, FailingGetKey);
+        assert!(chain.get_key(&request_a, &secp).unwrap().is_some());
+        assert_eq!(
+            chain.get_key(&request_b, &secp),
+            Err(ChainedGetKeyError::Second("unavailable"))
+        );
+        let chain = FailingGetKey.chain(&key_map_a);
+        assert_eq!(
+            chain.get_key(&request_a, &secp),
+            Err(ChainedGetKeyError::First("unavailable"))
+        );
+    }
+
+    #[test]
+    fn bip32_index() {
+        let secp = secp256k1::Secp256k1::new();
+        let (pk, sk) = key_pair("[aabbccdd/90']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/1'/*");
+        let request = KeyRequest::Bip32((
+            bip32::Fingerprint::from_str("aabbccdd").unwrap(),
+            bip32::DerivationPath::from_str("m/90'/0'/1'/5").unwrap(),
+        ));
+
+        let mut key_map = KeyMap::new();
+        key_map.insert(pk.clone(), sk);
+        assert!(key_map.get_key(&request, &secp).unwrap().is_some());
+
+        // Path outside of the key's origin
+        let other_request = KeyRequest::Bip32((
+            bip32::Fingerprint::from_str("aabbccdd").unwrap(),
+            bip32::DerivationPath::from_str("m/91'/0'/1'/5").unwrap(),
+        ));
+        assert_eq!(key_map.get_key(&other_request, &secp).unwrap(), None);
+
+        let sk = key_map.remove(&pk).unwrap();
+        assert_eq!(key_map.get_key(&request, &secp).unwrap(), None);
+
+        // Vacant entries are not indexed
+        let _ = key_map.entry(pk.clone());
+        assert_eq!(key_map.get_key(&request, &secp).unwrap(), None);
+        assert!(key_map.fingerprints.is_empty());
+        key_map.entry(pk).or_insert(sk);
+        assert!(key_map.get_key(&request, &secp).unwrap().is_some());
+    }
+
+    #[test]
+    fn merge() {
+        let (pk_a, sk_a) = key_pair("tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/1'/2");
+        let (pk_b, sk_b) = key_pair("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA");
+
+        let mut receive = KeyMap::new();
+        receive.insert(pk_a.clone(), sk_a);
+        let mut change = KeyMap::new();
+        change.insert(pk_a.clone(), key_pair("tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/1'/2").1);
+        change.insert(pk_b.clone(), sk_b);
+
+        // Identical entries are not conflicts
+        receive.merge(change).unwrap();
+        assert_eq!(receive.len(), 2);
+
+        // The same public key with a different secret is
+        let mut conflicting = KeyMap::new();
+        conflicting.insert(
+            pk_b.clone(),
+            key_pair("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").1,
+        );
+        assert_eq!(
+            receive.merge(conflicting),
+            Err(KeyMapMergeError {
+                conflicts: vec![pk_b],
+            })
+        );
+        assert_eq!(receive.len(), 2);
+    }
+
+    #[test]
+    fn derive_secret() {
+        let secp = secp256k1::Secp256k1::new();
+        let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, "wsh(multi(1,[aabbccdd/90']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/1'/*,tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/*))").unwrap();
+
+        let mut keys = vec![];
+        descriptor.for_each_key(|key| {
+            keys.push(key.as_key().clone());
+            true
+        });
+        for key in keys {
+            assert_eq!(
+                key_map.derive_secret(&key, &secp),
+                Err(DeriveSecretError::Conversion(ConversionError::Wildcard))
+            );
+            let derived = key.derive(42);
+            let sk = key_map.derive_secret(&derived, &secp).unwrap().unwrap();
+            assert_eq!(
+                sk.public_key(&secp),
+                derived.derive_public_key(&secp).unwrap()
+            );
+        }
+
+        let (unrelated, _) = key_pair("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA");
+        assert_eq!(key_map.derive_secret(&unrelated, &secp), Ok(None));
+    }
+}
@@ -0,0 +1,189 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP 39 Mnemonics
+//!
+//! Secret keys derived from a BIP 39 mnemonic and passphrase, as written down
+//! by the users of most wallets, and the standard single signature
+//! descriptors of their accounts, along with the secret keys to sign for
+//! them.
+//!
+
+use bip39::Mnemonic;
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::util::bip32;
+use bitcoin::Network;
+
+use super::templates::{self, Template};
+use super::{
+    Descriptor, DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey, KeyMap, Wildcard,
+};
+use {Error, ForEachKey};
+
+/// The master key of `mnemonic` with `passphrase`
+fn master_key(
+    mnemonic: &str,
+    passphrase: &str,
+    network: Network,
+) -> Result<bip32::ExtendedPrivKey, Error> {
+    let mnemonic =
+        Mnemonic::parse(mnemonic).map_err(|e| Error::BadDescriptor(format!("mnemonic: {}", e)))?;
+    bip32::ExtendedPrivKey::new_master(network, &mnemonic.to_seed(passphrase))
+        .map_err(|e| Error::BadDescriptor(e.to_string()))
+}
+
+impl DescriptorSecretKey {
+    /// The master xprv of the BIP 39 `mnemonic` with `passphrase`, on
+    /// `network`
+    ///
+    /// The passphrase may be empty.
+    pub fn from_mnemonic(
+        mnemonic: &str,
+        passphrase: &str,
+        network: Network,
+    ) -> Result<DescriptorSecretKey, Error> {
+        Ok(DescriptorSecretKey::XPrv(DescriptorXKey {
+            origin: None,
+            xkey: master_key(mnemonic, passphrase, network)?,
+            derivation_path: bip32::DerivationPath::from(vec![]),
+            wildcard: Wildcard::None,
+        }))
+    }
+
+    /// The xprv at `path` from the master key of the BIP 39 `mnemonic` with
+    /// `passphrase`, on `network`
+    ///
+    /// Its origin is the fingerprint of the master key and `path`, as needed
+    /// by signers to recognize the keys derived from it.
+    pub fn from_mnemonic_path<C: Signing>(
+        mnemonic: &str,
+        passphrase: &str,
+        network: Network,
+        path: &bip32::DerivationPath,
+        secp: &Secp256k1<C>,
+    ) -> Result<DescriptorSecretKey, Error> {
+        let master = master_key(mnemonic, passphrase, network)?;
+        let xkey = master
+            .derive_priv(secp, path)
+            .map_err(|e| Error::BadDescriptor(e.to_string()))?;
+        let fingerprint = master.fingerprint(secp);
+
+        let origin = if path.as_ref().is_empty() {
+            None
+        } else {
+            Some((fingerprint, path.clone()))
+        };
+        Ok(DescriptorSecretKey::XPrv(DescriptorXKey {
+            origin: origin,
+            xkey: xkey,
+            derivation_path: bip32::DerivationPath::from(vec![]),
+            wildcard: Wildcard::None,
+        }))
+    }
+}
+
+/// The descriptor of `template` for `keychain` of `account` of the wallet of
+/// `mnemonic`, with the account xprv it is derived from
+pub(super) fn descriptor<C: Signing>(
+    template: Template,
+    mnemonic: &str,
+    passphrase: &str,
+    network: Network,
+    account: u32,
+    keychain: u32,
+    secp: &Secp256k1<C>,
+) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+    let path = templates::account_path(template, network, account)?;
+    let master = master_key(mnemonic, passphrase, network)?;
+    let account_xprv = master
+        .derive_priv(secp, &path)
+        .map_err(|e| Error::BadDescriptor(e.to_string()))?;
+    let fingerprint = master.fingerprint(secp);
+
+    let xpub = bip32::ExtendedPubKey::from_private(secp, &account_xprv);
+    let desc = templates::descriptor(template, xpub, fingerprint, account, keychain)?;
+    let mut key_map = KeyMap::new();
+    desc.for_each_key(|key| {
+        if let DescriptorPublicKey::XPub(ref xpub) = *key.as_key() {
+            let sk = DescriptorSecretKey::XPrv(DescriptorXKey {
+                origin: xpub.origin.clone(),
+                xkey: account_xprv,
+                derivation_path: xpub.derivation_path.clone(),
+                wildcard: xpub.wildcard,
+            });
+            key_map.insert(key.as_key().clone(), sk);
+        }
+        true
+    });
+    Ok((desc, key_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use {DescriptorTrait, TranslatePk2};
+
+    const MNEMONIC: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn mnemonic_keys() {
+        let secp = Secp256k1::new();
+        let master = DescriptorSecretKey::from_mnemonic(MNEMONIC, "", Network::Bitcoin).unwrap();
+        let fingerprint = match master {
+            DescriptorSecretKey::XPrv(ref xprv) => xprv.xkey.fingerprint(&secp),
+            DescriptorSecretKey::SinglePriv(_) => unreachable!(),
+        };
+        assert_eq!(fingerprint.to_string(), "73c5da0a");
+
+        let path = bip32::DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let account =
+            DescriptorSecretKey::from_mnemonic_path(MNEMONIC, "", Network::Bitcoin, &path, &secp)
+                .unwrap();
+        match account {
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                assert_eq!(xprv.origin, Some((fingerprint, path.clone())))
+            }
+            DescriptorSecretKey::SinglePriv(_) => unreachable!(),
+        }
+
+        // A different passphrase gives a different wallet
+        let other = DescriptorSecretKey::from_mnemonic(MNEMONIC, "TREZOR", Network::Bitcoin);
+        assert!(other.unwrap() != master);
+        assert!(DescriptorSecretKey::from_mnemonic("abandon about", "", Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn mnemonic_descriptor() {
+        let secp = Secp256k1::new();
+        let (desc, key_map) =
+            descriptor(Template::Bip84, MNEMONIC, "", Network::Bitcoin, 0, 0, &secp).unwrap();
+        let address = desc
+            .derive(0)
+            .translate_pk2(|pk| pk.derive_public_key(&secp))
+            .unwrap()
+            .address(Network::Bitcoin)
+            .unwrap();
+        assert_eq!(
+            address.to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+
+        // The key map holds the secret key of the descriptor key
+        assert_eq!(key_map.len(), 1);
+        let (pk, sk) = key_map.iter().next().unwrap();
+        assert_eq!(sk.as_public(&secp).unwrap(), *pk);
+        assert!(desc.to_string().contains(&pk.to_string()));
+    }
+}
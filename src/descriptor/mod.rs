@@ -23,29 +23,46 @@
 //! these with BIP32 paths, pay-to-contract instructions, etc.
 //!
 
-use std::{collections::HashMap, sync::Arc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 use std::{
-    fmt,
+    cmp, error, fmt, ops,
     str::{self, FromStr},
+    u32,
 };
 
+#[cfg(feature = "arbitrary")]
+use arbitrary::{self, Arbitrary, Unstructured};
 use bitcoin::secp256k1;
+use bitcoin::util::bip32;
 use bitcoin::{self, Script};
 
 use self::checksum::verify_checksum;
+use self::key::networks_agree;
+use self::templates::Template;
 use expression;
+use interpreter::{Interpreter, SatisfiedConstraint};
 use miniscript;
+use miniscript::limits::{
+    HEIGHT_TIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
 use miniscript::{Legacy, Miniscript, Segwitv0};
+use policy::{semantic, Liftable};
 use {
     BareCtx, Error, ForEach, ForEachKey, MiniscriptKey, Satisfier, ToPublicKey, TranslatePk,
     TranslatePk2,
 };
 
+mod addr;
+mod aliases;
 mod bare;
+mod compact;
 mod segwitv0;
 mod sh;
 mod sortedmulti;
 // Descriptor Exports
+pub use self::addr::{Addr, Raw};
+pub use self::aliases::KeyAliases;
 pub use self::bare::{Bare, Pkh};
 pub use self::segwitv0::{Wpkh, Wsh, WshInner};
 pub use self::sh::{Sh, ShInner};
@@ -53,18 +70,174 @@ pub use self::sortedmulti::SortedMultiVec;
 
 mod checksum;
 mod key;
+mod key_map;
+#[cfg(feature = "bip39")]
+mod mnemonic;
+#[cfg(feature = "slip132")]
+mod slip132;
+mod spk_index;
+mod templates;
+#[cfg(feature = "bc-ur")]
+mod ur;
+mod wallet_policy;
 pub use self::key::{
     ConversionError, DescriptorKeyParseError, DescriptorPublicKey, DescriptorSecretKey,
     DescriptorSinglePriv, DescriptorSinglePub, DescriptorXKey, InnerXKey, Wildcard,
 };
+pub use self::key_map::{
+    ChainedGetKey, ChainedGetKeyError, DeriveSecretError, GetKey, KeyMap, KeyMapEntry,
+    KeyMapMergeError, KeyRequest,
+};
+#[cfg(feature = "slip132")]
+pub use self::slip132::{Slip132Map, Slip132Version};
+pub use self::spk_index::{DescriptorSpkIndex, SpkOrigin};
+#[cfg(feature = "bc-ur")]
+pub use self::ur::CryptoAccount;
+pub use self::wallet_policy::{KeyPlaceholder, KeychainKind, WalletPolicy};
+
+/// Upper bound on the weight of a satisfying witness along one spend path of a
+/// descriptor, see `Descriptor::satisfaction_weights`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpendPathWeight<Pk: MiniscriptKey> {
+    /// The leaf conditions satisfied along the path, lifted to semantic policies
+    pub conditions: Vec<semantic::Policy<Pk>>,
+    /// Upper bound on the weight of a satisfying witness along the path, in the
+    /// same terms as `DescriptorTrait::max_satisfaction_weight`
+    pub weight: usize,
+}
 
-/// Alias type for a map of public key to secret key
-///
-/// This map is returned whenever a descriptor that contains secrets is parsed using
-/// [`Descriptor::parse_descriptor`], since the descriptor will always only contain
-/// public keys. This map allows looking up the corresponding secret key given a
-/// public key from the descriptor.
-pub type KeyMap = HashMap<DescriptorPublicKey, DescriptorSecretKey>;
+impl<Pk: MiniscriptKey> SpendPathWeight<Pk> {
+    /// Whether all the conditions of the path can be satisfied now
+    fn is_satisfiable(&self, assets: &semantic::Assets<Pk>, chain: semantic::ChainState) -> bool {
+        let policy = semantic::Policy::Threshold(self.conditions.len(), self.conditions.clone());
+        policy.satisfiability(assets, chain) == semantic::Satisfiability::Now
+    }
+}
+
+/// The transaction fields which the timelocks satisfied by a witness require,
+/// see `Descriptor::get_satisfaction_with_timelocks`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimelockRequirements {
+    /// The minimum nLockTime of the transaction, if the witness satisfies an
+    /// `after`. The nSequence of the input must then not be final.
+    pub lock_time: Option<u32>,
+    /// The minimum nSequence of the input, if the witness satisfies an
+    /// `older`. The transaction version must then be at least 2.
+    pub sequence: Option<u32>,
+}
+
+impl TimelockRequirements {
+    /// Sets the nLockTime, the nSequence of the input at `index` and the
+    /// version of `tx` as required, keeping the values already meeting the
+    /// requirements
+    ///
+    /// Returns Error, leaving `tx` unchanged, if it has no input at `index`,
+    /// or if its nLockTime or the relative timelock of the input is set in
+    /// blocks where the requirement is in time, or conversely.
+    pub fn apply(&self, tx: &mut bitcoin::Transaction, index: usize) -> Result<(), TimelockError> {
+        if index >= tx.input.len() {
+            return Err(TimelockError::InputOutOfRange(index));
+        }
+        if let Some(lock_time) = self.lock_time {
+            let current = tx.lock_time;
+            if current != 0
+                && (current < HEIGHT_TIME_THRESHOLD) != (lock_time < HEIGHT_TIME_THRESHOLD)
+            {
+                return Err(TimelockError::LockTimeUnitMismatch {
+                    current: current,
+                    required: lock_time,
+                });
+            }
+        }
+        if let Some(sequence) = self.sequence {
+            // A disabled relative timelock may be replaced by either unit
+            let current = tx.input[index].sequence;
+            if current & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                && current & SEQUENCE_LOCKTIME_TYPE_FLAG != sequence & SEQUENCE_LOCKTIME_TYPE_FLAG
+            {
+                return Err(TimelockError::SequenceUnitMismatch {
+                    current: current,
+                    required: sequence,
+                });
+            }
+        }
+
+        if let Some(lock_time) = self.lock_time {
+            if tx.lock_time < lock_time {
+                tx.lock_time = lock_time;
+            }
+            if tx.input[index].sequence == 0xffffffff {
+                tx.input[index].sequence = 0xfffffffe;
+            }
+        }
+        if let Some(sequence) = self.sequence {
+            // A disabled relative timelock does not meet the requirement
+            let current = tx.input[index].sequence;
+            if current & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 || current < sequence {
+                tx.input[index].sequence = sequence;
+            }
+            if tx.version < 2 {
+                tx.version = 2;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error applying [TimelockRequirements] to a transaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimelockError {
+    /// The transaction has no input at the index
+    InputOutOfRange(usize),
+    /// The nLockTime of the transaction is a height and the required one a
+    /// time, or conversely
+    LockTimeUnitMismatch {
+        /// The nLockTime of the transaction
+        current: u32,
+        /// The required nLockTime
+        required: u32,
+    },
+    /// The relative timelock of the input is in blocks and the required one
+    /// in time, or conversely
+    SequenceUnitMismatch {
+        /// The nSequence of the input
+        current: u32,
+        /// The required nSequence
+        required: u32,
+    },
+}
+
+impl fmt::Display for TimelockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimelockError::InputOutOfRange(index) => write!(f, "no input at index {}", index),
+            TimelockError::LockTimeUnitMismatch { current, required } => write!(
+                f,
+                "nLockTime {} cannot be raised to {} of another unit",
+                current, required
+            ),
+            TimelockError::SequenceUnitMismatch { current, required } => write!(
+                f,
+                "nSequence {} cannot be raised to {} of another unit",
+                current, required
+            ),
+        }
+    }
+}
+
+impl error::Error for TimelockError {}
+
+/// The only spend path of descriptors without disjunctions
+fn single_spend_path<Pk, T>(desc: &T) -> Result<Vec<SpendPathWeight<Pk>>, Error>
+where
+    Pk: MiniscriptKey,
+    T: DescriptorTrait<Pk> + Liftable<Pk>,
+{
+    Ok(vec![SpendPathWeight {
+        conditions: vec![desc.lift()?],
+        weight: desc.max_satisfaction_weight()?,
+    }])
+}
 
 /// A general trait for Bitcoin descriptor.
 /// Offers function for witness cost estimation, script pubkey creation
@@ -166,6 +339,10 @@ pub enum Descriptor<Pk: MiniscriptKey> {
     Sh(Sh<Pk>),
     /// Pay-to-Witness-ScriptHash with Segwitv0 context
     Wsh(Wsh<Pk>),
+    /// An address, for watch-only use
+    Addr(Addr),
+    /// A raw scriptPubKey, for watch-only use
+    Raw(Raw),
 }
 
 /// Descriptor Type of the descriptor
@@ -191,6 +368,10 @@ pub enum DescriptorType {
     WshSortedMulti,
     /// Sh Wsh Sorted Multi
     ShWshSortedMulti,
+    /// Addr Descriptor
+    Addr,
+    /// Raw Descriptor
+    Raw,
 }
 
 impl<Pk: MiniscriptKey> Descriptor<Pk> {
@@ -255,6 +436,18 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
         Ok(Descriptor::Bare(Bare::new(ms)?))
     }
 
+    // watch-only
+
+    /// Create a new addr descriptor
+    pub fn new_addr(address: bitcoin::Address) -> Self {
+        Descriptor::Addr(Addr::new(address))
+    }
+
+    /// Create a new raw descriptor
+    pub fn new_raw(script: Script) -> Self {
+        Descriptor::Raw(Raw::new(script))
+    }
+
     // sorted multi
 
     /// Create a new sh sortedmulti descriptor with threshold `k`
@@ -296,10 +489,275 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
                 WshInner::SortedMulti(ref _smv) => DescriptorType::WshSortedMulti,
                 WshInner::Ms(ref _ms) => DescriptorType::Wsh,
             },
+            Descriptor::Addr(ref _addr) => DescriptorType::Addr,
+            Descriptor::Raw(ref _raw) => DescriptorType::Raw,
+        }
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness along each
+    /// spend path of the descriptor
+    ///
+    /// A spend path is a choice of branch at each disjunction and threshold of
+    /// the descriptor's miniscript, see `Miniscript::satisfaction_paths`;
+    /// descriptors without miniscript have a single path. The weights are
+    /// computed as in `max_satisfaction_weight`, which is the maximum of them.
+    /// Returns Error when the descriptor is impossible to satisfy.
+    pub fn satisfaction_weights(&self) -> Result<Vec<SpendPathWeight<Pk>>, Error> {
+        let weights = match *self {
+            Descriptor::Bare(ref bare) => bare.satisfaction_weights(),
+            Descriptor::Pkh(ref pkh) => single_spend_path(pkh)?,
+            Descriptor::Wpkh(ref wpkh) => single_spend_path(wpkh)?,
+            Descriptor::Wsh(ref wsh) => wsh.satisfaction_weights()?,
+            Descriptor::Sh(ref sh) => sh.satisfaction_weights()?,
+            Descriptor::Addr(..) | Descriptor::Raw(..) => return Err(Error::WatchOnlyDescriptor),
+        };
+        if weights.is_empty() {
+            Err(Error::ImpossibleSatisfaction)
+        } else {
+            Ok(weights)
+        }
+    }
+
+    /// Whether both descriptors have the same spending conditions, whatever
+    /// their type, their choice of fragments or the order of their keys,
+    /// comparing their semantic policies. Returns Error when a descriptor
+    /// cannot be lifted or the policies are too large to compare, see
+    /// `semantic::Policy::entails`.
+    pub fn is_equivalent(&self, other: &Descriptor<Pk>) -> Result<bool, Error> {
+        let policy = self.lift()?.normalized();
+        let other = other.lift()?.normalized();
+        if policy == other {
+            return Ok(true);
+        }
+        Ok(policy.is_equivalent(&other)?)
+    }
+
+    /// Returns the lightest spend path of the descriptor which can be
+    /// satisfied now with `assets`, see `semantic::Policy::satisfiability`.
+    /// Of spend paths of equal weight, the first one is picked. Returns None
+    /// when no spend path can be satisfied, and Error as
+    /// `satisfaction_weights` does.
+    pub fn cheapest_spend_path(
+        &self,
+        assets: &semantic::Assets<Pk>,
+        chain: semantic::ChainState,
+    ) -> Result<Option<SpendPathWeight<Pk>>, Error> {
+        let mut cheapest: Option<SpendPathWeight<Pk>> = None;
+        for path in self.satisfaction_weights()? {
+            if !path.is_satisfiable(assets, chain) {
+                continue;
+            }
+            if cheapest.as_ref().map_or(true, |c| path.weight < c.weight) {
+                cheapest = Some(path);
+            }
+        }
+        Ok(cheapest)
+    }
+
+    /// Returns the spend path at `index` in `satisfaction_weights`, to spend
+    /// along it even when other paths are cheaper, such as a recovery path.
+    /// Returns Error when there is no such path or when it cannot be
+    /// satisfied now with `assets`, see `semantic::Policy::satisfiability`.
+    pub fn spend_path(
+        &self,
+        index: usize,
+        assets: &semantic::Assets<Pk>,
+        chain: semantic::ChainState,
+    ) -> Result<SpendPathWeight<Pk>, Error> {
+        let mut paths = self.satisfaction_weights()?;
+        if index >= paths.len() {
+            return Err(Error::BadDescriptor(format!(
+                "no spend path {}, the descriptor has {}",
+                index,
+                paths.len()
+            )));
+        }
+        let path = paths.swap_remove(index);
+        if path.is_satisfiable(assets, chain) {
+            Ok(path)
+        } else {
+            Err(Error::CouldNotSatisfy)
+        }
+    }
+}
+
+/// Picks the cheapest spend path of each candidate descriptor which can be
+/// satisfied now with the candidate's assets, see
+/// `Descriptor::cheapest_spend_path`, and returns them with the index of
+/// their candidate, sorted by weight, as input to coin selection.
+///
+/// Candidates of equal weight are kept in their original order. Candidates
+/// which cannot be satisfied, including watch-only descriptors, are left out.
+/// Returns the index of the first candidate whose spend paths cannot be
+/// weighed, with its error, rather than leaving it out.
+pub fn cheapest_spend_paths<'a, Pk, I>(
+    candidates: I,
+    chain: semantic::ChainState,
+) -> Result<Vec<(usize, SpendPathWeight<Pk>)>, (usize, Error)>
+where
+    Pk: MiniscriptKey + 'a,
+    I: IntoIterator<Item = (&'a Descriptor<Pk>, &'a semantic::Assets<Pk>)>,
+{
+    let mut paths = vec![];
+    for (i, (desc, assets)) in candidates.into_iter().enumerate() {
+        match desc.cheapest_spend_path(assets, chain) {
+            Ok(Some(path)) => paths.push((i, path)),
+            Ok(None) | Err(Error::WatchOnlyDescriptor) => {}
+            Err(e) => return Err((i, e)),
+        }
+    }
+    // The sort is stable, which keeps ties in the order of the candidates
+    paths.sort_by_key(|&(_, ref path)| path.weight);
+    Ok(paths)
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Returns satisfying witness and scriptSig, as `get_satisfaction` does,
+    /// along with the nLockTime and nSequence which the timelocks of the
+    /// chosen spend path require. The witness is interpreted to find these
+    /// timelocks, without checking its signatures.
+    ///
+    /// Returns Error if the chosen spend path has two absolute, or two
+    /// relative, timelocks of different units, as no transaction meets both.
+    /// Sane descriptors have no such spend path.
+    pub fn get_satisfaction_with_timelocks<S: Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+    ) -> Result<(Vec<Vec<u8>>, Script, TimelockRequirements), Error> {
+        let (witness, script_sig) = self.get_satisfaction(satisfier)?;
+        let mut requirements = TimelockRequirements::default();
+        {
+            let spk = self.script_pubkey();
+            let mut interpreter =
+                Interpreter::from_txdata(&spk, &script_sig, &witness, u32::MAX, u32::MAX)
+                    .map_err(|e| Error::InterpreterError(Box::new(e)))?;
+            for constraint in interpreter.iter_assume_sigs() {
+                match constraint.map_err(|e| Error::InterpreterError(Box::new(e)))? {
+                    SatisfiedConstraint::AbsoluteTimeLock { time } => {
+                        let lock_time = match requirements.lock_time {
+                            Some(lock_time)
+                                if (lock_time < HEIGHT_TIME_THRESHOLD)
+                                    != (*time < HEIGHT_TIME_THRESHOLD) =>
+                            {
+                                return Err(Error::MixedLockTimeUnits(lock_time, *time));
+                            }
+                            Some(lock_time) => cmp::max(lock_time, *time),
+                            None => *time,
+                        };
+                        requirements.lock_time = Some(lock_time);
+                    }
+                    SatisfiedConstraint::RelativeTimeLock { time } => {
+                        let sequence = match requirements.sequence {
+                            Some(sequence)
+                                if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG
+                                    != *time & SEQUENCE_LOCKTIME_TYPE_FLAG =>
+                            {
+                                return Err(Error::MixedSequenceUnits(sequence, *time));
+                            }
+                            Some(sequence) => cmp::max(sequence, *time),
+                            None => *time,
+                        };
+                        requirements.sequence = Some(sequence);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok((witness, script_sig, requirements))
+    }
+
+    /// Replaces the `multi` of a `sh`, `wsh` or `sh(wsh)` descriptor by a
+    /// `sortedmulti` when its keys are in the order of BIP 67
+    ///
+    /// Both have the same script, so a descriptor inferred from a spend, or
+    /// built from a Miniscript parsed from a script, has a `multi`. Wallets use
+    /// `sortedmulti` more often, whose keys are sorted again at every derivation
+    /// index, so this gives the descriptor which is more likely to describe
+    /// their other outputs. Other descriptors are returned as they are.
+    pub fn detect_sortedmulti(self) -> Descriptor<Pk> {
+        let sortedmulti = match self {
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Ms(ref ms) => {
+                    sorted_multi(ms).map(|(k, pks)| Descriptor::new_sh_sortedmulti(k, pks))
+                }
+                ShInner::Wsh(ref wsh) => match *wsh.as_inner() {
+                    WshInner::Ms(ref ms) => {
+                        sorted_multi(ms).map(|(k, pks)| Descriptor::new_sh_wsh_sortedmulti(k, pks))
+                    }
+                    WshInner::SortedMulti(_) => None,
+                },
+                ShInner::Wpkh(_) | ShInner::SortedMulti(_) => None,
+            },
+            Descriptor::Wsh(ref wsh) => match *wsh.as_inner() {
+                WshInner::Ms(ref ms) => {
+                    sorted_multi(ms).map(|(k, pks)| Descriptor::new_wsh_sortedmulti(k, pks))
+                }
+                WshInner::SortedMulti(_) => None,
+            },
+            Descriptor::Bare(_)
+            | Descriptor::Pkh(_)
+            | Descriptor::Wpkh(_)
+            | Descriptor::Addr(_)
+            | Descriptor::Raw(_) => None,
+        };
+        match sortedmulti {
+            Some(Ok(desc)) => desc,
+            Some(Err(_)) | None => self,
         }
     }
 }
 
+/// The threshold and keys of `ms` if it is a `multi` whose keys are in the
+/// order of BIP 67
+fn sorted_multi<Pk, Ctx>(ms: &Miniscript<Pk, Ctx>) -> Option<(usize, Vec<Pk>)>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    Ctx: miniscript::ScriptContext,
+{
+    match ms.node {
+        miniscript::decode::Terminal::Multi(k, ref pks) => {
+            let sorted = pks.windows(2).all(|pair| {
+                pair[0].to_public_key().key.serialize()[..]
+                    <= pair[1].to_public_key().key.serialize()[..]
+            });
+            if sorted {
+                Some((k, pks.clone()))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Descriptor<bitcoin::PublicKey> {
+    /// Infers the descriptor of the output `spk` from the scriptSig and
+    /// witness spending it
+    ///
+    /// The keys of `pkh` and `wpkh` outputs, and the scripts of `sh` and `wsh`
+    /// outputs, are those revealed by the spend. As for
+    /// `Interpreter::inferred_descriptor`, a `multi` cannot be told apart from
+    /// a `sortedmulti`, see `detect_sortedmulti`, and key origins are lost, and
+    /// the descriptor must be sane. Signatures are not checked, nor whether the
+    /// spend satisfies the script: only its structure is.
+    pub fn from_spend(
+        spk: &Script,
+        script_sig: &Script,
+        witness: &[Vec<u8>],
+    ) -> Result<Descriptor<bitcoin::PublicKey>, Error> {
+        let interpreter = Interpreter::from_txdata(spk, script_sig, witness, 0, 0)
+            .map_err(|e| Error::Unexpected(e.to_string()))?;
+        let desc = interpreter.inferred_descriptor()?;
+        if desc.script_pubkey() != *spk {
+            return Err(Error::BadDescriptor(format!(
+                "inferred descriptor {} does not pay to {}",
+                desc, spk
+            )));
+        }
+        Ok(desc)
+    }
+}
+
 impl<P: MiniscriptKey, Q: MiniscriptKey> TranslatePk<P, Q> for Descriptor<P> {
     type Output = Descriptor<Q>;
     /// Convert a descriptor using abstract keys to one using specific keys
@@ -332,6 +790,8 @@ impl<P: MiniscriptKey, Q: MiniscriptKey> TranslatePk<P, Q> for Descriptor<P> {
             Descriptor::Wsh(ref wsh) => {
                 Descriptor::Wsh(wsh.translate_pk(&mut translatefpk, &mut translatefpkh)?)
             }
+            Descriptor::Addr(ref addr) => Descriptor::Addr(addr.clone()),
+            Descriptor::Raw(ref raw) => Descriptor::Raw(raw.clone()),
         };
         Ok(desc)
     }
@@ -353,6 +813,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.sanity_check(),
             Descriptor::Wsh(ref wsh) => wsh.sanity_check(),
             Descriptor::Sh(ref sh) => sh.sanity_check(),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::sanity_check(addr),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::sanity_check(raw),
         }
     }
     /// Computes the Bitcoin address of the descriptor, if one exists
@@ -366,6 +828,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.address(network),
             Descriptor::Wsh(ref wsh) => wsh.address(network),
             Descriptor::Sh(ref sh) => sh.address(network),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::address(addr, network),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::address(raw, network),
         }
     }
 
@@ -380,6 +844,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.script_pubkey(),
             Descriptor::Wsh(ref wsh) => wsh.script_pubkey(),
             Descriptor::Sh(ref sh) => sh.script_pubkey(),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::script_pubkey(addr),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::script_pubkey(raw),
         }
     }
 
@@ -401,6 +867,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.unsigned_script_sig(),
             Descriptor::Wsh(ref wsh) => wsh.unsigned_script_sig(),
             Descriptor::Sh(ref sh) => sh.unsigned_script_sig(),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::unsigned_script_sig(addr),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::unsigned_script_sig(raw),
         }
     }
 
@@ -418,6 +886,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.explicit_script(),
             Descriptor::Wsh(ref wsh) => wsh.explicit_script(),
             Descriptor::Sh(ref sh) => sh.explicit_script(),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::explicit_script(addr),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::explicit_script(raw),
         }
     }
 
@@ -435,6 +905,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.get_satisfaction(satisfier),
             Descriptor::Wsh(ref wsh) => wsh.get_satisfaction(satisfier),
             Descriptor::Sh(ref sh) => sh.get_satisfaction(satisfier),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::get_satisfaction(addr, satisfier),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::get_satisfaction(raw, satisfier),
         }
     }
 
@@ -449,6 +921,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.max_satisfaction_weight(),
             Descriptor::Wsh(ref wsh) => wsh.max_satisfaction_weight(),
             Descriptor::Sh(ref sh) => sh.max_satisfaction_weight(),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::max_satisfaction_weight(addr),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::max_satisfaction_weight(raw),
         }
     }
 
@@ -466,6 +940,8 @@ impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.script_code(),
             Descriptor::Wsh(ref wsh) => wsh.script_code(),
             Descriptor::Sh(ref sh) => sh.script_code(),
+            Descriptor::Addr(ref addr) => DescriptorTrait::<Pk>::script_code(addr),
+            Descriptor::Raw(ref raw) => DescriptorTrait::<Pk>::script_code(raw),
         }
     }
 }
@@ -482,6 +958,19 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.for_each_key(pred),
             Descriptor::Wsh(ref wsh) => wsh.for_each_key(pred),
             Descriptor::Sh(ref sh) => sh.for_each_key(pred),
+            Descriptor::Addr(..) | Descriptor::Raw(..) => true,
+        }
+    }
+
+    fn key_path(&self, index: usize) -> Option<Vec<usize>> {
+        match *self {
+            Descriptor::Bare(ref bare) => bare.key_path(index),
+            Descriptor::Wsh(ref wsh) => wsh.key_path(index),
+            Descriptor::Sh(ref sh) => sh.key_path(index),
+            Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::Addr(..)
+            | Descriptor::Raw(..) => None,
         }
     }
 }
@@ -499,6 +988,376 @@ impl Descriptor<DescriptorPublicKey> {
         self.translate_pk2_infallible(|pk| pk.clone().derive(index))
     }
 
+    /// The BIP 44 descriptor `pkh([fingerprint/44'/coin'/account']xpub/keychain/*)`
+    ///
+    /// `xpub` is the key of the account and `fingerprint` the fingerprint of
+    /// the master key. The coin type is `0'` for mainnet keys and `1'`
+    /// otherwise. `keychain` is `0` for receive addresses and `1` for change
+    /// addresses; `WalletPolicy::bip44` describes both at once.
+    pub fn bip44(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: u32,
+    ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        templates::descriptor(Template::Bip44, xpub, fingerprint, account, keychain)
+    }
+
+    /// The BIP 49 descriptor `sh(wpkh([fingerprint/49'/coin'/account']xpub/keychain/*))`
+    ///
+    /// See `bip44` for the meaning of the arguments.
+    pub fn bip49(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: u32,
+    ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        templates::descriptor(Template::Bip49, xpub, fingerprint, account, keychain)
+    }
+
+    /// The BIP 84 descriptor `wpkh([fingerprint/84'/coin'/account']xpub/keychain/*)`
+    ///
+    /// See `bip44` for the meaning of the arguments.
+    pub fn bip84(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: u32,
+    ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        templates::descriptor(Template::Bip84, xpub, fingerprint, account, keychain)
+    }
+
+    /// The BIP 44 descriptor of `account` of the wallet of the BIP 39
+    /// `mnemonic` with `passphrase`, for `keychain`, and the key map holding
+    /// the xprv of the account
+    ///
+    /// The coin type is `0'` for mainnet and `1'` otherwise. See `bip44` for
+    /// the meaning of `keychain`.
+    #[cfg(feature = "bip39")]
+    pub fn bip44_mnemonic<C: secp256k1::Signing>(
+        mnemonic: &str,
+        passphrase: &str,
+        network: bitcoin::Network,
+        account: u32,
+        keychain: u32,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+        mnemonic::descriptor(
+            Template::Bip44,
+            mnemonic,
+            passphrase,
+            network,
+            account,
+            keychain,
+            secp,
+        )
+    }
+
+    /// The BIP 49 descriptor of `account` of the wallet of the BIP 39
+    /// `mnemonic`, see `bip44_mnemonic`
+    #[cfg(feature = "bip39")]
+    pub fn bip49_mnemonic<C: secp256k1::Signing>(
+        mnemonic: &str,
+        passphrase: &str,
+        network: bitcoin::Network,
+        account: u32,
+        keychain: u32,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+        mnemonic::descriptor(
+            Template::Bip49,
+            mnemonic,
+            passphrase,
+            network,
+            account,
+            keychain,
+            secp,
+        )
+    }
+
+    /// The BIP 84 descriptor of `account` of the wallet of the BIP 39
+    /// `mnemonic`, see `bip44_mnemonic`
+    #[cfg(feature = "bip39")]
+    pub fn bip84_mnemonic<C: secp256k1::Signing>(
+        mnemonic: &str,
+        passphrase: &str,
+        network: bitcoin::Network,
+        account: u32,
+        keychain: u32,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+        mnemonic::descriptor(
+            Template::Bip84,
+            mnemonic,
+            passphrase,
+            network,
+            account,
+            keychain,
+            secp,
+        )
+    }
+
+    /// Derives all wildcard keys in the descriptor using the supplied index,
+    /// including hardened ones, with the secret keys of `key_map`
+    ///
+    /// Keys which have hardened derivation steps once derived are replaced by
+    /// their single public key, computed from their secret key, with the full
+    /// derivation path from their master key as origin. Other keys are derived
+    /// as with `derive`. Returns `ConversionError::HardenedChild` if such a key
+    /// has no secret key in `key_map`, or the error of `KeyMap::derive_secret`.
+    ///
+    /// Panics if given an index ≥ 2^31
+    pub fn derive_with_secrets<C: secp256k1::Signing>(
+        &self,
+        index: u32,
+        key_map: &KeyMap,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Descriptor<DescriptorPublicKey>, DeriveSecretError> {
+        self.translate_pk2(|pk| {
+            let derived = pk.clone().derive(index);
+            let hardened = match derived {
+                DescriptorPublicKey::XPub(ref xpub) => {
+                    xpub.derivation_path
+                        .as_ref()
+                        .iter()
+                        .any(|child| match *child {
+                            bip32::ChildNumber::Hardened { .. } => true,
+                            bip32::ChildNumber::Normal { .. } => false,
+                        })
+                }
+                DescriptorPublicKey::SinglePub(..) => false,
+            };
+            if !hardened {
+                return Ok(derived);
+            }
+
+            let sk = key_map
+                .derive_secret(&derived, secp)?
+                .ok_or(ConversionError::HardenedChild)?;
+            Ok(DescriptorPublicKey::SinglePub(DescriptorSinglePub {
+                origin: Some((derived.master_fingerprint(), derived.full_derivation_path())),
+                key: sk.public_key(secp),
+            }))
+        })
+    }
+
+    /// Derives the descriptors at all the indices of `range`, with every key
+    /// converted to a bitcoin public key
+    ///
+    /// This is equivalent to calling `derive` and then `derive_public_key` on
+    /// every key for each index, except that the derivation path of each extended
+    /// key is only derived once: only the wildcard step is derived per index.
+    ///
+    /// Panics if `range` contains an index ≥ 2^31
+    pub fn derive_batch<C: secp256k1::Verification>(
+        &self,
+        range: ops::Range<u32>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Vec<Descriptor<bitcoin::PublicKey>>, ConversionError> {
+        let cache = self.derivation_cache(secp)?;
+        range
+            .map(|index| self.translate_pk2(|pk| Ok(cache[pk].at(index, secp))))
+            .collect()
+    }
+
+    /// Derives the script pubkeys of the descriptor at all the indices of
+    /// `range`, in order, splitting the range across the threads of the rayon
+    /// thread pool
+    ///
+    /// Keys are derived as with `derive_batch`, each thread sharing the
+    /// derivation paths of the extended keys derived once beforehand.
+    ///
+    /// Panics if `range` contains an index ≥ 2^31
+    #[cfg(feature = "rayon")]
+    pub fn derive_spks<C: secp256k1::Verification>(
+        &self,
+        range: ops::Range<u32>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Vec<Script>, ConversionError> {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        let cache = self.derivation_cache(secp)?;
+        range
+            .into_par_iter()
+            .map(|index| {
+                self.translate_pk2(|pk| Ok(cache[pk].at(index, secp)))
+                    .map(|desc| desc.script_pubkey())
+            })
+            .collect()
+    }
+
+    /// Derives every key of the descriptor up to its wildcard
+    fn derivation_cache<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<BTreeMap<DescriptorPublicKey, CachedDerivation>, ConversionError> {
+        let mut keys = BTreeSet::new();
+        self.for_each_key(|key| {
+            keys.insert(key.as_key().clone());
+            true
+        });
+        let mut cache = BTreeMap::new();
+        for key in keys {
+            let cached = CachedDerivation::new(&key, secp)?;
+            cache.insert(key, cached);
+        }
+        Ok(cache)
+    }
+
+    /// Finds which of the script pubkeys `spks` are derived from the descriptor at
+    /// an index of `range`, and at which index
+    ///
+    /// The script pubkeys of all the indices of `range` are derived once, see
+    /// `derive_batch`, and every script pubkey of `spks` is then looked up in
+    /// that table. Script pubkeys which don't belong to the descriptor are
+    /// absent from the returned map. If the descriptor has no wildcard, every
+    /// matching script pubkey is reported at the start of `range`.
+    ///
+    /// Panics if `range` contains an index ≥ 2^31
+    pub fn find_derivation_indices<'s, C, I>(
+        &self,
+        spks: I,
+        range: ops::Range<u32>,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<BTreeMap<Script, u32>, ConversionError>
+    where
+        C: secp256k1::Verification,
+        I: IntoIterator<Item = &'s Script>,
+    {
+        let mut table = BTreeMap::new();
+        for (index, desc) in range.clone().zip(self.derive_batch(range, secp)?) {
+            table.entry(desc.script_pubkey()).or_insert(index);
+        }
+
+        let mut ret = BTreeMap::new();
+        for spk in spks {
+            if let Some(index) = table.get(spk) {
+                ret.insert(spk.clone(), *index);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Checks that all the extended keys of the descriptor are for `network`
+    ///
+    /// Test networks share their key encoding, so keys for any of them are
+    /// accepted for another. Single public keys don't encode a network.
+    pub fn check_network(&self, network: bitcoin::Network) -> Result<(), Error> {
+        let mut mismatch = None;
+        self.for_each_key(|key| match *key.as_key() {
+            DescriptorPublicKey::XPub(ref xpub) if !networks_agree(xpub.xkey.network, network) => {
+                mismatch = Some(xpub.xkey.network);
+                false
+            }
+            _ => true,
+        });
+        match mismatch {
+            Some(key_network) => Err(Error::BadDescriptor(format!(
+                "extended key for {} used on {}",
+                key_network, network
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Converts all the extended keys of the descriptor to `network`, replacing
+    /// `xpub`s by `tpub`s or conversely
+    pub fn to_network(&self, network: bitcoin::Network) -> Descriptor<DescriptorPublicKey> {
+        self.translate_pk2_infallible(|pk| match *pk {
+            DescriptorPublicKey::XPub(ref xpub) => {
+                let mut xpub = xpub.clone();
+                xpub.xkey.network = network;
+                DescriptorPublicKey::XPub(xpub)
+            }
+            DescriptorPublicKey::SinglePub(_) => pk.clone(),
+        })
+    }
+
+    /// Replaces every occurrence of the key `old` in the descriptor with `new`
+    ///
+    /// The resulting descriptor is validated as if it had been parsed from a
+    /// string. If this descriptor passes `sanity_check`, the resulting one must
+    /// too, e.g. rotating a key to another key of the descriptor is an error. Its
+    /// checksum is computed from the new keys when displayed.
+    ///
+    /// Returns an error if `old` does not appear in the descriptor.
+    pub fn replace_key(
+        &self,
+        old: &DescriptorPublicKey,
+        new: DescriptorPublicKey,
+    ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        if !self.for_any_key(|key| key.as_key() == old) {
+            return Err(Error::BadDescriptor(format!(
+                "key {} not found in descriptor",
+                old
+            )));
+        }
+        let replaced =
+            self.translate_pk2_infallible(|pk| if pk == old { new.clone() } else { pk.clone() });
+        let replaced = Descriptor::from_str(&replaced.to_string())?;
+        if self.sanity_check().is_ok() {
+            replaced.sanity_check()?;
+        }
+        Ok(replaced)
+    }
+
+    /// Parse a descriptor whose extended public keys may use the SLIP-132
+    /// versions (`ypub`, `zpub`, `vpub`...)
+    ///
+    /// Such keys are converted to standard `xpub`s and `tpub`s. The returned map
+    /// records the SLIP-132 version of each converted key, to display them as
+    /// they were given with `to_string_with_slip132`.
+    #[cfg(feature = "slip132")]
+    pub fn parse_descriptor_slip132(
+        s: &str,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, Slip132Map), Error> {
+        slip132::parse_descriptor(s)
+    }
+
+    /// Serialize a descriptor to string, displaying the extended public keys
+    /// found in `versions` with their SLIP-132 version
+    ///
+    /// The checksum is computed over the displayed string.
+    #[cfg(feature = "slip132")]
+    pub fn to_string_with_slip132(&self, versions: &Slip132Map) -> String {
+        slip132::to_string(self, versions)
+    }
+
+    /// Serializes the descriptor in a compact binary encoding, for QR codes and
+    /// devices with little storage
+    ///
+    /// The encoding is a tree of tag-length-value records over the fragments
+    /// and keys of the descriptor, which are serialized in binary. It does not
+    /// include the checksum.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        compact::encode(self)
+    }
+
+    /// Parses a descriptor from the encoding of `to_compact_bytes`
+    ///
+    /// The descriptor is validated as if it was parsed from a string.
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        compact::decode(bytes)
+    }
+
+    /// Serializes the descriptor to the CBOR of the Blockchain Commons
+    /// `crypto-output` UR type (BCR-2020-010)
+    ///
+    /// Returns an error for descriptors which are not expressible in the
+    /// `crypto-output` format, such as descriptors with miniscript beyond `pk`
+    /// and `multi`.
+    #[cfg(feature = "bc-ur")]
+    pub fn to_crypto_output(&self) -> Result<Vec<u8>, Error> {
+        ur::to_crypto_output(self)
+    }
+
+    /// Parses a descriptor from the CBOR of the Blockchain Commons
+    /// `crypto-output` UR type (BCR-2020-010)
+    #[cfg(feature = "bc-ur")]
+    pub fn from_crypto_output(bytes: &[u8]) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        ur::from_crypto_output(bytes)
+    }
+
     /// Parse a descriptor that may contain secret keys
     ///
     /// Internally turns every secret key found into the corresponding public key and then returns a
@@ -558,6 +1417,52 @@ impl Descriptor<DescriptorPublicKey> {
     }
 }
 
+/// A descriptor public key derived up to its wildcard, if any
+#[derive(Clone, Debug)]
+enum CachedDerivation {
+    /// The key has no wildcard
+    Key(bitcoin::PublicKey),
+    /// The key has an unhardened wildcard
+    Wildcard(bip32::ExtendedPubKey),
+}
+
+impl CachedDerivation {
+    fn new<C: secp256k1::Verification>(
+        key: &DescriptorPublicKey,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<CachedDerivation, ConversionError> {
+        match *key {
+            DescriptorPublicKey::XPub(ref xpub) if xpub.wildcard == Wildcard::Unhardened => {
+                match xpub.xkey.derive_pub(secp, &xpub.derivation_path) {
+                    Ok(xkey) => Ok(CachedDerivation::Wildcard(xkey)),
+                    Err(bip32::Error::CannotDeriveFromHardenedKey) => {
+                        Err(ConversionError::HardenedChild)
+                    }
+                    Err(e) => unreachable!("cryptographically unreachable: {}", e),
+                }
+            }
+            _ => key.derive_public_key(secp).map(CachedDerivation::Key),
+        }
+    }
+
+    fn at<C: secp256k1::Verification>(
+        &self,
+        index: u32,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> bitcoin::PublicKey {
+        match *self {
+            CachedDerivation::Key(pk) => pk,
+            CachedDerivation::Wildcard(ref xkey) => {
+                let child = bip32::ChildNumber::from_normal_idx(index).unwrap();
+                match xkey.ckd_pub(secp, child) {
+                    Ok(xkey) => xkey.public_key,
+                    Err(e) => unreachable!("cryptographically unreachable: {}", e),
+                }
+            }
+        }
+    }
+}
+
 impl<Pk> expression::FromTree for Descriptor<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
@@ -567,12 +1472,14 @@ where
 {
     /// Parse an expression tree into a descriptor
     fn from_tree(top: &expression::Tree) -> Result<Descriptor<Pk>, Error> {
-        Ok(match (top.name, top.args.len() as u32) {
-            ("pkh", 1) => Descriptor::Pkh(Pkh::from_tree(top)?),
-            ("wpkh", 1) => Descriptor::Wpkh(Wpkh::from_tree(top)?),
-            ("sh", 1) => Descriptor::Sh(Sh::from_tree(top)?),
-            ("wsh", 1) => Descriptor::Wsh(Wsh::from_tree(top)?),
-            _ => Descriptor::Bare(Bare::from_tree(top)?),
+        top.spanned(match (top.name, top.args.len() as u32) {
+            ("pkh", 1) => Pkh::from_tree(top).map(Descriptor::Pkh),
+            ("wpkh", 1) => Wpkh::from_tree(top).map(Descriptor::Wpkh),
+            ("sh", 1) => Sh::from_tree(top).map(Descriptor::Sh),
+            ("wsh", 1) => Wsh::from_tree(top).map(Descriptor::Wsh),
+            ("addr", 1) => Addr::from_tree(top).map(Descriptor::Addr),
+            ("raw", 1) => Raw::from_tree(top).map(Descriptor::Raw),
+            _ => Bare::from_tree(top).map(Descriptor::Bare),
         })
     }
 }
@@ -601,28 +1508,115 @@ impl<Pk: MiniscriptKey> fmt::Debug for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => write!(f, "{:?}", wpkh),
             Descriptor::Sh(ref sub) => write!(f, "{:?}", sub),
             Descriptor::Wsh(ref sub) => write!(f, "{:?}", sub),
+            Descriptor::Addr(ref addr) => write!(f, "{:?}", addr),
+            Descriptor::Raw(ref raw) => write!(f, "{:?}", raw),
         }
     }
 }
 
+impl<Pk: MiniscriptKey> Descriptor<Pk> {
+    // Write the descriptor as an indented tree, without its checksum
+    fn fmt_tree(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Descriptor::Bare(ref bare) => bare.as_inner().fmt_tree(f, 0),
+            Descriptor::Pkh(ref pkh) => write!(f, "pkh({})", pkh.as_inner()),
+            Descriptor::Wpkh(ref wpkh) => write!(f, "wpkh({})", wpkh.as_inner()),
+            Descriptor::Sh(ref sh) => {
+                f.write_str("sh\n")?;
+                match *sh.as_inner() {
+                    ShInner::Wsh(ref wsh) => {
+                        f.write_str("  wsh\n")?;
+                        fmt_wsh_tree(wsh, f, 2)
+                    }
+                    ShInner::Wpkh(ref wpkh) => write!(f, "  wpkh({})", wpkh.as_inner()),
+                    ShInner::SortedMulti(ref smv) => write!(f, "  {}", smv),
+                    ShInner::Ms(ref ms) => ms.fmt_tree(f, 1),
+                }
+            }
+            Descriptor::Wsh(ref wsh) => {
+                f.write_str("wsh\n")?;
+                fmt_wsh_tree(wsh, f, 1)
+            }
+            Descriptor::Addr(ref addr) => write!(f, "{:?}", addr),
+            Descriptor::Raw(ref raw) => write!(f, "{:?}", raw),
+        }
+    }
+}
+
+fn fmt_wsh_tree<Pk: MiniscriptKey>(
+    wsh: &Wsh<Pk>,
+    f: &mut fmt::Formatter,
+    depth: usize,
+) -> fmt::Result {
+    match *wsh.as_inner() {
+        WshInner::SortedMulti(ref smv) => {
+            for _ in 0..depth {
+                f.write_str("  ")?;
+            }
+            write!(f, "{}", smv)
+        }
+        WshInner::Ms(ref ms) => ms.fmt_tree(f, depth),
+    }
+}
+
+/// The alternate format `{:#}` writes the descriptor as an indented tree, with
+/// one fragment per line, its miniscript fragments annotated with their type
+/// and script size. The checksum is omitted.
 impl<Pk: MiniscriptKey> fmt::Display for Descriptor<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            return self.fmt_tree(f);
+        }
         match *self {
             Descriptor::Bare(ref sub) => write!(f, "{}", sub),
             Descriptor::Pkh(ref pkh) => write!(f, "{}", pkh),
             Descriptor::Wpkh(ref wpkh) => write!(f, "{}", wpkh),
             Descriptor::Sh(ref sub) => write!(f, "{}", sub),
             Descriptor::Wsh(ref sub) => write!(f, "{}", sub),
+            Descriptor::Addr(ref addr) => write!(f, "{}", addr),
+            Descriptor::Raw(ref raw) => write!(f, "{}", raw),
         }
     }
 }
 
 serde_string_impl_pk!(Descriptor, "a script descriptor");
 
+/// Key-based descriptors over arbitrary keys: `pkh`, `wpkh`, `sh(wpkh)`, and
+/// `sortedmulti` in `wsh` or `sh`. With the `compiler` feature, also `wsh` and
+/// `sh(wsh)` descriptors of arbitrary Miniscripts. Combinations which are not
+/// valid descriptors, such as an empty `sortedmulti`, are rejected as
+/// `IncorrectFormat`.
+#[cfg(feature = "arbitrary")]
+impl<'a, Pk: MiniscriptKey + Arbitrary<'a>> Arbitrary<'a> for Descriptor<Pk> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let kinds = if cfg!(feature = "compiler") { 7 } else { 5 };
+        let desc = match u.int_in_range(0..=kinds - 1)? {
+            0 => Ok(Descriptor::new_pkh(u.arbitrary()?)),
+            1 => Descriptor::new_wpkh(u.arbitrary()?),
+            2 => Descriptor::new_sh_wpkh(u.arbitrary()?),
+            n @ 3..=4 => {
+                let pks: Vec<Pk> = u.arbitrary()?;
+                let k = u.int_in_range(1..=cmp::max(pks.len(), 1))?;
+                if n == 3 {
+                    Descriptor::new_wsh_sortedmulti(k, pks)
+                } else {
+                    Descriptor::new_sh_sortedmulti(k, pks)
+                }
+            }
+            #[cfg(feature = "compiler")]
+            5 => Descriptor::new_wsh(u.arbitrary()?),
+            #[cfg(feature = "compiler")]
+            6 => Descriptor::new_sh_wsh(u.arbitrary()?),
+            _ => unreachable!("out of range"),
+        };
+        desc.map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::checksum::desc_checksum;
-    use super::DescriptorTrait;
+    use super::{DescriptorTrait, DescriptorType, TimelockError, TimelockRequirements};
     use bitcoin::blockdata::opcodes::all::{OP_CLTV, OP_CSV};
     use bitcoin::blockdata::script::Instruction;
     use bitcoin::blockdata::{opcodes, script};
@@ -632,40 +1626,27 @@ mod tests {
     use bitcoin::{self, secp256k1, PublicKey};
     use descriptor::key::Wildcard;
     use descriptor::{
-        DescriptorPublicKey, DescriptorSecretKey, DescriptorSinglePub, DescriptorXKey,
+        ConversionError, DeriveSecretError, DescriptorPublicKey, DescriptorSecretKey,
+        DescriptorSinglePub, DescriptorXKey, KeyMap,
     };
+    use expression::{parse_with_span, Span};
     use hex_script;
-    use miniscript::satisfy::BitcoinSig;
-    use std::cmp;
+    use miniscript::limits::SEQUENCE_LOCKTIME_TYPE_FLAG;
+    use miniscript::satisfy::{self, BitcoinSig};
     use std::collections::HashMap;
     use std::str::FromStr;
-    use {Descriptor, DummyKey, Error, Miniscript, Satisfier, TranslatePk2};
+    use {
+        Descriptor, DummyKey, DummyKeyHash, Error, Miniscript, MiniscriptKey, PkPkh, Satisfier,
+        TranslateError, TranslatePk, TranslatePk2,
+    };
 
-    #[cfg(feature = "compiler")]
     use policy;
+    use policy::semantic::{Assets, ChainState};
 
     type StdDescriptor = Descriptor<PublicKey>;
     const TEST_PK: &'static str =
         "pk(020000000000000000000000000000000000000000000000000000000000000002)";
 
-    impl cmp::PartialEq for DescriptorSecretKey {
-        fn eq(&self, other: &Self) -> bool {
-            match (self, other) {
-                (
-                    &DescriptorSecretKey::SinglePriv(ref a),
-                    &DescriptorSecretKey::SinglePriv(ref b),
-                ) => a.origin == b.origin && a.key == b.key,
-                (&DescriptorSecretKey::XPrv(ref a), &DescriptorSecretKey::XPrv(ref b)) => {
-                    a.origin == b.origin
-                        && a.xkey == b.xkey
-                        && a.derivation_path == b.derivation_path
-                        && a.wildcard == b.wildcard
-                }
-                _ => false,
-            }
-        }
-    }
-
     fn roundtrip_descriptor(s: &str) {
         let desc = Descriptor::<DummyKey>::from_str(&s).unwrap();
         let output = desc.to_string();
@@ -1086,6 +2067,86 @@ mod tests {
         assert_eq!(check, &Ok(Instruction::Op(OP_CSV)))
     }
 
+    #[test]
+    fn derive_hardened_wildcard() {
+        let secp = secp256k1::Secp256k1::new();
+        let tprv = "tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc";
+        let (desc, key_map) =
+            Descriptor::parse_descriptor(&secp, &format!("wpkh({}/0'/*')", tprv)).unwrap();
+
+        let sk = DescriptorSecretKey::from_str(&format!("{}/0'/5'", tprv)).unwrap();
+        let pk = sk.as_public(&secp).unwrap();
+        let expected = Descriptor::new_wpkh(DescriptorPublicKey::SinglePub(DescriptorSinglePub {
+            origin: Some((pk.master_fingerprint(), pk.full_derivation_path())),
+            key: pk.derive_public_key(&secp).unwrap(),
+        }))
+        .unwrap();
+        assert_eq!(
+            desc.derive_with_secrets(5, &key_map, &secp).unwrap(),
+            expected
+        );
+
+        assert_eq!(
+            desc.derive_with_secrets(5, &KeyMap::new(), &secp)
+                .unwrap_err(),
+            DeriveSecretError::Conversion(ConversionError::HardenedChild)
+        );
+    }
+
+    #[test]
+    fn network_check() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let (desc, key_map) = Descriptor::parse_descriptor(&secp, "wsh(multi(1,tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/*,03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))").unwrap();
+        desc.check_network(bitcoin::Network::Testnet).unwrap();
+        desc.check_network(bitcoin::Network::Regtest).unwrap();
+        desc.check_network(bitcoin::Network::Bitcoin).unwrap_err();
+        key_map.check_network(bitcoin::Network::Testnet).unwrap();
+        key_map
+            .check_network(bitcoin::Network::Bitcoin)
+            .unwrap_err();
+
+        let mainnet = desc.to_network(bitcoin::Network::Bitcoin);
+        mainnet.check_network(bitcoin::Network::Bitcoin).unwrap();
+        assert!(mainnet.to_string().contains("xpub"));
+        assert_eq!(mainnet.to_network(bitcoin::Network::Testnet), desc);
+    }
+
+    #[test]
+    fn tree_display() {
+        let key = "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556";
+        let desc =
+            StdDescriptor::from_str(&format!("wsh(and_v(v:pk({}),older(1000)))", key)).unwrap();
+        assert_eq!(
+            format!("{:#}", desc),
+            format!(
+                "wsh\n  and_v [B/onfsm] (39 bytes)\n    v:pk({}) [V/onfsm] (35 bytes)\n    older(1000) [B/zfm] (4 bytes)",
+                key
+            )
+        );
+
+        let desc = StdDescriptor::from_str(&format!("sh(wpkh({}))", key)).unwrap();
+        assert_eq!(format!("{:#}", desc), format!("sh\n  wpkh({})", key));
+    }
+
+    #[test]
+    fn parse_error_spans() {
+        let key = "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556";
+        let parse = |s: &str| parse_with_span(s, StdDescriptor::from_str).unwrap_err();
+        let (_, span) = parse(&format!("wsh(or_d(pk({}),pk(02zz)))", key));
+        assert_eq!(span, Some(Span { start: 83, end: 87 }));
+
+        // and_v requires its first argument to be a V expression
+        let (err, span) = parse(&format!("wsh(and_v(pk({}),older(1000)))", key));
+        assert_eq!(span, Some(Span { start: 4, end: 93 }));
+        match err {
+            Error::TypeCheck(_) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let (_, span) = parse(&format!("sh(wsh(pk({}))", key));
+        assert_eq!(span, Some(Span { start: 78, end: 78 }));
+    }
+
     #[test]
     fn roundtrip_tests() {
         let descriptor = Descriptor::<bitcoin::PublicKey>::from_str("multi");
@@ -1421,6 +2482,283 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         assert_eq!(res_descriptor, derived_descriptor);
     }
 
+    #[test]
+    fn satisfaction_weights() {
+        let desc = Descriptor::<PublicKey>::from_str("wsh(or_d(pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),and_v(v:pk(02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e),older(144))))").unwrap();
+        let weights = desc.satisfaction_weights().unwrap();
+        assert_eq!(weights.len(), 2);
+        assert_eq!(
+            weights[1].conditions[1],
+            policy::semantic::Policy::Older(144)
+        );
+        // The backup path additionally dissatisfies the first key
+        assert_eq!(weights[1].weight, weights[0].weight + 1);
+        assert_eq!(
+            weights.iter().map(|w| w.weight).max(),
+            Some(desc.max_satisfaction_weight().unwrap())
+        );
+
+        let desc = Descriptor::<PublicKey>::from_str("sh(wsh(or_i(pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),pk(02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e))))").unwrap();
+        let weights = desc.satisfaction_weights().unwrap();
+        assert_eq!(weights.len(), 2);
+        assert_eq!(weights[0].weight, weights[1].weight + 1);
+        assert_eq!(weights[0].weight, desc.max_satisfaction_weight().unwrap());
+
+        let desc = Descriptor::<PublicKey>::from_str(
+            "pkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)",
+        )
+        .unwrap();
+        let weights = desc.satisfaction_weights().unwrap();
+        assert_eq!(weights.len(), 1);
+        assert_eq!(weights[0].weight, desc.max_satisfaction_weight().unwrap());
+    }
+
+    #[test]
+    fn cheapest_spend_paths() {
+        let a = PublicKey::from_str(
+            "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556",
+        )
+        .unwrap();
+        let b = PublicKey::from_str(
+            "02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e",
+        )
+        .unwrap();
+        let wsh = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(or_d(pk({}),and_v(v:pk({}),older(144))))",
+            a, b
+        ))
+        .unwrap();
+        let wpkh = Descriptor::new_wpkh(a).unwrap();
+        let addr =
+            Descriptor::<PublicKey>::from_str("addr(bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq)")
+                .unwrap();
+
+        let mut only_a = Assets::default();
+        only_a.keys.insert(a.to_pubkeyhash());
+        let mut only_b = Assets::default();
+        only_b.keys.insert(b.to_pubkeyhash());
+        let chain = ChainState {
+            height: 1000,
+            ..Default::default()
+        };
+
+        let weights = wsh.satisfaction_weights().unwrap();
+        assert_eq!(
+            wsh.cheapest_spend_path(&only_b, chain).unwrap(),
+            Some(weights[1].clone())
+        );
+        // The backup path is not available before the timelock expires
+        let early = ChainState {
+            height: 100,
+            ..Default::default()
+        };
+        assert_eq!(wsh.cheapest_spend_path(&only_b, early).unwrap(), None);
+
+        // A path can be forced even though another one is cheaper
+        let mut both = only_a.clone();
+        both.keys.insert(b.to_pubkeyhash());
+        assert_eq!(
+            wsh.cheapest_spend_path(&both, chain).unwrap(),
+            Some(weights[0].clone())
+        );
+        assert_eq!(wsh.spend_path(1, &both, chain).unwrap(), weights[1]);
+        assert_eq!(
+            wsh.spend_path(1, &only_a, chain).unwrap_err().to_string(),
+            Error::CouldNotSatisfy.to_string()
+        );
+        assert!(wsh.spend_path(2, &only_b, chain).is_err());
+
+        let none = Assets::default();
+        let candidates = vec![
+            (&wsh, &only_b),
+            (&wpkh, &only_a),
+            (&addr, &only_a),
+            (&wsh, &none),
+            (&wsh, &only_a),
+        ];
+        let paths = super::cheapest_spend_paths(candidates, chain).unwrap();
+        let order: Vec<_> = paths.iter().map(|&(i, _)| i).collect();
+        assert_eq!(order, vec![1, 4, 0]);
+        assert_eq!(paths[1].1, weights[0]);
+
+        // Descriptors whose spend paths cannot be weighed are reported
+        let unsatisfiable = Descriptor::new_wsh(Miniscript::from_str_insane("0").unwrap()).unwrap();
+        let candidates = vec![(&wsh, &only_b), (&unsatisfiable, &only_a)];
+        match super::cheapest_spend_paths(candidates, chain) {
+            Err((1, _)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn watch_only() {
+        let desc =
+            Descriptor::<PublicKey>::from_str("addr(bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq)")
+                .unwrap();
+        assert_eq!(desc.desc_type(), DescriptorType::Addr);
+        assert_eq!(
+            desc.to_string(),
+            format!(
+                "addr(bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq)#{}",
+                desc_checksum("addr(bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq)").unwrap()
+            )
+        );
+        assert_eq!(
+            Descriptor::<PublicKey>::from_str(&desc.to_string()).unwrap(),
+            desc
+        );
+        assert_eq!(
+            desc.script_pubkey(),
+            hex_script("0014e8df018c7e326cc253faac7e46cdc51e68542c42")
+        );
+        assert_eq!(
+            desc.address(bitcoin::Network::Bitcoin).unwrap().to_string(),
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+        );
+        match desc.address(bitcoin::Network::Testnet) {
+            Err(Error::AddrNetworkMismatch(
+                bitcoin::Network::Bitcoin,
+                bitcoin::Network::Testnet,
+            )) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+        let desc =
+            Descriptor::<PublicKey>::from_str("addr(mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn)").unwrap();
+        assert_eq!(
+            desc.address(bitcoin::Network::Regtest).unwrap().to_string(),
+            "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn"
+        );
+        assert!(desc.address(bitcoin::Network::Bitcoin).is_err());
+
+        let desc =
+            Descriptor::<PublicKey>::from_str("raw(0014e8df018c7e326cc253faac7e46cdc51e68542c42)")
+                .unwrap();
+        assert_eq!(desc.desc_type(), DescriptorType::Raw);
+        assert_eq!(
+            desc.address(bitcoin::Network::Bitcoin).unwrap().to_string(),
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+        );
+        assert_eq!(
+            Descriptor::<PublicKey>::from_str(&desc.to_string()).unwrap(),
+            desc
+        );
+
+        match desc.max_satisfaction_weight() {
+            Err(Error::WatchOnlyDescriptor) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+        match desc.get_satisfaction(HashMap::<PublicKey, BitcoinSig>::new()) {
+            Err(Error::WatchOnlyDescriptor) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+        match policy::Liftable::lift(&desc) {
+            Err(Error::WatchOnlyDescriptor) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let desc = Descriptor::<PublicKey>::from_str("raw(6a)").unwrap();
+        match desc.address(bitcoin::Network::Bitcoin) {
+            Err(Error::RawDescriptorAddr) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+
+        assert!(Descriptor::<PublicKey>::from_str("addr(bc1qnotanaddress)").is_err());
+        assert!(Descriptor::<PublicKey>::from_str("raw(0g)").is_err());
+    }
+
+    #[test]
+    fn find_derivation_indices() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("wpkh(tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/0/*)").unwrap();
+        let spk_at = |index| {
+            desc.derive(index)
+                .translate_pk2(|pk| pk.derive_public_key(&secp))
+                .unwrap()
+                .script_pubkey()
+        };
+        let spks = vec![spk_at(7), spk_at(0), spk_at(30), bitcoin::Script::new()];
+
+        let found = desc.find_derivation_indices(&spks, 0..20, &secp).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[&spks[0]], 7);
+        assert_eq!(found[&spks[1]], 0);
+
+        let found = desc.find_derivation_indices(&spks, 25..31, &secp).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[&spks[2]], 30);
+    }
+
+    #[test]
+    fn replace_key() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("wsh(sortedmulti(1,03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556,02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e))").unwrap();
+        let old = DescriptorPublicKey::from_str(
+            "02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e",
+        )
+        .unwrap();
+        let new = DescriptorPublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+
+        let rotated = desc.replace_key(&old, new.clone()).unwrap();
+        let expected = Descriptor::<DescriptorPublicKey>::from_str("wsh(sortedmulti(1,03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798))").unwrap();
+        assert_eq!(rotated, expected);
+        assert_eq!(rotated.to_string(), expected.to_string());
+
+        // The old key is gone
+        assert!(rotated.replace_key(&old, new.clone()).is_err());
+        // Rotating to a key already in the descriptor
+        let dup = DescriptorPublicKey::from_str(
+            "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556",
+        )
+        .unwrap();
+        assert!(desc.replace_key(&old, dup).is_err());
+    }
+
+    #[test]
+    fn derive_batch() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str("wsh(multi(2,[78412e3a/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*,xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/2/3,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))").unwrap();
+
+        let batch = descriptor.derive_batch(5..10, &secp).unwrap();
+        assert_eq!(batch.len(), 5);
+        for (descriptor_at, index) in batch.iter().zip(5..10) {
+            let expected = descriptor
+                .derive(index)
+                .translate_pk2(|pk| pk.derive_public_key(&secp))
+                .unwrap();
+            assert_eq!(*descriptor_at, expected);
+        }
+
+        let hardened = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*')").unwrap();
+        assert_eq!(
+            hardened.derive_batch(0..1, &secp),
+            Err(ConversionError::HardenedWildcard)
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn derive_spks() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str("sh(wsh(multi(1,xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)))").unwrap();
+
+        let spks = descriptor.derive_spks(0..100, &secp).unwrap();
+        let expected: Vec<_> = descriptor
+            .derive_batch(0..100, &secp)
+            .unwrap()
+            .iter()
+            .map(|desc| desc.script_pubkey())
+            .collect();
+        assert_eq!(spks, expected);
+
+        let hardened = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*')").unwrap();
+        assert_eq!(
+            hardened.derive_spks(0..1, &secp),
+            Err(ConversionError::HardenedWildcard)
+        );
+    }
+
     #[test]
     fn parse_with_secrets() {
         let secp = &secp256k1::Secp256k1::signing_only();
@@ -1436,6 +2774,222 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         assert_eq!(descriptor_str, descriptor.to_string_with_secret(&keymap));
     }
 
+    #[test]
+    fn to_string_with_secret() {
+        let secp = &secp256k1::Secp256k1::signing_only();
+        let descriptor_str = "wsh(multi(2,[aabbccdd/48'/0'/0'/2']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/*,cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798))";
+        let (descriptor, mut keymap) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor_str).unwrap();
+        assert_eq!(keymap.len(), 2);
+
+        let with_secrets = descriptor.to_string_with_secret(&keymap);
+        assert_eq!(
+            &with_secrets[..with_secrets.find('#').unwrap()],
+            descriptor_str
+        );
+        // The output parses back to the same descriptor and keys
+        let (descriptor_two, keymap_two) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &with_secrets).unwrap();
+        assert_eq!(descriptor_two, descriptor);
+        assert_eq!(keymap_two.len(), 2);
+
+        // Without secrets, this is the public descriptor
+        keymap.clear();
+        assert_eq!(
+            descriptor.to_string_with_secret(&keymap),
+            descriptor.to_string()
+        );
+    }
+
+    #[test]
+    fn satisfaction_timelocks() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let mut sigs = HashMap::new();
+        sigs.insert(pk, (secp.sign(&msg, &sk), bitcoin::SigHashType::All));
+
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(or_d(pk({}),and_v(v:pk({}),and_v(v:older(144),after(500000)))))",
+            "020000000000000000000000000000000000000000000000000000000000000002", pk
+        ))
+        .unwrap();
+        let satisfier = (&sigs, satisfy::Older(144), satisfy::After(600000));
+        let (witness, _, requirements) = desc.get_satisfaction_with_timelocks(satisfier).unwrap();
+        assert_eq!(witness.len(), 3);
+        assert_eq!(
+            requirements,
+            TimelockRequirements {
+                lock_time: Some(500000),
+                sequence: Some(144),
+            }
+        );
+
+        let mut tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        requirements.apply(&mut tx, 0).unwrap();
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, 500000);
+        assert_eq!(tx.input[0].sequence, 144);
+
+        assert_eq!(
+            requirements.apply(&mut tx, 1),
+            Err(TimelockError::InputOutOfRange(1))
+        );
+        // Timelocks of another unit are left unchanged
+        let mut timed = tx.clone();
+        timed.lock_time = 1_600_000_000;
+        assert_eq!(
+            requirements.apply(&mut timed, 0),
+            Err(TimelockError::LockTimeUnitMismatch {
+                current: 1_600_000_000,
+                required: 500000,
+            })
+        );
+        let mut timed = tx.clone();
+        timed.input[0].sequence = SEQUENCE_LOCKTIME_TYPE_FLAG | 2;
+        assert!(requirements.apply(&mut timed, 0).is_err());
+        assert_eq!(timed.lock_time, 500000);
+        assert_eq!(timed.input[0].sequence, SEQUENCE_LOCKTIME_TYPE_FLAG | 2);
+
+        // Without the timelocks, there is nothing to satisfy them with
+        let desc = Descriptor::<PublicKey>::from_str(&format!("wpkh({})", pk)).unwrap();
+        let (_, _, requirements) = desc.get_satisfaction_with_timelocks(&sigs).unwrap();
+        assert_eq!(requirements, TimelockRequirements::default());
+
+        // Insane descriptors may require timelocks of both units at once
+        struct AnyTimelock;
+        impl Satisfier<PublicKey> for AnyTimelock {
+            fn check_older(&self, _: u32) -> bool {
+                true
+            }
+
+            fn check_after(&self, _: u32) -> bool {
+                true
+            }
+        }
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),and_v(v:after(500000),after(1600000000))))",
+            pk
+        ))
+        .unwrap();
+        match desc.get_satisfaction_with_timelocks((&sigs, AnyTimelock)) {
+            Err(Error::MixedLockTimeUnits(500000, 1_600_000_000)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),and_v(v:older(144),older({}))))",
+            pk,
+            SEQUENCE_LOCKTIME_TYPE_FLAG | 1
+        ))
+        .unwrap();
+        match desc.get_satisfaction_with_timelocks((&sigs, AnyTimelock)) {
+            Err(Error::MixedSequenceUnits(144, n)) if n == SEQUENCE_LOCKTIME_TYPE_FLAG | 1 => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn from_spend() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let mut sigs = HashMap::new();
+        sigs.insert(pk, (secp.sign(&msg, &sk), bitcoin::SigHashType::All));
+        let other = "020000000000000000000000000000000000000000000000000000000000000002";
+
+        for s in &[
+            format!("pkh({})", pk),
+            format!("wpkh({})", pk),
+            format!("sh(wpkh({}))", pk),
+            format!("sh(multi(1,{},{}))", other, pk),
+            format!("wsh(or_d(pk({}),and_v(v:pk({}),older(144))))", other, pk),
+            format!("sh(wsh(and_v(v:pk({}),after(500000))))", pk),
+        ] {
+            let desc = Descriptor::<PublicKey>::from_str(s).unwrap();
+            let satisfier = (&sigs, satisfy::Older(144), satisfy::After(600000));
+            let (witness, script_sig) = desc.get_satisfaction(satisfier).unwrap();
+            let spk = desc.script_pubkey();
+            assert_eq!(
+                Descriptor::<PublicKey>::from_spend(&spk, &script_sig, &witness).unwrap(),
+                desc
+            );
+        }
+
+        // The witness of a P2WPKH spend does not reveal a P2WSH script
+        let desc = Descriptor::<PublicKey>::from_str(&format!("wpkh({})", pk)).unwrap();
+        let (witness, script_sig) = desc.get_satisfaction(&sigs).unwrap();
+        let spk = Descriptor::<PublicKey>::from_str(&format!("wsh(pk({}))", pk))
+            .unwrap()
+            .script_pubkey();
+        assert!(Descriptor::<PublicKey>::from_spend(&spk, &script_sig, &witness).is_err());
+    }
+
+    #[test]
+    fn detect_sortedmulti() {
+        let a = "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556";
+        let b = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let desc = |s: &str| {
+            Descriptor::<PublicKey>::from_str(&s.replace("{a}", a).replace("{b}", b)).unwrap()
+        };
+
+        for &(multi, expected) in &[
+            ("wsh(multi(2,{b},{a}))", "wsh(sortedmulti(2,{b},{a}))"),
+            ("sh(multi(1,{b},{a}))", "sh(sortedmulti(1,{b},{a}))"),
+            (
+                "sh(wsh(multi(1,{b},{a})))",
+                "sh(wsh(sortedmulti(1,{b},{a})))",
+            ),
+            // Unsorted keys and other descriptors are left as they are
+            ("wsh(multi(2,{a},{b}))", "wsh(multi(2,{a},{b}))"),
+            (
+                "wsh(and_v(v:pk({b}),pk({a})))",
+                "wsh(and_v(v:pk({b}),pk({a})))",
+            ),
+            ("multi(1,{b},{a})", "multi(1,{b},{a})"),
+            ("wpkh({a})", "wpkh({a})"),
+        ] {
+            let detected = desc(multi).detect_sortedmulti();
+            assert_eq!(detected, desc(expected));
+            assert_eq!(detected.script_pubkey(), desc(multi).script_pubkey());
+        }
+    }
+
+    #[test]
+    fn is_equivalent() {
+        let a = "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556";
+        let b = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let desc = |s: String| Descriptor::<PublicKey>::from_str(&s).unwrap();
+
+        let sorted = desc(format!("wsh(sortedmulti(2,{},{}))", a, b));
+        let nested = desc(format!("sh(wsh(multi(2,{},{})))", b, a));
+        let and_v = desc(format!("wsh(and_v(v:pk({}),pk({})))", a, b));
+        assert!(sorted.is_equivalent(&nested).unwrap());
+        assert!(sorted.is_equivalent(&and_v).unwrap());
+
+        let or_b = desc(format!("wsh(or_b(pk({}),s:pk({})))", a, b));
+        let or_d = desc(format!("wsh(or_d(pk({}),pk({})))", b, a));
+        assert!(or_b.is_equivalent(&or_d).unwrap());
+        assert!(!or_b.is_equivalent(&sorted).unwrap());
+    }
+
     #[test]
     fn checksum_for_nested_sh() {
         let descriptor_str = "sh(wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL))";
@@ -1446,4 +3000,109 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         let descriptor: Descriptor<DescriptorPublicKey> = descriptor_str.parse().unwrap();
         assert_eq!(descriptor.to_string(), "sh(wsh(pk(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL)))#6c6hwr22");
     }
+
+    #[test]
+    fn translate_pk_ctx() {
+        let desc =
+            Descriptor::<String>::from_str("wsh(or_d(pk(a),and_v(v:pkh(b),pk(c))))").unwrap();
+        let translate = |name: &String| match name.as_str() {
+            "a" | "b" => Ok(DummyKey),
+            _ => Err(format!("unknown key {}", name)),
+        };
+        let translate_hash = |name: &String| translate(name).map(|_| DummyKeyHash);
+        let err = desc
+            .translate_pk_ctx(translate, translate_hash)
+            .unwrap_err();
+        // `pk(c)` is `c:pk_k(c)`, in the second branch of the `and_v`
+        assert_eq!(
+            err,
+            TranslateError {
+                key: PkPkh::PlainPubkey("c".to_owned()),
+                path: Some(vec![1, 1, 0]),
+                error: "unknown key c".to_owned(),
+            }
+        );
+        assert_eq!(err.to_string(), "key c at path [1, 1, 0]: unknown key c");
+
+        let translate_hash = |_: &String| Err("no hashes".to_owned());
+        let err = desc
+            .translate_pk_ctx(translate, translate_hash)
+            .unwrap_err();
+        assert_eq!(err.key, PkPkh::HashedPubkey("b".to_owned()));
+        assert_eq!(err.path, Some(vec![1, 0, 0, 0]));
+
+        let desc = Descriptor::<String>::from_str("wsh(sortedmulti(1,a,c))").unwrap();
+        let err = desc
+            .translate_pk_ctx(translate, translate_hash)
+            .unwrap_err();
+        assert_eq!(err.key, PkPkh::PlainPubkey("c".to_owned()));
+        assert_eq!(err.path, None);
+        assert_eq!(err.to_string(), "key c: unknown key c");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_translate_pk() {
+        use TranslatePkPar;
+
+        let a = PublicKey::from_str(
+            "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556",
+        )
+        .unwrap();
+        let b = PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let translate = |name: &String| match name.as_str() {
+            "a" => Ok(a),
+            "b" => Ok(b),
+            _ => Err(name.clone()),
+        };
+        let translate_hash = |name: &String| translate(name).map(|pk| pk.to_pubkeyhash());
+
+        let desc =
+            Descriptor::<String>::from_str("wsh(or_d(pk(a),and_v(v:pkh(b),pk(a))))").unwrap();
+        assert_eq!(
+            desc.par_translate_pk(&translate, &translate_hash),
+            desc.translate_pk2(&translate),
+        );
+
+        // The error is that of the first key which fails to translate
+        let desc = Descriptor::<String>::from_str("wsh(or_d(pk(c),and_v(v:pk(a),pk(d))))").unwrap();
+        assert_eq!(
+            desc.par_translate_pk(&translate, &translate_hash),
+            Err("c".to_owned())
+        );
+        assert_eq!(desc.translate_pk2(&translate), Err("c".to_owned()));
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let mut state = 0x1234_5678u32;
+        let bytes: Vec<u8> = (0..1 << 16)
+            .map(|_| {
+                // xorshift32
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let mut u = Unstructured::new(&bytes);
+        let mut generated = 0;
+        for _ in 0..20 {
+            if let Ok(desc) = Descriptor::<DescriptorPublicKey>::arbitrary(&mut u) {
+                assert!(desc.sanity_check().is_ok());
+                assert_eq!(
+                    Descriptor::<DescriptorPublicKey>::from_str(&desc.to_string()).unwrap(),
+                    desc
+                );
+                generated += 1;
+            }
+        }
+        assert!(generated > 0);
+    }
 }
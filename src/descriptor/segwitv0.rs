@@ -31,7 +31,7 @@ use {
 
 use super::{
     checksum::{desc_checksum, verify_checksum},
-    DescriptorTrait, SortedMultiVec,
+    single_spend_path, DescriptorTrait, SortedMultiVec, SpendPathWeight,
 };
 /// A Segwitv0 wsh descriptor
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -76,6 +76,32 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
             WshInner::Ms(ref ms) => format!("wsh({})", ms),
         }
     }
+
+    /// Computes an upper bound on the weight of a satisfying witness along
+    /// each spend path of the descriptor, see `Descriptor::satisfaction_weights`
+    pub fn satisfaction_weights(&self) -> Result<Vec<SpendPathWeight<Pk>>, Error> {
+        match self.inner {
+            WshInner::SortedMulti(..) => single_spend_path(self),
+            WshInner::Ms(ref ms) => {
+                let script_size = ms.script_size();
+                // The number of witness elements of the worst path is used for
+                // every path, it only matters beyond 252 elements.
+                let max_sat_elems = ms.max_satisfaction_witness_elements()?;
+                Ok(ms
+                    .satisfaction_paths()
+                    .into_iter()
+                    .map(|path| SpendPathWeight {
+                        conditions: path.conditions,
+                        weight: 4 // scriptSig length byte
+                            + varint_len(script_size)
+                            + script_size
+                            + varint_len(max_sat_elems)
+                            + path.max_witness_size,
+                    })
+                    .collect())
+            }
+        }
+    }
 }
 
 /// Wsh Inner
@@ -108,11 +134,11 @@ where
             let top = &top.args[0];
             if top.name == "sortedmulti" {
                 return Ok(Wsh {
-                    inner: WshInner::SortedMulti(SortedMultiVec::from_tree(&top)?),
+                    inner: WshInner::SortedMulti(top.spanned(SortedMultiVec::from_tree(&top))?),
                 });
             }
             let sub = Miniscript::from_tree(&top)?;
-            Segwitv0::top_level_checks(&sub)?;
+            top.spanned(Segwitv0::top_level_checks(&sub))?;
             Ok(Wsh {
                 inner: WshInner::Ms(sub),
             })
@@ -254,6 +280,13 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Wsh<Pk> {
             WshInner::Ms(ref ms) => ms.for_each_key(pred),
         }
     }
+
+    fn key_path(&self, index: usize) -> Option<Vec<usize>> {
+        match self.inner {
+            WshInner::SortedMulti(..) => None,
+            WshInner::Ms(ref ms) => ms.key_path(index),
+        }
+    }
 }
 
 impl<P: MiniscriptKey, Q: MiniscriptKey> TranslatePk<P, Q> for Wsh<P> {
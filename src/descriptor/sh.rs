@@ -34,7 +34,7 @@ use {
 
 use super::{
     checksum::{desc_checksum, verify_checksum},
-    DescriptorTrait, SortedMultiVec, Wpkh, Wsh,
+    single_spend_path, DescriptorTrait, SortedMultiVec, SpendPathWeight, Wpkh, Wsh,
 };
 
 /// A Legacy p2sh Descriptor
@@ -103,12 +103,14 @@ where
         if top.name == "sh" && top.args.len() == 1 {
             let top = &top.args[0];
             let inner = match top.name {
-                "wsh" => ShInner::Wsh(Wsh::from_tree(&top)?),
-                "wpkh" => ShInner::Wpkh(Wpkh::from_tree(&top)?),
-                "sortedmulti" => ShInner::SortedMulti(SortedMultiVec::from_tree(&top)?),
+                "wsh" => ShInner::Wsh(top.spanned(Wsh::from_tree(&top))?),
+                "wpkh" => ShInner::Wpkh(top.spanned(Wpkh::from_tree(&top))?),
+                "sortedmulti" => {
+                    ShInner::SortedMulti(top.spanned(SortedMultiVec::from_tree(&top))?)
+                }
                 _ => {
                     let sub = Miniscript::from_tree(&top)?;
-                    Legacy::top_level_checks(&sub)?;
+                    top.spanned(Legacy::top_level_checks(&sub))?;
                     ShInner::Ms(sub)
                 }
             };
@@ -191,6 +193,38 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
             inner: ShInner::Wpkh(Wpkh::new(pk)?),
         })
     }
+
+    /// Computes an upper bound on the weight of a satisfying witness along
+    /// each spend path of the descriptor, see `Descriptor::satisfaction_weights`
+    pub fn satisfaction_weights(&self) -> Result<Vec<SpendPathWeight<Pk>>, Error> {
+        match self.inner {
+            // add weighted script sig, len byte stays the same
+            ShInner::Wsh(ref wsh) => Ok(wsh
+                .satisfaction_weights()?
+                .into_iter()
+                .map(|path| SpendPathWeight {
+                    conditions: path.conditions,
+                    weight: 4 * 35 + path.weight,
+                })
+                .collect()),
+            ShInner::Wpkh(..) | ShInner::SortedMulti(..) => single_spend_path(self),
+            ShInner::Ms(ref ms) => {
+                let ss = ms.script_size();
+                let ps = push_opcode_size(ss);
+                Ok(ms
+                    .satisfaction_paths()
+                    .into_iter()
+                    .map(|path| {
+                        let scriptsig_len = ps + ss + path.max_scriptsig_size;
+                        SpendPathWeight {
+                            conditions: path.conditions,
+                            weight: 4 * (varint_len(scriptsig_len) + scriptsig_len),
+                        }
+                    })
+                    .collect())
+            }
+        }
+    }
 }
 
 impl<Pk: MiniscriptKey> DescriptorTrait<Pk> for Sh<Pk> {
@@ -343,6 +377,14 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Sh<Pk> {
             ShInner::Ms(ref ms) => ms.for_each_key(pred),
         }
     }
+
+    fn key_path(&self, index: usize) -> Option<Vec<usize>> {
+        match self.inner {
+            ShInner::Wsh(ref wsh) => wsh.key_path(index),
+            ShInner::SortedMulti(..) | ShInner::Wpkh(..) => None,
+            ShInner::Ms(ref ms) => ms.key_path(index),
+        }
+    }
 }
 
 impl<P: MiniscriptKey, Q: MiniscriptKey> TranslatePk<P, Q> for Sh<P> {
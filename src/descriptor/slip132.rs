@@ -0,0 +1,263 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # SLIP-132 Extended Keys
+//!
+//! Support for the extended public key prefixes of SLIP-132 (`ypub`, `zpub`,
+//! `vpub`...), which some wallets use to encode the script type of an account
+//! in its extended key. Descriptors make the script type explicit, so such
+//! keys are converted to standard `xpub`s and `tpub`s when parsed, and their
+//! original version is recorded so they can be displayed as they were given.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::util::base58;
+
+use super::{Descriptor, DescriptorPublicKey};
+use {Error, TranslatePk};
+
+/// Version bytes of mainnet `xpub`s
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+/// Version bytes of testnet `tpub`s
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// The SLIP-132 versions of extended public keys
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slip132Version {
+    /// `ypub`, P2WPKH nested in P2SH on mainnet
+    Ypub,
+    /// `Ypub`, multisig P2WSH nested in P2SH on mainnet
+    YpubMultisig,
+    /// `zpub`, P2WPKH on mainnet
+    Zpub,
+    /// `Zpub`, multisig P2WSH on mainnet
+    ZpubMultisig,
+    /// `upub`, P2WPKH nested in P2SH on testnet
+    Upub,
+    /// `Upub`, multisig P2WSH nested in P2SH on testnet
+    UpubMultisig,
+    /// `vpub`, P2WPKH on testnet
+    Vpub,
+    /// `Vpub`, multisig P2WSH on testnet
+    VpubMultisig,
+}
+
+const VERSIONS: [(Slip132Version, [u8; 4]); 8] = [
+    (Slip132Version::Ypub, [0x04, 0x9d, 0x7c, 0xb2]),
+    (Slip132Version::YpubMultisig, [0x02, 0x95, 0xb4, 0x3f]),
+    (Slip132Version::Zpub, [0x04, 0xb2, 0x47, 0x46]),
+    (Slip132Version::ZpubMultisig, [0x02, 0xaa, 0x7e, 0xd3]),
+    (Slip132Version::Upub, [0x04, 0x4a, 0x52, 0x62]),
+    (Slip132Version::UpubMultisig, [0x02, 0x42, 0x89, 0xef]),
+    (Slip132Version::Vpub, [0x04, 0x5f, 0x1c, 0xf6]),
+    (Slip132Version::VpubMultisig, [0x02, 0x57, 0x54, 0x83]),
+];
+
+impl Slip132Version {
+    /// The version bytes of the extended keys
+    pub fn version_bytes(&self) -> [u8; 4] {
+        VERSIONS
+            .iter()
+            .find(|version| version.0 == *self)
+            .expect("all versions are listed")
+            .1
+    }
+
+    /// Whether the extended keys are for testnet
+    pub fn is_testnet(&self) -> bool {
+        match *self {
+            Slip132Version::Ypub
+            | Slip132Version::YpubMultisig
+            | Slip132Version::Zpub
+            | Slip132Version::ZpubMultisig => false,
+            Slip132Version::Upub
+            | Slip132Version::UpubMultisig
+            | Slip132Version::Vpub
+            | Slip132Version::VpubMultisig => true,
+        }
+    }
+
+    fn from_version_bytes(bytes: &[u8]) -> Option<Slip132Version> {
+        VERSIONS
+            .iter()
+            .find(|version| version.1[..] == *bytes)
+            .map(|version| version.0)
+    }
+}
+
+impl fmt::Display for Slip132Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Slip132Version::Ypub => "ypub",
+            Slip132Version::YpubMultisig => "Ypub",
+            Slip132Version::Zpub => "zpub",
+            Slip132Version::ZpubMultisig => "Zpub",
+            Slip132Version::Upub => "upub",
+            Slip132Version::UpubMultisig => "Upub",
+            Slip132Version::Vpub => "vpub",
+            Slip132Version::VpubMultisig => "Vpub",
+        })
+    }
+}
+
+/// The SLIP-132 versions of the keys of a descriptor
+pub type Slip132Map = BTreeMap<DescriptorPublicKey, Slip132Version>;
+
+/// Replace the version bytes of the base58 encoded extended key `xkey`,
+/// returning the new encoding and the previous version if it is a SLIP-132 one
+fn replace_version(xkey: &str, version: [u8; 4]) -> Option<(String, Option<Slip132Version>)> {
+    let mut data = base58::from_check(xkey).ok()?;
+    if data.len() != 78 {
+        return None;
+    }
+    let previous = Slip132Version::from_version_bytes(&data[..4]);
+    data[..4].copy_from_slice(&version);
+    Some((base58::check_encode_slice(&data), previous))
+}
+
+/// Split a key expression around its extended key
+fn split_xkey(s: &str) -> (&str, &str, &str) {
+    let start = match s.find(']') {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let end = match s[start..].find('/') {
+        Some(i) => start + i,
+        None => s.len(),
+    };
+    (&s[..start], &s[start..end], &s[end..])
+}
+
+/// Parse a key expression whose extended key may have a SLIP-132 version
+pub(super) fn parse_key(s: &str) -> Result<(DescriptorPublicKey, Option<Slip132Version>), Error> {
+    let (origin, xkey, path) = split_xkey(s);
+    // Only mainnet SLIP-132 versions are converted to mainnet keys
+    let converted = match replace_version(xkey, XPUB_VERSION) {
+        Some((xpub, Some(version))) if !version.is_testnet() => Some((xpub, version)),
+        Some((_, Some(version))) => {
+            let (tpub, _) = replace_version(xkey, TPUB_VERSION).expect("decoded above");
+            Some((tpub, version))
+        }
+        _ => None,
+    };
+
+    let (key, version) = match converted {
+        Some((xkey, version)) => (
+            DescriptorPublicKey::from_str(&format!("{}{}{}", origin, xkey, path)),
+            Some(version),
+        ),
+        None => (DescriptorPublicKey::from_str(s), None),
+    };
+    key.map(|key| (key, version))
+        .map_err(|e| Error::BadDescriptor(e.to_string()))
+}
+
+/// Display a key, with the SLIP-132 version recorded for it if any
+fn key_to_string(key: &DescriptorPublicKey, versions: &Slip132Map) -> String {
+    let s = key.to_string();
+    match (versions.get(key), key) {
+        (Some(version), &DescriptorPublicKey::XPub(..)) => {
+            let (origin, xkey, path) = split_xkey(&s);
+            let (xkey, _) =
+                replace_version(xkey, version.version_bytes()).expect("Extended keys are valid");
+            format!("{}{}{}", origin, xkey, path)
+        }
+        _ => s,
+    }
+}
+
+/// Parse a descriptor whose extended keys may have SLIP-132 versions
+pub(super) fn parse_descriptor(
+    s: &str,
+) -> Result<(Descriptor<DescriptorPublicKey>, Slip132Map), Error> {
+    let mut versions_pk = Slip132Map::new();
+    let mut versions_pkh = Slip132Map::new();
+    let descriptor = Descriptor::<String>::from_str(s)?;
+    let descriptor = descriptor.translate_pk(
+        |pk| {
+            let (key, version) = parse_key(pk)?;
+            if let Some(version) = version {
+                versions_pk.insert(key.clone(), version);
+            }
+            Ok(key)
+        },
+        |pkh| {
+            let (key, version) = parse_key(pkh)?;
+            if let Some(version) = version {
+                versions_pkh.insert(key.clone(), version);
+            }
+            Ok(key)
+        },
+    )?;
+    versions_pk.extend(versions_pkh.into_iter());
+    Ok((descriptor, versions_pk))
+}
+
+/// Display a descriptor, with the SLIP-132 versions of its extended keys
+pub(super) fn to_string(desc: &Descriptor<DescriptorPublicKey>, versions: &Slip132Map) -> String {
+    desc.translate_pk::<_, _, ()>(
+        |pk| Ok(key_to_string(pk, versions)),
+        |pkh| Ok(key_to_string(pkh, versions)),
+    )
+    .expect("Translation to string cannot fail")
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TPUB: &str = "tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF";
+    const VPUB: &str = "vpub5a5DHQcL3XDUgZF5kcLTEZTTUdKD7mhUSLvzazQaKFR8kApmQ2rRxtwLkDPdJ4aFXG8yoohe944DCkckNwt4JxQh3XxKTnkC7acRfdnmEUP";
+    const VPUB_MULTISIG: &str = "Vpub5kyJQeLmcUmr78QThGoS4doGCRMUL8P4kcafWFg7h2FYNMPgASEi41oGYvM6rVo9kjCxgPhm2GSiKvEWgB31TSXMt14isCDC1JtJFb42U9Y";
+
+    #[test]
+    fn slip132_key() {
+        let (key, version) = parse_key(&format!("[deadbeef/84'/1'/0']{}/0/*", VPUB)).unwrap();
+        assert_eq!(version, Some(Slip132Version::Vpub));
+        assert_eq!(
+            key,
+            DescriptorPublicKey::from_str(&format!("[deadbeef/84'/1'/0']{}/0/*", TPUB)).unwrap()
+        );
+
+        let (key, version) = parse_key(VPUB_MULTISIG).unwrap();
+        assert_eq!(version, Some(Slip132Version::VpubMultisig));
+        assert_eq!(key, DescriptorPublicKey::from_str(TPUB).unwrap());
+
+        let (_, version) = parse_key(TPUB).unwrap();
+        assert_eq!(version, None);
+    }
+
+    #[test]
+    fn slip132_descriptor() {
+        let s = format!("wsh(sortedmulti(1,{}/0/*,{}/1/*))", VPUB_MULTISIG, TPUB);
+        let (desc, versions) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor_slip132(&s).unwrap();
+        assert_eq!(
+            desc,
+            Descriptor::<DescriptorPublicKey>::from_str(&format!(
+                "wsh(sortedmulti(1,{}/0/*,{}/1/*))",
+                TPUB, TPUB
+            ))
+            .unwrap()
+        );
+        assert_eq!(versions.len(), 1);
+        assert_eq!(
+            desc.to_string_with_slip132(&versions),
+            Descriptor::<String>::from_str(&s).unwrap().to_string()
+        );
+    }
+}
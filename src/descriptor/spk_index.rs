@@ -0,0 +1,239 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Script Pubkey Index
+//!
+//! Lookup of the descriptor, keychain and derivation index a script pubkey
+//! was derived at, among the descriptors of a wallet, as needed to recognize
+//! its outputs when scanning transactions. Script pubkeys are derived up to
+//! a revealed index, which grows as the wallet hands out addresses.
+//!
+
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::{secp256k1, Script};
+
+use super::{CachedDerivation, ConversionError, Descriptor, DescriptorPublicKey, KeychainKind};
+use {DescriptorTrait, TranslatePk2};
+
+/// Where a script pubkey of a [DescriptorSpkIndex] was derived from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpkOrigin {
+    /// Identifier of the descriptor, as returned by
+    /// [DescriptorSpkIndex::add_descriptor]
+    pub descriptor_id: usize,
+    /// The keychain of the descriptor
+    pub keychain: KeychainKind,
+    /// The derivation index of the script pubkey
+    pub index: u32,
+}
+
+/// A descriptor of the index, with its derived script pubkeys
+#[derive(Clone, Debug)]
+struct IndexedDescriptor {
+    descriptor: Descriptor<DescriptorPublicKey>,
+    keychain: KeychainKind,
+    /// The keys of the descriptor, derived up to their wildcard
+    cache: BTreeMap<DescriptorPublicKey, CachedDerivation>,
+    /// The script pubkeys at the revealed indices, in order
+    spks: Vec<Script>,
+}
+
+/// Index of the script pubkeys of a set of descriptors, mapping each
+/// script pubkey to the descriptor, keychain and index it was derived at
+///
+/// The script pubkeys of each descriptor are derived up to the index given
+/// to `reveal_to`. A descriptor without wildcard has a single script pubkey,
+/// at index 0. If several descriptors derive the same script pubkey, it is
+/// attributed to the one revealing it first.
+#[derive(Clone, Debug, Default)]
+pub struct DescriptorSpkIndex {
+    descriptors: Vec<IndexedDescriptor>,
+    origins: HashMap<Script, SpkOrigin>,
+}
+
+impl DescriptorSpkIndex {
+    /// An index without descriptors
+    pub fn new() -> DescriptorSpkIndex {
+        DescriptorSpkIndex::default()
+    }
+
+    /// Adds a descriptor of `keychain` to the index, with no script pubkey
+    /// revealed, and returns its identifier
+    ///
+    /// Returns an error if the keys of the descriptor cannot be derived, such
+    /// as keys with a hardened wildcard.
+    pub fn add_descriptor<C: secp256k1::Verification>(
+        &mut self,
+        descriptor: Descriptor<DescriptorPublicKey>,
+        keychain: KeychainKind,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<usize, ConversionError> {
+        let cache = descriptor.derivation_cache(secp)?;
+        self.descriptors.push(IndexedDescriptor {
+            descriptor: descriptor,
+            keychain: keychain,
+            cache: cache,
+            spks: vec![],
+        });
+        Ok(self.descriptors.len() - 1)
+    }
+
+    /// The descriptor with identifier `id`, and its keychain
+    pub fn descriptor(
+        &self,
+        id: usize,
+    ) -> Option<(&Descriptor<DescriptorPublicKey>, KeychainKind)> {
+        self.descriptors
+            .get(id)
+            .map(|indexed| (&indexed.descriptor, indexed.keychain))
+    }
+
+    /// The number of revealed script pubkeys of the descriptor with
+    /// identifier `id`, None if there is no such descriptor
+    pub fn revealed(&self, id: usize) -> Option<u32> {
+        self.descriptors
+            .get(id)
+            .map(|indexed| indexed.spks.len() as u32)
+    }
+
+    /// Derives the script pubkeys of the descriptor with identifier `id` up
+    /// to `index` included, and returns the ones newly revealed
+    ///
+    /// Indices already revealed are not derived again; for a descriptor
+    /// without wildcard only index 0 is. Returns None if there is no such
+    /// descriptor.
+    ///
+    /// Panics if given an index ≥ 2^31
+    pub fn reveal_to<C: secp256k1::Verification>(
+        &mut self,
+        id: usize,
+        index: u32,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Option<&[Script]> {
+        let indexed = self.descriptors.get_mut(id)?;
+        let end = if indexed.descriptor.is_deriveable() {
+            index + 1
+        } else {
+            1
+        };
+        let start = indexed.spks.len() as u32;
+        for i in start..end {
+            let cache = &indexed.cache;
+            let spk = indexed
+                .descriptor
+                .translate_pk2_infallible(|pk| cache[pk].at(i, secp))
+                .script_pubkey();
+            self.origins.entry(spk.clone()).or_insert(SpkOrigin {
+                descriptor_id: id,
+                keychain: indexed.keychain,
+                index: i,
+            });
+            indexed.spks.push(spk);
+        }
+        Some(&indexed.spks[start as usize..])
+    }
+
+    /// The script pubkey of the descriptor with identifier `id` at `index`, if
+    /// revealed
+    pub fn spk_at(&self, id: usize, index: u32) -> Option<&Script> {
+        self.descriptors
+            .get(id)
+            .and_then(|indexed| indexed.spks.get(index as usize))
+    }
+
+    /// The descriptor, keychain and index a revealed script pubkey was
+    /// derived at
+    pub fn lookup(&self, spk: &Script) -> Option<SpkOrigin> {
+        self.origins.get(spk).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DescriptorSpkIndex, SpkOrigin};
+    use bitcoin::secp256k1::Secp256k1;
+    use descriptor::{ConversionError, Descriptor, DescriptorPublicKey, KeychainKind};
+    use std::str::FromStr;
+    use {DescriptorTrait, TranslatePk2};
+
+    const XPUB: &'static str = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+
+    #[test]
+    fn spk_index() {
+        let secp = Secp256k1::verification_only();
+        let desc = |s: String| Descriptor::<DescriptorPublicKey>::from_str(&s).unwrap();
+        let derive = |desc: &Descriptor<DescriptorPublicKey>, i| {
+            desc.derive(i)
+                .translate_pk2(|pk| pk.derive_public_key(&secp))
+                .unwrap()
+                .script_pubkey()
+        };
+        let receive = desc(format!("wpkh({}/0/*)", XPUB));
+        let change = desc(format!("wpkh({}/1/*)", XPUB));
+        let single = desc(format!("wpkh({}/2)", XPUB));
+
+        let mut index = DescriptorSpkIndex::new();
+        let r = index
+            .add_descriptor(receive.clone(), KeychainKind::External, &secp)
+            .unwrap();
+        let c = index
+            .add_descriptor(change.clone(), KeychainKind::Internal, &secp)
+            .unwrap();
+        let s = index
+            .add_descriptor(single.clone(), KeychainKind::External, &secp)
+            .unwrap();
+        assert_eq!(index.revealed(r), Some(0));
+        assert_eq!(index.revealed(3), None);
+
+        assert_eq!(index.reveal_to(r, 9, &secp).unwrap().len(), 10);
+        assert_eq!(index.reveal_to(r, 4, &secp).unwrap().len(), 0);
+        assert_eq!(index.reveal_to(r, 14, &secp).unwrap().len(), 5);
+        assert_eq!(index.reveal_to(c, 4, &secp).unwrap().len(), 5);
+        assert_eq!(index.reveal_to(s, 4, &secp).unwrap().len(), 1);
+        assert_eq!(index.revealed(r), Some(15));
+        assert_eq!(index.revealed(s), Some(1));
+
+        let spk = derive(&receive, 12);
+        assert_eq!(index.spk_at(r, 12), Some(&spk));
+        assert_eq!(
+            index.lookup(&spk),
+            Some(SpkOrigin {
+                descriptor_id: r,
+                keychain: KeychainKind::External,
+                index: 12,
+            })
+        );
+        let spk = derive(&change, 3);
+        assert_eq!(
+            index.lookup(&spk),
+            Some(SpkOrigin {
+                descriptor_id: c,
+                keychain: KeychainKind::Internal,
+                index: 3,
+            })
+        );
+        let spk = derive(&single, 0);
+        assert_eq!(index.lookup(&spk).map(|origin| origin.index), Some(0));
+
+        // Not revealed yet
+        let spk = derive(&change, 5);
+        assert_eq!(index.lookup(&spk), None);
+
+        let hardened = desc(format!("wpkh({}/1/*')", XPUB));
+        assert_eq!(
+            index.add_descriptor(hardened, KeychainKind::External, &secp),
+            Err(ConversionError::HardenedWildcard)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Descriptor Templates
+//!
+//! The standard single signature descriptors of BIP 44 (`pkh`), BIP 49
+//! (`sh(wpkh)`) and BIP 84 (`wpkh`), built from the extended public key of an
+//! account and the fingerprint of the master key it was derived from. The
+//! coin type of the derivation path is `0'` for mainnet keys and `1'` for
+//! keys of the test networks.
+//!
+//! BIP 86 descriptors need `tr()`, which is not supported yet.
+//!
+
+use bitcoin::{self, util::bip32};
+
+use super::{Descriptor, DescriptorPublicKey, DescriptorXKey, WalletPolicy, Wildcard};
+use Error;
+
+/// A standard single signature template
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Template {
+    /// BIP 44, P2PKH
+    Bip44,
+    /// BIP 49, P2WPKH nested in P2SH
+    Bip49,
+    /// BIP 84, P2WPKH
+    Bip84,
+}
+
+impl Template {
+    fn purpose(self) -> u32 {
+        match self {
+            Template::Bip44 => 44,
+            Template::Bip49 => 49,
+            Template::Bip84 => 84,
+        }
+    }
+}
+
+fn hardened(index: u32) -> Result<bip32::ChildNumber, Error> {
+    bip32::ChildNumber::from_hardened_idx(index)
+        .map_err(|_| Error::BadDescriptor(format!("invalid account {}", index)))
+}
+
+/// The derivation path of the account from the master key,
+/// `purpose'/coin'/account'`
+pub(super) fn account_path(
+    template: Template,
+    network: bitcoin::Network,
+    account: u32,
+) -> Result<bip32::DerivationPath, Error> {
+    let coin_type = match network {
+        bitcoin::Network::Bitcoin => 0,
+        _ => 1,
+    };
+    Ok(bip32::DerivationPath::from(vec![
+        hardened(template.purpose())?,
+        hardened(coin_type)?,
+        hardened(account)?,
+    ]))
+}
+
+/// The key of the account, derived at `keychain/*` if any
+fn account_key(
+    template: Template,
+    xpub: bip32::ExtendedPubKey,
+    fingerprint: bip32::Fingerprint,
+    account: u32,
+    keychain: Option<u32>,
+) -> Result<DescriptorPublicKey, Error> {
+    let origin_path = account_path(template, xpub.network, account)?;
+
+    let (derivation_path, wildcard) = match keychain {
+        Some(keychain) => {
+            let step = bip32::ChildNumber::from_normal_idx(keychain)
+                .map_err(|_| Error::BadDescriptor(format!("invalid keychain {}", keychain)))?;
+            (vec![step], Wildcard::Unhardened)
+        }
+        None => (vec![], Wildcard::None),
+    };
+
+    Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+        origin: Some((fingerprint, origin_path)),
+        xkey: xpub,
+        derivation_path: bip32::DerivationPath::from(derivation_path),
+        wildcard: wildcard,
+    }))
+}
+
+/// The descriptor of `template` for the addresses of `keychain`
+pub(super) fn descriptor(
+    template: Template,
+    xpub: bip32::ExtendedPubKey,
+    fingerprint: bip32::Fingerprint,
+    account: u32,
+    keychain: u32,
+) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    let key = account_key(template, xpub, fingerprint, account, Some(keychain))?;
+    match template {
+        Template::Bip44 => Ok(Descriptor::new_pkh(key)),
+        Template::Bip49 => Descriptor::new_sh_wpkh(key),
+        Template::Bip84 => Descriptor::new_wpkh(key),
+    }
+}
+
+/// The wallet policy of `template`, with both the receive and change keychains
+pub(super) fn wallet_policy(
+    template: Template,
+    xpub: bip32::ExtendedPubKey,
+    fingerprint: bip32::Fingerprint,
+    account: u32,
+) -> Result<WalletPolicy, Error> {
+    let key = account_key(template, xpub, fingerprint, account, None)?;
+    let policy = match template {
+        Template::Bip44 => "pkh(@0/**)",
+        Template::Bip49 => "sh(wpkh(@0/**))",
+        Template::Bip84 => "wpkh(@0/**)",
+    };
+    WalletPolicy::new(policy, vec![key])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const TPUB: &str = "tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF";
+
+    #[test]
+    fn templates() {
+        let xpub = bip32::ExtendedPubKey::from_str(TPUB).unwrap();
+        let fingerprint = bip32::Fingerprint::from(&[0x2c, 0xbe, 0x2a, 0x6d][..]);
+
+        for &(template, expected) in &[
+            (Template::Bip44, "pkh([2cbe2a6d/44'/1'/3']{}/1/*)"),
+            (Template::Bip49, "sh(wpkh([2cbe2a6d/49'/1'/3']{}/1/*))"),
+            (Template::Bip84, "wpkh([2cbe2a6d/84'/1'/3']{}/1/*)"),
+        ] {
+            let desc = descriptor(template, xpub, fingerprint, 3, 1).unwrap();
+            let expected =
+                Descriptor::<DescriptorPublicKey>::from_str(&expected.replace("{}", TPUB)).unwrap();
+            assert_eq!(desc, expected);
+
+            let policy = wallet_policy(template, xpub, fingerprint, 3).unwrap();
+            assert_eq!(policy.to_descriptor(true), expected);
+        }
+
+        assert!(descriptor(Template::Bip84, xpub, fingerprint, 1 << 31, 0).is_err());
+    }
+}
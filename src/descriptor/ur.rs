@@ -0,0 +1,727 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Blockchain Commons UR
+//!
+//! Conversion of descriptors to and from the CBOR structures of the
+//! `crypto-output` (BCR-2020-010) and `crypto-account` (BCR-2020-015) UR
+//! types, used by air-gapped hardware wallets to exchange descriptors over
+//! QR codes. The UR envelope itself (bytewords, fountain codes) is left to
+//! UR libraries.
+//!
+//! Only the script expressions of BCR-2020-010 are supported: `sh`, `wsh`,
+//! `pk`, `pkh`, `wpkh`, `multi` and `sortedmulti`. Extended keys must either
+//! be master keys or have an origin whose path matches their depth and child
+//! number, since `crypto-hdkey` only records the child number in the origin.
+//!
+
+use std::str::FromStr;
+
+use bitcoin::util::bip32;
+use bitcoin::{self, Network};
+
+use super::checksum::verify_checksum;
+use super::{Descriptor, DescriptorPublicKey, DescriptorSinglePub, DescriptorXKey, Wildcard};
+use expression;
+use {Error, MAX_RECURSION_DEPTH};
+
+const TAG_HDKEY: u64 = 303;
+const TAG_KEYPATH: u64 = 304;
+const TAG_COININFO: u64 = 305;
+const TAG_ECKEY: u64 = 306;
+const TAG_OUTPUT: u64 = 308;
+
+const SCRIPTS: [(&str, u64); 7] = [
+    ("sh", 400),
+    ("wsh", 401),
+    ("pk", 402),
+    ("pkh", 403),
+    ("wpkh", 404),
+    ("multi", 406),
+    ("sortedmulti", 407),
+];
+
+fn bad(msg: &str) -> Error {
+    Error::BadDescriptor(format!("crypto-output: {}", msg))
+}
+
+/// The subset of CBOR used by the UR types
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Array(Vec<Cbor>),
+    /// Maps of the UR types only have integer keys
+    Map(Vec<(u64, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+}
+
+impl Cbor {
+    fn tag(tag: u64, value: Cbor) -> Cbor {
+        Cbor::Tag(tag, Box::new(value))
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        self.encode(&mut ret);
+        ret
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Cbor::Uint(n) => write_head(0, n, out),
+            Cbor::Bytes(ref bytes) => {
+                write_head(2, bytes.len() as u64, out);
+                out.extend_from_slice(bytes);
+            }
+            Cbor::Array(ref items) => {
+                write_head(4, items.len() as u64, out);
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Cbor::Map(ref entries) => {
+                write_head(5, entries.len() as u64, out);
+                for &(key, ref value) in entries {
+                    write_head(0, key, out);
+                    value.encode(out);
+                }
+            }
+            Cbor::Tag(tag, ref value) => {
+                write_head(6, tag, out);
+                value.encode(out);
+            }
+            Cbor::Bool(false) => out.push(0xf4),
+            Cbor::Bool(true) => out.push(0xf5),
+        }
+    }
+
+    fn deserialize(mut bytes: &[u8]) -> Result<Cbor, Error> {
+        let ret = Cbor::decode(&mut bytes, 0)?;
+        if !bytes.is_empty() {
+            return Err(bad("trailing data"));
+        }
+        Ok(ret)
+    }
+
+    fn decode(cursor: &mut &[u8], depth: u32) -> Result<Cbor, Error> {
+        if depth >= MAX_RECURSION_DEPTH {
+            return Err(Error::MaxRecursiveDepthExceeded);
+        }
+
+        let (major, n) = read_head(cursor)?;
+        match major {
+            0 => Ok(Cbor::Uint(n)),
+            2 => Ok(Cbor::Bytes(read_bytes(cursor, n)?.to_vec())),
+            4 => {
+                // Every item takes at least one byte
+                if n > cursor.len() as u64 {
+                    return Err(bad("unexpected end of data"));
+                }
+                let mut items = Vec::with_capacity(n as usize);
+                for _ in 0..n {
+                    items.push(Cbor::decode(cursor, depth + 1)?);
+                }
+                Ok(Cbor::Array(items))
+            }
+            5 => {
+                if n > cursor.len() as u64 {
+                    return Err(bad("unexpected end of data"));
+                }
+                let mut entries = Vec::with_capacity(n as usize);
+                for _ in 0..n {
+                    let key = match read_head(cursor)? {
+                        (0, key) => key,
+                        _ => return Err(bad("non-integer map key")),
+                    };
+                    entries.push((key, Cbor::decode(cursor, depth + 1)?));
+                }
+                Ok(Cbor::Map(entries))
+            }
+            6 => Ok(Cbor::tag(n, Cbor::decode(cursor, depth + 1)?)),
+            7 if n == 20 => Ok(Cbor::Bool(false)),
+            7 if n == 21 => Ok(Cbor::Bool(true)),
+            _ => Err(bad("unsupported CBOR item")),
+        }
+    }
+
+    fn as_uint(&self) -> Result<u64, Error> {
+        match *self {
+            Cbor::Uint(n) => Ok(n),
+            _ => Err(bad("expected an integer")),
+        }
+    }
+
+    fn as_bool(&self) -> Result<bool, Error> {
+        match *self {
+            Cbor::Bool(b) => Ok(b),
+            _ => Err(bad("expected a boolean")),
+        }
+    }
+
+    fn as_bytes(&self) -> Result<&[u8], Error> {
+        match *self {
+            Cbor::Bytes(ref bytes) => Ok(bytes),
+            _ => Err(bad("expected a byte string")),
+        }
+    }
+
+    fn as_array(&self) -> Result<&[Cbor], Error> {
+        match *self {
+            Cbor::Array(ref items) => Ok(items),
+            _ => Err(bad("expected an array")),
+        }
+    }
+
+    fn as_map(&self) -> Result<&[(u64, Cbor)], Error> {
+        match *self {
+            Cbor::Map(ref entries) => Ok(entries),
+            _ => Err(bad("expected a map")),
+        }
+    }
+
+    /// The value of a tag, which must be `tag`
+    fn untag(&self, tag: u64) -> Result<&Cbor, Error> {
+        match *self {
+            Cbor::Tag(t, ref value) if t == tag => Ok(value),
+            _ => Err(bad(&format!("expected tag {}", tag))),
+        }
+    }
+}
+
+fn get(map: &[(u64, Cbor)], key: u64) -> Option<&Cbor> {
+    map.iter()
+        .find(|entry| entry.0 == key)
+        .map(|entry| &entry.1)
+}
+
+fn write_head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    let len = if n < 24 {
+        out.push(major | n as u8);
+        return;
+    } else if n <= 0xff {
+        out.push(major | 24);
+        1
+    } else if n <= 0xffff {
+        out.push(major | 25);
+        2
+    } else if n <= 0xffff_ffff {
+        out.push(major | 26);
+        4
+    } else {
+        out.push(major | 27);
+        8
+    };
+    for i in (0..len).rev() {
+        out.push((n >> (8 * i)) as u8);
+    }
+}
+
+fn read_bytes<'a>(cursor: &mut &'a [u8], len: u64) -> Result<&'a [u8], Error> {
+    let bytes: &'a [u8] = *cursor;
+    if len > bytes.len() as u64 {
+        return Err(bad("unexpected end of data"));
+    }
+    let (ret, rest) = bytes.split_at(len as usize);
+    *cursor = rest;
+    Ok(ret)
+}
+
+fn read_head(cursor: &mut &[u8]) -> Result<(u8, u64), Error> {
+    let initial = read_bytes(cursor, 1)?[0];
+    let len = match initial & 0x1f {
+        n if n < 24 => return Ok((initial >> 5, u64::from(n))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(bad("indefinite lengths are not supported")),
+    };
+    let n = read_bytes(cursor, len)?
+        .iter()
+        .fold(0u64, |n, byte| (n << 8) | u64::from(*byte));
+    Ok((initial >> 5, n))
+}
+
+/// A `crypto-account`: the output descriptors of an account of a wallet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CryptoAccount {
+    /// Fingerprint of the master key of the wallet
+    pub master_fingerprint: bip32::Fingerprint,
+    /// The output descriptors of the account
+    pub outputs: Vec<Descriptor<DescriptorPublicKey>>,
+}
+
+impl CryptoAccount {
+    /// Serializes the account to the CBOR of the `crypto-account` UR type
+    pub fn to_cbor(&self) -> Result<Vec<u8>, Error> {
+        let mut outputs = Vec::with_capacity(self.outputs.len());
+        for desc in &self.outputs {
+            outputs.push(Cbor::tag(TAG_OUTPUT, output_to_cbor(desc)?));
+        }
+        Ok(Cbor::Map(vec![
+            (1, Cbor::Uint(fingerprint_to_u64(&self.master_fingerprint))),
+            (2, Cbor::Array(outputs)),
+        ])
+        .serialize())
+    }
+
+    /// Parses an account from the CBOR of the `crypto-account` UR type
+    pub fn from_cbor(bytes: &[u8]) -> Result<CryptoAccount, Error> {
+        let cbor = Cbor::deserialize(bytes)?;
+        let map = cbor.as_map()?;
+        let master_fingerprint = match get(map, 1) {
+            Some(fingerprint) => fingerprint_from_u64(fingerprint.as_uint()?)?,
+            None => return Err(bad("missing master fingerprint")),
+        };
+        let outputs = match get(map, 2) {
+            Some(outputs) => outputs.as_array()?,
+            None => return Err(bad("missing outputs")),
+        };
+        let outputs = outputs
+            .iter()
+            .map(|output| output_from_cbor(output.untag(TAG_OUTPUT)?))
+            .collect::<Result<_, _>>()?;
+        Ok(CryptoAccount {
+            master_fingerprint,
+            outputs,
+        })
+    }
+}
+
+/// Serialize `desc` to the CBOR of the `crypto-output` UR type
+pub(super) fn to_crypto_output(desc: &Descriptor<DescriptorPublicKey>) -> Result<Vec<u8>, Error> {
+    Ok(output_to_cbor(desc)?.serialize())
+}
+
+/// Parse a descriptor from the CBOR of the `crypto-output` UR type, which may
+/// be tagged as when embedded in other structures
+pub(super) fn from_crypto_output(bytes: &[u8]) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    let cbor = Cbor::deserialize(bytes)?;
+    match cbor {
+        Cbor::Tag(TAG_OUTPUT, ref output) => output_from_cbor(output),
+        ref output => output_from_cbor(output),
+    }
+}
+
+fn output_to_cbor(desc: &Descriptor<DescriptorPublicKey>) -> Result<Cbor, Error> {
+    let desc = desc.to_string();
+    let desc = verify_checksum(&desc).expect("Descriptors display a valid checksum");
+    let tree = expression::Tree::from_str(desc).expect("Descriptors display a valid tree");
+    script_to_cbor(&tree)
+}
+
+fn output_from_cbor(cbor: &Cbor) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    Descriptor::from_str(&script_from_cbor(cbor)?)
+}
+
+fn script_to_cbor(node: &expression::Tree) -> Result<Cbor, Error> {
+    let tag = match SCRIPTS.iter().find(|script| script.0 == node.name) {
+        Some(script) => script.1,
+        None => {
+            return Err(bad(&format!(
+                "{} is not a supported script expression",
+                node.name
+            )))
+        }
+    };
+
+    let value = match node.args.len() {
+        1 if tag == 400 || tag == 401 => script_to_cbor(&node.args[0])?,
+        1 => key_to_cbor(&node.args[0])?,
+        n if n > 1 && (tag == 406 || tag == 407) => {
+            let k = expression::parse_num(node.args[0].name)?;
+            let keys = node.args[1..]
+                .iter()
+                .map(key_to_cbor)
+                .collect::<Result<_, _>>()?;
+            Cbor::Map(vec![(1, Cbor::Uint(u64::from(k))), (2, Cbor::Array(keys))])
+        }
+        _ => return Err(bad(&format!("invalid arguments to {}", node.name))),
+    };
+    Ok(Cbor::tag(tag, value))
+}
+
+fn script_from_cbor(cbor: &Cbor) -> Result<String, Error> {
+    let (tag, value) = match *cbor {
+        Cbor::Tag(tag, ref value) => (tag, value),
+        _ => return Err(bad("expected a script expression")),
+    };
+    let name = match SCRIPTS.iter().find(|script| script.1 == tag) {
+        Some(script) => script.0,
+        None => return Err(bad(&format!("unsupported script expression tag {}", tag))),
+    };
+
+    let args = match tag {
+        400 | 401 => script_from_cbor(value)?,
+        406 | 407 => {
+            let map = value.as_map()?;
+            let k = match get(map, 1) {
+                Some(k) => k.as_uint()?,
+                None => return Err(bad("missing threshold")),
+            };
+            let keys = match get(map, 2) {
+                Some(keys) => keys.as_array()?,
+                None => return Err(bad("missing keys")),
+            };
+            let mut args = vec![k.to_string()];
+            for key in keys {
+                args.push(key_from_cbor(key)?.to_string());
+            }
+            args.join(",")
+        }
+        _ => key_from_cbor(value)?.to_string(),
+    };
+    Ok(format!("{}({})", name, args))
+}
+
+fn fingerprint_to_u64(fingerprint: &bip32::Fingerprint) -> u64 {
+    fingerprint[..]
+        .iter()
+        .fold(0, |n, byte| (n << 8) | u64::from(*byte))
+}
+
+fn fingerprint_from_u64(n: u64) -> Result<bip32::Fingerprint, Error> {
+    if n > 0xffff_ffff {
+        return Err(bad("invalid fingerprint"));
+    }
+    let bytes = [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8];
+    Ok(bip32::Fingerprint::from(&bytes[..]))
+}
+
+/// A `crypto-keypath` with the path `path`, followed by a wildcard if any
+fn keypath_to_cbor(
+    path: &bip32::DerivationPath,
+    wildcard: Wildcard,
+    fingerprint: Option<&bip32::Fingerprint>,
+) -> Cbor {
+    let mut components = vec![];
+    for child in path {
+        let (index, hardened) = match *child {
+            bip32::ChildNumber::Normal { index } => (index, false),
+            bip32::ChildNumber::Hardened { index } => (index, true),
+        };
+        components.push(Cbor::Uint(u64::from(index)));
+        components.push(Cbor::Bool(hardened));
+    }
+    match wildcard {
+        Wildcard::None => {}
+        Wildcard::Unhardened => components.extend(vec![Cbor::Array(vec![]), Cbor::Bool(false)]),
+        Wildcard::Hardened => components.extend(vec![Cbor::Array(vec![]), Cbor::Bool(true)]),
+    }
+
+    let mut map = vec![(1, Cbor::Array(components))];
+    if let Some(fingerprint) = fingerprint {
+        map.push((2, Cbor::Uint(fingerprint_to_u64(fingerprint))));
+    }
+    Cbor::tag(TAG_KEYPATH, Cbor::Map(map))
+}
+
+/// The path, wildcard and source fingerprint of a `crypto-keypath`
+fn keypath_from_cbor(
+    cbor: &Cbor,
+) -> Result<(bip32::DerivationPath, Wildcard, Option<bip32::Fingerprint>), Error> {
+    let map = cbor.untag(TAG_KEYPATH)?.as_map()?;
+    let components = match get(map, 1) {
+        Some(components) => components.as_array()?,
+        None => return Err(bad("missing keypath components")),
+    };
+    if components.len() % 2 != 0 {
+        return Err(bad("invalid keypath components"));
+    }
+
+    let mut path = vec![];
+    let mut wildcard = Wildcard::None;
+    for component in components.chunks(2) {
+        if wildcard != Wildcard::None {
+            return Err(bad("wildcard must be the last keypath component"));
+        }
+        let hardened = component[1].as_bool()?;
+        match component[0] {
+            Cbor::Array(ref range) if range.is_empty() => {
+                wildcard = if hardened {
+                    Wildcard::Hardened
+                } else {
+                    Wildcard::Unhardened
+                };
+            }
+            ref index => {
+                let index = index.as_uint()?;
+                if index >= 1 << 31 {
+                    return Err(bad("invalid child index"));
+                }
+                path.push(if hardened {
+                    bip32::ChildNumber::Hardened {
+                        index: index as u32,
+                    }
+                } else {
+                    bip32::ChildNumber::Normal {
+                        index: index as u32,
+                    }
+                });
+            }
+        }
+    }
+
+    let fingerprint = match get(map, 2) {
+        Some(fingerprint) => Some(fingerprint_from_u64(fingerprint.as_uint()?)?),
+        None => None,
+    };
+    Ok((bip32::DerivationPath::from(path), wildcard, fingerprint))
+}
+
+fn key_to_cbor(node: &expression::Tree) -> Result<Cbor, Error> {
+    if !node.args.is_empty() {
+        return Err(bad("expected a key"));
+    }
+    let key = DescriptorPublicKey::from_str(node.name)
+        .map_err(|e| Error::BadDescriptor(e.to_string()))?;
+    match key {
+        DescriptorPublicKey::SinglePub(ref single) => {
+            if single.origin.is_some() {
+                return Err(bad("crypto-eckey cannot record a key origin"));
+            }
+            Ok(Cbor::tag(
+                TAG_ECKEY,
+                Cbor::Map(vec![(3, Cbor::Bytes(single.key.to_bytes()))]),
+            ))
+        }
+        DescriptorPublicKey::XPub(ref xpub) => xpub_to_cbor(xpub),
+    }
+}
+
+fn xpub_to_cbor(xpub: &DescriptorXKey<bip32::ExtendedPubKey>) -> Result<Cbor, Error> {
+    let xkey = &xpub.xkey;
+    let mut map = vec![
+        (3, Cbor::Bytes(xkey.public_key.to_bytes())),
+        (4, Cbor::Bytes(xkey.chain_code[..].to_vec())),
+    ];
+    if xkey.network != Network::Bitcoin {
+        map.push((
+            5,
+            Cbor::tag(TAG_COININFO, Cbor::Map(vec![(2, Cbor::Uint(1))])),
+        ));
+    }
+
+    match xpub.origin {
+        Some((ref fingerprint, ref origin_path)) => {
+            let path: &[bip32::ChildNumber] = origin_path.as_ref();
+            let matches_key = match path.last() {
+                Some(child) => path.len() == xkey.depth as usize && *child == xkey.child_number,
+                None => xkey.depth == 0,
+            };
+            if !matches_key {
+                return Err(bad("key origin does not match the extended key"));
+            }
+            map.push((
+                6,
+                keypath_to_cbor(origin_path, Wildcard::None, Some(fingerprint)),
+            ));
+        }
+        None if xkey.depth == 0 => {}
+        None => return Err(bad("non-master extended keys need an origin")),
+    }
+
+    if xpub.derivation_path.into_iter().next().is_some() || xpub.wildcard != Wildcard::None {
+        map.push((
+            7,
+            keypath_to_cbor(&xpub.derivation_path, xpub.wildcard, None),
+        ));
+    }
+    if xkey.depth > 0 {
+        map.push((8, Cbor::Uint(fingerprint_to_u64(&xkey.parent_fingerprint))));
+    }
+    Ok(Cbor::tag(TAG_HDKEY, Cbor::Map(map)))
+}
+
+fn key_from_cbor(cbor: &Cbor) -> Result<DescriptorPublicKey, Error> {
+    match *cbor {
+        Cbor::Tag(TAG_ECKEY, ref eckey) => {
+            let map = eckey.as_map()?;
+            if let Some(private) = get(map, 2) {
+                if private.as_bool()? {
+                    return Err(bad("private keys are not supported"));
+                }
+            }
+            let key = match get(map, 3) {
+                Some(data) => bitcoin::PublicKey::from_slice(data.as_bytes()?)
+                    .map_err(|_| bad("invalid key"))?,
+                None => return Err(bad("missing key data")),
+            };
+            Ok(DescriptorPublicKey::SinglePub(DescriptorSinglePub {
+                origin: None,
+                key,
+            }))
+        }
+        Cbor::Tag(TAG_HDKEY, ref hdkey) => xpub_from_cbor(hdkey.as_map()?),
+        _ => Err(bad("expected a key")),
+    }
+}
+
+fn xpub_from_cbor(map: &[(u64, Cbor)]) -> Result<DescriptorPublicKey, Error> {
+    if let Some(private) = get(map, 2) {
+        if private.as_bool()? {
+            return Err(bad("private keys are not supported"));
+        }
+    }
+    let public_key = match get(map, 3) {
+        Some(data) => {
+            bitcoin::PublicKey::from_slice(data.as_bytes()?).map_err(|_| bad("invalid key"))?
+        }
+        None => return Err(bad("missing key data")),
+    };
+    let chain_code = match get(map, 4) {
+        Some(data) => data.as_bytes()?,
+        None => return Err(bad("missing chain code")),
+    };
+    if chain_code.len() != 32 {
+        return Err(bad("invalid chain code"));
+    }
+    let network = match get(map, 5) {
+        Some(coininfo) => match get(coininfo.untag(TAG_COININFO)?.as_map()?, 2) {
+            Some(network) => match network.as_uint()? {
+                0 => Network::Bitcoin,
+                1 => Network::Testnet,
+                _ => return Err(bad("unknown network")),
+            },
+            None => Network::Bitcoin,
+        },
+        None => Network::Bitcoin,
+    };
+
+    let (origin, depth, child_number) = match get(map, 6) {
+        Some(keypath) => {
+            let (path, wildcard, fingerprint) = keypath_from_cbor(keypath)?;
+            if wildcard != Wildcard::None {
+                return Err(bad("wildcard in key origin"));
+            }
+            let fingerprint = match fingerprint {
+                Some(fingerprint) => fingerprint,
+                None => return Err(bad("missing key origin fingerprint")),
+            };
+            let (depth, child_number) = {
+                let children: &[bip32::ChildNumber] = path.as_ref();
+                if children.len() > 0xff {
+                    return Err(bad("key origin is too deep"));
+                }
+                match children.last() {
+                    Some(child) => (children.len() as u8, *child),
+                    None => (0, bip32::ChildNumber::Normal { index: 0 }),
+                }
+            };
+            (Some((fingerprint, path)), depth, child_number)
+        }
+        None => (None, 0, bip32::ChildNumber::Normal { index: 0 }),
+    };
+    let parent_fingerprint = match get(map, 8) {
+        Some(fingerprint) => fingerprint_from_u64(fingerprint.as_uint()?)?,
+        None => bip32::Fingerprint::from(&[0; 4][..]),
+    };
+
+    let (derivation_path, wildcard) = match get(map, 7) {
+        Some(keypath) => {
+            let (path, wildcard, _) = keypath_from_cbor(keypath)?;
+            (path, wildcard)
+        }
+        None => (bip32::DerivationPath::from(vec![]), Wildcard::None),
+    };
+
+    Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+        origin,
+        xkey: bip32::ExtendedPubKey {
+            network,
+            depth,
+            parent_fingerprint,
+            child_number,
+            public_key,
+            chain_code: bip32::ChainCode::from(chain_code),
+        },
+        derivation_path,
+        wildcard,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cbor_roundtrip() {
+        let cbor = Cbor::Map(vec![
+            (1, Cbor::Uint(0xdeadbeef)),
+            (
+                2,
+                Cbor::Array(vec![
+                    Cbor::tag(TAG_OUTPUT, Cbor::Bytes(vec![1, 2, 3])),
+                    Cbor::Bool(true),
+                    Cbor::Uint(23),
+                    Cbor::Uint(24),
+                ]),
+            ),
+        ]);
+        let bytes = cbor.serialize();
+        assert_eq!(
+            bytes,
+            vec![
+                0xa2, 0x01, 0x1a, 0xde, 0xad, 0xbe, 0xef, 0x02, 0x84, 0xd9, 0x01, 0x34, 0x43, 1, 2,
+                3, 0xf5, 0x17, 0x18, 0x18
+            ]
+        );
+        assert_eq!(Cbor::deserialize(&bytes).unwrap(), cbor);
+        assert!(Cbor::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn crypto_output() {
+        for s in &[
+            "wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)",
+            "sh(wsh(multi(1,03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556,02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e)))",
+            "wsh(sortedmulti(2,[deadbeef/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/0/*,[deadbeef/48'/1'/1'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK/0/*h))",
+        ] {
+            let desc = Descriptor::<DescriptorPublicKey>::from_str(s).unwrap();
+            let cbor = desc.to_crypto_output().unwrap();
+            assert_eq!(
+                Descriptor::<DescriptorPublicKey>::from_crypto_output(&cbor).unwrap(),
+                desc
+            );
+        }
+
+        // pk inside wsh is a script expression, other miniscript is not
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("wsh(and_v(v:pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),older(144)))").unwrap();
+        assert!(desc.to_crypto_output().is_err());
+        // The origin must account for the depth of the extended key
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("wpkh([deadbeef/84'/1'/0']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/0/*)").unwrap();
+        assert!(desc.to_crypto_output().is_err());
+    }
+
+    #[test]
+    fn crypto_account() {
+        let account = CryptoAccount {
+            master_fingerprint: bip32::Fingerprint::from_str("deadbeef").unwrap(),
+            outputs: vec![
+                Descriptor::from_str("wpkh([deadbeef/84'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF)").unwrap(),
+                Descriptor::from_str("sh(wpkh([deadbeef/49'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK))").unwrap(),
+            ],
+        };
+        let cbor = account.to_cbor().unwrap();
+        assert_eq!(CryptoAccount::from_cbor(&cbor).unwrap(), account);
+        // Outputs of an account are tagged
+        assert_eq!(
+            &cbor[..9],
+            &[0xa2, 0x01, 0x1a, 0xde, 0xad, 0xbe, 0xef, 0x02, 0x82][..]
+        );
+        assert_eq!(&cbor[9..12], &[0xd9, 0x01, 0x34][..]);
+    }
+}
@@ -0,0 +1,426 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Wallet Policies
+//!
+//! Implementation of the wallet policies of BIP 388: descriptor templates
+//! whose keys are placeholders such as `@0/**`, together with the vector of
+//! keys they refer to. This is the form in which descriptors are registered on
+//! hardware signing devices.
+//!
+
+use std::{fmt, str::FromStr};
+
+use bitcoin::util::bip32;
+
+use super::templates::{self, Template};
+use super::{Descriptor, DescriptorPublicKey, DescriptorXKey, Wildcard};
+use {Error, ForEach, ForEachKey, MiniscriptKey, TranslatePk2};
+
+/// The keychain of a wallet: the addresses it hands out or its change
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeychainKind {
+    /// Receive addresses, the first step of `<a;b>`
+    External,
+    /// Change addresses, the second step of `<a;b>`
+    Internal,
+}
+
+/// A key placeholder of a wallet policy template, `@i/<a;b>/*`
+///
+/// The placeholder refers to the key at index `i` of the key vector of the
+/// policy, derived at `a/*` for receive addresses and at `b/*` for change
+/// addresses. `@i/**` is a shorthand for `@i/<0;1>/*`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyPlaceholder {
+    /// Index of the key in the key vector of the policy
+    pub index: u32,
+    /// Unhardened derivation step of receive addresses
+    pub receive: u32,
+    /// Unhardened derivation step of change addresses
+    pub change: u32,
+}
+
+impl KeyPlaceholder {
+    /// The descriptor public key this placeholder stands for, given the key
+    /// it refers to
+    fn instantiate(&self, key: &DescriptorPublicKey, change: bool) -> DescriptorPublicKey {
+        let step = if change { self.change } else { self.receive };
+        match *key {
+            DescriptorPublicKey::XPub(ref xpub) => DescriptorPublicKey::XPub(DescriptorXKey {
+                origin: xpub.origin.clone(),
+                xkey: xpub.xkey,
+                derivation_path: bip32::DerivationPath::from(vec![
+                    bip32::ChildNumber::from_normal_idx(step).expect("checked at parse time"),
+                ]),
+                wildcard: Wildcard::Unhardened,
+            }),
+            DescriptorPublicKey::SinglePub(..) => unreachable!("checked in WalletPolicy::new"),
+        }
+    }
+}
+
+impl fmt::Display for KeyPlaceholder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.receive == 0 && self.change == 1 {
+            write!(f, "@{}/**", self.index)
+        } else {
+            write!(f, "@{}/<{};{}>/*", self.index, self.receive, self.change)
+        }
+    }
+}
+
+fn parse_placeholder_num(s: &str) -> Result<u32, Error> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::BadDescriptor(format!(
+            "invalid number '{}' in key placeholder",
+            s
+        )));
+    }
+    match u32::from_str(s) {
+        Ok(n) if n < (1 << 31) => Ok(n),
+        _ => Err(Error::BadDescriptor(format!(
+            "number '{}' out of range in key placeholder",
+            s
+        ))),
+    }
+}
+
+impl FromStr for KeyPlaceholder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<KeyPlaceholder, Error> {
+        if !s.starts_with('@') {
+            return Err(Error::BadDescriptor(format!(
+                "key placeholder '{}' does not start with '@'",
+                s
+            )));
+        }
+        let slash = s.find('/').ok_or_else(|| {
+            Error::BadDescriptor(format!("key placeholder '{}' has no derivation", s))
+        })?;
+        let index = parse_placeholder_num(&s[1..slash])?;
+
+        let derivation = &s[slash + 1..];
+        let (receive, change) = if derivation == "**" {
+            (0, 1)
+        } else if derivation.starts_with('<') && derivation.ends_with(">/*") {
+            let steps = &derivation[1..derivation.len() - 3];
+            let semicolon = steps.find(';').ok_or_else(|| {
+                Error::BadDescriptor(format!("key placeholder '{}' has no ';'", s))
+            })?;
+            (
+                parse_placeholder_num(&steps[..semicolon])?,
+                parse_placeholder_num(&steps[semicolon + 1..])?,
+            )
+        } else {
+            return Err(Error::BadDescriptor(format!(
+                "key placeholder '{}' must end with '/**' or '/<a;b>/*'",
+                s
+            )));
+        };
+        if receive == change {
+            return Err(Error::BadDescriptor(format!(
+                "key placeholder '{}' has identical receive and change steps",
+                s
+            )));
+        }
+
+        Ok(KeyPlaceholder {
+            index,
+            receive,
+            change,
+        })
+    }
+}
+
+impl MiniscriptKey for KeyPlaceholder {
+    type Hash = KeyPlaceholder;
+
+    fn to_pubkeyhash(&self) -> KeyPlaceholder {
+        *self
+    }
+}
+
+/// A BIP 388 wallet policy: a descriptor template and the keys its
+/// placeholders refer to
+///
+/// Keys are extended public keys, with optional origin information but without
+/// derivation steps or wildcards, which are given by the placeholders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalletPolicy {
+    template: Descriptor<KeyPlaceholder>,
+    keys: Vec<DescriptorPublicKey>,
+}
+
+impl WalletPolicy {
+    /// Parses the descriptor template `template` and checks it against `keys`
+    ///
+    /// Every key must be referred to by the template, the first occurrences of
+    /// the placeholders must be in increasing order of index, and placeholders
+    /// referring to the same key must use disjoint derivation steps.
+    pub fn new(template: &str, keys: Vec<DescriptorPublicKey>) -> Result<WalletPolicy, Error> {
+        let template = Descriptor::<KeyPlaceholder>::from_str(template)?;
+
+        for (i, key) in keys.iter().enumerate() {
+            match *key {
+                DescriptorPublicKey::XPub(ref xpub)
+                    if xpub.wildcard == Wildcard::None
+                        && xpub.derivation_path.into_iter().next().is_none() => {}
+                _ => {
+                    return Err(Error::BadDescriptor(format!(
+                        "wallet policy key '{}' is not an xpub without derivation",
+                        key
+                    )))
+                }
+            }
+            if keys[..i].contains(key) {
+                return Err(Error::BadDescriptor(format!(
+                    "duplicate wallet policy key '{}'",
+                    key
+                )));
+            }
+        }
+
+        let mut placeholders: Vec<KeyPlaceholder> = vec![];
+        template.for_each_key(|pk: ForEach<KeyPlaceholder>| {
+            placeholders.push(*pk.as_key());
+            true
+        });
+
+        let mut next_index = 0;
+        for (i, placeholder) in placeholders.iter().enumerate() {
+            if placeholder.index as usize >= keys.len() {
+                return Err(Error::BadDescriptor(format!(
+                    "key placeholder '{}' refers to a missing key",
+                    placeholder
+                )));
+            }
+            if placeholder.index > next_index {
+                return Err(Error::BadDescriptor(format!(
+                    "key placeholder '{}' appears before @{}",
+                    placeholder, next_index
+                )));
+            }
+            if placeholder.index == next_index {
+                next_index += 1;
+            }
+            let overlapping = placeholders[..i].iter().any(|other| {
+                other.index == placeholder.index
+                    && (other.receive == placeholder.receive
+                        || other.receive == placeholder.change
+                        || other.change == placeholder.receive
+                        || other.change == placeholder.change)
+            });
+            if overlapping {
+                return Err(Error::BadDescriptor(format!(
+                    "key placeholder '{}' reuses a derivation of @{}",
+                    placeholder, placeholder.index
+                )));
+            }
+        }
+        if next_index as usize != keys.len() {
+            return Err(Error::BadDescriptor(format!(
+                "wallet policy key @{} is not used by the template",
+                next_index
+            )));
+        }
+
+        Ok(WalletPolicy { template, keys })
+    }
+
+    /// The BIP 44 policy `pkh(@0/**)` of the account key
+    /// `[fingerprint/44'/coin'/account']xpub`
+    ///
+    /// `xpub` is the key of the account and `fingerprint` the fingerprint of
+    /// the master key. The coin type is `0'` for mainnet keys and `1'`
+    /// otherwise.
+    pub fn bip44(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+    ) -> Result<WalletPolicy, Error> {
+        templates::wallet_policy(Template::Bip44, xpub, fingerprint, account)
+    }
+
+    /// The BIP 49 policy `sh(wpkh(@0/**))` of the account key
+    /// `[fingerprint/49'/coin'/account']xpub`, see `bip44`
+    pub fn bip49(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+    ) -> Result<WalletPolicy, Error> {
+        templates::wallet_policy(Template::Bip49, xpub, fingerprint, account)
+    }
+
+    /// The BIP 84 policy `wpkh(@0/**)` of the account key
+    /// `[fingerprint/84'/coin'/account']xpub`, see `bip44`
+    pub fn bip84(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+    ) -> Result<WalletPolicy, Error> {
+        templates::wallet_policy(Template::Bip84, xpub, fingerprint, account)
+    }
+
+    /// The descriptor template of the policy
+    pub fn template(&self) -> &Descriptor<KeyPlaceholder> {
+        &self.template
+    }
+
+    /// The descriptor template of the policy, as a string without checksum
+    pub fn template_string(&self) -> String {
+        let template = self.template.to_string();
+        match template.find('#') {
+            Some(pos) => template[..pos].to_owned(),
+            None => template,
+        }
+    }
+
+    /// The keys the placeholders of the template refer to
+    pub fn keys(&self) -> &[DescriptorPublicKey] {
+        &self.keys
+    }
+
+    /// Instantiates the descriptor of the receive addresses (`change` false) or
+    /// change addresses (`change` true) of the policy
+    pub fn to_descriptor(&self, change: bool) -> Descriptor<DescriptorPublicKey> {
+        self.template.translate_pk2_infallible(|placeholder| {
+            placeholder.instantiate(&self.keys[placeholder.index as usize], change)
+        })
+    }
+
+    /// Instantiates the descriptor of `keychain`
+    pub fn keychain_descriptor(&self, keychain: KeychainKind) -> Descriptor<DescriptorPublicKey> {
+        self.to_descriptor(keychain == KeychainKind::Internal)
+    }
+
+    /// Instantiates the descriptors of both keychains, external first
+    pub fn keychain_descriptors(&self) -> Vec<(KeychainKind, Descriptor<DescriptorPublicKey>)> {
+        [KeychainKind::External, KeychainKind::Internal]
+            .iter()
+            .map(|&keychain| (keychain, self.keychain_descriptor(keychain)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyPlaceholder, KeychainKind, WalletPolicy};
+    use descriptor::{Descriptor, DescriptorPublicKey};
+    use std::str::FromStr;
+
+    const XPUB_A: &'static str = "[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF";
+    const XPUB_B: &'static str = "[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK";
+
+    fn keys() -> Vec<DescriptorPublicKey> {
+        vec![
+            DescriptorPublicKey::from_str(XPUB_A).unwrap(),
+            DescriptorPublicKey::from_str(XPUB_B).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn placeholder_roundtrip() {
+        for s in &["@0/**", "@12/<2;3>/*", "@1/<1;0>/*"] {
+            assert_eq!(KeyPlaceholder::from_str(s).unwrap().to_string(), *s);
+        }
+        assert_eq!(
+            KeyPlaceholder::from_str("@0/<0;1>/*").unwrap().to_string(),
+            "@0/**"
+        );
+        for s in &[
+            "0/**",
+            "@/**",
+            "@0",
+            "@0/*",
+            "@0/<0;0>/*",
+            "@0/<0;1>",
+            "@0/<0;2147483648>/*",
+            "@+1/**",
+            "@0/<1'>/*",
+        ] {
+            assert!(KeyPlaceholder::from_str(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn wallet_policy() {
+        let policy = WalletPolicy::new("wsh(sortedmulti(2,@0/**,@1/**))", keys()).unwrap();
+        assert_eq!(policy.template_string(), "wsh(sortedmulti(2,@0/**,@1/**))");
+        assert_eq!(policy.keys(), &keys()[..]);
+
+        let receive = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(sortedmulti(2,{}/0/*,{}/0/*))",
+            XPUB_A, XPUB_B
+        ))
+        .unwrap();
+        let change = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(sortedmulti(2,{}/1/*,{}/1/*))",
+            XPUB_A, XPUB_B
+        ))
+        .unwrap();
+        assert_eq!(policy.to_descriptor(false), receive);
+        assert_eq!(policy.to_descriptor(true), change);
+        assert_eq!(
+            policy.keychain_descriptors(),
+            vec![
+                (KeychainKind::External, receive),
+                (KeychainKind::Internal, change)
+            ]
+        );
+
+        // Same key with disjoint derivations, in a miniscript
+        let policy = WalletPolicy::new(
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@1/**),and_v(v:pk(@0/<2;3>/*),older(65535)))))",
+            keys(),
+        )
+        .unwrap();
+        let change = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(or_d(pk({a}/1/*),and_v(v:pkh({b}/1/*),and_v(v:pk({a}/3/*),older(65535)))))",
+            a = XPUB_A,
+            b = XPUB_B
+        ))
+        .unwrap();
+        assert_eq!(policy.to_descriptor(true), change);
+    }
+
+    #[test]
+    fn invalid_wallet_policy() {
+        let invalid = [
+            // Missing key
+            "wsh(multi(2,@0/**,@2/**))",
+            // Unused key
+            "wpkh(@0/**)",
+            // Out of order
+            "wsh(multi(2,@1/**,@0/**))",
+            // Overlapping derivations of the same key
+            "wsh(multi(2,@0/**,@1/**,@0/<1;2>/*))",
+            // Not a placeholder
+            "wsh(multi(2,@0/**,@1))",
+        ];
+        for template in &invalid {
+            assert!(WalletPolicy::new(template, keys()).is_err(), "{}", template);
+        }
+
+        // Keys must not have derivation steps
+        let mut derived_keys = keys();
+        derived_keys[1] = DescriptorPublicKey::from_str(&format!("{}/0/*", XPUB_B)).unwrap();
+        assert!(WalletPolicy::new("wsh(multi(2,@0/**,@1/**))", derived_keys).is_err());
+
+        // Keys must be unique
+        let mut same_keys = keys();
+        same_keys[1] = same_keys[0].clone();
+        assert!(WalletPolicy::new("wsh(multi(2,@0/**,@1/**))", same_keys).is_err());
+    }
+}
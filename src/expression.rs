@@ -15,6 +15,7 @@
 //! # Function-like Expression Language
 //!
 
+use std::cell::Cell;
 use std::str::FromStr;
 
 use errstr;
@@ -22,6 +23,67 @@ use Error;
 
 use MAX_RECURSION_DEPTH;
 
+/// A range of byte offsets in a parsed string, used to locate parse errors
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Span {
+    /// Offset of the first byte of the range
+    pub start: usize,
+    /// Offset one past the last byte of the range
+    pub end: usize,
+}
+
+thread_local! {
+    // The innermost token at which parsing failed last in this thread, as a
+    // range of addresses in the parsed string, see `parse_with_span`
+    static ERROR_RANGE: Cell<Option<(usize, usize)>> = Cell::new(None);
+}
+
+/// Record that parsing failed at the addresses `start..end`, unless it
+/// failed at a token within them, which is located more precisely
+fn record_range(start: usize, end: usize) {
+    ERROR_RANGE.with(|range| match range.get() {
+        Some((s, e)) if start <= s && e <= end => {}
+        _ => range.set(Some((start, end))),
+    })
+}
+
+/// Record that parsing failed at `token`, a slice of the parsed string, see
+/// `parse_with_span`
+pub(crate) fn record_error(token: &str) {
+    record_range(addr(token), addr(token) + token.len())
+}
+
+/// Parses `s` with `parse`, giving on failure the span of `s` where the error
+/// was found, if known
+///
+/// The span is the innermost token of the descriptor or miniscript which
+/// failed to parse, for example an invalid key or a fragment with the wrong
+/// number of arguments. The error itself is the one returned by `parse`.
+pub fn parse_with_span<'a, T, F>(s: &'a str, parse: F) -> Result<T, (Error, Option<Span>)>
+where
+    F: FnOnce(&'a str) -> Result<T, Error>,
+{
+    let outer = ERROR_RANGE.with(|range| range.replace(None));
+    let res = parse(s);
+    let recorded = ERROR_RANGE.with(|range| range.replace(outer));
+    res.map_err(|e| {
+        let base = s.as_ptr() as usize;
+        let span = match recorded {
+            Some((start, end)) if base <= start && end <= base + s.len() => Some(Span {
+                start: start - base,
+                end: end - base,
+            }),
+            _ => None,
+        };
+        (e, span)
+    })
+}
+
+/// The address of the start of `s`, to locate errors in the parsed string
+fn addr(s: &str) -> usize {
+    s.as_ptr() as usize
+}
+
 #[derive(Debug)]
 /// A token of the form `x(...)` or `x`
 pub struct Tree<'a> {
@@ -38,14 +100,52 @@ pub trait FromTree: Sized {
 }
 
 impl<'a> Tree<'a> {
-    fn from_slice(sl: &'a str) -> Result<(Tree<'a>, &'a str), Error> {
-        Self::from_slice_helper(sl, 0u32)
+    /// The addresses of the whole token in the parsed string, from its name
+    /// to its closing parenthesis
+    fn range(&self) -> (usize, usize) {
+        // The token ends with its last descendant and the parentheses
+        // closing its ancestors
+        let mut last = self;
+        let mut closing = 0;
+        while let Some(arg) = last.args.last() {
+            last = arg;
+            closing += 1;
+        }
+        (addr(self.name), addr(last.name) + last.name.len() + closing)
+    }
+
+    /// The span of the whole token in `s`, the string it was parsed from,
+    /// from its name to its closing parenthesis; None if it was not parsed
+    /// from `s`
+    pub fn span_in(&self, s: &str) -> Option<Span> {
+        let (start, end) = self.range();
+        if addr(s) <= start && end <= addr(s) + s.len() {
+            Some(Span {
+                start: start - addr(s),
+                end: end - addr(s),
+            })
+        } else {
+            None
+        }
     }
 
-    fn from_slice_helper(mut sl: &'a str, depth: u32) -> Result<(Tree<'a>, &'a str), Error> {
-        if depth >= MAX_RECURSION_DEPTH {
-            return Err(Error::MaxRecursiveDepthExceeded);
+    /// Locate the error of `res`, if any, at this token, see
+    /// `parse_with_span`; the result is returned unchanged
+    ///
+    /// Errors already located at a sub-token keep their more precise span.
+    pub fn spanned<T>(&self, res: Result<T, Error>) -> Result<T, Error> {
+        if res.is_err() {
+            let (start, end) = self.range();
+            record_range(start, end);
         }
+        res
+    }
+
+    /// Parses a tree from the start of `sl`, returning it with the rest of
+    /// `sl`. Function calls still open are kept on an explicit stack rather
+    /// than parsed by recursion, so that deep trees cannot overflow the call
+    /// stack.
+    fn from_slice(mut sl: &'a str) -> Result<(Tree<'a>, &'a str), Error> {
         enum Found {
             Nothing,
             Lparen(usize),
@@ -53,66 +153,90 @@ impl<'a> Tree<'a> {
             Rparen(usize),
         }
 
-        let mut found = Found::Nothing;
-        for (n, ch) in sl.char_indices() {
-            match ch {
-                '(' => {
-                    found = Found::Lparen(n);
-                    break;
+        let mut open: Vec<Tree<'a>> = vec![];
+        loop {
+            if open.len() as u32 >= MAX_RECURSION_DEPTH {
+                record_error(&sl[..0]);
+                return Err(Error::MaxRecursiveDepthExceeded);
+            }
+
+            let mut found = Found::Nothing;
+            for (n, ch) in sl.char_indices() {
+                match ch {
+                    '(' => {
+                        found = Found::Lparen(n);
+                        break;
+                    }
+                    ',' => {
+                        found = Found::Comma(n);
+                        break;
+                    }
+                    ')' => {
+                        found = Found::Rparen(n);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut done = match found {
+                // String-ending terminal
+                Found::Nothing => {
+                    let tree = Tree {
+                        name: &sl[..],
+                        args: vec![],
+                    };
+                    sl = &sl[sl.len()..];
+                    tree
                 }
-                ',' => {
-                    found = Found::Comma(n);
-                    break;
+                // Terminal
+                Found::Comma(n) | Found::Rparen(n) => {
+                    let tree = Tree {
+                        name: &sl[..n],
+                        args: vec![],
+                    };
+                    sl = &sl[n..];
+                    tree
                 }
-                ')' => {
-                    found = Found::Rparen(n);
-                    break;
+                // Function call, whose arguments come next
+                Found::Lparen(n) => {
+                    open.push(Tree {
+                        name: &sl[..n],
+                        args: vec![],
+                    });
+                    sl = &sl[n + 1..];
+                    continue;
                 }
-                _ => {}
-            }
-        }
+            };
 
-        match found {
-            // String-ending terminal
-            Found::Nothing => Ok((
-                Tree {
-                    name: &sl[..],
-                    args: vec![],
-                },
-                "",
-            )),
-            // Terminal
-            Found::Comma(n) | Found::Rparen(n) => Ok((
-                Tree {
-                    name: &sl[..n],
-                    args: vec![],
-                },
-                &sl[n..],
-            )),
-            // Function call
-            Found::Lparen(n) => {
-                let mut ret = Tree {
-                    name: &sl[..n],
-                    args: vec![],
+            // Add the finished tree to the innermost open call, closing the
+            // calls it ends
+            loop {
+                let mut parent = match open.pop() {
+                    Some(parent) => parent,
+                    None => return Ok((done, sl)),
                 };
+                parent.args.push(done);
 
-                sl = &sl[n + 1..];
-                loop {
-                    let (arg, new_sl) = Tree::from_slice_helper(sl, depth + 1)?;
-                    ret.args.push(arg);
-
-                    if new_sl.is_empty() {
-                        return Err(Error::ExpectedChar(')'));
+                if sl.is_empty() {
+                    record_error(sl);
+                    return Err(Error::ExpectedChar(')'));
+                }
+                match sl.as_bytes()[0] {
+                    b',' => {
+                        open.push(parent);
+                        sl = &sl[1..];
+                        break;
                     }
-
-                    sl = &new_sl[1..];
-                    match new_sl.as_bytes()[0] {
-                        b',' => {}
-                        b')' => break,
-                        _ => return Err(Error::ExpectedChar(',')),
+                    b')' => {
+                        done = parent;
+                        sl = &sl[1..];
+                    }
+                    _ => {
+                        record_error(&sl[..1]);
+                        return Err(Error::ExpectedChar(','));
                     }
                 }
-                Ok((ret, sl))
             }
         }
     }
@@ -121,8 +245,9 @@ impl<'a> Tree<'a> {
     pub fn from_str(s: &'a str) -> Result<Tree<'a>, Error> {
         // Filter out non-ASCII because we byte-index strings all over the
         // place and Rust gets very upset when you splinch a string.
-        for ch in s.bytes() {
+        for (pos, ch) in s.bytes().enumerate() {
             if ch > 0x7f {
+                record_error(&s[pos..pos + 1]);
                 return Err(Error::Unprintable(ch));
             }
         }
@@ -131,6 +256,7 @@ impl<'a> Tree<'a> {
         if rem.is_empty() {
             Ok(top)
         } else {
+            record_error(rem);
             Err(errstr(rem))
         }
     }
@@ -155,11 +281,12 @@ where
     F: FnOnce(&str) -> Result<T, Err>,
     Err: ToString,
 {
-    if term.args.is_empty() {
+    let res = if term.args.is_empty() {
         convert(term.name).map_err(|e| Error::Unexpected(e.to_string()))
     } else {
         Err(errstr(term.name))
-    }
+    };
+    term.spanned(res)
 }
 
 /// Attempts to parse an expression with exactly one child
@@ -172,7 +299,7 @@ where
         let left = FromTree::from_tree(&term.args[0])?;
         Ok(convert(left))
     } else {
-        Err(errstr(term.name))
+        term.spanned(Err(errstr(term.name)))
     }
 }
 
@@ -188,14 +315,15 @@ where
         let right = FromTree::from_tree(&term.args[1])?;
         Ok(convert(left, right))
     } else {
-        Err(errstr(term.name))
+        term.spanned(Err(errstr(term.name)))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::parse_num;
+    use super::{parse_num, parse_with_span, Span, Tree};
+    use Error;
 
     #[test]
     fn test_parse_num() {
@@ -206,4 +334,59 @@ mod tests {
         assert!(parse_num("+6").is_err());
         assert!(parse_num("-6").is_err());
     }
+
+    #[test]
+    fn tree_spans() {
+        let s = "wsh(and_v(v:pk(A),older(9)))";
+        let tree = Tree::from_str(s).unwrap();
+        assert_eq!(tree.span_in(s), Some(Span { start: 0, end: 28 }));
+        let and_v = &tree.args[0];
+        assert_eq!(and_v.span_in(s), Some(Span { start: 4, end: 27 }));
+        assert_eq!(
+            and_v.args[0].args[0].span_in(s),
+            Some(Span { start: 15, end: 16 })
+        );
+        assert_eq!(and_v.args[1].span_in(s), Some(Span { start: 18, end: 26 }));
+        assert_eq!(tree.span_in(&s.to_owned()), None);
+
+        let span = |s: &str| parse_with_span(s, Tree::from_str).unwrap_err().1;
+        assert_eq!(span("wsh(pk(A)"), Some(Span { start: 9, end: 9 }));
+        assert_eq!(span("pk(A)B"), Some(Span { start: 5, end: 6 }));
+        assert_eq!(
+            span("or_b(pk(A)x,pk(B))"),
+            Some(Span { start: 10, end: 11 })
+        );
+    }
+
+    #[test]
+    fn deep_trees() {
+        fn nested(depth: usize) -> String {
+            let mut s = "f(".repeat(depth);
+            s.push('x');
+            s.push_str(&")".repeat(depth));
+            s
+        }
+
+        let s = nested(401);
+        let mut tree = &Tree::from_str(&s).unwrap();
+        for _ in 0..401 {
+            assert_eq!(tree.name, "f");
+            tree = &tree.args[0];
+        }
+        assert_eq!(tree.name, "x");
+        assert!(tree.args.is_empty());
+
+        let err = parse_with_span(&nested(402), Tree::from_str).unwrap_err();
+        match err.0 {
+            Error::MaxRecursiveDepthExceeded => {}
+            ref e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(
+            err.1,
+            Some(Span {
+                start: 804,
+                end: 804
+            })
+        );
+    }
 }
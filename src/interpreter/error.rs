@@ -12,6 +12,7 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+use bitcoin::hashes::sha256;
 use bitcoin::hashes::{hash160, hex::ToHex};
 use bitcoin::{self, secp256k1};
 use std::{error, fmt};
@@ -45,6 +46,8 @@ pub enum Error {
     Miniscript(::Error),
     /// MultiSig requires 1 extra zero element apart from the `k` signatures
     MissingExtraZeroMultiSig,
+    /// The output spent by an input was not given
+    MissingPrevout,
     /// Script abortion because of incorrect dissatisfaction for multisig.
     /// Any input witness apart from sat(0 sig ...) or nsat(0 0 ..) leads to
     /// this error. This is network standardness assumption and miniscript only
@@ -71,6 +74,9 @@ pub enum Error {
     Secp(secp256k1::Error),
     /// Miniscript requires the entire top level script to be satisfied.
     ScriptSatisfactionError,
+    /// The spending transaction does not match the template committed to by
+    /// a `txtemplate` fragment
+    TxTemplateMismatch(sha256::Hash),
     /// An uncompressed public key was encountered in a context where it is
     /// disallowed (e.g. in a Segwit script or p2wpkh output)
     UncompressedPubkey,
@@ -141,6 +147,7 @@ impl fmt::Display for Error {
             Error::NonEmptyScriptSig => f.write_str("segwit spend had nonempty scriptsig"),
             Error::Miniscript(ref e) => write!(f, "parse error: {}", e),
             Error::MissingExtraZeroMultiSig => f.write_str("CMS missing extra zero"),
+            Error::MissingPrevout => f.write_str("missing output spent by the input"),
             Error::MultiSigEvaluationError => {
                 f.write_str("CMS script aborted, incorrect satisfaction/dissatisfaction")
             }
@@ -152,6 +159,9 @@ impl fmt::Display for Error {
             }
             Error::ScriptSatisfactionError => f.write_str("Top level script must be satisfied"),
             Error::Secp(ref e) => fmt::Display::fmt(e, f),
+            Error::TxTemplateMismatch(ref hash) => {
+                write!(f, "transaction does not match template {}", hash)
+            }
             Error::UncompressedPubkey => {
                 f.write_str("uncompressed pubkey in non-legacy descriptor")
             }
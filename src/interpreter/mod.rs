@@ -19,20 +19,27 @@
 //! assuming that the spent coin was descriptor controlled.
 //!
 
+use bitcoin::consensus::encode::serialize;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::hashes::{Hash, HashEngine};
 use bitcoin::util::bip143;
 use bitcoin::{self, secp256k1};
 use miniscript::context::NoChecks;
+use miniscript::limits::{
+    HEIGHT_TIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
 use miniscript::ScriptContext;
+use std::ptr;
 use Miniscript;
 use Terminal;
-use {BitcoinSig, Descriptor, ToPublicKey};
+use {BitcoinSig, Descriptor, MiniscriptKey, ToPublicKey};
 
 mod error;
 mod inner;
 mod stack;
 
 pub use self::error::Error;
+pub use self::stack::Element;
 use self::stack::Stack;
 
 /// An iterable Miniscript-structured representation of the spending of a coin
@@ -77,7 +84,8 @@ impl<'txin> Interpreter<'txin> {
     /// been used.
     ///
     /// In case the script is actually dissatisfied, this may return several values
-    /// before ultimately returning an error.
+    /// before ultimately returning an error. The position in the script of the
+    /// fragment which raised it is then given by `Iter::error_location`.
     ///
     /// Running the iterator through will consume the internal stack of the
     /// `Iterpreter`, and it should not be used again after this.
@@ -92,6 +100,11 @@ impl<'txin> Interpreter<'txin> {
             } else {
                 None
             },
+            script: if let inner::Inner::Script(ref script, _) = self.inner {
+                Some(script)
+            } else {
+                None
+            },
             state: if let inner::Inner::Script(ref script, _) = self.inner {
                 vec![NodeEvaluationState {
                     node: script,
@@ -105,6 +118,34 @@ impl<'txin> Interpreter<'txin> {
             age: self.age,
             height: self.height,
             has_errored: false,
+            failed: None,
+        }
+    }
+
+    /// Creates an iterator over the satisfied spending conditions, as `iter`
+    /// does, without verifying signatures: they are assumed valid as long as
+    /// they are DER-encoded with a standard sighash type. This checks the
+    /// structure of the spend, its timelocks and its hash preimages without
+    /// any cryptography, so that no secp context is needed.
+    pub fn iter_assume_sigs<'iter>(
+        &'iter mut self,
+    ) -> Iter<'txin, 'iter, fn(&bitcoin::PublicKey, BitcoinSig) -> bool> {
+        self.iter(assume_valid_sig as fn(&bitcoin::PublicKey, BitcoinSig) -> bool)
+    }
+
+    /// Creates an iterator over the steps of the evaluation of the witness,
+    /// which is done as for `iter`, to debug a witness failing to satisfy
+    /// the script: the last step is then followed by the error
+    ///
+    /// Running the iterator through will consume the internal stack of the
+    /// `Iterpreter`, and it should not be used again after this.
+    pub fn trace<'iter, F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool>(
+        &'iter mut self,
+        verify_sig: F,
+    ) -> Trace<'txin, 'iter, F> {
+        Trace {
+            iter: self.iter(verify_sig),
+            done: false,
         }
     }
 
@@ -232,6 +273,110 @@ impl<'txin> Interpreter<'txin> {
     }
 }
 
+/// Verifies the spending of every input of `tx`, `prevouts` being the outputs
+/// spent by its inputs, in order: the scripts and signatures, and that the
+/// locktime of the transaction and the sequences of its inputs meet the
+/// timelocks satisfied, as consensus rules require. Returns a result per
+/// input.
+///
+/// As for `Interpreter::from_txdata`, only the spending of Miniscript
+/// scripts and of single keys can be verified.
+pub fn verify_transaction<C: secp256k1::Verification>(
+    tx: &bitcoin::Transaction,
+    prevouts: &[bitcoin::TxOut],
+    secp: &secp256k1::Secp256k1<C>,
+) -> Vec<Result<(), Error>> {
+    (0..tx.input.len())
+        .map(|index| match prevouts.get(index) {
+            Some(prevout) => verify_input(tx, index, prevout, secp),
+            None => Err(Error::MissingPrevout),
+        })
+        .collect()
+}
+
+/// Helper function to verify the spending of a single input
+fn verify_input<C: secp256k1::Verification>(
+    tx: &bitcoin::Transaction,
+    index: usize,
+    prevout: &bitcoin::TxOut,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<(), Error> {
+    let txin = &tx.input[index];
+    let mut interpreter = Interpreter::from_txdata(
+        &prevout.script_pubkey,
+        &txin.script_sig,
+        &txin.witness,
+        tx.lock_time,
+        txin.sequence,
+    )?;
+    let vfyfn = interpreter.sighash_verify(secp, tx, index, prevout.value);
+    for constraint in interpreter.iter(vfyfn) {
+        match constraint? {
+            // The interpreter only compares the values, while CLTV also
+            // requires the same kind of locktime and a non-final sequence
+            SatisfiedConstraint::AbsoluteTimeLock { time } => {
+                let same_kind =
+                    (*time < HEIGHT_TIME_THRESHOLD) == (tx.lock_time < HEIGHT_TIME_THRESHOLD);
+                if !same_kind || txin.sequence == 0xffffffff {
+                    return Err(Error::AbsoluteLocktimeNotMet(*time));
+                }
+            }
+            // CSV requires a transaction of version 2, an enabled sequence of
+            // the same kind, and only compares the low 16 bits
+            SatisfiedConstraint::RelativeTimeLock { time } => {
+                if *time & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                    continue;
+                }
+                let sequence = txin.sequence;
+                if tx.version < 2
+                    || sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0
+                    || sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != *time & SEQUENCE_LOCKTIME_TYPE_FLAG
+                    || sequence & 0xffff < *time & 0xffff
+                {
+                    return Err(Error::RelativeLocktimeNotMet(*time));
+                }
+            }
+            // The interpreter has no transaction, so the template is checked here
+            SatisfiedConstraint::TxTemplate { hash } => {
+                if template_hash(tx, index) != *hash {
+                    return Err(Error::TxTemplateMismatch(*hash));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Computes the BIP 119 template hash of `tx` spent at input `index`, which a
+/// `txtemplate` fragment must commit to for `tx` to satisfy it
+pub fn template_hash(tx: &bitcoin::Transaction, index: usize) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&serialize(&tx.version));
+    engine.input(&serialize(&tx.lock_time));
+    if tx.input.iter().any(|txin| !txin.script_sig.is_empty()) {
+        let mut script_sigs = sha256::Hash::engine();
+        for txin in &tx.input {
+            script_sigs.input(&serialize(&txin.script_sig));
+        }
+        engine.input(&sha256::Hash::from_engine(script_sigs)[..]);
+    }
+    engine.input(&serialize(&(tx.input.len() as u32)));
+    let mut sequences = sha256::Hash::engine();
+    for txin in &tx.input {
+        sequences.input(&serialize(&txin.sequence));
+    }
+    engine.input(&sha256::Hash::from_engine(sequences)[..]);
+    engine.input(&serialize(&(tx.output.len() as u32)));
+    let mut outputs = sha256::Hash::engine();
+    for txout in &tx.output {
+        outputs.input(&serialize(txout));
+    }
+    engine.input(&sha256::Hash::from_engine(outputs)[..]);
+    engine.input(&serialize(&(index as u32)));
+    sha256::Hash::from_engine(engine)
+}
+
 /// Type of HashLock used for SatisfiedConstraint structure
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum HashLockType<'intp> {
@@ -283,6 +428,11 @@ pub enum SatisfiedConstraint<'intp, 'txin> {
         /// The value of Absolute timelock
         time: &'intp u32,
     },
+    ///Transaction template for CTV.
+    TxTemplate {
+        /// The hash of the template
+        hash: &'intp sha256::Hash,
+    },
 }
 
 ///This is used by the interpreter to know which evaluation state a AstemElem is.
@@ -300,6 +450,21 @@ struct NodeEvaluationState<'intp> {
     n_satisfied: usize,
 }
 
+/// The position in the script of the fragment whose evaluation raised an
+/// error, see `Iter::error_location`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// The indices of the children leading to the fragment from the root,
+    /// see `Miniscript::branches`
+    pub path: Vec<usize>,
+    /// Offset of the first opcode of the fragment in the script, in bytes
+    pub offset: usize,
+    /// The top stack element before the fragment was evaluated, a satisfied
+    /// element being `[1]` and a dissatisfied one empty; None if the stack
+    /// was empty
+    pub stack_top: Option<Vec<u8>>,
+}
+
 /// Iterator over all the constraints satisfied by a completed scriptPubKey
 /// and witness stack
 ///
@@ -310,15 +475,22 @@ struct NodeEvaluationState<'intp> {
 /// been used.
 ///
 /// In case the script is actually dissatisfied, this may return several values
-/// before ultimately returning an error.
+/// before ultimately returning an error, see `Iter::error_location`.
 pub struct Iter<'intp, 'txin: 'intp, F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool> {
     verify_sig: F,
     public_key: Option<&'intp bitcoin::PublicKey>,
+    script: Option<&'intp Miniscript<bitcoin::PublicKey, NoChecks>>,
     state: Vec<NodeEvaluationState<'intp>>,
     stack: &'intp mut Stack<'txin>,
     age: u32,
     height: u32,
     has_errored: bool,
+    /// The fragment which raised the error, if any, with the top stack
+    /// element before it was evaluated
+    failed: Option<(
+        &'intp Miniscript<bitcoin::PublicKey, NoChecks>,
+        Option<stack::Element<'txin>>,
+    )>,
 }
 
 ///Iterator for Iter
@@ -365,362 +537,422 @@ where
     /// Helper function to step the iterator
     fn iter_next(&mut self) -> Option<Result<SatisfiedConstraint<'intp, 'txin>, Error>> {
         while let Some(node_state) = self.state.pop() {
-            //non-empty stack
-            match node_state.node.node {
-                Terminal::True => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.stack.push(stack::Element::Satisfied);
+            let res = self.step(node_state);
+            if res.is_some() {
+                return res;
+            }
+        }
+        self.finish()
+    }
+
+    /// The position in the script of the fragment which raised the error
+    /// returned by the iterator, if it returned one
+    ///
+    /// This is None if no error was returned, or if it was not raised by a
+    /// fragment of the script, such as the error of the signature check of
+    /// a descriptor without script.
+    pub fn error_location(&self) -> Option<ErrorLocation> {
+        let script = self.script?;
+        let (node, stack_top) = self.failed?;
+        // The fragment starts with the opcodes of the first of its
+        // descendants to generate any, as wrappers like `v:` may not generate
+        // opcodes of their own
+        let subtree: Vec<_> = node.iter().map(|ms| &ms.node).collect();
+        let offset = script
+            .annotate()
+            .annotations
+            .iter()
+            .find(|a| subtree.iter().any(|&frag| ptr::eq(frag, a.fragment)))
+            .map_or(0, |a| a.offset);
+        Some(ErrorLocation {
+            path: fragment_path(script, node).unwrap_or_default(),
+            offset: offset,
+            stack_top: stack_top.map(|elem| match elem {
+                stack::Element::Satisfied => vec![1],
+                stack::Element::Dissatisfied => vec![],
+                stack::Element::Push(v) => v.to_vec(),
+            }),
+        })
+    }
+
+    /// Helper function to evaluate a node, returning None if it satisfied no
+    /// constraint. The node is recorded if it raises an error, see
+    /// `Iter::error_location`.
+    fn step(
+        &mut self,
+        node_state: NodeEvaluationState<'intp>,
+    ) -> Option<Result<SatisfiedConstraint<'intp, 'txin>, Error>> {
+        let node = node_state.node;
+        let stack_top = self.stack.last().cloned();
+        let res = self.evaluate_node(node_state);
+        if let Some(Err(_)) = res {
+            self.failed = Some((node, stack_top));
+        }
+        res
+    }
+
+    /// Helper function to evaluate a node, returning None if it satisfied no
+    /// constraint
+    fn evaluate_node(
+        &mut self,
+        node_state: NodeEvaluationState<'intp>,
+    ) -> Option<Result<SatisfiedConstraint<'intp, 'txin>, Error>> {
+        match node_state.node.node {
+            Terminal::True => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.stack.push(stack::Element::Satisfied);
+            }
+            Terminal::False => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::PkK(ref pk) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_pk(&mut self.verify_sig, pk);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::False => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::PkH(ref pkh) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_pkh(&mut self.verify_sig, pkh);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::PkK(ref pk) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_pk(&mut self.verify_sig, pk);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::After(ref n) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_after(n, self.age);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::PkH(ref pkh) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_pkh(&mut self.verify_sig, pkh);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Older(ref n) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_older(n, self.height);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::After(ref n) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_after(n, self.age);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::TxTemplate(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_tx_template(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Older(ref n) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_older(n, self.height);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Sha256(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_sha256(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Sha256(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_sha256(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Hash256(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_hash256(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Hash256(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_hash256(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Hash160(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_hash160(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Hash160(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_hash160(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Ripemd160(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_ripemd160(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Ripemd160(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_ripemd160(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::DupIf(ref sub) if node_state.n_evaluated == 0 => match self.stack.pop() {
+                Some(stack::Element::Dissatisfied) => {
+                    self.stack.push(stack::Element::Dissatisfied);
                 }
-                Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
+                Some(stack::Element::Satisfied) => {
+                    self.push_evaluation_state(node_state.node, 1, 1);
                     self.push_evaluation_state(sub, 0, 0);
                 }
-                Terminal::DupIf(ref sub) if node_state.n_evaluated == 0 => match self.stack.pop() {
+                Some(stack::Element::Push(_v)) => {
+                    return Some(Err(Error::UnexpectedStackElementPush))
+                }
+                None => return Some(Err(Error::UnexpectedStackEnd)),
+            },
+            Terminal::DupIf(ref _sub) if node_state.n_evaluated == 1 => {
+                self.stack.push(stack::Element::Satisfied);
+            }
+            Terminal::ZeroNotEqual(ref sub) | Terminal::Verify(ref sub)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::Verify(ref _sub) if node_state.n_evaluated == 1 => match self.stack.pop() {
+                Some(stack::Element::Satisfied) => (),
+                Some(_) => return Some(Err(Error::VerifyFailed)),
+                None => return Some(Err(Error::UnexpectedStackEnd)),
+            },
+            Terminal::ZeroNotEqual(ref _sub) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
                     Some(stack::Element::Dissatisfied) => {
-                        self.stack.push(stack::Element::Dissatisfied);
+                        self.stack.push(stack::Element::Dissatisfied)
+                    }
+                    Some(_) => self.stack.push(stack::Element::Satisfied),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
+                }
+            }
+            Terminal::NonZero(ref sub) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                match self.stack.last() {
+                    Some(&stack::Element::Dissatisfied) => (),
+                    Some(_) => self.push_evaluation_state(sub, 0, 0),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
+                }
+            }
+            Terminal::AndV(ref left, ref right) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.push_evaluation_state(right, 0, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref left, ref _right) | Terminal::AndB(ref left, ref _right)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref _left, ref right) | Terminal::AndB(ref _left, ref right)
+                if node_state.n_evaluated == 1 =>
+            {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) => {
+                        self.push_evaluation_state(node_state.node, 2, 0);
+                        self.push_evaluation_state(right, 0, 0);
                     }
                     Some(stack::Element::Satisfied) => {
-                        self.push_evaluation_state(node_state.node, 1, 1);
-                        self.push_evaluation_state(sub, 0, 0);
+                        self.push_evaluation_state(node_state.node, 2, 1);
+                        self.push_evaluation_state(right, 0, 0);
                     }
                     Some(stack::Element::Push(_v)) => {
                         return Some(Err(Error::UnexpectedStackElementPush))
                     }
                     None => return Some(Err(Error::UnexpectedStackEnd)),
-                },
-                Terminal::DupIf(ref _sub) if node_state.n_evaluated == 1 => {
-                    self.stack.push(stack::Element::Satisfied);
                 }
-                Terminal::ZeroNotEqual(ref sub) | Terminal::Verify(ref sub)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(sub, 0, 0);
-                }
-                Terminal::Verify(ref _sub) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => (),
-                        Some(_) => return Some(Err(Error::VerifyFailed)),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) if node_state.n_satisfied == 1 => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
+                    Some(_) => self.stack.push(stack::Element::Dissatisfied),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::ZeroNotEqual(ref _sub) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(_) => self.stack.push(stack::Element::Satisfied),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndOr(ref left, ref _right, _)
+            | Terminal::OrC(ref left, ref _right)
+            | Terminal::OrD(ref left, ref _right)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) if node_state.n_satisfied == 0 => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
-                }
-                Terminal::NonZero(ref sub) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    match self.stack.last() {
-                        Some(&stack::Element::Dissatisfied) => (),
-                        Some(_) => self.push_evaluation_state(sub, 0, 0),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(_) => {
+                        self.stack.push(stack::Element::Satisfied);
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndV(ref left, ref right) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.push_evaluation_state(right, 0, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref left, ref _right) | Terminal::AndB(ref left, ref _right)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref _left, ref right) | Terminal::AndB(ref _left, ref right)
-                    if node_state.n_evaluated == 1 =>
-                {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(node_state.node, 2, 0);
-                            self.push_evaluation_state(right, 0, 0);
-                        }
-                        Some(stack::Element::Satisfied) => {
-                            self.push_evaluation_state(node_state.node, 2, 1);
-                            self.push_evaluation_state(right, 0, 0);
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::OrC(ref _left, ref right) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => (),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) if node_state.n_satisfied == 1 => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(_) => self.stack.push(stack::Element::Dissatisfied),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::OrD(ref _left, ref right) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => self.stack.push(stack::Element::Satisfied),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndOr(ref left, ref _right, _)
-                | Terminal::OrC(ref left, ref _right)
-                | Terminal::OrD(ref left, ref _right)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) if node_state.n_satisfied == 0 => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(_) => {
-                            self.stack.push(stack::Element::Satisfied);
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndOr(_, ref left, ref right) | Terminal::OrI(ref left, ref right) => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => self.push_evaluation_state(left, 0, 0),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::OrC(ref _left, ref right) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => (),
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated == 0 => {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(&subs[0], 0, 0);
+            }
+            Terminal::Thresh(k, ref subs) if node_state.n_evaluated == subs.len() => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) if node_state.n_satisfied == k => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
-                }
-                Terminal::OrD(ref _left, ref right) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Satisfied) if node_state.n_satisfied == k - 1 => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
-                }
-                Terminal::AndOr(_, ref left, ref right) | Terminal::OrI(ref left, ref right) => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => self.push_evaluation_state(left, 0, 0),
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Satisfied) | Some(stack::Element::Dissatisfied) => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
-                }
-                Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated == 0 => {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(&subs[0], 0, 0);
-                }
-                Terminal::Thresh(k, ref subs) if node_state.n_evaluated == subs.len() => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) if node_state.n_satisfied == k => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Satisfied) if node_state.n_satisfied == k - 1 => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Satisfied) | Some(stack::Element::Dissatisfied) => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated != 0 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(
-                                node_state.node,
-                                node_state.n_evaluated + 1,
-                                node_state.n_satisfied,
-                            );
-                            self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
-                        }
-                        Some(stack::Element::Satisfied) => {
-                            self.push_evaluation_state(
-                                node_state.node,
-                                node_state.n_evaluated + 1,
-                                node_state.n_satisfied + 1,
-                            );
-                            self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated != 0 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) => {
+                        self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied,
+                        );
+                        self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                    }
+                    Some(stack::Element::Satisfied) => {
+                        self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied + 1,
+                        );
+                        self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                    }
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Multi(ref k, ref subs) if node_state.n_evaluated == 0 => {
-                    let len = self.stack.len();
-                    if len < k + 1 {
-                        return Some(Err(Error::InsufficientSignaturesMultiSig));
-                    } else {
-                        //Non-sat case. If the first sig is empty, others k elements must
-                        //be empty.
-                        match self.stack.last() {
-                            Some(&stack::Element::Dissatisfied) => {
-                                //Remove the extra zero from multi-sig check
-                                let sigs = self.stack.split_off(len - (k + 1));
-                                let nonsat = sigs
-                                    .iter()
-                                    .map(|sig| *sig == stack::Element::Dissatisfied)
-                                    .filter(|empty| *empty)
-                                    .count();
-                                if nonsat == *k + 1 {
-                                    self.stack.push(stack::Element::Dissatisfied);
-                                } else {
-                                    return Some(Err(Error::MissingExtraZeroMultiSig));
-                                }
+            }
+            Terminal::Multi(ref k, ref subs) if node_state.n_evaluated == 0 => {
+                let len = self.stack.len();
+                if len < k + 1 {
+                    return Some(Err(Error::InsufficientSignaturesMultiSig));
+                } else {
+                    //Non-sat case. If the first sig is empty, others k elements must
+                    //be empty.
+                    match self.stack.last() {
+                        Some(&stack::Element::Dissatisfied) => {
+                            //Remove the extra zero from multi-sig check
+                            let sigs = self.stack.split_off(len - (k + 1));
+                            let nonsat = sigs
+                                .iter()
+                                .map(|sig| *sig == stack::Element::Dissatisfied)
+                                .filter(|empty| *empty)
+                                .count();
+                            if nonsat == *k + 1 {
+                                self.stack.push(stack::Element::Dissatisfied);
+                            } else {
+                                return Some(Err(Error::MissingExtraZeroMultiSig));
                             }
-                            None => return Some(Err(Error::UnexpectedStackEnd)),
-                            _ => {
-                                match self
-                                    .stack
-                                    .evaluate_multi(&mut self.verify_sig, &subs[subs.len() - 1])
-                                {
-                                    Some(Ok(x)) => {
-                                        self.push_evaluation_state(
-                                            node_state.node,
-                                            node_state.n_evaluated + 1,
-                                            node_state.n_satisfied + 1,
-                                        );
-                                        return Some(Ok(x));
-                                    }
-                                    None => self.push_evaluation_state(
+                        }
+                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                        _ => {
+                            match self
+                                .stack
+                                .evaluate_multi(&mut self.verify_sig, &subs[subs.len() - 1])
+                            {
+                                Some(Ok(x)) => {
+                                    self.push_evaluation_state(
                                         node_state.node,
                                         node_state.n_evaluated + 1,
-                                        node_state.n_satisfied,
-                                    ),
-                                    x => return x, //forward errors as is
+                                        node_state.n_satisfied + 1,
+                                    );
+                                    return Some(Ok(x));
                                 }
+                                None => self.push_evaluation_state(
+                                    node_state.node,
+                                    node_state.n_evaluated + 1,
+                                    node_state.n_satisfied,
+                                ),
+                                x => return x, //forward errors as is
                             }
                         }
                     }
                 }
-                Terminal::Multi(k, ref subs) => {
-                    if node_state.n_satisfied == k {
-                        //multi-sig bug: Pop extra 0
-                        if let Some(stack::Element::Dissatisfied) = self.stack.pop() {
-                            self.stack.push(stack::Element::Satisfied);
-                        } else {
-                            return Some(Err(Error::MissingExtraZeroMultiSig));
-                        }
-                    } else if node_state.n_evaluated == subs.len() {
-                        return Some(Err(Error::MultiSigEvaluationError));
+            }
+            Terminal::Multi(k, ref subs) => {
+                if node_state.n_satisfied == k {
+                    //multi-sig bug: Pop extra 0
+                    if let Some(stack::Element::Dissatisfied) = self.stack.pop() {
+                        self.stack.push(stack::Element::Satisfied);
                     } else {
-                        match self.stack.evaluate_multi(
-                            &mut self.verify_sig,
-                            &subs[subs.len() - node_state.n_evaluated - 1],
-                        ) {
-                            Some(Ok(x)) => {
-                                self.push_evaluation_state(
-                                    node_state.node,
-                                    node_state.n_evaluated + 1,
-                                    node_state.n_satisfied + 1,
-                                );
-                                return Some(Ok(x));
-                            }
-                            None => self.push_evaluation_state(
+                        return Some(Err(Error::MissingExtraZeroMultiSig));
+                    }
+                } else if node_state.n_evaluated == subs.len() {
+                    return Some(Err(Error::MultiSigEvaluationError));
+                } else {
+                    match self.stack.evaluate_multi(
+                        &mut self.verify_sig,
+                        &subs[subs.len() - node_state.n_evaluated - 1],
+                    ) {
+                        Some(Ok(x)) => {
+                            self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied,
-                            ),
-                            x => return x, //forward errors as is
+                                node_state.n_satisfied + 1,
+                            );
+                            return Some(Ok(x));
                         }
+                        None => self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied,
+                        ),
+                        x => return x, //forward errors as is
                     }
                 }
-                //All other match patterns should not be reached in any valid
-                //type checked Miniscript
-                _ => return Some(Err(Error::CouldNotEvaluate)),
-            };
-        }
+            }
+            //All other match patterns should not be reached in any valid
+            //type checked Miniscript
+            _ => return Some(Err(Error::CouldNotEvaluate)),
+        };
+        None
+    }
 
-        //state empty implies that either the execution has terminated or we have a
-        //Pk based descriptor
+    /// Helper function to end the evaluation once the state is empty, which
+    /// implies that either the execution has terminated or we have a Pk based
+    /// descriptor
+    fn finish(&mut self) -> Option<Result<SatisfiedConstraint<'intp, 'txin>, Error>> {
         if let Some(pk) = self.public_key {
             if let Some(stack::Element::Push(sig)) = self.stack.pop() {
                 if let Ok(sig) = verify_sersig(&mut self.verify_sig, &pk, &sig) {
@@ -747,6 +979,126 @@ where
     }
 }
 
+/// A step of the evaluation of a witness by the interpreter, see
+/// `Interpreter::trace`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEvent<'intp, 'txin> {
+    /// The fragment evaluated, or None for the signature check of a
+    /// descriptor without script, such as `pkh`
+    pub fragment: Option<&'intp Miniscript<bitcoin::PublicKey, NoChecks>>,
+    /// Number of children of the fragment evaluated before the step. A
+    /// fragment with children is evaluated in several steps: before its
+    /// children, between them and after them.
+    pub n_evaluated: usize,
+    /// The stack before the step, its top last
+    pub stack_before: Vec<Element<'txin>>,
+    /// The stack after the step, its top last
+    pub stack_after: Vec<Element<'txin>>,
+    /// The constraint satisfied by the step, if any
+    pub constraint: Option<SatisfiedConstraint<'intp, 'txin>>,
+}
+
+/// Iterator over the steps of the evaluation of a witness, see
+/// `Interpreter::trace`
+pub struct Trace<'intp, 'txin: 'intp, F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool> {
+    iter: Iter<'intp, 'txin, F>,
+    done: bool,
+}
+
+impl<'intp, 'txin: 'intp, F> Trace<'intp, 'txin, F>
+where
+    NoChecks: ScriptContext,
+    F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+{
+    /// The position in the script of the fragment which raised the error
+    /// ending the trace, see `Iter::error_location`
+    pub fn error_location(&self) -> Option<ErrorLocation> {
+        self.iter.error_location()
+    }
+}
+
+impl<'intp, 'txin: 'intp, F> Iterator for Trace<'intp, 'txin, F>
+where
+    NoChecks: ScriptContext,
+    F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+{
+    type Item = Result<TraceEvent<'intp, 'txin>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let stack_before = self.iter.stack.elements().to_vec();
+        let (fragment, n_evaluated, res) = match self.iter.state.pop() {
+            Some(node_state) => {
+                let (node, n_evaluated) = (node_state.node, node_state.n_evaluated);
+                (Some(node), n_evaluated, self.iter.step(node_state))
+            }
+            None => {
+                // Once the script is executed, the signature of a descriptor
+                // without script is checked, or the evaluation ends
+                let res = self.iter.finish();
+                if res.is_none() {
+                    self.done = true;
+                    return None;
+                }
+                (None, 0, res)
+            }
+        };
+        let constraint = match res {
+            Some(Err(e)) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+            Some(Ok(constraint)) => Some(constraint),
+            None => None,
+        };
+        Some(Ok(TraceEvent {
+            fragment: fragment,
+            n_evaluated: n_evaluated,
+            stack_before: stack_before,
+            stack_after: self.iter.stack.elements().to_vec(),
+            constraint: constraint,
+        }))
+    }
+}
+
+/// Helper function giving the indices of the children leading from `root` to
+/// `node`, if it is one of its descendants
+fn fragment_path<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    root: &Miniscript<Pk, Ctx>,
+    node: &Miniscript<Pk, Ctx>,
+) -> Option<Vec<usize>> {
+    if ptr::eq(root, node) {
+        return Some(vec![]);
+    }
+    // Depth-first search, with the children of each fragment on the way to
+    // the current one and the index of the next child to visit
+    let mut stack = vec![(root.branches(), 0)];
+    let mut path = vec![];
+    while let Some(top) = stack.len().checked_sub(1) {
+        let i = stack[top].1;
+        if i == stack[top].0.len() {
+            stack.pop();
+            path.pop();
+            continue;
+        }
+        stack[top].1 += 1;
+        let child = stack[top].0[i];
+        path.push(i);
+        if ptr::eq(child, node) {
+            return Some(path);
+        }
+        stack.push((child.branches(), 0));
+    }
+    None
+}
+
+/// Signature check of `Interpreter::iter_assume_sigs`
+fn assume_valid_sig(_: &bitcoin::PublicKey, _: BitcoinSig) -> bool {
+    true
+}
+
 /// Helper function to verify serialized signature
 fn verify_sersig<'txin, F>(
     verify_sig: F,
@@ -838,6 +1190,7 @@ mod tests {
                 verify_sig: verify_fn,
                 stack: stack,
                 public_key: None,
+                script: Some(ms),
                 state: vec![NodeEvaluationState {
                     node: ms,
                     n_evaluated: 0,
@@ -846,6 +1199,7 @@ mod tests {
                 age: 1002,
                 height: 1002,
                 has_errored: false,
+                failed: None,
             }
         };
 
@@ -1246,4 +1600,177 @@ mod tests {
         let multi_error: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert!(multi_error.is_err());
     }
+
+    #[test]
+    fn trace() {
+        let (pks, der_sigs, secp_sigs, sighash, secp) = setup_keys_sigs(2);
+        let mut vfyfn =
+            |pk: &bitcoin::PublicKey, (sig, _)| secp.verify(&sighash, &sig, &pk.key).is_ok();
+
+        let ms: Miniscript<bitcoin::PublicKey, NoChecks> =
+            ms_str!("and_v(v:pk({}),pk({}))", pks[0], pks[1]);
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        let witness = vec![der_sigs[1].clone(), der_sigs[0].clone(), script.to_bytes()];
+        let mut interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let events: Result<Vec<TraceEvent>, Error> = interpreter.trace(&mut vfyfn).collect();
+        let events = events.unwrap();
+
+        // and_v, v:, c:, pk_k, v: again once its child is evaluated, c:, pk_k
+        assert_eq!(events.len(), 7);
+        let constraints: Vec<_> = events.iter().filter_map(|e| e.constraint).collect();
+        assert_eq!(
+            constraints,
+            vec![
+                SatisfiedConstraint::PublicKey {
+                    key: &pks[0],
+                    sig: secp_sigs[0].clone(),
+                },
+                SatisfiedConstraint::PublicKey {
+                    key: &pks[1],
+                    sig: secp_sigs[1].clone(),
+                },
+            ]
+        );
+        assert_eq!(
+            events[3].stack_before,
+            vec![Element::Push(&der_sigs[1]), Element::Push(&der_sigs[0]),]
+        );
+        assert_eq!(
+            events[3].stack_after,
+            vec![Element::Push(&der_sigs[1]), Element::Satisfied]
+        );
+        assert_eq!(events[4].n_evaluated, 1);
+        assert_eq!(events[4].stack_after, vec![Element::Push(&der_sigs[1])]);
+        assert_eq!(events[6].stack_after, vec![Element::Satisfied]);
+
+        // A missing signature ends the trace with an error
+        let witness = vec![der_sigs[0].clone(), script.to_bytes()];
+        let mut interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let mut trace = interpreter.trace(&mut vfyfn);
+        let events: Vec<_> = trace.by_ref().collect();
+        assert_eq!(events.len(), 7);
+        assert!(events[..6].iter().all(|e| e.is_ok()));
+        // The error is located at the pk_k of the second key, which starts
+        // after the 34-byte push of the first key and its CHECKSIGVERIFY
+        match events[6] {
+            Err(Error::UnexpectedStackEnd) => {}
+            ref e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(
+            trace.error_location(),
+            Some(ErrorLocation {
+                path: vec![1, 0],
+                offset: 35,
+                stack_top: None,
+            })
+        );
+    }
+
+    #[test]
+    fn verify_transaction() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).expect("secret key");
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let ms: Miniscript<bitcoin::PublicKey, NoChecks> = ms_str!("and_v(v:pk({}),older(10))", pk);
+        let script = ms.encode();
+        let prevout = bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: script.to_v0_p2wsh(),
+        };
+
+        // Signs the only input of a transaction with the given version and
+        // sequence
+        let signed = |version: i32, sequence: u32| {
+            let mut tx = bitcoin::Transaction {
+                version: version,
+                lock_time: 0,
+                input: vec![bitcoin::TxIn {
+                    previous_output: Default::default(),
+                    script_sig: bitcoin::Script::new(),
+                    sequence: sequence,
+                    witness: vec![],
+                }],
+                output: vec![],
+            };
+            let sighash = bip143::SigHashCache::new(&tx).signature_hash(
+                0,
+                &script,
+                prevout.value,
+                bitcoin::SigHashType::All,
+            );
+            let msg = secp256k1::Message::from_slice(&sighash[..]).expect("32 bytes");
+            let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+            sig.push(0x01);
+            tx.input[0].witness = vec![sig, script.to_bytes()];
+            tx
+        };
+
+        let tx = signed(2, 10);
+        let results = super::verify_transaction(&tx, &[prevout.clone()], &secp);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_ok());
+
+        match super::verify_transaction(&tx, &[], &secp)[0] {
+            Err(Error::MissingPrevout) => {}
+            ref e => panic!("unexpected {:?}", e),
+        }
+
+        // The interpreter alone accepts these, CSV does not
+        for &(version, sequence) in &[(1, 10), (2, 10 | SEQUENCE_LOCKTIME_TYPE_FLAG)] {
+            let tx = signed(version, sequence);
+            match super::verify_transaction(&tx, &[prevout.clone()], &secp)[0] {
+                Err(Error::RelativeLocktimeNotMet(10)) => {}
+                ref e => panic!("unexpected {:?}", e),
+            }
+        }
+
+        // A signature over another transaction
+        let mut tx = signed(2, 10);
+        tx.lock_time = 1;
+        match super::verify_transaction(&tx, &[prevout.clone()], &secp)[0] {
+            Err(Error::InvalidSignature(..)) => {}
+            ref e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn iter_assume_sigs() {
+        let (pks, der_sigs, secp_sigs, _, _) = setup_keys_sigs(2);
+        let ms: Miniscript<bitcoin::PublicKey, NoChecks> =
+            ms_str!("and_v(v:pk({}),pk({}))", pks[0], pks[1]);
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+
+        // The signatures are not over the sighash of any transaction
+        let witness = vec![der_sigs[1].clone(), der_sigs[0].clone(), script.to_bytes()];
+        let mut interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let constraints: Result<Vec<SatisfiedConstraint>, Error> =
+            interpreter.iter_assume_sigs().collect();
+        assert_eq!(
+            constraints.unwrap(),
+            vec![
+                SatisfiedConstraint::PublicKey {
+                    key: &pks[0],
+                    sig: secp_sigs[0].clone(),
+                },
+                SatisfiedConstraint::PublicKey {
+                    key: &pks[1],
+                    sig: secp_sigs[1].clone(),
+                },
+            ]
+        );
+
+        // Signatures must still be well-formed
+        let witness = vec![vec![0xab; 72], der_sigs[0].clone(), script.to_bytes()];
+        let mut interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let constraints: Result<Vec<SatisfiedConstraint>, Error> =
+            interpreter.iter_assume_sigs().collect();
+        assert!(constraints.is_err());
+    }
 }
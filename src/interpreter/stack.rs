@@ -115,6 +115,11 @@ impl<'txin> Stack<'txin> {
         self.0.split_off(k)
     }
 
+    /// The elements of the stack, its top last
+    pub fn elements(&self) -> &[Element<'txin>] {
+        &self.0
+    }
+
     /// Returns a reference to the top stack element, if the stack is nonempty
     pub fn last(&self) -> Option<&Element<'txin>> {
         self.0.last()
@@ -255,6 +260,17 @@ impl<'txin> Stack<'txin> {
         }
     }
 
+    /// Helper function to evaluate a TxTemplate Node. Takes no argument from
+    /// stack: `h CHECKTEMPLATEVERIFY` only constrains the spending
+    /// transaction, which the caller checks against the returned constraint
+    pub fn evaluate_tx_template<'intp>(
+        &mut self,
+        hash: &'intp sha256::Hash,
+    ) -> Option<Result<SatisfiedConstraint<'intp, 'txin>, Error>> {
+        self.push(Element::Satisfied);
+        Some(Ok(SatisfiedConstraint::TxTemplate { hash: hash }))
+    }
+
     /// Helper function to evaluate a Sha256 Node.
     /// `SIZE 32 EQUALVERIFY SHA256 h EQUAL`
     pub fn evaluate_sha256<'intp>(
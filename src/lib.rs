@@ -99,7 +99,15 @@
 #![deny(unused_imports)]
 #![deny(missing_docs)]
 
+#[cfg(feature = "arbitrary")]
+pub extern crate arbitrary;
+#[cfg(feature = "bip39")]
+extern crate bip39;
 pub extern crate bitcoin;
+#[cfg(feature = "proptest")]
+pub extern crate proptest;
+#[cfg(feature = "rayon")]
+extern crate rayon;
 #[cfg(feature = "serde")]
 pub extern crate serde;
 #[cfg(all(test, feature = "unstable"))]
@@ -108,6 +116,7 @@ extern crate test;
 #[macro_use]
 mod macros;
 
+pub mod bip322;
 pub mod descriptor;
 pub mod expression;
 pub mod interpreter;
@@ -117,6 +126,9 @@ pub mod psbt;
 
 mod util;
 
+use std::cell::Cell;
+#[cfg(feature = "rayon")]
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::{error, fmt, hash, str};
 
@@ -125,8 +137,10 @@ use bitcoin::hashes::{hash160, sha256, Hash};
 
 pub use descriptor::{Descriptor, DescriptorPublicKey, DescriptorTrait};
 pub use interpreter::Interpreter;
-pub use miniscript::context::{BareCtx, Legacy, ScriptContext, Segwitv0};
+pub use miniscript::context::{BareCtx, ContextLimits, CustomCtx, Legacy, ScriptContext, Segwitv0};
 pub use miniscript::decode::Terminal;
+pub use miniscript::ext::Extension;
+pub use miniscript::iter::PkPkh;
 pub use miniscript::satisfy::{BitcoinSig, Preimage32, Satisfier};
 pub use miniscript::Miniscript;
 
@@ -310,6 +324,79 @@ pub trait TranslatePk<P: MiniscriptKey, Q: MiniscriptKey> {
         self.translate_pk::<_, _, ()>(|pk| Ok(translatefpk(pk)), |pkh| Ok(translatefpkh(pkh)))
             .expect("infallible translation function")
     }
+
+    /// Calls `translate_pk`, giving the key or hash on which the translation
+    /// failed and its path, see `ForEachKey::key_path`, along with the error
+    fn translate_pk_ctx<Fpk, Fpkh, E>(
+        &self,
+        mut translatefpk: Fpk,
+        mut translatefpkh: Fpkh,
+    ) -> Result<Self::Output, TranslateError<P, E>>
+    where
+        Self: ForEachKey<P>,
+        Fpk: FnMut(&P) -> Result<Q, E>,
+        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+    {
+        // Keys are translated in the order in which `for_each_key` visits
+        // them, count them to find the path of the failing one
+        let index = Cell::new(0);
+        let next_index = || {
+            let i = index.get();
+            index.set(i + 1);
+            i
+        };
+        self.translate_pk(
+            |pk| {
+                let i = next_index();
+                translatefpk(pk).map_err(|e| TranslateError {
+                    key: PkPkh::PlainPubkey(pk.clone()),
+                    path: self.key_path(i),
+                    error: e,
+                })
+            },
+            |hash| {
+                let i = next_index();
+                translatefpkh(hash).map_err(|e| TranslateError {
+                    key: PkPkh::HashedPubkey(hash.clone()),
+                    path: self.key_path(i),
+                    error: e,
+                })
+            },
+        )
+    }
+}
+
+/// Error of a translation function, with the key or hash it failed on, see
+/// [TranslatePk::translate_pk_ctx]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TranslateError<Pk: MiniscriptKey, E> {
+    /// The key or hash which could not be translated
+    pub key: PkPkh<Pk>,
+    /// The path to the fragment holding the key from the root of its
+    /// Miniscript, see `ForEachKey::key_path`; None for the keys which are
+    /// not held in a Miniscript
+    pub path: Option<Vec<usize>>,
+    /// The error of the translation function
+    pub error: E,
+}
+
+impl<Pk: MiniscriptKey, E: fmt::Display> fmt::Display for TranslateError<Pk, E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key {
+            PkPkh::PlainPubkey(ref pk) => write!(f, "key {}", pk)?,
+            PkPkh::HashedPubkey(ref hash) => write!(f, "key hash {}", hash)?,
+        }
+        if let Some(ref path) = self.path {
+            write!(f, " at path {:?}", path)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl<Pk: MiniscriptKey, E: error::Error> error::Error for TranslateError<Pk, E> {
+    fn cause(&self) -> Option<&error::Error> {
+        Some(&self.error)
+    }
 }
 
 /// Variant of `TranslatePk` where P and Q both have the same hash
@@ -402,6 +489,89 @@ impl<
 {
 }
 
+/// Variant of `TranslatePk` where the keys and hashes are translated in
+/// parallel, with rayon, before the struct is rebuilt with them
+#[cfg(feature = "rayon")]
+pub trait TranslatePkPar<P, Q>: TranslatePk<P, Q> + ForEachKey<P>
+where
+    P: MiniscriptKey + Sync,
+    P::Hash: Sync,
+    Q: MiniscriptKey + Send,
+    Q::Hash: Send,
+{
+    /// Translate a struct from one generic to another where the
+    /// translation for Pk is provided by translatefpk, and translation for
+    /// PkH is provided by translatefpkh. Each distinct key and hash is
+    /// translated once, in no particular order.
+    ///
+    /// The result is the same as with `translate_pk`: on failure, the error
+    /// is that of the first key or hash `translate_pk` would have failed on,
+    /// although the keys and hashes after it are translated too.
+    fn par_translate_pk<Fpk, Fpkh, E>(
+        &self,
+        translatefpk: Fpk,
+        translatefpkh: Fpkh,
+    ) -> Result<<Self as TranslatePk<P, Q>>::Output, E>
+    where
+        Fpk: Fn(&P) -> Result<Q, E> + Sync,
+        Fpkh: Fn(&P::Hash) -> Result<Q::Hash, E> + Sync,
+        E: Send,
+    {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        let mut keys = HashSet::new();
+        let mut hashes = HashSet::new();
+        self.for_each_key(|key| {
+            match key {
+                ForEach::Key(pk) => keys.insert(pk),
+                ForEach::Hash(hash) => hashes.insert(hash),
+            };
+            true
+        });
+        let mut keys: HashMap<_, _> = keys
+            .into_par_iter()
+            .map(|pk| (pk, translatefpk(pk)))
+            .collect();
+        let mut hashes: HashMap<_, _> = hashes
+            .into_par_iter()
+            .map(|hash| (hash, translatefpkh(hash)))
+            .collect();
+
+        // Errors are moved out of the maps, as translate_pk stops on the
+        // first one
+        self.translate_pk(
+            |pk| {
+                if let Some(&Ok(ref q)) = keys.get(pk) {
+                    return Ok(q.clone());
+                }
+                match keys.remove(pk) {
+                    Some(Err(e)) => Err(e),
+                    _ => translatefpk(pk),
+                }
+            },
+            |hash| {
+                if let Some(&Ok(ref q)) = hashes.get(hash) {
+                    return Ok(q.clone());
+                }
+                match hashes.remove(hash) {
+                    Some(Err(e)) => Err(e),
+                    _ => translatefpkh(hash),
+                }
+            },
+        )
+    }
+}
+#[cfg(feature = "rayon")]
+impl<P, Q, T> TranslatePkPar<P, Q> for T
+where
+    P: MiniscriptKey + Sync,
+    P::Hash: Sync,
+    Q: MiniscriptKey + Send,
+    Q::Hash: Send,
+    T: TranslatePk<P, Q> + ForEachKey<P>,
+{
+}
+
 /// Either a key or a keyhash
 pub enum ForEach<'a, Pk: MiniscriptKey + 'a> {
     /// A key
@@ -438,6 +608,17 @@ pub trait ForEachKey<Pk: MiniscriptKey> {
     {
         !self.for_each_key(|key| !pred(key))
     }
+
+    /// The path to the fragment holding the `index`th key or key hash
+    /// visited by `for_each_key`: the indices of the children leading to it
+    /// from the root of the Miniscript holding it, see `Miniscript::branches`
+    ///
+    /// Returns None if there are not that many keys, or if the key is not
+    /// held in a Miniscript, such as the keys of `wpkh` and `sortedmulti`
+    /// descriptors. The default implementation always returns None.
+    fn key_path(&self, _index: usize) -> Option<Vec<usize>> {
+        None
+    }
 }
 
 /// Miniscript
@@ -521,6 +702,22 @@ pub enum Error {
     ImpossibleSatisfaction,
     /// Bare descriptors don't have any addresses
     BareDescriptorAddr,
+    /// addr() and raw() descriptors can't be satisfied or lifted
+    WatchOnlyDescriptor,
+    /// The address of an addr() descriptor is for another network than the
+    /// requested one: (address network, requested network)
+    AddrNetworkMismatch(bitcoin::Network, bitcoin::Network),
+    /// The script of a raw() descriptor is not of a standard output type with
+    /// an address
+    RawDescriptorAddr,
+    /// Interpreting a satisfaction of a descriptor failed
+    InterpreterError(Box<interpreter::Error>),
+    /// A satisfaction requires an absolute timelock in blocks and another in
+    /// time, which no nLockTime meets
+    MixedLockTimeUnits(u32, u32),
+    /// A satisfaction requires a relative timelock in blocks and another in
+    /// time, which no nSequence meets
+    MixedSequenceUnits(u32, u32),
 }
 
 #[doc(hidden)]
@@ -570,6 +767,7 @@ impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::BadPubkey(ref e) => Some(e),
+            Error::InterpreterError(ref e) => Some(&**e),
             _ => None,
         }
     }
@@ -642,6 +840,26 @@ impl fmt::Display for Error {
             Error::AnalysisError(ref e) => e.fmt(f),
             Error::ImpossibleSatisfaction => write!(f, "Impossible to satisfy Miniscript"),
             Error::BareDescriptorAddr => write!(f, "Bare descriptors don't have address"),
+            Error::WatchOnlyDescriptor => {
+                f.write_str("addr() and raw() descriptors are only for watching outputs")
+            }
+            Error::AddrNetworkMismatch(addr_network, network) => write!(
+                f,
+                "addr() descriptor for {} used on {}",
+                addr_network, network
+            ),
+            Error::RawDescriptorAddr => f.write_str("raw() script has no address form"),
+            Error::InterpreterError(ref e) => fmt::Display::fmt(e, f),
+            Error::MixedLockTimeUnits(a, b) => write!(
+                f,
+                "absolute timelocks {} and {} are of different units",
+                a, b
+            ),
+            Error::MixedSequenceUnits(a, b) => write!(
+                f,
+                "relative timelocks {} and {} are of different units",
+                a, b
+            ),
         }
     }
 }
@@ -19,6 +19,8 @@
 
 use error;
 use miniscript::iter::PkPkh;
+use miniscript::types::extra_props::TimeLockInfo;
+use std::cmp;
 use std::collections::HashSet;
 use std::fmt;
 use {Miniscript, MiniscriptKey, ScriptContext};
@@ -67,6 +69,90 @@ impl fmt::Display for AnalysisError {
 
 impl error::Error for AnalysisError {}
 
+/// A resource limit that Miniscripts are subject to, under some script context
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum ResourceLimit {
+    /// Number of non-push opcodes executed by a satisfaction
+    OpCount,
+    /// Size of the script, in bytes
+    ScriptSize,
+    /// Number of witness elements of a satisfaction, including the script
+    WitnessElements,
+    /// Size of a satisfying scriptSig, in bytes
+    SatisfactionSize,
+}
+
+impl fmt::Display for ResourceLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResourceLimit::OpCount => f.write_str("op count"),
+            ResourceLimit::ScriptSize => f.write_str("script size"),
+            ResourceLimit::WitnessElements => f.write_str("witness elements"),
+            ResourceLimit::SatisfactionSize => f.write_str("satisfaction size"),
+        }
+    }
+}
+
+/// How much of a resource limit a Miniscript uses in the worst case
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LimitUsage {
+    /// The limit
+    pub limit: ResourceLimit,
+    /// The amount used
+    pub used: usize,
+    /// The maximum allowed
+    pub max: usize,
+}
+
+impl LimitUsage {
+    /// Whether the limit is exceeded
+    pub fn is_exceeded(&self) -> bool {
+        self.used > self.max
+    }
+}
+
+/// Report of the resource usage and safety properties of a Miniscript, as
+/// returned by [Miniscript::analyze]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Analysis {
+    /// Maximum number of non-push opcodes executed by a satisfaction, `None`
+    /// if the Miniscript cannot be satisfied
+    pub ops_count: Option<usize>,
+    /// Maximum number of witness elements of a satisfaction, including the
+    /// script itself, `None` if the Miniscript cannot be satisfied
+    pub max_witness_elements: Option<usize>,
+    /// Maximum size of a satisfaction, `None` if the Miniscript cannot be
+    /// satisfied
+    pub max_satisfaction_size: Option<usize>,
+    /// Size of the script, in bytes
+    pub script_size: usize,
+    /// Number of public keys and public key hashes
+    pub pk_count: usize,
+    /// The kinds of timelocks used
+    pub timelocks: TimeLockInfo,
+    /// Whether all spend paths require a signature
+    pub requires_sig: bool,
+    /// Whether the Miniscript is non-malleable
+    pub non_malleable: bool,
+    /// Whether some public key or public key hash is repeated
+    pub repeated_keys: bool,
+    /// Usage of the resource limits of the script context, the one closest
+    /// to being exceeded first
+    pub limits: Vec<LimitUsage>,
+}
+
+impl Analysis {
+    /// The resource limit closest to being exceeded, if any applies
+    pub fn closest_limit(&self) -> Option<&LimitUsage> {
+        self.limits.first()
+    }
+
+    /// The resource limits which are exceeded
+    pub fn exceeded_limits(&self) -> Vec<&LimitUsage> {
+        self.limits.iter().filter(|l| l.is_exceeded()).collect()
+    }
+}
+
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     /// Whether all spend paths of miniscript require a signature
     pub fn requires_sig(&self) -> bool {
@@ -111,6 +197,55 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         unique_pkhs_len != all_pkhs_len
     }
 
+    /// Analyze the Miniscript at once, reporting its resource usage, how
+    /// close it gets to the limits of its script context, and its safety
+    /// properties
+    pub fn analyze(&self) -> Analysis {
+        let ops_count = self.ext.ops_count_sat;
+        let max_witness_elements = self.max_satisfaction_witness_elements().ok();
+        let max_satisfaction_size = self.max_satisfaction_size().ok();
+        let script_size = self.script_size();
+
+        let mut limits: Vec<LimitUsage> = Ctx::resource_limits()
+            .into_iter()
+            .filter_map(|(limit, max)| {
+                let used = match limit {
+                    ResourceLimit::OpCount => ops_count,
+                    ResourceLimit::ScriptSize => Some(script_size),
+                    ResourceLimit::WitnessElements => max_witness_elements,
+                    ResourceLimit::SatisfactionSize => max_satisfaction_size,
+                };
+                used.map(|used| LimitUsage {
+                    limit: limit,
+                    used: used,
+                    max: max,
+                })
+            })
+            .collect();
+        // Highest ratio of used to maximum first
+        limits.sort_by(|a, b| {
+            let ratio = (b.used * a.max).cmp(&(a.used * b.max));
+            if ratio == cmp::Ordering::Equal {
+                a.limit.cmp(&b.limit)
+            } else {
+                ratio
+            }
+        });
+
+        Analysis {
+            ops_count: ops_count,
+            max_witness_elements: max_witness_elements,
+            max_satisfaction_size: max_satisfaction_size,
+            script_size: script_size,
+            pk_count: self.iter_pk_pkh().count(),
+            timelocks: self.ext.timelock_info,
+            requires_sig: self.requires_sig(),
+            non_malleable: self.is_non_malleable(),
+            repeated_keys: self.has_repeated_keys(),
+            limits: limits,
+        }
+    }
+
     /// Check whether the underlying Miniscript is safe under the current context
     /// Lifting these polices would create a semantic representation that does
     /// not represent the underlying semantics when miniscript is spent.
@@ -0,0 +1,205 @@
+// Miniscript
+// Written in 2021 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Annotated Disassembly
+//!
+//! Listing of the script of a Miniscript with its opcodes grouped and
+//! labelled by the fragment which generated them. To map a script seen on
+//! chain back to its fragments, parse it first with
+//! [Miniscript::parse_insane].
+//!
+
+use std::{fmt, ptr};
+
+use bitcoin::blockdata::script;
+use bitcoin::hashes::hex::ToHex;
+
+use {Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
+
+/// A run of opcodes generated by a single fragment
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Annotation<'a, Pk: MiniscriptKey + 'a, Ctx: ScriptContext + 'a> {
+    /// The fragment which generated the opcodes
+    pub fragment: &'a Terminal<Pk, Ctx>,
+    /// Depth of the fragment below the root of the Miniscript
+    pub depth: usize,
+    /// Offset of the opcodes in the script, in bytes
+    pub offset: usize,
+    /// The opcodes
+    pub script: script::Script,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Annotation<'a, Pk, Ctx> {
+    /// Name of the fragment which generated the opcodes, with wrappers
+    /// written as `x:`
+    pub fn fragment_name(&self) -> &'static str {
+        match *self.fragment {
+            Terminal::True => "1",
+            Terminal::False => "0",
+            Terminal::PkK(..) => "pk_k",
+            Terminal::PkH(..) => "pk_h",
+            Terminal::After(..) => "after",
+            Terminal::Older(..) => "older",
+            Terminal::Sha256(..) => "sha256",
+            Terminal::Hash256(..) => "hash256",
+            Terminal::Ripemd160(..) => "ripemd160",
+            Terminal::Hash160(..) => "hash160",
+            Terminal::TxTemplate(..) => "txtemplate",
+            Terminal::Ext(..) => "ext",
+            Terminal::Alt(..) => "a:",
+            Terminal::Swap(..) => "s:",
+            Terminal::Check(..) => "c:",
+            Terminal::DupIf(..) => "d:",
+            Terminal::Verify(..) => "v:",
+            Terminal::NonZero(..) => "j:",
+            Terminal::ZeroNotEqual(..) => "n:",
+            Terminal::AndV(..) => "and_v",
+            Terminal::AndB(..) => "and_b",
+            Terminal::AndOr(..) => "andor",
+            Terminal::OrB(..) => "or_b",
+            Terminal::OrD(..) => "or_d",
+            Terminal::OrC(..) => "or_c",
+            Terminal::OrI(..) => "or_i",
+            Terminal::Thresh(..) => "thresh",
+            Terminal::Multi(..) => "multi",
+        }
+    }
+}
+
+/// The script of a Miniscript, as runs of opcodes labelled by the fragment
+/// which generated them, in script order. A fragment wrapping others, like
+/// `or_i`, has a run before, between and after the runs of its children.
+///
+/// Displays as a listing with one run per line, indented by depth.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AnnotatedScript<'a, Pk: MiniscriptKey + 'a, Ctx: ScriptContext + 'a> {
+    /// The runs of opcodes
+    pub annotations: Vec<Annotation<'a, Pk, Ctx>>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for AnnotatedScript<'a, Pk, Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for annotation in &self.annotations {
+            write!(f, "{:4} ", annotation.offset)?;
+            for _ in 0..annotation.depth {
+                f.write_str("  ")?;
+            }
+            f.write_str(annotation.fragment_name())?;
+            for ins in annotation.script.instructions() {
+                match ins {
+                    Ok(script::Instruction::PushBytes(bytes)) if bytes.is_empty() => {
+                        f.write_str(" OP_0")?
+                    }
+                    Ok(script::Instruction::PushBytes(bytes)) => {
+                        write!(f, " <{}>", bytes.to_hex())?
+                    }
+                    Ok(script::Instruction::Op(op)) => write!(f, " {:?}", op)?,
+                    Err(_) => f.write_str(" <invalid>")?,
+                }
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Encode the Miniscript as [Miniscript::encode] does, keeping track of
+    /// the fragment which generated each opcode. Opcodes turned into their
+    /// `VERIFY` form by a `v:` wrapper are labelled with the fragment which
+    /// generated them.
+    pub fn annotate(&self) -> AnnotatedScript<Pk, Ctx> {
+        let mut runs = vec![];
+        let script = self
+            .node
+            .encode_annotated(script::Builder::new(), |fragment, depth, range| {
+                runs.push((fragment, depth, range))
+            })
+            .into_script();
+
+        let mut annotations: Vec<Annotation<Pk, Ctx>> = vec![];
+        for (fragment, depth, range) in runs {
+            let bytes = &script.as_bytes()[range.clone()];
+            if let Some(last) = annotations.last_mut() {
+                // Merge consecutive runs of the same fragment
+                if ptr::eq(last.fragment, fragment)
+                    && last.offset + last.script.len() == range.start
+                {
+                    let mut merged = last.script.to_bytes();
+                    merged.extend_from_slice(bytes);
+                    last.script = script::Script::from(merged);
+                    continue;
+                }
+            }
+            annotations.push(Annotation {
+                fragment: fragment,
+                depth: depth,
+                offset: range.start,
+                script: script::Script::from(bytes.to_vec()),
+            });
+        }
+        AnnotatedScript {
+            annotations: annotations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin;
+    use std::str::FromStr;
+    use {Miniscript, Segwitv0};
+
+    #[test]
+    fn annotate() {
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(
+            "or_d(pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),\
+             and_v(v:pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798),older(9)))",
+        )
+        .unwrap();
+        let annotated = ms.annotate();
+        let names: Vec<_> = annotated
+            .annotations
+            .iter()
+            .map(|a| (a.fragment_name(), a.depth, a.offset))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("pk_k", 2, 0),
+                ("c:", 1, 34),
+                ("or_d", 0, 35),
+                ("pk_k", 4, 37),
+                ("c:", 3, 71),
+                ("older", 2, 72),
+                ("or_d", 0, 74),
+            ]
+        );
+
+        // The opcodes cover the whole script
+        let mut script = vec![];
+        for a in &annotated.annotations {
+            script.extend(a.script.to_bytes());
+        }
+        assert_eq!(script, ms.encode().to_bytes());
+
+        let listing = annotated.to_string();
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(lines[1], "  34   c: OP_CHECKSIG");
+        assert_eq!(lines[2], "  35 or_d OP_IFDUP OP_NOTIF");
+        assert_eq!(lines[4], "  71       c: OP_CHECKSIGVERIFY");
+        assert_eq!(lines[5], "  72     older OP_PUSHNUM_9 OP_CSV");
+        assert_eq!(lines[6], "  74 or_d OP_ENDIF");
+    }
+}
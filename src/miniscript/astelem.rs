@@ -19,6 +19,7 @@
 //! encoding in Bitcoin script, as well as a datatype. Full details
 //! are given on the Miniscript website.
 
+use std::ops::Range;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::{fmt, str};
@@ -29,6 +30,8 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 
 use errstr;
 use expression;
+use miniscript::ext::{ExtFragment, Extension, NoExt};
+use miniscript::lex::OP_CHECKTEMPLATEVERIFY;
 use miniscript::types::{self, Property};
 use miniscript::ScriptContext;
 use script_num_size;
@@ -96,6 +99,8 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
             | Terminal::Hash160(..)
             | Terminal::True
             | Terminal::False => true,
+            Terminal::TxTemplate(..) => true,
+            Terminal::Ext(..) => true,
             Terminal::Alt(ref sub)
             | Terminal::Swap(ref sub)
             | Terminal::Check(ref sub)
@@ -120,6 +125,29 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
             Terminal::Multi(_, ref keys) => keys.iter().all(|key| pred(ForEach::Key(key))),
         }
     }
+    /// Pushes onto `path` the path to the `index`th key visited by
+    /// `real_for_each_key` and returns true, or subtracts the number of keys
+    /// of the fragment from `index` and returns false if it has fewer keys
+    pub(super) fn real_key_path(&self, index: &mut usize, path: &mut Vec<usize>) -> bool {
+        let n_keys = match *self {
+            Terminal::PkK(..) | Terminal::PkH(..) => 1,
+            Terminal::Multi(_, ref keys) => keys.len(),
+            _ => 0,
+        };
+        if *index < n_keys {
+            return true;
+        }
+        *index -= n_keys;
+        for (i, sub) in self.branches().into_iter().enumerate() {
+            path.push(i);
+            if sub.node.real_key_path(index, path) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+
     pub(super) fn real_translate_pk<FPk, FPkh, Q, Error>(
         &self,
         translatefpk: &mut FPk,
@@ -139,6 +167,8 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
             Terminal::Hash256(x) => Terminal::Hash256(x),
             Terminal::Ripemd160(x) => Terminal::Ripemd160(x),
             Terminal::Hash160(x) => Terminal::Hash160(x),
+            Terminal::TxTemplate(x) => Terminal::TxTemplate(x),
+            Terminal::Ext(ref e) => Terminal::Ext(e.clone()),
             Terminal::True => Terminal::True,
             Terminal::False => Terminal::False,
             Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(
@@ -220,43 +250,109 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> ForEachKey<Pk> for Terminal<Pk, Ctx>
     {
         self.real_for_each_key(&mut pred)
     }
+
+    fn key_path(&self, mut index: usize) -> Option<Vec<usize>> {
+        let mut path = vec![];
+        if self.real_key_path(&mut index, &mut path) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Write the miniscript as a tree with one fragment per line, each
+    /// annotated with its type and script size, and indented by `depth`
+    /// levels below its parent. Wrappers are written with their fragment.
+    pub(crate) fn fmt_tree(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let mut inner = self;
+        while let Some((_, sub)) = inner.node.wrap_char() {
+            inner = sub;
+        }
+        let subs: Vec<&Arc<Miniscript<Pk, Ctx>>> = match inner.node {
+            Terminal::AndV(ref l, ref r)
+            | Terminal::AndB(ref l, ref r)
+            | Terminal::OrB(ref l, ref r)
+            | Terminal::OrD(ref l, ref r)
+            | Terminal::OrC(ref l, ref r)
+            | Terminal::OrI(ref l, ref r) => vec![l, r],
+            Terminal::AndOr(ref a, ref b, ref c) => {
+                if c.node == Terminal::False {
+                    vec![a, b]
+                } else {
+                    vec![a, b, c]
+                }
+            }
+            Terminal::Thresh(_, ref subs) => subs.iter().collect(),
+            _ => vec![],
+        };
+
+        for _ in 0..depth {
+            f.write_str("  ")?;
+        }
+        let s = self.to_string();
+        match s.find('(') {
+            Some(i) if !subs.is_empty() => f.write_str(&s[..i])?,
+            _ => f.write_str(&s)?,
+        }
+        if let Terminal::Thresh(k, _) = inner.node {
+            write!(f, "({})", k)?;
+        }
+        f.write_str(" [")?;
+        fmt_type(f, &self.ty)?;
+        write!(f, "] ({} bytes)", self.script_size())?;
+
+        for sub in subs {
+            f.write_str("\n")?;
+            sub.fmt_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a type in the compact notation of the miniscript website, such as
+/// `B/onduesm`
+fn fmt_type(f: &mut fmt::Formatter, type_map: &types::Type) -> fmt::Result {
+    f.write_str(match type_map.corr.base {
+        types::Base::B => "B",
+        types::Base::K => "K",
+        types::Base::V => "V",
+        types::Base::W => "W",
+    })?;
+    fmt::Write::write_char(f, '/')?;
+    f.write_str(match type_map.corr.input {
+        types::Input::Zero => "z",
+        types::Input::One => "o",
+        types::Input::OneNonZero => "on",
+        types::Input::Any => "",
+        types::Input::AnyNonZero => "n",
+    })?;
+    if type_map.corr.dissatisfiable {
+        fmt::Write::write_char(f, 'd')?;
+    }
+    if type_map.corr.unit {
+        fmt::Write::write_char(f, 'u')?;
+    }
+    f.write_str(match type_map.mall.dissat {
+        types::Dissat::None => "f",
+        types::Dissat::Unique => "e",
+        types::Dissat::Unknown => "",
+    })?;
+    if type_map.mall.safe {
+        fmt::Write::write_char(f, 's')?;
+    }
+    if type_map.mall.non_malleable {
+        fmt::Write::write_char(f, 'm')?;
+    }
+    Ok(())
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Debug for Terminal<Pk, Ctx> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str("[")?;
         if let Ok(type_map) = types::Type::type_check(self, |_| None) {
-            f.write_str(match type_map.corr.base {
-                types::Base::B => "B",
-                types::Base::K => "K",
-                types::Base::V => "V",
-                types::Base::W => "W",
-            })?;
-            fmt::Write::write_char(f, '/')?;
-            f.write_str(match type_map.corr.input {
-                types::Input::Zero => "z",
-                types::Input::One => "o",
-                types::Input::OneNonZero => "on",
-                types::Input::Any => "",
-                types::Input::AnyNonZero => "n",
-            })?;
-            if type_map.corr.dissatisfiable {
-                fmt::Write::write_char(f, 'd')?;
-            }
-            if type_map.corr.unit {
-                fmt::Write::write_char(f, 'u')?;
-            }
-            f.write_str(match type_map.mall.dissat {
-                types::Dissat::None => "f",
-                types::Dissat::Unique => "e",
-                types::Dissat::Unknown => "",
-            })?;
-            if type_map.mall.safe {
-                fmt::Write::write_char(f, 's')?;
-            }
-            if type_map.mall.non_malleable {
-                fmt::Write::write_char(f, 'm')?;
-            }
+            fmt_type(f, &type_map)?;
         } else {
             f.write_str("TYPECHECK FAILED")?;
         }
@@ -281,6 +377,8 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Debug for Terminal<Pk, Ctx> {
                 }
                 Terminal::Ripemd160(h) => write!(f, "ripemd160({})", h),
                 Terminal::Hash160(h) => write!(f, "hash160({})", h),
+                Terminal::TxTemplate(h) => write!(f, "txtemplate({})", h),
+                Terminal::Ext(ref e) => write!(f, "{}", e),
                 Terminal::True => f.write_str("1"),
                 Terminal::False => f.write_str("0"),
                 Terminal::AndV(ref l, ref r) => write!(f, "and_v({:?},{:?})", l, r),
@@ -316,8 +414,37 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Debug for Terminal<Pk, Ctx> {
     }
 }
 
-impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+/// A pending step of writing a fragment
+enum FmtStep<'a, Pk: MiniscriptKey + 'a, Ctx: ScriptContext + 'a> {
+    /// Write the fragment
+    Node(&'a Terminal<Pk, Ctx>),
+    /// Write a separator or closing parenthesis
+    Str(&'static str),
+}
+
+/// Queue `subs` to be written as comma separated arguments, followed by the
+/// closing parenthesis
+fn fmt_args<'a, Pk: MiniscriptKey, Ctx: ScriptContext>(
+    steps: &mut Vec<FmtStep<'a, Pk, Ctx>>,
+    subs: &[&'a Arc<Miniscript<Pk, Ctx>>],
+) {
+    steps.push(FmtStep::Str(")"));
+    for (n, sub) in subs.iter().enumerate().rev() {
+        steps.push(FmtStep::Node(&sub.node));
+        if n > 0 {
+            steps.push(FmtStep::Str(","));
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
+    /// Write the start of the fragment and queue its sub-fragments on `steps`,
+    /// to be written after it
+    fn fmt_node<'a>(
+        &'a self,
+        f: &mut fmt::Formatter,
+        steps: &mut Vec<FmtStep<'a, Pk, Ctx>>,
+    ) -> fmt::Result {
         match *self {
             Terminal::PkK(ref pk) => write!(f, "pk_k({})", pk),
             Terminal::PkH(ref pkh) => write!(f, "pk_h({})", pkh),
@@ -331,33 +458,48 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
             }
             Terminal::Ripemd160(h) => write!(f, "ripemd160({})", h),
             Terminal::Hash160(h) => write!(f, "hash160({})", h),
+            Terminal::TxTemplate(h) => write!(f, "txtemplate({})", h),
+            Terminal::Ext(ref e) => write!(f, "{}", e),
             Terminal::True => f.write_str("1"),
             Terminal::False => f.write_str("0"),
             Terminal::AndV(ref l, ref r) if r.node != Terminal::True => {
-                write!(f, "and_v({},{})", l, r)
+                fmt_args(steps, &[l, r]);
+                f.write_str("and_v(")
+            }
+            Terminal::AndB(ref l, ref r) => {
+                fmt_args(steps, &[l, r]);
+                f.write_str("and_b(")
             }
-            Terminal::AndB(ref l, ref r) => write!(f, "and_b({},{})", l, r),
             Terminal::AndOr(ref a, ref b, ref c) => {
                 if c.node == Terminal::False {
-                    write!(f, "and_n({},{})", a, b)
+                    fmt_args(steps, &[a, b]);
+                    f.write_str("and_n(")
                 } else {
-                    write!(f, "andor({},{},{})", a, b, c)
+                    fmt_args(steps, &[a, b, c]);
+                    f.write_str("andor(")
                 }
             }
-            Terminal::OrB(ref l, ref r) => write!(f, "or_b({},{})", l, r),
-            Terminal::OrD(ref l, ref r) => write!(f, "or_d({},{})", l, r),
-            Terminal::OrC(ref l, ref r) => write!(f, "or_c({},{})", l, r),
+            Terminal::OrB(ref l, ref r) => {
+                fmt_args(steps, &[l, r]);
+                f.write_str("or_b(")
+            }
+            Terminal::OrD(ref l, ref r) => {
+                fmt_args(steps, &[l, r]);
+                f.write_str("or_d(")
+            }
+            Terminal::OrC(ref l, ref r) => {
+                fmt_args(steps, &[l, r]);
+                f.write_str("or_c(")
+            }
             Terminal::OrI(ref l, ref r)
                 if l.node != Terminal::False && r.node != Terminal::False =>
             {
-                write!(f, "or_i({},{})", l, r)
+                fmt_args(steps, &[l, r]);
+                f.write_str("or_i(")
             }
             Terminal::Thresh(k, ref subs) => {
-                write!(f, "thresh({}", k)?;
-                for s in subs {
-                    write!(f, ",{}", s)?;
-                }
-                f.write_str(")")
+                fmt_args(steps, &subs.iter().collect::<Vec<_>>());
+                write!(f, "thresh({},", k)
             }
             Terminal::Multi(k, ref keys) => {
                 write!(f, "multi({}", k)?;
@@ -395,7 +537,8 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
                         }
                         _ => {}
                     };
-                    write!(f, "{}", sub)
+                    steps.push(FmtStep::Node(&sub.node));
+                    Ok(())
                 } else {
                     unreachable!();
                 }
@@ -404,6 +547,21 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
     }
 }
 
+/// Fragments are written from an explicit stack of pending steps rather than
+/// by recursion, so that deep miniscripts cannot overflow the call stack.
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut steps = vec![FmtStep::Node(self)];
+        while let Some(step) = steps.pop() {
+            match step {
+                FmtStep::Node(node) => node.fmt_node(f, &mut steps)?,
+                FmtStep::Str(s) => f.write_str(s)?,
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<Pk, Ctx> expression::FromTree for Arc<Terminal<Pk, Ctx>>
 where
     Pk: MiniscriptKey + str::FromStr,
@@ -426,6 +584,41 @@ where
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Terminal<Pk, Ctx>, Error> {
+        let subs: Result<Vec<Arc<Miniscript<Pk, Ctx>>>, _> = sub_trees(top)
+            .into_iter()
+            .map(expression::FromTree::from_tree)
+            .collect();
+        Terminal::from_tree_with_subs::<NoExt>(top, subs?)
+    }
+}
+
+/// The sub-expressions of `top` which are themselves Miniscripts, in order
+pub(super) fn sub_trees<'a, 'b>(top: &'a expression::Tree<'b>) -> Vec<&'a expression::Tree<'b>> {
+    let name = top.name.rsplit(':').next().unwrap_or("");
+    match name {
+        "and_v" | "and_b" | "and_n" | "andor" | "or_b" | "or_d" | "or_c" | "or_i" => {
+            top.args.iter().collect()
+        }
+        "thresh" => top.args.iter().skip(1).collect(),
+        _ => vec![],
+    }
+}
+
+impl<Pk, Ctx> Terminal<Pk, Ctx>
+where
+    Pk: MiniscriptKey + str::FromStr,
+    Pk::Hash: str::FromStr,
+    Ctx: ScriptContext,
+    <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+{
+    /// Parse the fragment at the top of an expression tree, given its
+    /// already parsed sub-fragments, as returned by [sub_trees]. Names unknown
+    /// to this crate are parsed as fragments of the extension `E`.
+    pub(super) fn from_tree_with_subs<E: Extension>(
+        top: &expression::Tree,
+        subs: Vec<Arc<Miniscript<Pk, Ctx>>>,
+    ) -> Result<Terminal<Pk, Ctx>, Error> {
         let mut aliased_wrap;
         let frag_name;
         let frag_wrap;
@@ -501,39 +694,36 @@ where
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
                 hash160::Hash::from_hex(x).map(Terminal::Hash160)
             }),
+            ("txtemplate", 1) if cfg!(feature = "ctv") => expression::terminal(&top.args[0], |x| {
+                sha256::Hash::from_hex(x).map(Terminal::TxTemplate)
+            }),
             ("1", 0) => Ok(Terminal::True),
             ("0", 0) => Ok(Terminal::False),
             ("and_v", 2) => {
-                let expr = expression::binary(top, Terminal::AndV)?;
-                if let Terminal::AndV(_, ref right) = expr {
-                    if let Terminal::True = right.node {
-                        return Err(Error::NonCanonicalTrue);
-                    }
+                if let Terminal::True = subs[1].node {
+                    return Err(Error::NonCanonicalTrue);
                 }
-                Ok(expr)
+                Ok(Terminal::AndV(subs[0].clone(), subs[1].clone()))
             }
-            ("and_b", 2) => expression::binary(top, Terminal::AndB),
+            ("and_b", 2) => Ok(Terminal::AndB(subs[0].clone(), subs[1].clone())),
             ("and_n", 2) => Ok(Terminal::AndOr(
-                expression::FromTree::from_tree(&top.args[0])?,
-                expression::FromTree::from_tree(&top.args[1])?,
+                subs[0].clone(),
+                subs[1].clone(),
                 Arc::new(Miniscript::from_ast(Terminal::False)?),
             )),
             ("andor", 3) => Ok(Terminal::AndOr(
-                expression::FromTree::from_tree(&top.args[0])?,
-                expression::FromTree::from_tree(&top.args[1])?,
-                expression::FromTree::from_tree(&top.args[2])?,
+                subs[0].clone(),
+                subs[1].clone(),
+                subs[2].clone(),
             )),
-            ("or_b", 2) => expression::binary(top, Terminal::OrB),
-            ("or_d", 2) => expression::binary(top, Terminal::OrD),
-            ("or_c", 2) => expression::binary(top, Terminal::OrC),
+            ("or_b", 2) => Ok(Terminal::OrB(subs[0].clone(), subs[1].clone())),
+            ("or_d", 2) => Ok(Terminal::OrD(subs[0].clone(), subs[1].clone())),
+            ("or_c", 2) => Ok(Terminal::OrC(subs[0].clone(), subs[1].clone())),
             ("or_i", 2) => {
-                let expr = expression::binary(top, Terminal::OrI)?;
-                if let Terminal::OrI(ref left, ref right) = expr {
-                    if left.node == Terminal::False || right.node == Terminal::False {
-                        return Err(Error::NonCanonicalFalse);
-                    }
+                if subs[0].node == Terminal::False || subs[1].node == Terminal::False {
+                    return Err(Error::NonCanonicalFalse);
                 }
-                Ok(expr)
+                Ok(Terminal::OrI(subs[0].clone(), subs[1].clone()))
             }
             ("thresh", n) => {
                 if n == 0 {
@@ -547,12 +737,7 @@ where
                     return Err(errstr("empty thresholds not allowed in descriptors"));
                 }
 
-                let subs: Result<Vec<Arc<Miniscript<Pk, Ctx>>>, _> = top.args[1..]
-                    .iter()
-                    .map(|sub| expression::FromTree::from_tree(sub))
-                    .collect();
-
-                Ok(Terminal::Thresh(k, subs?))
+                Ok(Terminal::Thresh(k, subs))
             }
             ("multi", n) => {
                 if n == 0 {
@@ -570,11 +755,14 @@ where
 
                 pks.map(|pks| Terminal::Multi(k, pks))
             }
-            _ => Err(Error::Unexpected(format!(
-                "{}({} args) while parsing Miniscript",
-                top.name,
-                top.args.len(),
-            ))),
+            _ => match E::from_name_tree(frag_name, &top.args)? {
+                Some(ext) => Ok(Terminal::Ext(ExtFragment::new(ext))),
+                None => Err(Error::Unexpected(format!(
+                    "{}({} args) while parsing Miniscript",
+                    top.name,
+                    top.args.len(),
+                ))),
+            },
         }?;
         for ch in frag_wrap.chars().rev() {
             // Check whether the wrapper is valid under the current context
@@ -619,146 +807,222 @@ where
     }
 }
 
-/// Helper trait to add a `push_astelem` method to `script::Builder`
-trait PushAstElem<Pk: MiniscriptKey, Ctx: ScriptContext> {
-    fn push_astelem(self, ast: &Miniscript<Pk, Ctx>) -> Self
-    where
-        Pk: ToPublicKey;
+/// Encode a hash fragment using the hashing opcode `op`
+fn encode_hash(builder: script::Builder, op: opcodes::All, hash: &[u8]) -> script::Builder {
+    builder
+        .push_opcode(opcodes::all::OP_SIZE)
+        .push_int(32)
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .push_opcode(op)
+        .push_slice(hash)
+        .push_opcode(opcodes::all::OP_EQUAL)
 }
 
-impl<Pk: MiniscriptKey, Ctx: ScriptContext> PushAstElem<Pk, Ctx> for script::Builder {
-    fn push_astelem(self, ast: &Miniscript<Pk, Ctx>) -> Self
-    where
-        Pk: ToPublicKey,
-    {
-        ast.node.encode(self)
-    }
+/// A pending step of encoding a fragment
+enum EncodeStep<'a, Pk: MiniscriptKey + 'a, Ctx: ScriptContext + 'a> {
+    /// Encode the fragment
+    Node(&'a Terminal<Pk, Ctx>),
+    /// Push an opcode
+    Op(opcodes::All),
+    /// Push an integer
+    Int(i64),
+    /// Make the last pushed opcode a verifying one
+    Verify,
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
     /// Encode the element as a fragment of Bitcoin Script. The inverse
     /// function, from Script to an AST element, is implemented in the
     /// `parse` module.
-    pub fn encode(&self, mut builder: script::Builder) -> script::Builder
+    ///
+    /// Fragments are encoded from an explicit stack of pending steps rather
+    /// than by recursion, so that deep miniscripts cannot overflow the call
+    /// stack.
+    pub fn encode(&self, builder: script::Builder) -> script::Builder
     where
         Pk: ToPublicKey,
     {
-        match *self {
-            Terminal::PkK(ref pk) => builder.push_key(&pk.to_public_key()),
-            Terminal::PkH(ref hash) => builder
-                .push_opcode(opcodes::all::OP_DUP)
-                .push_opcode(opcodes::all::OP_HASH160)
-                .push_slice(&Pk::hash_to_hash160(&hash)[..])
-                .push_opcode(opcodes::all::OP_EQUALVERIFY),
-            Terminal::After(t) => builder
-                .push_int(t as i64)
-                .push_opcode(opcodes::all::OP_CLTV),
-            Terminal::Older(t) => builder.push_int(t as i64).push_opcode(opcodes::all::OP_CSV),
-            Terminal::Sha256(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_SHA256)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Hash256(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_HASH256)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Ripemd160(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_RIPEMD160)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Hash160(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_HASH160)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::True => builder.push_opcode(opcodes::OP_TRUE),
-            Terminal::False => builder.push_opcode(opcodes::OP_FALSE),
-            Terminal::Alt(ref sub) => builder
-                .push_opcode(opcodes::all::OP_TOALTSTACK)
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_FROMALTSTACK),
-            Terminal::Swap(ref sub) => builder.push_opcode(opcodes::all::OP_SWAP).push_astelem(sub),
-            Terminal::Check(ref sub) => builder
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_CHECKSIG),
-            Terminal::DupIf(ref sub) => builder
-                .push_opcode(opcodes::all::OP_DUP)
-                .push_opcode(opcodes::all::OP_IF)
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::Verify(ref sub) => builder.push_astelem(sub).push_verify(),
-            Terminal::NonZero(ref sub) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_opcode(opcodes::all::OP_0NOTEQUAL)
-                .push_opcode(opcodes::all::OP_IF)
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::ZeroNotEqual(ref sub) => builder
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_0NOTEQUAL),
-            Terminal::AndV(ref left, ref right) => builder.push_astelem(left).push_astelem(right),
-            Terminal::AndB(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_BOOLAND),
-            Terminal::AndOr(ref a, ref b, ref c) => builder
-                .push_astelem(a)
-                .push_opcode(opcodes::all::OP_NOTIF)
-                .push_astelem(c)
-                .push_opcode(opcodes::all::OP_ELSE)
-                .push_astelem(b)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::OrB(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_BOOLOR),
-            Terminal::OrD(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_opcode(opcodes::all::OP_IFDUP)
-                .push_opcode(opcodes::all::OP_NOTIF)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::OrC(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_opcode(opcodes::all::OP_NOTIF)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::OrI(ref left, ref right) => builder
-                .push_opcode(opcodes::all::OP_IF)
-                .push_astelem(left)
-                .push_opcode(opcodes::all::OP_ELSE)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_ENDIF),
+        self.encode_annotated(builder, |_, _, _| {})
+    }
+
+    /// Encode the element as [Terminal::encode] does, calling `emitted` with
+    /// every fragment, its depth below this one and the range of bytes it
+    /// generated itself, each time it adds to the script. Opcodes turned into
+    /// their `VERIFY` form by a `v:` wrapper stay with the fragment which
+    /// generated them.
+    pub(super) fn encode_annotated<'a, F>(
+        &'a self,
+        mut builder: script::Builder,
+        mut emitted: F,
+    ) -> script::Builder
+    where
+        Pk: ToPublicKey,
+        F: FnMut(&'a Terminal<Pk, Ctx>, usize, Range<usize>),
+    {
+        let mut stack = vec![(EncodeStep::Node(self), self, 0)];
+        while let Some((step, owner, depth)) = stack.pop() {
+            let start = builder.len();
+            builder = match step {
+                EncodeStep::Node(node) => {
+                    let (builder, steps) = node.encode_node(builder);
+                    for step in steps.into_iter().rev() {
+                        match step {
+                            EncodeStep::Node(sub) => {
+                                stack.push((EncodeStep::Node(sub), sub, depth + 1))
+                            }
+                            step => stack.push((step, node, depth)),
+                        }
+                    }
+                    builder
+                }
+                EncodeStep::Op(op) => builder.push_opcode(op),
+                EncodeStep::Int(n) => builder.push_int(n),
+                EncodeStep::Verify => builder.push_verify(),
+            };
+            if builder.len() > start {
+                emitted(owner, depth, start..builder.len());
+            }
+        }
+        builder
+    }
+
+    /// Encode the element itself, returning the steps needed to encode the
+    /// rest of it, in order
+    fn encode_node(
+        &self,
+        mut builder: script::Builder,
+    ) -> (script::Builder, Vec<EncodeStep<Pk, Ctx>>)
+    where
+        Pk: ToPublicKey,
+    {
+        use self::EncodeStep::{Int, Node, Op, Verify};
+
+        let steps = match *self {
+            Terminal::PkK(ref pk) => {
+                builder = builder.push_key(&pk.to_public_key());
+                vec![]
+            }
+            Terminal::PkH(ref hash) => {
+                builder = builder
+                    .push_opcode(opcodes::all::OP_DUP)
+                    .push_opcode(opcodes::all::OP_HASH160)
+                    .push_slice(&Pk::hash_to_hash160(&hash)[..])
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY);
+                vec![]
+            }
+            Terminal::After(t) => vec![Int(t as i64), Op(opcodes::all::OP_CLTV)],
+            Terminal::Older(t) => vec![Int(t as i64), Op(opcodes::all::OP_CSV)],
+            Terminal::Sha256(h) => {
+                builder = encode_hash(builder, opcodes::all::OP_SHA256, &h[..]);
+                vec![]
+            }
+            Terminal::Hash256(h) => {
+                builder = encode_hash(builder, opcodes::all::OP_HASH256, &h[..]);
+                vec![]
+            }
+            Terminal::Ripemd160(h) => {
+                builder = encode_hash(builder, opcodes::all::OP_RIPEMD160, &h[..]);
+                vec![]
+            }
+            Terminal::Hash160(h) => {
+                builder = encode_hash(builder, opcodes::all::OP_HASH160, &h[..]);
+                vec![]
+            }
+            Terminal::TxTemplate(h) => {
+                builder = builder.push_slice(&h[..]);
+                vec![Op(OP_CHECKTEMPLATEVERIFY)]
+            }
+            Terminal::Ext(ref e) => {
+                builder = e.push_to_builder(builder);
+                vec![]
+            }
+            Terminal::True => vec![Op(opcodes::OP_TRUE)],
+            Terminal::False => vec![Op(opcodes::OP_FALSE)],
+            Terminal::Alt(ref sub) => vec![
+                Op(opcodes::all::OP_TOALTSTACK),
+                Node(&sub.node),
+                Op(opcodes::all::OP_FROMALTSTACK),
+            ],
+            Terminal::Swap(ref sub) => vec![Op(opcodes::all::OP_SWAP), Node(&sub.node)],
+            Terminal::Check(ref sub) => vec![Node(&sub.node), Op(opcodes::all::OP_CHECKSIG)],
+            Terminal::DupIf(ref sub) => vec![
+                Op(opcodes::all::OP_DUP),
+                Op(opcodes::all::OP_IF),
+                Node(&sub.node),
+                Op(opcodes::all::OP_ENDIF),
+            ],
+            Terminal::Verify(ref sub) => vec![Node(&sub.node), Verify],
+            Terminal::NonZero(ref sub) => vec![
+                Op(opcodes::all::OP_SIZE),
+                Op(opcodes::all::OP_0NOTEQUAL),
+                Op(opcodes::all::OP_IF),
+                Node(&sub.node),
+                Op(opcodes::all::OP_ENDIF),
+            ],
+            Terminal::ZeroNotEqual(ref sub) => {
+                vec![Node(&sub.node), Op(opcodes::all::OP_0NOTEQUAL)]
+            }
+            Terminal::AndV(ref left, ref right) => vec![Node(&left.node), Node(&right.node)],
+            Terminal::AndB(ref left, ref right) => vec![
+                Node(&left.node),
+                Node(&right.node),
+                Op(opcodes::all::OP_BOOLAND),
+            ],
+            Terminal::AndOr(ref a, ref b, ref c) => vec![
+                Node(&a.node),
+                Op(opcodes::all::OP_NOTIF),
+                Node(&c.node),
+                Op(opcodes::all::OP_ELSE),
+                Node(&b.node),
+                Op(opcodes::all::OP_ENDIF),
+            ],
+            Terminal::OrB(ref left, ref right) => vec![
+                Node(&left.node),
+                Node(&right.node),
+                Op(opcodes::all::OP_BOOLOR),
+            ],
+            Terminal::OrD(ref left, ref right) => vec![
+                Node(&left.node),
+                Op(opcodes::all::OP_IFDUP),
+                Op(opcodes::all::OP_NOTIF),
+                Node(&right.node),
+                Op(opcodes::all::OP_ENDIF),
+            ],
+            Terminal::OrC(ref left, ref right) => vec![
+                Node(&left.node),
+                Op(opcodes::all::OP_NOTIF),
+                Node(&right.node),
+                Op(opcodes::all::OP_ENDIF),
+            ],
+            Terminal::OrI(ref left, ref right) => vec![
+                Op(opcodes::all::OP_IF),
+                Node(&left.node),
+                Op(opcodes::all::OP_ELSE),
+                Node(&right.node),
+                Op(opcodes::all::OP_ENDIF),
+            ],
             Terminal::Thresh(k, ref subs) => {
-                builder = builder.push_astelem(&subs[0]);
+                let mut steps = vec![Node(&subs[0].node)];
                 for sub in &subs[1..] {
-                    builder = builder.push_astelem(sub).push_opcode(opcodes::all::OP_ADD);
+                    steps.push(Node(&sub.node));
+                    steps.push(Op(opcodes::all::OP_ADD));
                 }
-                builder
-                    .push_int(k as i64)
-                    .push_opcode(opcodes::all::OP_EQUAL)
+                steps.push(Int(k as i64));
+                steps.push(Op(opcodes::all::OP_EQUAL));
+                steps
             }
             Terminal::Multi(k, ref keys) => {
                 builder = builder.push_int(k as i64);
                 for pk in keys {
                     builder = builder.push_key(&pk.to_public_key());
                 }
-                builder
+                builder = builder
                     .push_int(keys.len() as i64)
-                    .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+                    .push_opcode(opcodes::all::OP_CHECKMULTISIG);
+                vec![]
             }
-        }
+        };
+        (builder, steps)
     }
 
     /// Size, in bytes of the script-pubkey. If this Miniscript is used outside
@@ -778,6 +1042,8 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
             Terminal::Hash256(..) => 33 + 6,
             Terminal::Ripemd160(..) => 21 + 6,
             Terminal::Hash160(..) => 21 + 6,
+            Terminal::TxTemplate(..) => 33 + 1,
+            Terminal::Ext(ref e) => e.extra_prop().pk_cost,
             Terminal::True => 1,
             Terminal::False => 1,
             Terminal::Alt(ref sub) => sub.node.script_size() + 2,
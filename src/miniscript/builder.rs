@@ -0,0 +1,347 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Miniscript Builder
+//!
+//! Programmatic construction of Miniscript, without formatting a string to
+//! parse it back. Every fragment is type checked as soon as it is built, and
+//! the first error is carried through the rest of the construction, so that
+//! it only has to be handled once, in `build`:
+//!
+//! ```rust
+//! use miniscript::miniscript::builder::Builder;
+//! use miniscript::{bitcoin, Miniscript, Segwitv0};
+//! use std::str::FromStr;
+//!
+//! let key = bitcoin::PublicKey::from_str(
+//!     "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556",
+//! ).unwrap();
+//! let ms: Miniscript<bitcoin::PublicKey, Segwitv0> = Builder::pk(key)
+//!     .verify()
+//!     .and_v(Builder::older(1000))
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(ms.to_string(), "and_v(v:pk(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556),older(1000))");
+//! ```
+//!
+
+use std::sync::Arc;
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+
+use miniscript::types;
+use {Error, Miniscript, MiniscriptKey, ScriptContext, Terminal};
+
+/// A type checked Miniscript fragment under construction, or the first error
+/// met while constructing it
+#[derive(Debug)]
+pub struct Builder<Pk: MiniscriptKey, Ctx: ScriptContext> {
+    ms: Result<Miniscript<Pk, Ctx>, Error>,
+}
+
+type Arm<Pk, Ctx> = Arc<Miniscript<Pk, Ctx>>;
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Builder<Pk, Ctx> {
+    fn from_ast(t: Terminal<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        Builder {
+            ms: Miniscript::from_ast(t),
+        }
+    }
+
+    fn wrap(self, wrapper: fn(Arm<Pk, Ctx>) -> Terminal<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        Builder {
+            ms: self
+                .ms
+                .and_then(|ms| Miniscript::from_ast(wrapper(Arc::new(ms)))),
+        }
+    }
+
+    fn combine(
+        self,
+        other: Builder<Pk, Ctx>,
+        combinator: fn(Arm<Pk, Ctx>, Arm<Pk, Ctx>) -> Terminal<Pk, Ctx>,
+    ) -> Builder<Pk, Ctx> {
+        Builder {
+            ms: self.ms.and_then(|left| {
+                other.ms.and_then(|right| {
+                    Miniscript::from_ast(combinator(Arc::new(left), Arc::new(right)))
+                })
+            }),
+        }
+    }
+
+    /// `1`
+    pub fn tru() -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::True)
+    }
+
+    /// `0`
+    pub fn fals() -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::False)
+    }
+
+    /// `pk_k(key)`
+    pub fn pk_k(key: Pk) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::PkK(key))
+    }
+
+    /// `pk_h(hash)`
+    pub fn pk_h(hash: Pk::Hash) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::PkH(hash))
+    }
+
+    /// `pk(key)`, that is `c:pk_k(key)`
+    pub fn pk(key: Pk) -> Builder<Pk, Ctx> {
+        Builder::pk_k(key).check()
+    }
+
+    /// `pkh(hash)`, that is `c:pk_h(hash)`
+    pub fn pkh(hash: Pk::Hash) -> Builder<Pk, Ctx> {
+        Builder::pk_h(hash).check()
+    }
+
+    /// `after(n)`
+    pub fn after(n: u32) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::After(n))
+    }
+
+    /// `older(n)`
+    pub fn older(n: u32) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::Older(n))
+    }
+
+    /// `sha256(hash)`
+    pub fn sha256(hash: sha256::Hash) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::Sha256(hash))
+    }
+
+    /// `hash256(hash)`
+    pub fn hash256(hash: sha256d::Hash) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::Hash256(hash))
+    }
+
+    /// `ripemd160(hash)`
+    pub fn ripemd160(hash: ripemd160::Hash) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::Ripemd160(hash))
+    }
+
+    /// `hash160(hash)`
+    pub fn hash160(hash: hash160::Hash) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::Hash160(hash))
+    }
+
+    /// `txtemplate(hash)`
+    pub fn tx_template(hash: sha256::Hash) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::TxTemplate(hash))
+    }
+
+    /// `multi(k, keys...)`
+    pub fn multi(k: usize, keys: Vec<Pk>) -> Builder<Pk, Ctx> {
+        Builder::from_ast(Terminal::Multi(k, keys))
+    }
+
+    /// `thresh(k, subs...)`
+    pub fn thresh(k: usize, subs: Vec<Builder<Pk, Ctx>>) -> Builder<Pk, Ctx> {
+        let subs: Result<Vec<_>, _> = subs.into_iter().map(|sub| sub.ms.map(Arc::new)).collect();
+        Builder {
+            ms: subs.and_then(|subs| Miniscript::from_ast(Terminal::Thresh(k, subs))),
+        }
+    }
+
+    /// The `a:` wrapper
+    pub fn alt(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::Alt)
+    }
+
+    /// The `s:` wrapper
+    pub fn swap(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::Swap)
+    }
+
+    /// The `c:` wrapper
+    pub fn check(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::Check)
+    }
+
+    /// The `d:` wrapper
+    pub fn dup_if(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::DupIf)
+    }
+
+    /// The `v:` wrapper
+    pub fn verify(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::Verify)
+    }
+
+    /// The `j:` wrapper
+    pub fn non_zero(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::NonZero)
+    }
+
+    /// The `n:` wrapper
+    pub fn zero_not_equal(self) -> Builder<Pk, Ctx> {
+        self.wrap(Terminal::ZeroNotEqual)
+    }
+
+    /// `and_v(self, other)`
+    pub fn and_v(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.combine(other, Terminal::AndV)
+    }
+
+    /// `and_b(self, other)`
+    pub fn and_b(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.combine(other, Terminal::AndB)
+    }
+
+    /// `and_n(self, other)`, that is `andor(self, other, 0)`
+    pub fn and_n(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.andor(other, Builder::fals())
+    }
+
+    /// `andor(self, then, other)`
+    pub fn andor(self, then: Builder<Pk, Ctx>, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        Builder {
+            ms: self.ms.and_then(|cond| {
+                then.ms.and_then(|then| {
+                    other.ms.and_then(|other| {
+                        Miniscript::from_ast(Terminal::AndOr(
+                            Arc::new(cond),
+                            Arc::new(then),
+                            Arc::new(other),
+                        ))
+                    })
+                })
+            }),
+        }
+    }
+
+    /// `or_b(self, other)`
+    pub fn or_b(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.combine(other, Terminal::OrB)
+    }
+
+    /// `or_d(self, other)`
+    pub fn or_d(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.combine(other, Terminal::OrD)
+    }
+
+    /// `or_c(self, other)`
+    pub fn or_c(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.combine(other, Terminal::OrC)
+    }
+
+    /// `or_i(self, other)`
+    pub fn or_i(self, other: Builder<Pk, Ctx>) -> Builder<Pk, Ctx> {
+        self.combine(other, Terminal::OrI)
+    }
+
+    /// Finishes the construction of a top level Miniscript, which must be of
+    /// type `B` and, as when parsing one from a string, pass
+    /// [Miniscript::sanity_check]
+    pub fn build(self) -> Result<Miniscript<Pk, Ctx>, Error> {
+        let ms = self.build_insane()?;
+        ms.sanity_check()?;
+        Ok(ms)
+    }
+
+    /// Finishes the construction of a top level Miniscript of type `B`,
+    /// without the sanity checks of `build`
+    pub fn build_insane(self) -> Result<Miniscript<Pk, Ctx>, Error> {
+        let ms = self.ms?;
+        if ms.ty.corr.base != types::Base::B {
+            Err(Error::NonTopLevel(format!("{:?}", ms)))
+        } else {
+            Ok(ms)
+        }
+    }
+
+    /// Finishes the construction of a fragment of any type, to be used as
+    /// part of a larger Miniscript
+    pub fn build_fragment(self) -> Result<Miniscript<Pk, Ctx>, Error> {
+        self.ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Builder;
+    use bitcoin;
+    use std::str::FromStr;
+    use {Error, Miniscript, Segwitv0};
+
+    type Segwitv0Builder = Builder<bitcoin::PublicKey, Segwitv0>;
+
+    fn keys() -> Vec<bitcoin::PublicKey> {
+        [
+            "03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556",
+            "0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352",
+        ]
+        .iter()
+        .map(|s| bitcoin::PublicKey::from_str(s).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn build() {
+        let keys = keys();
+        let ms = Segwitv0Builder::pk(keys[0])
+            .or_d(Builder::pk(keys[1]).verify().and_v(Builder::older(144)))
+            .build()
+            .unwrap();
+        let expected = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!(
+            "or_d(pk({}),and_v(v:pk({}),older(144)))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+        assert_eq!(ms, expected);
+
+        let ms =
+            Segwitv0Builder::thresh(2, vec![Builder::pk(keys[0]), Builder::pk(keys[1]).swap()])
+                .and_n(Builder::after(10).and_b(Builder::pk(keys[0]).swap()))
+                .build_insane()
+                .unwrap();
+        let expected = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "and_n(thresh(2,pk({a}),s:pk({b})),and_b(after(10),s:pk({a})))",
+            a = keys[0],
+            b = keys[1]
+        ))
+        .unwrap();
+        assert_eq!(ms, expected);
+    }
+
+    #[test]
+    fn build_errors() {
+        let keys = keys();
+        // Type errors are reported at the fragment that has them
+        match Segwitv0Builder::older(1)
+            .and_v(Builder::pk(keys[0]))
+            .or_i(Builder::pk(keys[1]))
+            .build()
+        {
+            Err(Error::TypeCheck(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        // Not a top level Miniscript
+        match Segwitv0Builder::pk(keys[0]).verify().build() {
+            Err(Error::NonTopLevel(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(Segwitv0Builder::pk(keys[0])
+            .verify()
+            .build_fragment()
+            .is_ok());
+        // Sane but for sanity checks
+        assert!(Segwitv0Builder::older(1).build().is_err());
+        assert!(Segwitv0Builder::older(1).build_insane().is_ok());
+    }
+}
@@ -12,8 +12,9 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
-use std::{fmt, hash};
+use std::{fmt, hash, marker};
 
+use miniscript::analyzable::ResourceLimit;
 use miniscript::limits::{
     MAX_OPS_PER_SCRIPT, MAX_SCRIPTSIG_SIZE, MAX_SCRIPT_ELEMENT_SIZE, MAX_SCRIPT_SIZE,
     MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS,
@@ -55,6 +56,9 @@ pub enum ScriptContextError {
     MaxScriptSigSizeExceeded,
     /// Impossible to satisfy the miniscript under the current context
     ImpossibleSatisfaction,
+    /// Descriptors cannot hold extension fragments, as their strings could
+    /// not be parsed back
+    ExtensionFragment,
 }
 
 impl fmt::Display for ScriptContextError {
@@ -99,6 +103,9 @@ impl fmt::Display for ScriptContextError {
                     "Impossible to satisfy Miniscript under the current context"
                 )
             }
+            ScriptContextError::ExtensionFragment => {
+                write!(f, "Extension fragments are not allowed in descriptors")
+            }
         }
     }
 }
@@ -138,6 +145,10 @@ pub trait ScriptContext:
     fn max_satisfaction_size<Pk: MiniscriptKey, Ctx: ScriptContext>(
         ms: &Miniscript<Pk, Ctx>,
     ) -> Option<usize>;
+
+    /// The resource limits a Miniscript is subject to under this context,
+    /// along with their maximum values
+    fn resource_limits() -> Vec<(ResourceLimit, usize)>;
     /// Depending on script Context, some of the Terminals might not
     /// be valid under the current consensus rules.
     /// Or some of the script resource limits may have been exceeded.
@@ -241,6 +252,12 @@ pub trait ScriptContext:
         ms: &Miniscript<Pk, Ctx>,
     ) -> Result<(), Error> {
         Self::top_level_type_check(ms)?;
+        if ms.iter().any(|sub| match sub.node {
+            Terminal::Ext(..) => true,
+            _ => false,
+        }) {
+            return Err(Error::ContextError(ScriptContextError::ExtensionFragment));
+        }
         Self::other_top_level_checks(ms)
     }
 }
@@ -317,6 +334,14 @@ impl ScriptContext for Legacy {
         // The scriptSig cost is the second element of the tuple
         ms.ext.max_sat_size.map(|x| x.1)
     }
+
+    fn resource_limits() -> Vec<(ResourceLimit, usize)> {
+        vec![
+            (ResourceLimit::OpCount, MAX_OPS_PER_SCRIPT),
+            (ResourceLimit::ScriptSize, MAX_SCRIPT_ELEMENT_SIZE),
+            (ResourceLimit::SatisfactionSize, MAX_SCRIPTSIG_SIZE),
+        ]
+    }
 }
 
 /// Segwitv0 ScriptContext
@@ -400,6 +425,17 @@ impl ScriptContext for Segwitv0 {
         // The witness stack cost is the first element of the tuple
         ms.ext.max_sat_size.map(|x| x.0)
     }
+
+    fn resource_limits() -> Vec<(ResourceLimit, usize)> {
+        vec![
+            (ResourceLimit::OpCount, MAX_OPS_PER_SCRIPT),
+            (ResourceLimit::ScriptSize, MAX_STANDARD_P2WSH_SCRIPT_SIZE),
+            (
+                ResourceLimit::WitnessElements,
+                MAX_STANDARD_P2WSH_STACK_ITEMS,
+            ),
+        ]
+    }
 }
 
 /// Bare ScriptContext
@@ -461,6 +497,13 @@ impl ScriptContext for BareCtx {
         // The witness stack cost is the first element of the tuple
         ms.ext.max_sat_size.map(|x| x.1)
     }
+
+    fn resource_limits() -> Vec<(ResourceLimit, usize)> {
+        vec![
+            (ResourceLimit::OpCount, MAX_OPS_PER_SCRIPT),
+            (ResourceLimit::ScriptSize, MAX_SCRIPT_SIZE),
+        ]
+    }
 }
 
 /// "No Checks" Context
@@ -506,11 +549,114 @@ impl ScriptContext for NoChecks {
     ) -> Option<usize> {
         panic!("Tried to compute a satisfaction size bound on a no-checks miniscript")
     }
+
+    fn resource_limits() -> Vec<(ResourceLimit, usize)> {
+        vec![]
+    }
+}
+
+/// Resource limits of a [CustomCtx]
+///
+/// Unlike [ScriptContext], this trait can be implemented outside the crate,
+/// to reuse Miniscript under other limits than those of Bitcoin, such as
+/// the consensus limits only, or the limits of a sidechain.
+pub trait ContextLimits:
+    fmt::Debug + Clone + Ord + PartialOrd + Eq + PartialEq + hash::Hash
+{
+    /// Maximum number of non-push opcodes executed by a satisfaction
+    const MAX_OPS: usize;
+    /// Maximum size of the script, in bytes
+    const MAX_SCRIPT_SIZE: usize;
+    /// Maximum number of witness elements of a satisfaction, including the
+    /// script
+    const MAX_STACK_ITEMS: usize;
+}
+
+/// ScriptContext with the resource limits of `L`
+///
+/// Otherwise, scripts are treated as under [Segwitv0]: they are satisfied by
+/// witnesses and only take compressed keys.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CustomCtx<L: ContextLimits> {
+    limits: marker::PhantomData<L>,
+}
+
+impl<L: ContextLimits> ScriptContext for CustomCtx<L> {
+    fn check_terminal_non_malleable<Pk: MiniscriptKey, Ctx: ScriptContext>(
+        _frag: &Terminal<Pk, Ctx>,
+    ) -> Result<(), ScriptContextError> {
+        Ok(())
+    }
+
+    fn check_witness<Pk: MiniscriptKey, Ctx: ScriptContext>(
+        witness: &[Vec<u8>],
+    ) -> Result<(), ScriptContextError> {
+        if witness.len() > L::MAX_STACK_ITEMS {
+            return Err(ScriptContextError::MaxWitnessItemssExceeded);
+        }
+        Ok(())
+    }
+
+    fn check_global_consensus_validity<Pk: MiniscriptKey, Ctx: ScriptContext>(
+        ms: &Miniscript<Pk, Ctx>,
+    ) -> Result<(), ScriptContextError> {
+        if ms.ext.pk_cost > L::MAX_SCRIPT_SIZE {
+            return Err(ScriptContextError::MaxWitnessScriptSizeExceeded);
+        }
+
+        match ms.node {
+            Terminal::PkK(ref pk) => {
+                if pk.is_uncompressed() {
+                    return Err(ScriptContextError::CompressedOnly);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_local_consensus_validity<Pk: MiniscriptKey, Ctx: ScriptContext>(
+        ms: &Miniscript<Pk, Ctx>,
+    ) -> Result<(), ScriptContextError> {
+        match ms.ext.ops_count_sat {
+            None => Err(ScriptContextError::MaxOpCountExceeded),
+            Some(op_count) if op_count > L::MAX_OPS => Err(ScriptContextError::MaxOpCountExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_local_policy_validity<Pk: MiniscriptKey, Ctx: ScriptContext>(
+        ms: &Miniscript<Pk, Ctx>,
+    ) -> Result<(), ScriptContextError> {
+        match ms.max_satisfaction_witness_elements() {
+            // No possible satisfactions
+            Err(_e) => Err(ScriptContextError::ImpossibleSatisfaction),
+            Ok(max_witness_items) if max_witness_items > L::MAX_STACK_ITEMS => {
+                Err(ScriptContextError::MaxWitnessItemssExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn max_satisfaction_size<Pk: MiniscriptKey, Ctx: ScriptContext>(
+        ms: &Miniscript<Pk, Ctx>,
+    ) -> Option<usize> {
+        // The witness stack cost is the first element of the tuple
+        ms.ext.max_sat_size.map(|x| x.0)
+    }
+
+    fn resource_limits() -> Vec<(ResourceLimit, usize)> {
+        vec![
+            (ResourceLimit::OpCount, L::MAX_OPS),
+            (ResourceLimit::ScriptSize, L::MAX_SCRIPT_SIZE),
+            (ResourceLimit::WitnessElements, L::MAX_STACK_ITEMS),
+        ]
+    }
 }
 
 /// Private Mod to prevent downstream from implementing this public trait
 mod private {
-    use super::{BareCtx, Legacy, NoChecks, Segwitv0};
+    use super::{BareCtx, ContextLimits, CustomCtx, Legacy, NoChecks, Segwitv0};
 
     pub trait Sealed {}
 
@@ -519,4 +665,5 @@ mod private {
     impl Sealed for Legacy {}
     impl Sealed for Segwitv0 {}
     impl Sealed for NoChecks {}
+    impl<L: ContextLimits> Sealed for CustomCtx<L> {}
 }
@@ -21,6 +21,7 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use std::marker::PhantomData;
 use {bitcoin, Miniscript};
 
+use miniscript::ext::{ExtFragment, Extension, NoExt};
 use miniscript::lex::{Token as Tk, TokenIter};
 use miniscript::types::extra_props::ExtData;
 use miniscript::types::Property;
@@ -87,6 +88,11 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext> {
     Ripemd160(ripemd160::Hash),
     /// `SIZE 32 EQUALVERIFY HASH160 <hash> EQUAL`
     Hash160(hash160::Hash),
+    // covenants
+    /// `<hash> CHECKTEMPLATEVERIFY`, as proposed in BIP 119
+    ///
+    /// Only parsed, from strings and scripts, with the `ctv` feature.
+    TxTemplate(sha256::Hash),
     // Wrappers
     /// `TOALTSTACK [E] FROMALTSTACK`
     Alt(Arc<Miniscript<Pk, Ctx>>),
@@ -127,17 +133,26 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext> {
     Thresh(usize, Vec<Arc<Miniscript<Pk, Ctx>>>),
     /// k (<key>)* n CHECKMULTISIG
     Multi(usize, Vec<Pk>),
+    // Extensions
+    /// A fragment defined outside of this crate, see [Extension]
+    ///
+    /// A Miniscript holding it is only parsed back from its string and script
+    /// by the `_ext` parsing functions of [Miniscript], with the extension.
+    /// Descriptors cannot hold it.
+    Ext(ExtFragment),
 }
 
 macro_rules! match_token {
     // Base case
     ($tokens:expr => $sub:expr,) => { $sub };
     // Recursive case
-    ($tokens:expr, $($first:pat $(,$rest:pat)* => $sub:expr,)*) => {
+    ($tokens:expr, $($(#[$attr:meta])* $first:pat $(,$rest:pat)* => $sub:expr,)*) => {
         match $tokens.next() {
             $(
+                $(#[$attr])*
                 Some($first) => match_token!($tokens $(,$rest)* => $sub,),
             )*
+            Some(Tk::Op(op)) => return Err(Error::InvalidOpcode(op)),
             Some(other) => return Err(Error::Unexpected(other.to_string())),
             None => return Err(Error::UnexpectedStart),
         }
@@ -214,9 +229,17 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> TerminalStack<Pk, Ctx> {
 }
 
 /// Parse a script fragment into an `Terminal`
-#[allow(unreachable_patterns)]
 pub fn parse<Ctx: ScriptContext>(
     tokens: &mut TokenIter,
+) -> Result<Miniscript<bitcoin::PublicKey, Ctx>, Error> {
+    parse_ext::<Ctx, NoExt>(tokens)
+}
+
+/// Parse a script fragment into an `Terminal`, which may contain fragments of
+/// the extension `E`. They are tried before the fragments of this crate.
+#[allow(unreachable_patterns)]
+pub fn parse_ext<Ctx: ScriptContext, E: Extension>(
+    tokens: &mut TokenIter,
 ) -> Result<Miniscript<bitcoin::PublicKey, Ctx>, Error> {
     let mut non_term = Vec::with_capacity(tokens.len());
     let mut term = TerminalStack(Vec::with_capacity(tokens.len()));
@@ -227,6 +250,10 @@ pub fn parse<Ctx: ScriptContext>(
     loop {
         match non_term.pop() {
             Some(NonTerm::Expression) => {
+                if let Some(ext) = E::from_token_iter(tokens)? {
+                    term.reduce0(Terminal::Ext(ExtFragment::new(ext)))?;
+                    continue;
+                }
                 match_token!(
                     tokens,
                     // pubkey
@@ -303,6 +330,9 @@ pub fn parse<Ctx: ScriptContext>(
                         => term.reduce0(Terminal::Older(n))?,
                     Tk::CheckLockTimeVerify, Tk::Num(n)
                         => term.reduce0(Terminal::After(n))?,
+                    // covenants
+                    Tk::CheckTemplateVerify, Tk::Hash32(hash)
+                        => term.reduce0(Terminal::TxTemplate(sha256::Hash::from_inner(hash)))?,
                     // hashlocks
                     Tk::Equal => match_token!(
                         tokens,
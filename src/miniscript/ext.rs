@@ -0,0 +1,283 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Extensions
+//!
+//! Fragments defined outside of this crate, which a Miniscript holds as
+//! `Terminal::Ext` leaves. An extension fragment has no keys; it gives its own
+//! type properties, and its own parsing from strings and scripts, which are
+//! tried by the `_ext` parsing functions of `Miniscript`, such as
+//! `Miniscript::from_str_ext` and `Miniscript::parse_ext`. The other parsing
+//! functions reject them. It is satisfied by satisfiers implementing
+//! `Satisfier::lookup_ext`.
+//!
+//! Extension fragments cannot be lifted to policies, nor be produced by the
+//! compiler. Descriptors reject them, as descriptor strings could not be
+//! parsed back.
+//!
+
+use std::any::{Any, TypeId};
+use std::sync::Arc;
+use std::{cmp, fmt, hash};
+
+use bitcoin::blockdata::script;
+
+use expression;
+use miniscript::lex::TokenIter;
+use miniscript::types::extra_props::ExtData;
+use miniscript::types::{Correctness, Malleability};
+use Error;
+
+/// A fragment defined outside of this crate, see the module documentation
+///
+/// Its `Display` implementation gives its string form, `name(args)`, which
+/// must be parsed back by [Extension::from_name_tree]. Fragments are compared,
+/// ordered and hashed by their `Ord` and `Hash` implementations.
+pub trait Extension:
+    fmt::Display + fmt::Debug + Eq + Ord + hash::Hash + Send + Sync + 'static
+{
+    /// Correctness properties of the fragment
+    fn corr_prop(&self) -> Correctness;
+
+    /// Malleability properties of the fragment
+    fn mall_prop(&self) -> Malleability;
+
+    /// Extra properties of the fragment: script size, opcode count, witness
+    /// sizes and timelocks
+    fn extra_prop(&self) -> ExtData;
+
+    /// Pushes the script of the fragment onto `builder`
+    fn push_to_builder(&self, builder: script::Builder) -> script::Builder;
+
+    /// The witness dissatisfying the fragment, if it can be dissatisfied
+    fn dissatisfaction(&self) -> Option<Vec<Vec<u8>>> {
+        None
+    }
+
+    /// Parses the fragment from its name and arguments. Returns None if they
+    /// are not those of a fragment of the extension, and Err if they are but
+    /// the arguments are invalid.
+    fn from_name_tree(name: &str, args: &[expression::Tree]) -> Result<Option<Self>, Error>;
+
+    /// Parses the fragment from the tokens at the end of `tokens`, which are
+    /// read backward. Returns None if they are not those of a fragment of the
+    /// extension, in which case `tokens` must be left as it was given, and
+    /// Err if they are but are invalid.
+    fn from_token_iter(tokens: &mut TokenIter) -> Result<Option<Self>, Error>;
+}
+
+/// The extension with no fragments, used when parsing without extensions
+pub enum NoExt {}
+
+impl fmt::Debug for NoExt {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl fmt::Display for NoExt {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl PartialEq for NoExt {
+    fn eq(&self, _: &NoExt) -> bool {
+        match *self {}
+    }
+}
+
+impl Eq for NoExt {}
+
+impl PartialOrd for NoExt {
+    fn partial_cmp(&self, _: &NoExt) -> Option<cmp::Ordering> {
+        match *self {}
+    }
+}
+
+impl Ord for NoExt {
+    fn cmp(&self, _: &NoExt) -> cmp::Ordering {
+        match *self {}
+    }
+}
+
+impl hash::Hash for NoExt {
+    fn hash<H: hash::Hasher>(&self, _: &mut H) {
+        match *self {}
+    }
+}
+
+impl Extension for NoExt {
+    fn corr_prop(&self) -> Correctness {
+        match *self {}
+    }
+
+    fn mall_prop(&self) -> Malleability {
+        match *self {}
+    }
+
+    fn extra_prop(&self) -> ExtData {
+        match *self {}
+    }
+
+    fn push_to_builder(&self, _: script::Builder) -> script::Builder {
+        match *self {}
+    }
+
+    fn from_name_tree(_: &str, _: &[expression::Tree]) -> Result<Option<Self>, Error> {
+        Ok(None)
+    }
+
+    fn from_token_iter(_: &mut TokenIter) -> Result<Option<Self>, Error> {
+        Ok(None)
+    }
+}
+
+/// The object safe part of [Extension], through which [ExtFragment] holds
+/// fragments of any extension
+trait DynExtension: fmt::Display + fmt::Debug + Send + Sync {
+    fn corr_prop(&self) -> Correctness;
+    fn mall_prop(&self) -> Malleability;
+    fn extra_prop(&self) -> ExtData;
+    fn push_to_builder(&self, builder: script::Builder) -> script::Builder;
+    fn dissatisfaction(&self) -> Option<Vec<Vec<u8>>>;
+    fn as_any(&self) -> &dyn Any;
+    fn ext_type(&self) -> TypeId;
+    fn dyn_cmp(&self, other: &dyn DynExtension) -> cmp::Ordering;
+    fn dyn_hash(&self, state: &mut dyn hash::Hasher);
+}
+
+impl<E: Extension> DynExtension for E {
+    fn corr_prop(&self) -> Correctness {
+        Extension::corr_prop(self)
+    }
+
+    fn mall_prop(&self) -> Malleability {
+        Extension::mall_prop(self)
+    }
+
+    fn extra_prop(&self) -> ExtData {
+        Extension::extra_prop(self)
+    }
+
+    fn push_to_builder(&self, builder: script::Builder) -> script::Builder {
+        Extension::push_to_builder(self, builder)
+    }
+
+    fn dissatisfaction(&self) -> Option<Vec<Vec<u8>>> {
+        Extension::dissatisfaction(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn ext_type(&self) -> TypeId {
+        TypeId::of::<E>()
+    }
+
+    fn dyn_cmp(&self, other: &dyn DynExtension) -> cmp::Ordering {
+        match other.as_any().downcast_ref::<E>() {
+            Some(other) => self.cmp(other),
+            None => self.ext_type().cmp(&other.ext_type()),
+        }
+    }
+
+    fn dyn_hash(&self, mut state: &mut dyn hash::Hasher) {
+        self.ext_type().hash(&mut state);
+        self.hash(&mut state);
+    }
+}
+
+/// An extension fragment held by a `Terminal::Ext` leaf
+///
+/// Fragments of the same extension are compared, ordered and hashed as the
+/// extension does. Fragments of different extensions are never equal, and
+/// are ordered by their type in an unspecified order.
+#[derive(Clone)]
+pub struct ExtFragment(Arc<dyn DynExtension>);
+
+impl ExtFragment {
+    /// Wraps an extension fragment
+    pub fn new<E: Extension>(ext: E) -> ExtFragment {
+        ExtFragment(Arc::new(ext))
+    }
+
+    /// The extension fragment, if it is of type `E`
+    pub fn downcast_ref<E: Extension>(&self) -> Option<&E> {
+        self.0.as_any().downcast_ref()
+    }
+
+    /// Correctness properties of the fragment
+    pub fn corr_prop(&self) -> Correctness {
+        self.0.corr_prop()
+    }
+
+    /// Malleability properties of the fragment
+    pub fn mall_prop(&self) -> Malleability {
+        self.0.mall_prop()
+    }
+
+    /// Extra properties of the fragment
+    pub fn extra_prop(&self) -> ExtData {
+        self.0.extra_prop()
+    }
+
+    /// Pushes the script of the fragment onto `builder`
+    pub fn push_to_builder(&self, builder: script::Builder) -> script::Builder {
+        self.0.push_to_builder(builder)
+    }
+
+    /// The witness dissatisfying the fragment, if it can be dissatisfied
+    pub fn dissatisfaction(&self) -> Option<Vec<Vec<u8>>> {
+        self.0.dissatisfaction()
+    }
+}
+
+impl fmt::Debug for ExtFragment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for ExtFragment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl PartialEq for ExtFragment {
+    fn eq(&self, other: &ExtFragment) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for ExtFragment {}
+
+impl PartialOrd for ExtFragment {
+    fn partial_cmp(&self, other: &ExtFragment) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ExtFragment {
+    fn cmp(&self, other: &ExtFragment) -> cmp::Ordering {
+        self.0.dyn_cmp(&*other.0)
+    }
+}
+
+impl hash::Hash for ExtFragment {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.0.dyn_hash(state)
+    }
+}
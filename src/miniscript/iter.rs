@@ -20,6 +20,37 @@ use super::decode::Terminal;
 use super::{Miniscript, MiniscriptKey, ScriptContext};
 use std::ops::Deref;
 use std::sync::Arc;
+use util;
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
+    /// Enumerates the child nodes of the fragment, see `Miniscript::branches`
+    pub(super) fn branches(&self) -> Vec<&Miniscript<Pk, Ctx>> {
+        match *self {
+            Terminal::PkK(_) | Terminal::PkH(_) | Terminal::Multi(_, _) => vec![],
+
+            Terminal::Alt(ref node)
+            | Terminal::Swap(ref node)
+            | Terminal::Check(ref node)
+            | Terminal::DupIf(ref node)
+            | Terminal::Verify(ref node)
+            | Terminal::NonZero(ref node)
+            | Terminal::ZeroNotEqual(ref node) => vec![node],
+
+            Terminal::AndV(ref node1, ref node2)
+            | Terminal::AndB(ref node1, ref node2)
+            | Terminal::OrB(ref node1, ref node2)
+            | Terminal::OrD(ref node1, ref node2)
+            | Terminal::OrC(ref node1, ref node2)
+            | Terminal::OrI(ref node1, ref node2) => vec![node1, node2],
+
+            Terminal::AndOr(ref node1, ref node2, ref node3) => vec![node1, node2, node3],
+
+            Terminal::Thresh(_, ref node_vec) => node_vec.iter().map(Arc::deref).collect(),
+
+            _ => vec![],
+        }
+    }
+}
 
 /// Iterator-related extensions for [Miniscript]
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
@@ -54,9 +85,14 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     /// Enumerates all child nodes of the current AST node (`self`) and returns a `Vec` referencing
     /// them.
     pub fn branches(&self) -> Vec<&Miniscript<Pk, Ctx>> {
-        match self.node {
-            Terminal::PkK(_) | Terminal::PkH(_) | Terminal::Multi(_, _) => vec![],
+        self.node.branches()
+    }
 
+    /// Enumerates all child nodes of the current AST node (`self`) as the `Arc`s
+    /// holding them, which identical subtrees share after
+    /// [Miniscript::share_subtrees].
+    pub fn branch_arcs(&self) -> Vec<&Arc<Miniscript<Pk, Ctx>>> {
+        match self.node {
             Terminal::Alt(ref node)
             | Terminal::Swap(ref node)
             | Terminal::Check(ref node)
@@ -74,12 +110,33 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
 
             Terminal::AndOr(ref node1, ref node2, ref node3) => vec![node1, node2, node3],
 
-            Terminal::Thresh(_, ref node_vec) => node_vec.iter().map(Arc::deref).collect(),
+            Terminal::Thresh(_, ref node_vec) => node_vec.iter().collect(),
 
             _ => vec![],
         }
     }
 
+    /// Folds the AST in post-order: `f` is called on every node with its depth,
+    /// 0 for `self`, and the results of its branches in order. Returns the
+    /// result of `self`. The traversal does not recurse, so deep trees cannot
+    /// overflow the stack.
+    pub fn fold<T, F>(&self, f: F) -> T
+    where
+        F: FnMut(&Miniscript<Pk, Ctx>, usize, Vec<T>) -> T,
+    {
+        util::fold_post_order(self, Miniscript::branches, f)
+    }
+
+    /// Visits the AST in pre-order: `f` is called on every node with its depth,
+    /// 0 for `self`, and the branches of a node are only visited if it returns
+    /// `true`. The traversal does not recurse, as for [Miniscript::fold].
+    pub fn visit<F>(&self, f: F)
+    where
+        F: FnMut(&Miniscript<Pk, Ctx>, usize) -> bool,
+    {
+        util::visit_pre_order(self, Miniscript::branches, f)
+    }
+
     /// Returns child node with given index, if any
     pub fn get_nth_child(&self, n: usize) -> Option<&Miniscript<Pk, Ctx>> {
         match (n, &self.node) {
@@ -438,6 +495,7 @@ pub mod test {
     use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
     use bitcoin::secp256k1;
     use miniscript::context::Segwitv0;
+    use std::cmp;
 
     pub type TestData = (
         Miniscript<bitcoin::PublicKey, Segwitv0>,
@@ -658,4 +716,36 @@ pub mod test {
             );
         })
     }
+    #[test]
+    fn fold_and_visit() {
+        gen_testcases().into_iter().for_each(|(ms, k, _, _)| {
+            let mut visited = vec![];
+            let mut max_depth = 0;
+            ms.visit(|node, depth| {
+                visited.push(node);
+                max_depth = cmp::max(max_depth, depth);
+                true
+            });
+            assert_eq!(visited, ms.iter().collect::<Vec<_>>());
+            assert_eq!(
+                ms.fold(|_, depth, subs: Vec<usize>| subs.into_iter().max().unwrap_or(depth)),
+                max_depth
+            );
+
+            let keys = ms.fold(|node, _, subs: Vec<Vec<bitcoin::PublicKey>>| {
+                let mut keys = node.get_leaf_pk();
+                keys.extend(subs.concat());
+                keys
+            });
+            assert_eq!(keys, k);
+
+            // Only the root when not descending into its branches
+            let mut count = 0;
+            ms.visit(|_, _| {
+                count += 1;
+                false
+            });
+            assert_eq!(count, 1);
+        })
+    }
 }
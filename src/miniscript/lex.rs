@@ -24,6 +24,9 @@ use std::fmt;
 
 use super::Error;
 
+/// `OP_CHECKTEMPLATEVERIFY`, redefining `OP_NOP4` as proposed in BIP 119
+pub const OP_CHECKTEMPLATEVERIFY: opcodes::All = opcodes::all::OP_NOP4;
+
 /// Atom of a tokenized version of a script
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -36,6 +39,7 @@ pub enum Token {
     CheckMultiSig,
     CheckSequenceVerify,
     CheckLockTimeVerify,
+    CheckTemplateVerify,
     FromAltStack,
     ToAltStack,
     Drop,
@@ -57,6 +61,9 @@ pub enum Token {
     Hash20([u8; 20]),
     Hash32([u8; 32]),
     Pubkey(PublicKey),
+    /// An opcode not used by the fragments of this crate, which only
+    /// extensions may parse
+    Op(opcodes::All),
 }
 
 impl fmt::Display for Token {
@@ -156,6 +163,10 @@ pub fn lex(script: &script::Script) -> Result<Vec<Token>, Error> {
             script::Instruction::Op(op) if op == opcodes::all::OP_CLTV => {
                 ret.push(Token::CheckLockTimeVerify);
             }
+            #[cfg(feature = "ctv")]
+            script::Instruction::Op(op) if op == OP_CHECKTEMPLATEVERIFY => {
+                ret.push(Token::CheckTemplateVerify);
+            }
             script::Instruction::Op(opcodes::all::OP_FROMALTSTACK) => {
                 ret.push(Token::FromAltStack);
             }
@@ -299,7 +310,9 @@ pub fn lex(script: &script::Script) -> Result<Vec<Token>, Error> {
             script::Instruction::Op(opcodes::all::OP_PUSHNUM_16) => {
                 ret.push(Token::Num(16));
             }
-            script::Instruction::Op(op) => return Err(Error::InvalidOpcode(op)),
+            script::Instruction::Op(op) => {
+                ret.push(Token::Op(op));
+            }
         };
     }
     Ok(ret)
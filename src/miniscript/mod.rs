@@ -27,21 +27,30 @@
 use std::marker::PhantomData;
 use std::{fmt, str};
 
+#[cfg(all(feature = "arbitrary", feature = "compiler"))]
+use arbitrary::{self, Arbitrary, Unstructured};
 use bitcoin;
 use bitcoin::blockdata::script;
 
-pub use self::context::{BareCtx, Legacy, Segwitv0};
+pub use self::context::{BareCtx, ContextLimits, CustomCtx, Legacy, Segwitv0};
 
 pub mod analyzable;
+pub mod annotate;
 pub mod astelem;
+pub mod builder;
 pub(crate) mod context;
 pub mod decode;
+pub mod ext;
 pub mod iter;
 pub mod lex;
 pub mod limits;
+pub mod paths;
 pub mod satisfy;
+#[cfg(feature = "async")]
+pub mod satisfy_async;
 pub mod types;
 
+use self::ext::{Extension, NoExt};
 use self::lex::{lex, TokenIter};
 use self::types::Property;
 pub use miniscript::context::ScriptContext;
@@ -50,9 +59,10 @@ use miniscript::types::extra_props::ExtData;
 use miniscript::types::Type;
 
 use std::cmp;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use MiniscriptKey;
-use {expression, Error, ForEach, ForEachKey, ToPublicKey, TranslatePk};
+use {expression, util, Error, ForEach, ForEachKey, ToPublicKey, TranslatePk};
 
 /// Top-level script AST type
 #[derive(Clone, Hash)]
@@ -119,9 +129,15 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     }
 }
 
+/// The alternate format `{:#}` writes the miniscript as an indented tree,
+/// with one fragment per line annotated with its type and script size.
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Miniscript<Pk, Ctx> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.node)
+        if f.alternate() {
+            self.fmt_tree(f, 0)
+        } else {
+            write!(f, "{}", self.node)
+        }
     }
 }
 
@@ -137,6 +153,120 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     }
 }
 
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Rebuilds the miniscript so that identical subtrees share a single
+    /// `Arc`, making the whole tree as cheap to clone and hold as its distinct
+    /// fragments.
+    ///
+    /// Parsing does not do this: subtrees are compared whole, so that this
+    /// takes time quadratic in the size of the miniscript in the worst case.
+    /// It is worth it for large miniscripts with repeated subexpressions
+    /// which are held or cloned many times.
+    pub fn share_subtrees(&self) -> Miniscript<Pk, Ctx> {
+        share_node(self, &mut BTreeMap::new())
+    }
+}
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Replaces every occurrence of the fragment `pattern` by `replacement`.
+    ///
+    /// Every fragment above a replacement is type checked again, and the
+    /// result must still be a top level Miniscript within the limits of its
+    /// script context. Sanity checks are left to the caller, as for
+    /// [Miniscript::from_str_insane].
+    pub fn substitute(
+        &self,
+        pattern: &Miniscript<Pk, Ctx>,
+        replacement: &Miniscript<Pk, Ctx>,
+    ) -> Result<Miniscript<Pk, Ctx>, Error> {
+        let replacement = Arc::new(replacement.clone());
+        let ms = self.fold(
+            |ms, _, branches: Vec<Result<Arc<Miniscript<Pk, Ctx>>, Error>>| {
+                if ms == pattern {
+                    return Ok(Arc::clone(&replacement));
+                }
+                let branches = branches.into_iter().collect::<Result<Vec<_>, _>>()?;
+                Miniscript::from_ast(with_branches(&ms.node, branches)).map(Arc::new)
+            },
+        )?;
+        let ms = Arc::try_unwrap(ms).unwrap_or_else(|ms| (*ms).clone());
+
+        if ms.ty.corr.base != types::Base::B {
+            return Err(Error::NonTopLevel(format!("{:?}", ms)));
+        }
+        Ctx::check_global_validity(&ms)?;
+        Ok(ms)
+    }
+}
+
+/// The node `node` with its branches replaced, in order, by `branches`
+fn with_branches<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    node: &Terminal<Pk, Ctx>,
+    branches: Vec<Arc<Miniscript<Pk, Ctx>>>,
+) -> Terminal<Pk, Ctx> {
+    fn next<T, I: Iterator<Item = T>>(branches: &mut I) -> T {
+        branches
+            .next()
+            .expect("a branch for every child of the node")
+    }
+
+    let mut branches = branches.into_iter();
+    match *node {
+        Terminal::Alt(..) => Terminal::Alt(next(&mut branches)),
+        Terminal::Swap(..) => Terminal::Swap(next(&mut branches)),
+        Terminal::Check(..) => Terminal::Check(next(&mut branches)),
+        Terminal::DupIf(..) => Terminal::DupIf(next(&mut branches)),
+        Terminal::Verify(..) => Terminal::Verify(next(&mut branches)),
+        Terminal::NonZero(..) => Terminal::NonZero(next(&mut branches)),
+        Terminal::ZeroNotEqual(..) => Terminal::ZeroNotEqual(next(&mut branches)),
+        Terminal::AndV(..) => Terminal::AndV(next(&mut branches), next(&mut branches)),
+        Terminal::AndB(..) => Terminal::AndB(next(&mut branches), next(&mut branches)),
+        Terminal::AndOr(..) => Terminal::AndOr(
+            next(&mut branches),
+            next(&mut branches),
+            next(&mut branches),
+        ),
+        Terminal::OrB(..) => Terminal::OrB(next(&mut branches), next(&mut branches)),
+        Terminal::OrD(..) => Terminal::OrD(next(&mut branches), next(&mut branches)),
+        Terminal::OrC(..) => Terminal::OrC(next(&mut branches), next(&mut branches)),
+        Terminal::OrI(..) => Terminal::OrI(next(&mut branches), next(&mut branches)),
+        Terminal::Thresh(k, _) => Terminal::Thresh(k, branches.collect()),
+        ref leaf => leaf.clone(),
+    }
+}
+
+/// The miniscript `ms` with its branches taken from (or added to) `cache`
+fn share_node<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+    cache: &mut BTreeMap<Miniscript<Pk, Ctx>, Arc<Miniscript<Pk, Ctx>>>,
+) -> Miniscript<Pk, Ctx> {
+    let branches = ms
+        .branches()
+        .into_iter()
+        .map(|branch| intern(branch, cache))
+        .collect();
+    Miniscript {
+        node: with_branches(&ms.node, branches),
+        ty: ms.ty,
+        ext: ms.ext,
+        phantom: PhantomData,
+    }
+}
+
+/// The shared `Arc` of the miniscript `ms`
+fn intern<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+    cache: &mut BTreeMap<Miniscript<Pk, Ctx>, Arc<Miniscript<Pk, Ctx>>>,
+) -> Arc<Miniscript<Pk, Ctx>> {
+    let ms = share_node(ms, cache);
+    if let Some(shared) = cache.get(&ms) {
+        return Arc::clone(shared);
+    }
+    let shared = Arc::new(ms.clone());
+    cache.insert(ms, Arc::clone(&shared));
+    shared
+}
+
 impl<Ctx: ScriptContext> Miniscript<bitcoin::PublicKey, Ctx> {
     /// Attempt to parse an insane(scripts don't clear sanity checks)
     /// script into a Miniscript representation.
@@ -147,11 +277,19 @@ impl<Ctx: ScriptContext> Miniscript<bitcoin::PublicKey, Ctx> {
     /// accept sane scripts.
     pub fn parse_insane(
         script: &script::Script,
+    ) -> Result<Miniscript<bitcoin::PublicKey, Ctx>, Error> {
+        Self::parse_insane_ext::<NoExt>(script)
+    }
+
+    /// Attempt to parse an insane script, as [Miniscript::parse_insane] does,
+    /// into a Miniscript which may contain fragments of the extension `E`
+    pub fn parse_insane_ext<E: Extension>(
+        script: &script::Script,
     ) -> Result<Miniscript<bitcoin::PublicKey, Ctx>, Error> {
         let tokens = lex(script)?;
         let mut iter = TokenIter::new(tokens);
 
-        let top = decode::parse(&mut iter)?;
+        let top = decode::parse_ext::<Ctx, E>(&mut iter)?;
         Ctx::check_global_validity(&top)?;
         let type_check = types::Type::type_check(&top.node, |_| None)?;
         if type_check.corr.base != types::Base::B {
@@ -169,7 +307,15 @@ impl<Ctx: ScriptContext> Miniscript<bitcoin::PublicKey, Ctx> {
     /// the [Miniscript::sanity_check] checks. Use [Miniscript::parse_insane] to
     /// parse such scripts.
     pub fn parse(script: &script::Script) -> Result<Miniscript<bitcoin::PublicKey, Ctx>, Error> {
-        let ms = Self::parse_insane(script)?;
+        Self::parse_ext::<NoExt>(script)
+    }
+
+    /// Attempt to parse a Script, as [Miniscript::parse] does, into a
+    /// Miniscript which may contain fragments of the extension `E`
+    pub fn parse_ext<E: Extension>(
+        script: &script::Script,
+    ) -> Result<Miniscript<bitcoin::PublicKey, Ctx>, Error> {
+        let ms = Self::parse_insane_ext::<E>(script)?;
         ms.sanity_check()?;
         Ok(ms)
     }
@@ -232,6 +378,79 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     }
 }
 
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Maximum number of witness elements used to satisfy the Miniscript, as
+    /// [Miniscript::max_satisfaction_witness_elements], but only along the
+    /// branches that can be satisfied with the items `can_provide` accepts
+    pub fn max_satisfaction_witness_elements_assuming<F>(
+        &self,
+        can_provide: F,
+    ) -> Result<usize, Error>
+    where
+        F: FnMut(&satisfy::MissingItem<Pk>) -> bool,
+    {
+        self.assuming(can_provide)
+            .max_satisfaction_witness_elements()
+    }
+
+    /// Maximum size, in bytes, of a satisfying witness, as
+    /// [Miniscript::max_satisfaction_size], but only along the branches that
+    /// can be satisfied with the items `can_provide` accepts. A wallet that
+    /// can only ever take the cheap branch of a script thus does not pay for
+    /// the expensive one in its fee estimates.
+    pub fn max_satisfaction_size_assuming<F>(&self, can_provide: F) -> Result<usize, Error>
+    where
+        F: FnMut(&satisfy::MissingItem<Pk>) -> bool,
+    {
+        self.assuming(can_provide).max_satisfaction_size()
+    }
+
+    /// The miniscript with the satisfaction costs of the fragments that
+    /// cannot be satisfied with the items `can_provide` accepts removed
+    fn assuming<F>(&self, mut can_provide: F) -> Miniscript<Pk, Ctx>
+    where
+        F: FnMut(&satisfy::MissingItem<Pk>) -> bool,
+    {
+        use self::satisfy::MissingItem;
+
+        let ms = self.fold(|ms, _, branches: Vec<Arc<Miniscript<Pk, Ctx>>>| {
+            let node = with_branches(&ms.node, branches);
+            let mut ext = ExtData::type_check(&node, |_| None).expect("type checked before");
+            let satisfiable = match node {
+                Terminal::PkK(ref pk) => can_provide(&MissingItem::Signature(pk.clone())),
+                Terminal::PkH(ref pkh) => can_provide(&MissingItem::KeyHashSignature(pkh.clone())),
+                Terminal::After(n) => can_provide(&MissingItem::After(n)),
+                Terminal::Older(n) => can_provide(&MissingItem::Older(n)),
+                Terminal::Sha256(h) => can_provide(&MissingItem::Sha256Preimage(h)),
+                Terminal::Hash256(h) => can_provide(&MissingItem::Hash256Preimage(h)),
+                Terminal::Ripemd160(h) => can_provide(&MissingItem::Ripemd160Preimage(h)),
+                Terminal::Hash160(h) => can_provide(&MissingItem::Hash160Preimage(h)),
+                Terminal::TxTemplate(h) => can_provide(&MissingItem::TxTemplate(h)),
+                Terminal::Ext(ref e) => can_provide(&MissingItem::Ext(e.clone())),
+                Terminal::Multi(k, ref keys) => {
+                    keys.iter()
+                        .filter(|pk| can_provide(&MissingItem::Signature((*pk).clone())))
+                        .count()
+                        >= k
+                }
+                _ => true,
+            };
+            if !satisfiable {
+                ext.ops_count_sat = None;
+                ext.stack_elem_count_sat = None;
+                ext.max_sat_size = None;
+            }
+            Arc::new(Miniscript {
+                node: node,
+                ty: ms.ty,
+                ext: ext,
+                phantom: PhantomData,
+            })
+        });
+        Arc::try_unwrap(ms).unwrap_or_else(|ms| (*ms).clone())
+    }
+}
+
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> ForEachKey<Pk> for Miniscript<Pk, Ctx> {
     fn for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, mut pred: F) -> bool
     where
@@ -240,6 +459,10 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> ForEachKey<Pk> for Miniscript<Pk, Ct
     {
         self.real_for_each_key(&mut pred)
     }
+
+    fn key_path(&self, index: usize) -> Option<Vec<usize>> {
+        self.node.key_path(index)
+    }
 }
 
 impl<Pk: MiniscriptKey, Q: MiniscriptKey, Ctx: ScriptContext> TranslatePk<Pk, Q>
@@ -302,6 +525,19 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     /// insane scripts. In general, in a multi-party setting users should only
     /// accept sane scripts.
     pub fn from_str_insane(s: &str) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        Pk: str::FromStr,
+        Pk::Hash: str::FromStr,
+        <Pk as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    {
+        Self::from_str_insane_ext::<NoExt>(s)
+    }
+
+    /// Attempt to parse an insane string, as [Miniscript::from_str_insane]
+    /// does, into a Miniscript which may contain fragments of the extension
+    /// `E`
+    pub fn from_str_insane_ext<E: Extension>(s: &str) -> Result<Miniscript<Pk, Ctx>, Error>
     where
         Pk: str::FromStr,
         Pk::Hash: str::FromStr,
@@ -310,7 +546,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     {
         // This checks for invalid ASCII chars
         let top = expression::Tree::from_str(s)?;
-        let ms: Miniscript<Pk, Ctx> = expression::FromTree::from_tree(&top)?;
+        let ms = Miniscript::from_tree_ext::<E>(&top)?;
 
         if ms.ty.corr.base != types::Base::B {
             Err(Error::NonTopLevel(format!("{:?}", ms)))
@@ -318,6 +554,20 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
             Ok(ms)
         }
     }
+
+    /// Attempt to parse a string, as `Miniscript::from_str` does, into a
+    /// Miniscript which may contain fragments of the extension `E`
+    pub fn from_str_ext<E: Extension>(s: &str) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        Pk: str::FromStr,
+        Pk::Hash: str::FromStr,
+        <Pk as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    {
+        let ms = Self::from_str_insane_ext::<E>(s)?;
+        ms.sanity_check()?;
+        Ok(ms)
+    }
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
@@ -338,6 +588,23 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         }
     }
 
+    /// The items `satisfier` lacks to satisfy the Miniscript: signatures,
+    /// hash preimages and timelocks, as one set for each way of satisfying it.
+    ///
+    /// Only the minimal sets are returned, smallest first, and a single empty
+    /// set means the Miniscript can be satisfied. The sets follow what each
+    /// fragment requires and do not account for malleability, so `satisfy` may
+    /// still fail where `satisfy_malleable` would not.
+    pub fn missing_items<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+    ) -> Vec<Vec<satisfy::MissingItem<Pk>>>
+    where
+        Pk: ToPublicKey,
+    {
+        satisfy::missing_items(self, &satisfier)
+    }
+
     /// Attempt to produce a malleable satisfying witness for the
     /// witness script represented by the parse tree
     pub fn satisfy_malleable<S: satisfy::Satisfier<Pk>>(
@@ -383,19 +650,50 @@ where
     /// Parse an expression tree into a Miniscript. As a general rule, this
     /// should not be called directly; rather go through the descriptor API.
     fn from_tree(top: &expression::Tree) -> Result<Miniscript<Pk, Ctx>, Error> {
-        let inner: Terminal<Pk, Ctx> = expression::FromTree::from_tree(top)?;
-        Ok(Miniscript {
-            ty: Type::type_check(&inner, |_| None)?,
-            ext: ExtData::type_check(&inner, |_| None)?,
-            node: inner,
-            phantom: PhantomData,
-        })
+        Miniscript::from_tree_ext::<NoExt>(top)
+    }
+}
+
+impl<Pk, Ctx> Miniscript<Pk, Ctx>
+where
+    Pk: MiniscriptKey + str::FromStr,
+    Pk::Hash: str::FromStr,
+    Ctx: ScriptContext,
+    <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+{
+    /// Parse an expression tree into a Miniscript which may contain fragments
+    /// of the extension `E`
+    ///
+    /// The tree is folded bottom-up without recursion, so that deep trees
+    /// cannot overflow the call stack.
+    fn from_tree_ext<E: Extension>(top: &expression::Tree) -> Result<Miniscript<Pk, Ctx>, Error> {
+        type Parsed<Pk, Ctx> = Result<Arc<Miniscript<Pk, Ctx>>, Error>;
+        let ms = util::fold_post_order(
+            top,
+            astelem::sub_trees,
+            |tree, _, subs| -> Parsed<Pk, Ctx> {
+                let subs = subs.into_iter().collect::<Result<Vec<_>, _>>()?;
+                let inner = tree.spanned(Terminal::from_tree_with_subs::<E>(tree, subs))?;
+                let ty = tree.spanned(Type::type_check(&inner, |_| None).map_err(Error::from))?;
+                let ext =
+                    tree.spanned(ExtData::type_check(&inner, |_| None).map_err(Error::from))?;
+                Ok(Arc::new(Miniscript {
+                    ty: ty,
+                    ext: ext,
+                    node: inner,
+                    phantom: PhantomData,
+                }))
+            },
+        )?;
+        Ok(Arc::try_unwrap(ms).unwrap_or_else(|ms| (*ms).clone()))
     }
 }
 
 /// Parse a Miniscript from string and perform sanity checks
 /// See [Miniscript::from_str_insane] to parse scripts from string that
-/// do not clear the [Miniscript::sanity_check] checks.
+/// do not clear the [Miniscript::sanity_check] checks, and
+/// [Miniscript::from_str_ext] to parse extension fragments.
 impl<Pk, Ctx> str::FromStr for Miniscript<Pk, Ctx>
 where
     Pk: MiniscriptKey + str::FromStr,
@@ -407,17 +705,35 @@ where
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Miniscript<Pk, Ctx>, Error> {
-        let ms = Self::from_str_insane(s)?;
-        ms.sanity_check()?;
-        Ok(ms)
+        Self::from_str_ext::<NoExt>(s)
     }
 }
 
 serde_string_impl_pk!(Miniscript, "a miniscript", Ctx; ScriptContext);
 
+/// The compilations of arbitrary concrete policies. Policies which do not
+/// compile, for instance because a spending path requires no signature, are
+/// rejected as `IncorrectFormat`.
+#[cfg(all(feature = "arbitrary", feature = "compiler"))]
+impl<'a, Pk, Ctx> Arbitrary<'a> for Miniscript<Pk, Ctx>
+where
+    Pk: MiniscriptKey + Arbitrary<'a>,
+    Ctx: ScriptContext,
+{
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        use policy::Concrete;
+
+        Concrete::<Pk>::arbitrary(u)?
+            .compile()
+            .map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Segwitv0;
+    use super::analyzable::{LimitUsage, ResourceLimit};
+    use super::satisfy::{self, MissingItem};
+    use super::{ContextLimits, CustomCtx, Segwitv0};
     use super::{Miniscript, ScriptContext};
     use hex_script;
     use miniscript::types::{self, ExtData, Property, Type};
@@ -428,6 +744,7 @@ mod tests {
 
     use bitcoin::hashes::{hash160, sha256, Hash};
     use bitcoin::{self, secp256k1};
+    use std::collections::HashMap;
     use std::str;
     use std::str::FromStr;
     use std::sync::Arc;
@@ -900,6 +1217,187 @@ mod tests {
         .is_err());
     }
 
+    #[test]
+    fn shared_subtrees() {
+        let ms = Segwitv0Script::from_str_insane(
+            "or_i(and_v(v:pk(028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa),older(9)),and_v(v:pk(028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa),older(9)))",
+        )
+        .unwrap();
+        // Parsing does not share subtrees
+        let branches = ms.branch_arcs();
+        assert_eq!(branches[0], branches[1]);
+        assert!(!Arc::ptr_eq(branches[0], branches[1]));
+
+        let shared = ms.share_subtrees();
+        assert_eq!(shared, ms);
+        assert!(Arc::ptr_eq(
+            shared.branch_arcs()[0],
+            shared.branch_arcs()[1]
+        ));
+    }
+
+    #[test]
+    fn substitute() {
+        let keys = pubkeys(2);
+        let ms = Segwitv0Script::from_str(&format!(
+            "or_d(pk({a}),and_v(v:pk({b}),older(10)))",
+            a = keys[0],
+            b = keys[1]
+        ))
+        .unwrap();
+
+        // Timelock update
+        let older = |n| Segwitv0Script::from_ast(Terminal::Older(n)).unwrap();
+        let expected = Segwitv0Script::from_str(&format!(
+            "or_d(pk({a}),and_v(v:pk({b}),older(20)))",
+            a = keys[0],
+            b = keys[1]
+        ))
+        .unwrap();
+        assert_eq!(ms.substitute(&older(10), &older(20)).unwrap(), expected);
+        // Nothing to replace
+        assert_eq!(ms.substitute(&older(30), &older(20)).unwrap(), ms);
+
+        // Key rotation, including the type checks
+        let pk = |key| Segwitv0Script::from_ast(Terminal::PkK(key)).unwrap();
+        let expected = Segwitv0Script::from_str_insane(&format!(
+            "or_d(pk({b}),and_v(v:pk({b}),older(10)))",
+            b = keys[1]
+        ))
+        .unwrap();
+        assert_eq!(ms.substitute(&pk(keys[0]), &pk(keys[1])).unwrap(), expected);
+        assert!(ms.substitute(&pk(keys[0]), &older(10)).is_err());
+    }
+
+    #[test]
+    fn missing_items() {
+        let keys = pubkeys(4);
+        let ms = Segwitv0Script::from_str(&format!(
+            "or_d(multi(2,{},{},{}),and_v(v:pk({}),older(100)))",
+            keys[0], keys[1], keys[2], keys[3]
+        ))
+        .unwrap();
+
+        let secp = secp256k1::Secp256k1::signing_only();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+
+        let mut sigs = HashMap::new();
+        sigs.insert(keys[0], sig);
+        // Either of the other multisig keys, or the timelocked key
+        let missing = ms.missing_items(&sigs);
+        assert_eq!(missing.len(), 3);
+        assert!(missing.contains(&vec![MissingItem::Signature(keys[1])]));
+        assert!(missing.contains(&vec![MissingItem::Signature(keys[2])]));
+        assert_eq!(
+            missing[2],
+            vec![MissingItem::Signature(keys[3]), MissingItem::Older(100)]
+        );
+
+        sigs.insert(keys[3], sig);
+        assert_eq!(
+            ms.missing_items((&sigs, satisfy::Older(100))),
+            vec![Vec::<MissingItem<bitcoin::PublicKey>>::new()]
+        );
+        assert!(ms.satisfy((&sigs, satisfy::Older(100))).is_ok());
+    }
+
+    #[test]
+    fn max_satisfaction_size_assuming() {
+        let keys = pubkeys(2);
+        let ms = Segwitv0Script::from_str(&format!(
+            "or_d(pk({}),and_v(v:pk({}),older(100)))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 74);
+        assert_eq!(ms.max_satisfaction_witness_elements().unwrap(), 3);
+
+        // Only the first branch
+        let first =
+            |item: &MissingItem<bitcoin::PublicKey>| *item == MissingItem::Signature(keys[0]);
+        assert_eq!(ms.max_satisfaction_size_assuming(first).unwrap(), 73);
+        assert_eq!(
+            ms.max_satisfaction_witness_elements_assuming(first)
+                .unwrap(),
+            2
+        );
+        // Only the second branch, once the timelock is reached
+        assert_eq!(
+            ms.max_satisfaction_size_assuming(|item| *item != MissingItem::Signature(keys[0]))
+                .unwrap(),
+            74
+        );
+        // No branch
+        assert!(ms
+            .max_satisfaction_size_assuming(|item| match *item {
+                MissingItem::Older(_) => false,
+                _ => *item != MissingItem::Signature(keys[0]),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn analyze() {
+        let keys = pubkeys(1);
+        let ms = Segwitv0Script::from_str(&format!("and_v(v:pk({}),older(9))", keys[0])).unwrap();
+        let analysis = ms.analyze();
+        assert_eq!(analysis.ops_count, Some(2));
+        assert_eq!(analysis.max_witness_elements, Some(2));
+        assert_eq!(analysis.max_satisfaction_size, Some(73));
+        assert_eq!(analysis.script_size, 37);
+        assert_eq!(analysis.pk_count, 1);
+        assert!(analysis.timelocks.csv_with_height);
+        assert!(!analysis.timelocks.cltv_with_height);
+        assert!(analysis.requires_sig);
+        assert!(analysis.non_malleable);
+        assert!(!analysis.repeated_keys);
+        assert_eq!(
+            analysis.limits,
+            vec![
+                LimitUsage {
+                    limit: ResourceLimit::WitnessElements,
+                    used: 2,
+                    max: 100,
+                },
+                LimitUsage {
+                    limit: ResourceLimit::ScriptSize,
+                    used: 37,
+                    max: 3600,
+                },
+                LimitUsage {
+                    limit: ResourceLimit::OpCount,
+                    used: 2,
+                    max: 201,
+                },
+            ]
+        );
+        assert!(analysis.exceeded_limits().is_empty());
+
+        let ms = Segwitv0Script::from_str_insane("and_v(v:1,0)").unwrap();
+        let analysis = ms.analyze();
+        assert_eq!(analysis.max_satisfaction_size, None);
+        assert!(!analysis.requires_sig);
+        assert_eq!(
+            analysis.closest_limit().map(|l| l.limit),
+            Some(ResourceLimit::ScriptSize)
+        );
+    }
+
+    #[test]
+    fn deep_miniscript() {
+        let depth = 300;
+        let mut s = "and_v(v:1,".repeat(depth);
+        s.push('0');
+        s.push_str(&")".repeat(depth));
+
+        let ms = Segwitv0Script::from_str_insane(&s).unwrap();
+        assert_eq!(ms.to_string(), s);
+        assert_eq!(ms.encode().len(), 2 * depth + 1);
+        assert_eq!(Segwitv0Script::parse_insane(&ms.encode()).unwrap(), ms);
+    }
+
     #[test]
     fn non_ascii() {
         assert!(Segwitv0Script::from_str_insane("🌏")
@@ -907,4 +1405,214 @@ mod tests {
             .to_string()
             .contains("unprintable character"));
     }
+
+    #[test]
+    fn custom_context() {
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        enum Tiny {}
+        impl ContextLimits for Tiny {
+            const MAX_OPS: usize = 2;
+            const MAX_SCRIPT_SIZE: usize = 80;
+            const MAX_STACK_ITEMS: usize = 3;
+        }
+        type TinyScript = Miniscript<bitcoin::PublicKey, CustomCtx<Tiny>>;
+
+        let keys = pubkeys(3);
+        let ms =
+            TinyScript::from_str(&format!("and_v(v:pk({}),pk({}))", keys[0], keys[1])).unwrap();
+        assert_eq!(
+            ms.analyze().limits[0],
+            LimitUsage {
+                limit: ResourceLimit::OpCount,
+                used: 2,
+                max: 2,
+            }
+        );
+
+        // Too large a script
+        assert!(TinyScript::from_str_insane(&format!(
+            "multi(1,{},{},{})",
+            keys[0], keys[1], keys[2]
+        ))
+        .is_err());
+        // Too many opcodes
+        let s = format!("and_v(v:pk({}),and_v(v:older(1),pk({})))", keys[0], keys[1]);
+        let ms = TinyScript::from_str_insane(&s).unwrap();
+        assert!(!ms.within_resource_limits());
+        assert!(TinyScript::from_str(&s).is_err());
+        assert!(Segwitv0Script::from_str(&s).is_ok());
+    }
+
+    #[cfg(feature = "ctv")]
+    #[test]
+    fn tx_template() {
+        struct Template(sha256::Hash);
+        impl satisfy::Satisfier<bitcoin::PublicKey> for Template {
+            fn check_tx_template(&self, h: sha256::Hash) -> bool {
+                h == self.0
+            }
+        }
+
+        let hash = sha256::Hash::hash(&[0x42]);
+        let ms = Segwitv0Script::from_str_insane(&format!("txtemplate({})", hash)).unwrap();
+        assert_eq!(ms.to_string(), format!("txtemplate({})", hash));
+        assert_eq!(ms.ty.corr.base, types::Base::B);
+        assert_eq!(ms.ty.corr.input, types::Input::Zero);
+        assert!(!ms.ty.corr.dissatisfiable);
+        assert!(ms.ty.mall.non_malleable);
+        assert!(!ms.ty.mall.safe);
+        assert_eq!(ms.encode().len(), 34);
+        assert_eq!(Segwitv0Script::parse_insane(&ms.encode()).unwrap(), ms);
+        assert_eq!(ms.lift().unwrap(), ::policy::Semantic::TxTemplate(hash));
+
+        assert_eq!(ms.satisfy(Template(hash)).unwrap(), Vec::<Vec<u8>>::new());
+        let other = sha256::Hash::hash(&[0x43]);
+        assert!(ms.satisfy(Template(other)).is_err());
+        assert_eq!(
+            ms.missing_items(()),
+            vec![vec![MissingItem::TxTemplate(hash)]]
+        );
+
+        let key = pubkeys(1)[0];
+        let ms = Segwitv0Script::from_str(&format!("and_v(v:pk({}),txtemplate({}))", key, hash))
+            .unwrap();
+        #[cfg(feature = "compiler")]
+        {
+            let policy = ::policy::Concrete::<bitcoin::PublicKey>::from_str(&format!(
+                "and(pk({}),txtemplate({}))",
+                key, hash
+            ))
+            .unwrap();
+            let compiled: Segwitv0Script = policy.compile().unwrap();
+            let lifted = compiled.lift().unwrap();
+            assert!(lifted.is_equivalent(&ms.lift().unwrap()).unwrap());
+        }
+    }
+
+    #[cfg(not(feature = "ctv"))]
+    #[test]
+    fn tx_template_disabled() {
+        let hash = sha256::Hash::hash(&[0x42]);
+        let s = format!("txtemplate({})", hash);
+        assert!(Segwitv0Script::from_str_insane(&s).is_err());
+        assert!(::policy::Semantic::<bitcoin::PublicKey>::from_str(&s).is_err());
+        assert!(::policy::Concrete::<bitcoin::PublicKey>::from_str(&s).is_err());
+
+        let script = bitcoin::blockdata::script::Builder::new()
+            .push_slice(&hash[..])
+            .push_opcode(super::lex::OP_CHECKTEMPLATEVERIFY)
+            .into_script();
+        assert!(Segwitv0Script::parse_insane(&script).is_err());
+    }
+
+    #[test]
+    fn extension() {
+        use bitcoin::blockdata::{opcodes, script};
+        use expression;
+        use miniscript::ext::{ExtFragment, Extension};
+        use miniscript::lex::{Token, TokenIter};
+        use miniscript::types::extra_props::TimeLockInfo;
+        use miniscript::types::{Correctness, Malleability};
+        use std::fmt;
+        use Error;
+
+        /// OP_NOP10, a verify fragment with an empty satisfaction
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct Nop10;
+        impl fmt::Display for Nop10 {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("nop10")
+            }
+        }
+        impl Extension for Nop10 {
+            fn corr_prop(&self) -> Correctness {
+                Correctness {
+                    base: types::Base::V,
+                    input: types::Input::Zero,
+                    dissatisfiable: false,
+                    unit: false,
+                }
+            }
+
+            fn mall_prop(&self) -> Malleability {
+                Malleability {
+                    dissat: types::Dissat::None,
+                    safe: false,
+                    non_malleable: true,
+                }
+            }
+
+            fn extra_prop(&self) -> ExtData {
+                ExtData {
+                    pk_cost: 1,
+                    has_free_verify: false,
+                    ops_count_static: 1,
+                    ops_count_sat: Some(1),
+                    ops_count_nsat: None,
+                    stack_elem_count_sat: Some(0),
+                    stack_elem_count_dissat: None,
+                    max_sat_size: Some((0, 0)),
+                    max_dissat_size: None,
+                    timelock_info: TimeLockInfo::default(),
+                }
+            }
+
+            fn push_to_builder(&self, builder: script::Builder) -> script::Builder {
+                builder.push_opcode(opcodes::all::OP_NOP10)
+            }
+
+            fn from_name_tree(
+                name: &str,
+                args: &[expression::Tree],
+            ) -> Result<Option<Self>, Error> {
+                match (name, args.len()) {
+                    ("nop10", 0) => Ok(Some(Nop10)),
+                    ("nop10", n) => Err(Error::Unexpected(format!("nop10({} args)", n))),
+                    _ => Ok(None),
+                }
+            }
+
+            fn from_token_iter(tokens: &mut TokenIter) -> Result<Option<Self>, Error> {
+                match tokens.peek() {
+                    Some(&Token::Op(opcodes::all::OP_NOP10)) => {
+                        tokens.next();
+                        Ok(Some(Nop10))
+                    }
+                    _ => Ok(None),
+                }
+            }
+        }
+
+        struct Nop10Satisfier;
+        impl satisfy::Satisfier<bitcoin::PublicKey> for Nop10Satisfier {
+            fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+                e.downcast_ref::<Nop10>().map(|_| vec![])
+            }
+        }
+
+        let ms = Segwitv0Script::from_str_insane_ext::<Nop10>("t:nop10").unwrap();
+        assert_eq!(ms.to_string(), "t:nop10");
+        assert_eq!(
+            ms.node,
+            Terminal::AndV(
+                Arc::new(Segwitv0Script::from_ast(Terminal::Ext(ExtFragment::new(Nop10))).unwrap()),
+                Arc::new(Segwitv0Script::from_ast(Terminal::True).unwrap()),
+            )
+        );
+        assert_eq!(ms.encode(), hex_script("b951"));
+        assert_eq!(ms.script_size(), 2);
+        assert_eq!(
+            Segwitv0Script::parse_insane_ext::<Nop10>(&ms.encode()).unwrap(),
+            ms
+        );
+        assert!(Segwitv0Script::from_str_insane("t:nop10").is_err());
+        assert!(Segwitv0Script::from_str_insane_ext::<Nop10>("t:nop10(1)").is_err());
+        assert!(Segwitv0Script::parse_insane(&ms.encode()).is_err());
+        assert!(ms.lift().is_err());
+        assert!(::Descriptor::new_wsh(ms.clone()).is_err());
+        assert_eq!(ExtFragment::new(Nop10), ExtFragment::new(Nop10));
+
+        assert_eq!(ms.satisfy(Nop10Satisfier).unwrap(), Vec::<Vec<u8>>::new());
+        assert!(ms.satisfy(()).is_err());
+    }
 }
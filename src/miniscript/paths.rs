@@ -0,0 +1,340 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Satisfaction Paths
+//!
+//! Enumeration of the different ways a Miniscript can be satisfied, i.e. the
+//! combinations of branches taken at each disjunction, along with the
+//! worst-case witness size of each of them.
+//!
+
+use std::vec;
+
+use miniscript::satisfy::{self, MissingItem};
+use policy::semantic::Policy;
+use policy::Liftable;
+use {Miniscript, MiniscriptKey, ScriptContext, Terminal};
+
+/// A way of satisfying a Miniscript
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SatisfactionPath<Pk: MiniscriptKey> {
+    /// The leaf fragments satisfied along the path, in script order, lifted
+    /// to semantic policies
+    pub conditions: Vec<Policy<Pk>>,
+    /// Maximum size, in bytes, of a satisfying witness stack along the path,
+    /// for segwit contexts
+    pub max_witness_size: usize,
+    /// Maximum size, in bytes, of a satisfying scriptSig along the path, for
+    /// pre-segwit contexts
+    pub max_scriptsig_size: usize,
+}
+
+impl<Pk: MiniscriptKey> SatisfactionPath<Pk> {
+    fn empty() -> Self {
+        SatisfactionPath {
+            conditions: vec![],
+            max_witness_size: 0,
+            max_scriptsig_size: 0,
+        }
+    }
+
+    /// Adds `(witness, scriptsig)` bytes to the path
+    fn grow(mut self, (witness, scriptsig): (usize, usize)) -> Self {
+        self.max_witness_size += witness;
+        self.max_scriptsig_size += scriptsig;
+        self
+    }
+
+    /// Concatenates two paths through consecutive fragments
+    fn join(mut self, other: &SatisfactionPath<Pk>) -> Self {
+        self.conditions.extend(other.conditions.iter().cloned());
+        self.max_witness_size += other.max_witness_size;
+        self.max_scriptsig_size += other.max_scriptsig_size;
+        self
+    }
+}
+
+/// Iterator over the witness shapes of a Miniscript, returned by
+/// [Miniscript::witness_shapes]
+#[derive(Clone, Debug)]
+pub struct WitnessShapes<Pk: MiniscriptKey> {
+    shapes: vec::IntoIter<Vec<MissingItem<Pk>>>,
+}
+
+impl<Pk: MiniscriptKey> Iterator for WitnessShapes<Pk> {
+    type Item = Vec<MissingItem<Pk>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.shapes.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.shapes.size_hint()
+    }
+}
+
+impl<Pk: MiniscriptKey> ExactSizeIterator for WitnessShapes<Pk> {}
+
+/// Adds the dissatisfaction cost of `ms`, if it has one, to every path
+fn dissatisfy<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+    paths: Vec<SatisfactionPath<Pk>>,
+) -> Vec<SatisfactionPath<Pk>> {
+    match ms.ext.max_dissat_size {
+        Some(size) => paths.into_iter().map(|path| path.grow(size)).collect(),
+        None => vec![],
+    }
+}
+
+/// All the combinations of a path through each of `left` and `right`
+fn product<Pk: MiniscriptKey>(
+    left: &[SatisfactionPath<Pk>],
+    right: &[SatisfactionPath<Pk>],
+) -> Vec<SatisfactionPath<Pk>> {
+    let mut ret = Vec::with_capacity(left.len() * right.len());
+    for l in left {
+        for r in right {
+            ret.push(l.clone().join(r));
+        }
+    }
+    ret
+}
+
+/// The paths satisfying `k` of `subs`, dissatisfying the others
+fn threshold<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    k: usize,
+    subs: &[&Miniscript<Pk, Ctx>],
+    prefix: SatisfactionPath<Pk>,
+) -> Vec<SatisfactionPath<Pk>> {
+    let (first, rest) = match subs.split_first() {
+        Some((first, rest)) => (first, rest),
+        None if k == 0 => return vec![prefix],
+        None => return vec![],
+    };
+    if k > subs.len() {
+        return vec![];
+    }
+
+    let mut ret = vec![];
+    if k > 0 {
+        for path in product(&[prefix.clone()], &first.satisfaction_paths()) {
+            ret.extend(threshold(k - 1, rest, path));
+        }
+    }
+    if let Some(size) = first.ext.max_dissat_size {
+        ret.extend(threshold(k, rest, prefix.grow(size)));
+    }
+    ret
+}
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Iterates over the distinct minimal witness shapes of the Miniscript:
+    /// the sets of signatures, preimages and timelocks which satisfy it,
+    /// where no set includes another one. Shapes are sorted, smallest first,
+    /// and each of them has its items sorted.
+    ///
+    /// Unlike [Miniscript::satisfaction_paths], paths requiring the same
+    /// items are only reported once, and so are paths requiring more items
+    /// than some other one.
+    pub fn witness_shapes(&self) -> WitnessShapes<Pk> {
+        WitnessShapes {
+            shapes: satisfy::missing_items_with(self, |_| false).into_iter(),
+        }
+    }
+
+    /// Enumerates the ways of satisfying the Miniscript
+    ///
+    /// A path is a choice of branch at each disjunction and of satisfied
+    /// subexpressions at each threshold, described by the leaf fragments it
+    /// satisfies. The worst-case witness sizes of all the paths add up the same
+    /// way as `max_satisfaction_size`, which is the maximum over all paths.
+    ///
+    /// The number of paths grows exponentially with the number of nested
+    /// disjunctions and thresholds.
+    pub fn satisfaction_paths(&self) -> Vec<SatisfactionPath<Pk>> {
+        match self.node {
+            Terminal::True => vec![SatisfactionPath::empty()],
+            Terminal::False => vec![],
+            Terminal::PkK(..)
+            | Terminal::PkH(..)
+            | Terminal::After(..)
+            | Terminal::Older(..)
+            | Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..)
+            | Terminal::Multi(..) => {
+                let leaf = self.node.lift().expect("leaf fragments can be lifted");
+                let size = self
+                    .ext
+                    .max_sat_size
+                    .expect("leaf fragments are satisfiable");
+                let mut path = SatisfactionPath::empty().grow(size);
+                path.conditions.push(leaf);
+                vec![path]
+            }
+            Terminal::TxTemplate(..) => {
+                let leaf = self.node.lift().expect("leaf fragments can be lifted");
+                let size = self
+                    .ext
+                    .max_sat_size
+                    .expect("leaf fragments are satisfiable");
+                let mut path = SatisfactionPath::empty().grow(size);
+                path.conditions.push(leaf);
+                vec![path]
+            }
+            // Extension fragments cannot be lifted, so their paths have no
+            // conditions
+            Terminal::Ext(..) => match self.ext.max_sat_size {
+                Some(size) => vec![SatisfactionPath::empty().grow(size)],
+                None => vec![],
+            },
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.satisfaction_paths(),
+            Terminal::DupIf(ref sub) => sub
+                .satisfaction_paths()
+                .into_iter()
+                .map(|path| path.grow((2, 1)))
+                .collect(),
+            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
+                product(&l.satisfaction_paths(), &r.satisfaction_paths())
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => {
+                let mut ret = product(&a.satisfaction_paths(), &b.satisfaction_paths());
+                ret.extend(dissatisfy(a, c.satisfaction_paths()));
+                ret
+            }
+            Terminal::OrB(ref l, ref r) => {
+                let mut ret = dissatisfy(r, l.satisfaction_paths());
+                ret.extend(dissatisfy(l, r.satisfaction_paths()));
+                ret
+            }
+            Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => {
+                let mut ret = l.satisfaction_paths();
+                ret.extend(dissatisfy(l, r.satisfaction_paths()));
+                ret
+            }
+            Terminal::OrI(ref l, ref r) => {
+                let mut ret: Vec<_> = l
+                    .satisfaction_paths()
+                    .into_iter()
+                    .map(|path| path.grow((2, 1)))
+                    .collect();
+                ret.extend(
+                    r.satisfaction_paths()
+                        .into_iter()
+                        .map(|path| path.grow((1, 1))),
+                );
+                ret
+            }
+            Terminal::Thresh(k, ref subs) => {
+                let subs: Vec<&Miniscript<Pk, Ctx>> = subs.iter().map(|sub| &**sub).collect();
+                threshold(k, &subs, SatisfactionPath::empty())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SatisfactionPath;
+    use miniscript::satisfy::MissingItem;
+    use policy::semantic::Policy;
+    use std::str::FromStr;
+    use {Miniscript, Segwitv0};
+
+    type Segwitv0Script = Miniscript<String, Segwitv0>;
+
+    fn max_witness_size(paths: &[SatisfactionPath<String>]) -> usize {
+        paths
+            .iter()
+            .map(|path| path.max_witness_size)
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn satisfaction_paths() {
+        let ms = Segwitv0Script::from_str("or_d(pk(A),and_v(v:pk(B),older(144)))").unwrap();
+        let paths = ms.satisfaction_paths();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].conditions, vec![Policy::KeyHash("A".to_owned())]);
+        assert_eq!(paths[0].max_witness_size, 73);
+        assert_eq!(
+            paths[1].conditions,
+            vec![Policy::KeyHash("B".to_owned()), Policy::Older(144)]
+        );
+        // Dissatisfaction of the first key, then a signature
+        assert_eq!(paths[1].max_witness_size, 1 + 73);
+        assert_eq!(
+            max_witness_size(&paths),
+            ms.max_satisfaction_size().unwrap()
+        );
+
+        let ms = Segwitv0Script::from_str("thresh(2,pk(A),s:pk(B),a:pk(C))").unwrap();
+        let paths = ms.satisfaction_paths();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(
+            max_witness_size(&paths),
+            ms.max_satisfaction_size().unwrap()
+        );
+
+        let ms = Segwitv0Script::from_str("or_i(pk(A),multi(2,B,C,D))").unwrap();
+        let paths = ms.satisfaction_paths();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].max_witness_size, 73 + 2);
+        assert_eq!(paths[1].max_witness_size, 1 + 73 * 2 + 1);
+
+        // Unsatisfiable branches have no path
+        let ms = Segwitv0Script::from_str("or_i(pk(A),0)").unwrap();
+        assert_eq!(ms.satisfaction_paths().len(), 1);
+    }
+    #[test]
+    fn witness_shapes() {
+        let sig = |pk: &str| MissingItem::Signature(pk.to_owned());
+
+        let ms = Segwitv0Script::from_str("andor(pk(A),older(144),or_d(pk(B),pk(C)))").unwrap();
+        let shapes = ms.witness_shapes();
+        assert_eq!(shapes.len(), 3);
+        assert_eq!(
+            shapes.collect::<Vec<_>>(),
+            vec![
+                vec![sig("B")],
+                vec![sig("C")],
+                vec![sig("A"), MissingItem::Older(144)],
+            ]
+        );
+
+        // Paths requiring the same items, or more, are only reported once
+        let ms = Segwitv0Script::from_str_insane("or_d(pk(A),and_v(v:pk(A),older(1)))").unwrap();
+        assert_eq!(ms.satisfaction_paths().len(), 2);
+        assert_eq!(
+            ms.witness_shapes().collect::<Vec<_>>(),
+            vec![vec![sig("A")]]
+        );
+
+        let ms = Segwitv0Script::from_str("multi(2,A,B,C)").unwrap();
+        assert_eq!(
+            ms.witness_shapes().collect::<Vec<_>>(),
+            vec![
+                vec![sig("A"), sig("B")],
+                vec![sig("A"), sig("C")],
+                vec![sig("B"), sig("C")],
+            ]
+        );
+    }
+}
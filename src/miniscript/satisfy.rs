@@ -18,14 +18,17 @@
 //! scriptpubkeys.
 //!
 
-use std::collections::HashMap;
+use std::collections::{btree_set, BTreeSet, HashMap};
+use std::marker::PhantomData;
 use std::sync::Arc;
-use std::{cmp, i64, mem};
+use std::{cmp, fmt, i64, mem, str};
 
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use bitcoin::{self, secp256k1};
-use {MiniscriptKey, ToPublicKey};
+use {Error, MiniscriptKey, ToPublicKey};
 
+use miniscript::ext::ExtFragment;
 use miniscript::limits::{
     HEIGHT_TIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
 };
@@ -101,6 +104,17 @@ pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
     fn check_after(&self, _: u32) -> bool {
         false
     }
+
+    /// Assert whether the spending transaction matches the template with
+    /// the given hash, as defined in BIP 119
+    fn check_tx_template(&self, _: sha256::Hash) -> bool {
+        false
+    }
+
+    /// Given an extension fragment, look up the witness satisfying it
+    fn lookup_ext(&self, _: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        None
+    }
 }
 
 // Allow use of `()` as a "no conditions available" satisfier
@@ -206,6 +220,14 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
     fn check_after(&self, t: u32) -> bool {
         (**self).check_after(t)
     }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        (**self).check_tx_template(h)
+    }
+
+    fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        (**self).lookup_ext(e)
+    }
 }
 
 impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a mut S {
@@ -244,6 +266,14 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
     fn check_after(&self, t: u32) -> bool {
         (**self).check_after(t)
     }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        (**self).check_tx_template(h)
+    }
+
+    fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        (**self).lookup_ext(e)
+    }
 }
 
 macro_rules! impl_tuple_satisfier {
@@ -349,6 +379,26 @@ macro_rules! impl_tuple_satisfier {
                 )*
                 false
             }
+
+            fn check_tx_template(&self, h: sha256::Hash) -> bool {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if $ty.check_tx_template(h) {
+                        return true;
+                    }
+                )*
+                false
+            }
+
+            fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.lookup_ext(e) {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
         }
     }
 }
@@ -362,6 +412,374 @@ impl_tuple_satisfier!(A, B, C, D, E, F);
 impl_tuple_satisfier!(A, B, C, D, E, F, G);
 impl_tuple_satisfier!(A, B, C, D, E, F, G, H);
 
+impl<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk> + ?Sized> Satisfier<Pk> for Box<S> {
+    fn lookup_sig(&self, p: &Pk) -> Option<BitcoinSig> {
+        (**self).lookup_sig(p)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        (**self).lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        (**self).lookup_pkh_sig(pkh)
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+        (**self).lookup_sha256(h)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        (**self).lookup_hash256(h)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        (**self).lookup_ripemd160(h)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        (**self).lookup_hash160(h)
+    }
+
+    fn check_older(&self, t: u32) -> bool {
+        (**self).check_older(t)
+    }
+
+    fn check_after(&self, t: u32) -> bool {
+        (**self).check_after(t)
+    }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        (**self).check_tx_template(h)
+    }
+
+    fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        (**self).lookup_ext(e)
+    }
+}
+
+// Allow chaining a number of satisfiers only known at runtime, such as a
+// `Vec<Box<dyn Satisfier<Pk>>>`, like tuples do: the first satisfier which
+// has an item gives it.
+impl<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for Vec<S> {
+    fn lookup_sig(&self, key: &Pk) -> Option<BitcoinSig> {
+        self.iter().filter_map(|s| s.lookup_sig(key)).next()
+    }
+
+    fn lookup_pkh_pk(&self, key_hash: &Pk::Hash) -> Option<Pk> {
+        self.iter().filter_map(|s| s.lookup_pkh_pk(key_hash)).next()
+    }
+
+    fn lookup_pkh_sig(&self, key_hash: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        self.iter()
+            .filter_map(|s| s.lookup_pkh_sig(key_hash))
+            .next()
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+        self.iter().filter_map(|s| s.lookup_sha256(h)).next()
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.iter().filter_map(|s| s.lookup_hash256(h)).next()
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.iter().filter_map(|s| s.lookup_ripemd160(h)).next()
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.iter().filter_map(|s| s.lookup_hash160(h)).next()
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.iter().any(|s| s.check_older(n))
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.iter().any(|s| s.check_after(n))
+    }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        self.iter().any(|s| s.check_tx_template(h))
+    }
+
+    fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        self.iter().filter_map(|s| s.lookup_ext(e)).next()
+    }
+}
+
+/// Store of hash preimages, satisfying the hash fragments whose digest is the
+/// hash of one of them
+///
+/// Each preimage is indexed by its SHA256, HASH256, RIPEMD160 and HASH160
+/// digests. Displays, and serializes with serde, as a comma-separated list
+/// of the hex-encoded preimages.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PreimageStore {
+    preimages: BTreeSet<Preimage32>,
+    sha256: HashMap<sha256::Hash, Preimage32>,
+    hash256: HashMap<sha256d::Hash, Preimage32>,
+    ripemd160: HashMap<ripemd160::Hash, Preimage32>,
+    hash160: HashMap<hash160::Hash, Preimage32>,
+}
+
+impl PreimageStore {
+    /// Creates an empty store
+    pub fn new() -> PreimageStore {
+        PreimageStore::default()
+    }
+
+    /// Adds a preimage to the store. Returns whether it was not already
+    /// there.
+    pub fn insert(&mut self, preimage: Preimage32) -> bool {
+        if !self.preimages.insert(preimage) {
+            return false;
+        }
+        self.sha256.insert(sha256::Hash::hash(&preimage), preimage);
+        self.hash256
+            .insert(sha256d::Hash::hash(&preimage), preimage);
+        self.ripemd160
+            .insert(ripemd160::Hash::hash(&preimage), preimage);
+        self.hash160
+            .insert(hash160::Hash::hash(&preimage), preimage);
+        true
+    }
+
+    /// Removes a preimage from the store. Returns whether it was there.
+    pub fn remove(&mut self, preimage: &Preimage32) -> bool {
+        if !self.preimages.remove(preimage) {
+            return false;
+        }
+        self.sha256.remove(&sha256::Hash::hash(preimage));
+        self.hash256.remove(&sha256d::Hash::hash(preimage));
+        self.ripemd160.remove(&ripemd160::Hash::hash(preimage));
+        self.hash160.remove(&hash160::Hash::hash(preimage));
+        true
+    }
+
+    /// The preimages in the store, in lexicographic order
+    pub fn preimages(&self) -> btree_set::Iter<Preimage32> {
+        self.preimages.iter()
+    }
+
+    /// The number of preimages in the store
+    pub fn len(&self) -> usize {
+        self.preimages.len()
+    }
+
+    /// Whether the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.preimages.is_empty()
+    }
+}
+
+impl fmt::Display for PreimageStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, preimage) in self.preimages.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(&preimage[..].to_hex())?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for PreimageStore {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PreimageStore, Error> {
+        let mut store = PreimageStore::new();
+        if s.is_empty() {
+            return Ok(store);
+        }
+        for hex in s.split(',') {
+            let bytes = Vec::<u8>::from_hex(hex)
+                .map_err(|_| Error::Unexpected(format!("invalid preimage '{}'", hex)))?;
+            if bytes.len() != 32 {
+                return Err(Error::Unexpected(format!(
+                    "preimage '{}' is not 32 bytes long",
+                    hex
+                )));
+            }
+            let mut preimage = [0; 32];
+            preimage.copy_from_slice(&bytes);
+            store.insert(preimage);
+        }
+        Ok(store)
+    }
+}
+
+serde_string_impl!(PreimageStore, "a list of hash preimages");
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PreimageStore {
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+        self.sha256.get(&h).cloned()
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.hash256.get(&h).cloned()
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.ripemd160.get(&h).cloned()
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.hash160.get(&h).cloned()
+    }
+}
+
+/// Satisfier giving placeholder signatures and preimages of the right size for
+/// any key or hash, to build a complete fake witness and measure its size
+/// before signing
+///
+/// Signatures are 71 bytes long, plus the sighash byte, which is the size
+/// assumed by [Miniscript::max_satisfaction_size]. The public keys given for
+/// key hashes are compressed. No timelock is satisfied: to estimate the size
+/// of the witness of a timelocked branch, combine it with [Older] or
+/// [After], as in `(SizeEstimationSatisfier, Older(n))`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SizeEstimationSatisfier;
+
+impl SizeEstimationSatisfier {
+    /// The placeholder signature, whose `r` has its top bit set, taking 33
+    /// bytes in DER, and whose `s` does not, taking 32 bytes
+    fn signature() -> BitcoinSig {
+        let mut compact = [0; 64];
+        compact[0] = 0x80;
+        compact[31] = 1;
+        compact[32] = 0x7f;
+        compact[63] = 1;
+        let sig = secp256k1::Signature::from_compact(&compact).expect("valid signature");
+        (sig, bitcoin::SigHashType::All)
+    }
+
+    /// The placeholder public key, the generator
+    fn public_key() -> bitcoin::PublicKey {
+        let key = secp256k1::PublicKey::from_slice(&[
+            0x02, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce,
+            0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81,
+            0x5b, 0x16, 0xf8, 0x17, 0x98,
+        ])
+        .expect("valid public key");
+        bitcoin::PublicKey {
+            key: key,
+            compressed: true,
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for SizeEstimationSatisfier {
+    fn lookup_sig(&self, _: &Pk) -> Option<BitcoinSig> {
+        Some(SizeEstimationSatisfier::signature())
+    }
+
+    fn lookup_pkh_sig(&self, _: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        Some((
+            SizeEstimationSatisfier::public_key(),
+            SizeEstimationSatisfier::signature(),
+        ))
+    }
+
+    fn lookup_sha256(&self, _: sha256::Hash) -> Option<Preimage32> {
+        Some([0; 32])
+    }
+
+    fn lookup_hash256(&self, _: sha256d::Hash) -> Option<Preimage32> {
+        Some([0; 32])
+    }
+
+    fn lookup_ripemd160(&self, _: ripemd160::Hash) -> Option<Preimage32> {
+        Some([0; 32])
+    }
+
+    fn lookup_hash160(&self, _: hash160::Hash) -> Option<Preimage32> {
+        Some([0; 32])
+    }
+}
+
+/// Adapter using a satisfier for keys of type `Q` as a satisfier for keys of
+/// type `Pk`, by translating the keys and key hashes which are looked up
+///
+/// Keys which cannot be translated are not looked up. Since the keys given
+/// by `lookup_pkh_pk` cannot be translated back, that lookup is not
+/// forwarded; `lookup_pkh_sig`, which gives the public key, is.
+pub struct TranslateSatisfier<S, Q, Fpk, Fpkh> {
+    inner: S,
+    translatefpk: Fpk,
+    translatefpkh: Fpkh,
+    phantom: PhantomData<Q>,
+}
+
+impl<S, Q, Fpk, Fpkh> TranslateSatisfier<S, Q, Fpk, Fpkh> {
+    /// Wraps `inner`, translating keys with `translatefpk` and key hashes
+    /// with `translatefpkh`
+    pub fn new(inner: S, translatefpk: Fpk, translatefpkh: Fpkh) -> Self {
+        TranslateSatisfier {
+            inner: inner,
+            translatefpk: translatefpk,
+            translatefpkh: translatefpkh,
+            phantom: PhantomData,
+        }
+    }
+
+    /// The wrapped satisfier
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<Pk, Q, S, Fpk, Fpkh> Satisfier<Pk> for TranslateSatisfier<S, Q, Fpk, Fpkh>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    Q: MiniscriptKey + ToPublicKey,
+    S: Satisfier<Q>,
+    Fpk: Fn(&Pk) -> Option<Q>,
+    Fpkh: Fn(&Pk::Hash) -> Option<Q::Hash>,
+{
+    fn lookup_sig(&self, key: &Pk) -> Option<BitcoinSig> {
+        (self.translatefpk)(key).and_then(|key| self.inner.lookup_sig(&key))
+    }
+
+    fn lookup_pkh_sig(&self, key_hash: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        (self.translatefpkh)(key_hash).and_then(|hash| self.inner.lookup_pkh_sig(&hash))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+        self.inner.lookup_sha256(h)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.inner.lookup_hash256(h)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.inner.lookup_ripemd160(h)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.inner.lookup_hash160(h)
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.inner.check_older(n)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.inner.check_after(n)
+    }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        self.inner.check_tx_template(h)
+    }
+
+    fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        self.inner.lookup_ext(e)
+    }
+}
+
 /// A witness, if available, for a Miniscript fragment
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Witness {
@@ -787,6 +1205,25 @@ impl Satisfaction {
 
                 has_sig: false,
             },
+            Terminal::TxTemplate(h) => Satisfaction {
+                stack: if stfr.check_tx_template(h) {
+                    Witness::empty()
+                } else if root_has_sig {
+                    // As for timelocks, the signature covers the
+                    // transaction which does not match the template
+                    Witness::Impossible
+                } else {
+                    Witness::Unavailable
+                },
+                has_sig: false,
+            },
+            Terminal::Ext(ref e) => Satisfaction {
+                stack: match stfr.lookup_ext(e) {
+                    Some(stack) => Witness::Stack(stack),
+                    None => Witness::Unavailable,
+                },
+                has_sig: false,
+            },
             Terminal::Ripemd160(h) => Satisfaction {
                 stack: Witness::ripemd160_preimage(stfr, h),
                 has_sig: false,
@@ -990,6 +1427,17 @@ impl Satisfaction {
                 stack: Witness::Impossible,
                 has_sig: false,
             },
+            Terminal::TxTemplate(_) => Satisfaction {
+                stack: Witness::Impossible,
+                has_sig: false,
+            },
+            Terminal::Ext(ref e) => Satisfaction {
+                stack: match e.dissatisfaction() {
+                    Some(stack) => Witness::Stack(stack),
+                    None => Witness::Impossible,
+                },
+                has_sig: false,
+            },
             Terminal::Sha256(_)
             | Terminal::Hash256(_)
             | Terminal::Ripemd160(_)
@@ -1104,3 +1552,290 @@ impl Satisfaction {
         )
     }
 }
+
+/// An item a satisfier lacks to satisfy a Miniscript along some path
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum MissingItem<Pk: MiniscriptKey> {
+    /// A signature by the key
+    Signature(Pk),
+    /// A signature by the key with the hash, along with the key
+    KeyHashSignature(Pk::Hash),
+    /// The preimage of the SHA256 hash
+    Sha256Preimage(sha256::Hash),
+    /// The preimage of the SHA256d hash
+    Hash256Preimage(sha256d::Hash),
+    /// The preimage of the RIPEMD160 hash
+    Ripemd160Preimage(ripemd160::Hash),
+    /// The preimage of the HASH160 hash
+    Hash160Preimage(hash160::Hash),
+    /// The absolute timelock `after(n)`, not reached yet
+    After(u32),
+    /// The relative timelock `older(n)`, not reached yet
+    Older(u32),
+    /// A spending transaction matching the template with the hash
+    TxTemplate(sha256::Hash),
+    /// The witness satisfying the extension fragment
+    Ext(ExtFragment),
+}
+
+/// The sets of items missing to satisfy a fragment, one per way of satisfying
+/// it. A fragment that cannot be satisfied has none, one that is satisfied
+/// has a single empty set.
+type MissingPaths<Pk> = Vec<Vec<MissingItem<Pk>>>;
+
+/// Keeps only the minimal sets of `paths`, in order of size
+fn minimal_paths<Pk: MiniscriptKey>(mut paths: MissingPaths<Pk>) -> MissingPaths<Pk> {
+    for path in &mut paths {
+        path.sort();
+        path.dedup();
+    }
+    paths.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    paths.dedup();
+
+    let mut minimal: MissingPaths<Pk> = vec![];
+    for path in paths {
+        let is_superset = minimal
+            .iter()
+            .any(|min| min.iter().all(|item| path.binary_search(item).is_ok()));
+        if !is_superset {
+            minimal.push(path);
+        }
+    }
+    minimal
+}
+
+/// The paths through all of `subs`
+fn missing_all_of<Pk: MiniscriptKey>(subs: Vec<MissingPaths<Pk>>) -> MissingPaths<Pk> {
+    let mut paths = vec![vec![]];
+    for sub in &subs {
+        let combined = paths
+            .iter()
+            .flat_map(|path: &Vec<MissingItem<Pk>>| {
+                sub.iter().map(move |sub_path| {
+                    let mut path = path.clone();
+                    path.extend(sub_path.iter().cloned());
+                    path
+                })
+            })
+            .collect();
+        paths = minimal_paths(combined);
+    }
+    paths
+}
+
+/// The paths through any `k` of `subs`
+fn missing_k_of<Pk: MiniscriptKey>(k: usize, subs: Vec<MissingPaths<Pk>>) -> MissingPaths<Pk> {
+    // Sets of `k` indices of `subs`, built in increasing order
+    let mut choices: Vec<Vec<usize>> = vec![vec![]];
+    for _ in 0..k {
+        choices = choices
+            .iter()
+            .flat_map(|choice| {
+                let start = choice.last().map_or(0, |&last| last + 1);
+                (start..subs.len()).map(move |i| {
+                    let mut choice = choice.clone();
+                    choice.push(i);
+                    choice
+                })
+            })
+            .collect();
+    }
+
+    let paths = choices
+        .into_iter()
+        .flat_map(|choice| missing_all_of(choice.into_iter().map(|i| subs[i].clone()).collect()))
+        .collect();
+    minimal_paths(paths)
+}
+
+/// The items `satisfier` lacks to satisfy `ms`, see [Miniscript::missing_items]
+pub(crate) fn missing_items<Pk, Ctx, S>(ms: &Miniscript<Pk, Ctx>, satisfier: &S) -> MissingPaths<Pk>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    Ctx: ScriptContext,
+    S: Satisfier<Pk>,
+{
+    missing_items_with(ms, |item| match *item {
+        MissingItem::Signature(ref pk) => satisfier.lookup_sig(pk).is_some(),
+        MissingItem::KeyHashSignature(ref pkh) => satisfier.lookup_pkh_sig(pkh).is_some(),
+        MissingItem::Sha256Preimage(h) => satisfier.lookup_sha256(h).is_some(),
+        MissingItem::Hash256Preimage(h) => satisfier.lookup_hash256(h).is_some(),
+        MissingItem::Ripemd160Preimage(h) => satisfier.lookup_ripemd160(h).is_some(),
+        MissingItem::Hash160Preimage(h) => satisfier.lookup_hash160(h).is_some(),
+        MissingItem::After(n) => satisfier.check_after(n),
+        MissingItem::Older(n) => satisfier.check_older(n),
+        MissingItem::TxTemplate(h) => satisfier.check_tx_template(h),
+        MissingItem::Ext(ref e) => satisfier.lookup_ext(e).is_some(),
+    })
+}
+
+/// The items missing to satisfy `ms`, given which ones are `available`
+pub(crate) fn missing_items_with<Pk, Ctx, F>(
+    ms: &Miniscript<Pk, Ctx>,
+    available: F,
+) -> MissingPaths<Pk>
+where
+    Pk: MiniscriptKey,
+    Ctx: ScriptContext,
+    F: Fn(&MissingItem<Pk>) -> bool,
+{
+    let missing = |item: MissingItem<Pk>| {
+        if available(&item) {
+            vec![vec![]]
+        } else {
+            vec![vec![item]]
+        }
+    };
+    ms.fold(|ms, _, subs: Vec<MissingPaths<Pk>>| match ms.node {
+        Terminal::True => vec![vec![]],
+        Terminal::False => vec![],
+        Terminal::PkK(ref pk) => missing(MissingItem::Signature(pk.clone())),
+        Terminal::PkH(ref pkh) => missing(MissingItem::KeyHashSignature(pkh.clone())),
+        Terminal::After(n) => missing(MissingItem::After(n)),
+        Terminal::Older(n) => missing(MissingItem::Older(n)),
+        Terminal::Sha256(h) => missing(MissingItem::Sha256Preimage(h)),
+        Terminal::Hash256(h) => missing(MissingItem::Hash256Preimage(h)),
+        Terminal::Ripemd160(h) => missing(MissingItem::Ripemd160Preimage(h)),
+        Terminal::Hash160(h) => missing(MissingItem::Hash160Preimage(h)),
+        Terminal::TxTemplate(h) => missing(MissingItem::TxTemplate(h)),
+        Terminal::Ext(ref e) => missing(MissingItem::Ext(e.clone())),
+        Terminal::Alt(..)
+        | Terminal::Swap(..)
+        | Terminal::Check(..)
+        | Terminal::DupIf(..)
+        | Terminal::Verify(..)
+        | Terminal::NonZero(..)
+        | Terminal::ZeroNotEqual(..) => subs.concat(),
+        Terminal::AndV(..) | Terminal::AndB(..) => missing_all_of(subs),
+        Terminal::OrB(..) | Terminal::OrD(..) | Terminal::OrC(..) | Terminal::OrI(..) => {
+            minimal_paths(subs.concat())
+        }
+        Terminal::AndOr(..) => {
+            let mut subs = subs.into_iter();
+            let (a, b) = (subs.next().unwrap(), subs.next().unwrap());
+            let mut paths = missing_all_of(vec![a, b]);
+            paths.extend(subs.next().unwrap());
+            minimal_paths(paths)
+        }
+        Terminal::Thresh(k, _) => missing_k_of(k, subs),
+        Terminal::Multi(k, ref keys) => missing_k_of(
+            k,
+            keys.iter()
+                .map(|pk| missing(MissingItem::Signature(pk.clone())))
+                .collect(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use {DummyKey, DummyKeyHash, Segwitv0};
+
+    type Segwitv0Script = Miniscript<bitcoin::PublicKey, Segwitv0>;
+
+    fn pubkeys(n: usize) -> Vec<bitcoin::PublicKey> {
+        let secp = secp256k1::Secp256k1::new();
+        (1..n + 1)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i as u8; 32]).expect("secret key");
+                bitcoin::PublicKey {
+                    key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                    compressed: true,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn satisfier_combinators() {
+        let key = pubkeys(1)[0];
+        let ms = Miniscript::<DummyKey, Segwitv0>::from_str("and_v(v:pk(),older(100))").unwrap();
+
+        let secp = secp256k1::Secp256k1::signing_only();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = (secp.sign(&msg, &sk), bitcoin::SigHashType::All);
+        let mut sigs = HashMap::new();
+        sigs.insert(key, sig);
+
+        // The signatures are looked up with the translated key
+        let translated =
+            TranslateSatisfier::new(sigs, move |_: &DummyKey| Some(key), |_: &DummyKeyHash| None);
+        assert!(ms.satisfy(&translated).is_err());
+        assert!(ms.satisfy((&translated, Older(100))).is_ok());
+
+        let mut chain: Vec<Box<dyn Satisfier<DummyKey>>> = vec![Box::new(translated)];
+        assert!(ms.satisfy(&chain).is_err());
+        chain.push(Box::new(Older(100)));
+        assert_eq!(ms.satisfy(&chain).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn size_estimation_satisfier() {
+        let keys = pubkeys(2);
+        let hash = sha256::Hash::hash(&[0; 32]);
+        let ms = Segwitv0Script::from_str(&format!(
+            "andor(pk({}),sha256({}),and_v(v:pkh({}),older(100)))",
+            keys[0],
+            hash,
+            keys[1].to_pubkeyhash()
+        ))
+        .unwrap();
+
+        // The witness of the cheapest branch
+        let witness = ms.satisfy(SizeEstimationSatisfier).unwrap();
+        let mut sizes: Vec<_> = witness.iter().map(Vec::len).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![32, 72]);
+        let size: usize = witness.iter().map(|elem| elem.len() + 1).sum();
+        assert_eq!(
+            size,
+            ms.max_satisfaction_size_assuming(|item| match *item {
+                MissingItem::Older(_) => false,
+                _ => true,
+            })
+            .unwrap()
+        );
+
+        // Timelocks are only satisfied along with another satisfier
+        let ms = Segwitv0Script::from_str(&format!(
+            "and_v(v:pkh({}),older(100))",
+            keys[1].to_pubkeyhash()
+        ))
+        .unwrap();
+        assert!(ms.satisfy(SizeEstimationSatisfier).is_err());
+        let witness = ms.satisfy((SizeEstimationSatisfier, Older(100))).unwrap();
+        let mut sizes: Vec<_> = witness.iter().map(Vec::len).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![33, 72]);
+    }
+
+    #[test]
+    fn preimage_store() {
+        let mut store = PreimageStore::new();
+        assert!(store.insert([1; 32]));
+        assert!(!store.insert([1; 32]));
+        assert!(store.insert([2; 32]));
+        assert_eq!(store.len(), 2);
+
+        let ms = Segwitv0Script::from_str(&format!(
+            "and_v(v:sha256({}),hash160({}))",
+            sha256::Hash::hash(&[2; 32]),
+            hash160::Hash::hash(&[1; 32])
+        ))
+        .unwrap();
+        assert_eq!(ms.satisfy(&store).unwrap(), vec![vec![1; 32], vec![2; 32]]);
+
+        let s = store.to_string();
+        assert_eq!(s, format!("{},{}", "01".repeat(32), "02".repeat(32)));
+        assert_eq!(PreimageStore::from_str(&s).unwrap(), store);
+        assert!(PreimageStore::from_str("0102").is_err());
+
+        assert!(store.remove(&[2; 32]));
+        assert!(ms.satisfy(&store).is_err());
+    }
+}
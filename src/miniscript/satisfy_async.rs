@@ -0,0 +1,490 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Asynchronous Satisfaction
+//!
+//! Satisfaction with signatures and preimages looked up asynchronously, from
+//! hardware wallets, HSMs or co-signers reached over the network, without
+//! blocking or collecting them all beforehand. The items a satisfaction may
+//! use are all requested at once, and the witness is built as by
+//! [Satisfier]-based satisfaction once they are answered.
+//!
+//! This module requires the `async` feature, and Rust 1.36 for the `Future`
+//! trait.
+//!
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{hash, mem};
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::{self, Script};
+
+use super::ext::ExtFragment;
+use super::satisfy::{BitcoinSig, Preimage32, Satisfier};
+use descriptor::{Descriptor, DescriptorTrait};
+use {Error, Miniscript, MiniscriptKey, ScriptContext, ToPublicKey};
+
+/// A boxed future, as returned by the lookups of an [AsyncSatisfier]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Future which is immediately ready
+struct Ready<T>(Option<T>);
+
+impl<T> Unpin for Ready<T> {}
+
+impl<T> Future for Ready<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<T> {
+        Poll::Ready(self.get_mut().0.take().expect("polled after completion"))
+    }
+}
+
+/// A future which is immediately ready with `value`, for the lookups which
+/// can be answered without waiting
+pub fn ready<'a, T: 'a>(value: T) -> BoxFuture<'a, T> {
+    Box::pin(Ready(Some(value)))
+}
+
+/// Asynchronous counterpart of [Satisfier]: a lookup table for signatures
+/// and hash preimages, whose lookups are answered by futures
+///
+/// Every lookup has a default implementation answering `None`. Timelocks
+/// are checked against the transaction being satisfied, and so are not
+/// asynchronous.
+pub trait AsyncSatisfier<Pk: MiniscriptKey + ToPublicKey> {
+    /// Given a public key, look up a signature with that key
+    fn lookup_sig<'a>(&'a self, _: &Pk) -> BoxFuture<'a, Option<BitcoinSig>> {
+        ready(None)
+    }
+
+    /// Given a `Pkh`, lookup corresponding `Pk`
+    fn lookup_pkh_pk<'a>(&'a self, _: &Pk::Hash) -> BoxFuture<'a, Option<Pk>> {
+        ready(None)
+    }
+
+    /// Given a keyhash, look up the signature and the associated key
+    fn lookup_pkh_sig<'a>(
+        &'a self,
+        _: &Pk::Hash,
+    ) -> BoxFuture<'a, Option<(bitcoin::PublicKey, BitcoinSig)>> {
+        ready(None)
+    }
+
+    /// Given a SHA256 hash, look up its preimage
+    fn lookup_sha256<'a>(&'a self, _: sha256::Hash) -> BoxFuture<'a, Option<Preimage32>> {
+        ready(None)
+    }
+
+    /// Given a HASH256 hash, look up its preimage
+    fn lookup_hash256<'a>(&'a self, _: sha256d::Hash) -> BoxFuture<'a, Option<Preimage32>> {
+        ready(None)
+    }
+
+    /// Given a RIPEMD160 hash, look up its preimage
+    fn lookup_ripemd160<'a>(&'a self, _: ripemd160::Hash) -> BoxFuture<'a, Option<Preimage32>> {
+        ready(None)
+    }
+
+    /// Given a HASH160 hash, look up its preimage
+    fn lookup_hash160<'a>(&'a self, _: hash160::Hash) -> BoxFuture<'a, Option<Preimage32>> {
+        ready(None)
+    }
+
+    /// Assert whether an relative locktime is satisfied
+    fn check_older(&self, _: u32) -> bool {
+        false
+    }
+
+    /// Assert whether a absolute locktime is satisfied
+    fn check_after(&self, _: u32) -> bool {
+        false
+    }
+
+    /// Assert whether the spending transaction matches the template with
+    /// the given hash, as defined in BIP 119
+    fn check_tx_template(&self, _: sha256::Hash) -> bool {
+        false
+    }
+
+    /// Given an extension fragment, look up the witness satisfying it
+    fn lookup_ext(&self, _: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        None
+    }
+}
+
+/// An item requested from an [AsyncSatisfier]
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Request<Pk: MiniscriptKey> {
+    Sig(Pk),
+    PkhPk(Pk::Hash),
+    PkhSig(Pk::Hash),
+    Sha256(sha256::Hash),
+    Hash256(sha256d::Hash),
+    Ripemd160(ripemd160::Hash),
+    Hash160(hash160::Hash),
+}
+
+/// A lookup of an [AsyncSatisfier] still running
+enum Pending<'a, Pk: MiniscriptKey> {
+    Sig(Pk, BoxFuture<'a, Option<BitcoinSig>>),
+    PkhPk(Pk::Hash, BoxFuture<'a, Option<Pk>>),
+    PkhSig(
+        Pk::Hash,
+        BoxFuture<'a, Option<(bitcoin::PublicKey, BitcoinSig)>>,
+    ),
+    Sha256(sha256::Hash, BoxFuture<'a, Option<Preimage32>>),
+    Hash256(sha256d::Hash, BoxFuture<'a, Option<Preimage32>>),
+    Ripemd160(ripemd160::Hash, BoxFuture<'a, Option<Preimage32>>),
+    Hash160(hash160::Hash, BoxFuture<'a, Option<Preimage32>>),
+}
+
+/// The answers of an [AsyncSatisfier] received so far, used as a
+/// [Satisfier] which records the lookups it could not answer
+pub struct Lookups<'s, Pk: MiniscriptKey + 's, S: 's> {
+    satisfier: &'s S,
+    requests: RefCell<Vec<Request<Pk>>>,
+    sigs: HashMap<Pk, Option<BitcoinSig>>,
+    pkh_pks: HashMap<Pk::Hash, Option<Pk>>,
+    pkh_sigs: HashMap<Pk::Hash, Option<(bitcoin::PublicKey, BitcoinSig)>>,
+    sha256: HashMap<sha256::Hash, Option<Preimage32>>,
+    hash256: HashMap<sha256d::Hash, Option<Preimage32>>,
+    ripemd160: HashMap<ripemd160::Hash, Option<Preimage32>>,
+    hash160: HashMap<hash160::Hash, Option<Preimage32>>,
+}
+
+impl<'s, Pk: MiniscriptKey, S> Lookups<'s, Pk, S> {
+    fn new(satisfier: &'s S) -> Self {
+        Lookups {
+            satisfier: satisfier,
+            requests: RefCell::new(vec![]),
+            sigs: HashMap::new(),
+            pkh_pks: HashMap::new(),
+            pkh_sigs: HashMap::new(),
+            sha256: HashMap::new(),
+            hash256: HashMap::new(),
+            ripemd160: HashMap::new(),
+            hash160: HashMap::new(),
+        }
+    }
+
+    /// The answer to a lookup, recording the request if there is none yet
+    fn answer<T: Clone>(&self, answer: Option<&Option<T>>, request: Request<Pk>) -> Option<T> {
+        match answer {
+            Some(answer) => answer.clone(),
+            None => {
+                let mut requests = self.requests.borrow_mut();
+                if !requests.contains(&request) {
+                    requests.push(request);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<'s, Pk, S> Satisfier<Pk> for Lookups<'s, Pk, S>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    S: AsyncSatisfier<Pk>,
+{
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        self.answer(self.sigs.get(pk), Request::Sig(pk.clone()))
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.answer(self.pkh_pks.get(pkh), Request::PkhPk(pkh.clone()))
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        self.answer(self.pkh_sigs.get(pkh), Request::PkhSig(pkh.clone()))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+        self.answer(self.sha256.get(&h), Request::Sha256(h))
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.answer(self.hash256.get(&h), Request::Hash256(h))
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.answer(self.ripemd160.get(&h), Request::Ripemd160(h))
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.answer(self.hash160.get(&h), Request::Hash160(h))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.satisfier.check_older(n)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.satisfier.check_after(n)
+    }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        self.satisfier.check_tx_template(h)
+    }
+
+    fn lookup_ext(&self, e: &ExtFragment) -> Option<Vec<Vec<u8>>> {
+        self.satisfier.lookup_ext(e)
+    }
+}
+
+/// Future of an asynchronous satisfaction, see [satisfy_with]
+pub struct Satisfy<'s, Pk: MiniscriptKey + 's, S: 's, F> {
+    lookups: Lookups<'s, Pk, S>,
+    pending: Vec<Pending<'s, Pk>>,
+    satisfy: F,
+}
+
+// The future is never pinned in place, its lookups are boxed
+impl<'s, Pk: MiniscriptKey, S, F> Unpin for Satisfy<'s, Pk, S, F> {}
+
+impl<'s, Pk, S, F, T> Future for Satisfy<'s, Pk, S, F>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    S: AsyncSatisfier<Pk>,
+    F: Fn(&Lookups<'s, Pk, S>) -> Result<T, Error>,
+{
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Error>> {
+        let this = self.get_mut();
+        loop {
+            let mut i = 0;
+            while i < this.pending.len() {
+                if this.lookups.poll_pending(&mut this.pending[i], cx) {
+                    this.pending.swap_remove(i);
+                } else {
+                    i += 1;
+                }
+            }
+            if !this.pending.is_empty() {
+                return Poll::Pending;
+            }
+
+            // Satisfy with the answers so far, and start the lookups which
+            // the satisfaction needs and have not been answered yet
+            let result = (this.satisfy)(&this.lookups);
+            let requests = mem::replace(&mut *this.lookups.requests.borrow_mut(), vec![]);
+            if requests.is_empty() {
+                return Poll::Ready(result);
+            }
+            let satisfier = this.lookups.satisfier;
+            this.pending = requests
+                .into_iter()
+                .map(|request| match request {
+                    Request::Sig(pk) => {
+                        let future = satisfier.lookup_sig(&pk);
+                        Pending::Sig(pk, future)
+                    }
+                    Request::PkhPk(pkh) => {
+                        let future = satisfier.lookup_pkh_pk(&pkh);
+                        Pending::PkhPk(pkh, future)
+                    }
+                    Request::PkhSig(pkh) => {
+                        let future = satisfier.lookup_pkh_sig(&pkh);
+                        Pending::PkhSig(pkh, future)
+                    }
+                    Request::Sha256(h) => Pending::Sha256(h, satisfier.lookup_sha256(h)),
+                    Request::Hash256(h) => Pending::Hash256(h, satisfier.lookup_hash256(h)),
+                    Request::Ripemd160(h) => Pending::Ripemd160(h, satisfier.lookup_ripemd160(h)),
+                    Request::Hash160(h) => Pending::Hash160(h, satisfier.lookup_hash160(h)),
+                })
+                .collect();
+        }
+    }
+}
+
+impl<'s, Pk: MiniscriptKey, S> Lookups<'s, Pk, S> {
+    /// Polls a running lookup, storing its answer if it is ready. Returns
+    /// whether it was.
+    fn poll_pending(&mut self, pending: &mut Pending<'s, Pk>, cx: &mut Context) -> bool {
+        match *pending {
+            Pending::Sig(ref pk, ref mut future) => poll_into(&mut self.sigs, pk, future, cx),
+            Pending::PkhPk(ref pkh, ref mut future) => {
+                poll_into(&mut self.pkh_pks, pkh, future, cx)
+            }
+            Pending::PkhSig(ref pkh, ref mut future) => {
+                poll_into(&mut self.pkh_sigs, pkh, future, cx)
+            }
+            Pending::Sha256(ref h, ref mut future) => poll_into(&mut self.sha256, h, future, cx),
+            Pending::Hash256(ref h, ref mut future) => poll_into(&mut self.hash256, h, future, cx),
+            Pending::Ripemd160(ref h, ref mut future) => {
+                poll_into(&mut self.ripemd160, h, future, cx)
+            }
+            Pending::Hash160(ref h, ref mut future) => poll_into(&mut self.hash160, h, future, cx),
+        }
+    }
+}
+
+/// Polls `future`, inserting its output in `answers` under `item` if it is
+/// ready. Returns whether it was.
+fn poll_into<K, T>(
+    answers: &mut HashMap<K, T>,
+    item: &K,
+    future: &mut BoxFuture<T>,
+    cx: &mut Context,
+) -> bool
+where
+    K: Clone + Eq + hash::Hash,
+{
+    match future.as_mut().poll(cx) {
+        Poll::Ready(answer) => {
+            answers.insert(item.clone(), answer);
+            true
+        }
+        Poll::Pending => false,
+    }
+}
+
+/// Satisfies asynchronously with `satisfier`: `satisfy` is called with a
+/// [Satisfier] giving the answers of `satisfier` received so far, until it
+/// needs no other lookup, and the future resolves to its last result
+///
+/// `satisfy` is usually called twice: once to find the lookups, all
+/// started at once, and once with their answers.
+pub fn satisfy_with<'s, Pk, S, F, T>(satisfier: &'s S, satisfy: F) -> Satisfy<'s, Pk, S, F>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    S: AsyncSatisfier<Pk>,
+    F: Fn(&Lookups<'s, Pk, S>) -> Result<T, Error>,
+{
+    Satisfy {
+        lookups: Lookups::new(satisfier),
+        pending: vec![],
+        satisfy: satisfy,
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Attempt to produce a non-malleable satisfying witness, as
+    /// [Miniscript::satisfy] does, with an asynchronous satisfier
+    pub fn satisfy_async<'a, S: AsyncSatisfier<Pk>>(
+        &'a self,
+        satisfier: &'a S,
+    ) -> impl Future<Output = Result<Vec<Vec<u8>>, Error>> + 'a {
+        satisfy_with(satisfier, move |lookups| self.satisfy(lookups))
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Returns satisfying witness and scriptSig, as
+    /// `DescriptorTrait::get_satisfaction` does, with an asynchronous
+    /// satisfier
+    pub fn get_satisfaction_async<'a, S: AsyncSatisfier<Pk>>(
+        &'a self,
+        satisfier: &'a S,
+    ) -> impl Future<Output = Result<(Vec<Vec<u8>>, Script), Error>> + 'a {
+        satisfy_with(satisfier, move |lookups| self.get_satisfaction(lookups))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1;
+    use std::cell::Cell;
+    use std::str::FromStr;
+    use std::task::{RawWaker, RawWakerVTable, Waker};
+    use Segwitv0;
+
+    /// Future which is ready on its second poll
+    struct Delayed<T>(Option<T>, bool);
+
+    impl<T> Unpin for Delayed<T> {}
+
+    impl<T> Future for Delayed<T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+            let this = self.get_mut();
+            if this.1 {
+                Poll::Ready(this.0.take().unwrap())
+            } else {
+                this.1 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(&(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(&(), &VTABLE)) };
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// A remote signer, only knowing the key `pk`
+    struct Remote {
+        pk: bitcoin::PublicKey,
+        sig: BitcoinSig,
+        lookups: Cell<usize>,
+    }
+
+    impl AsyncSatisfier<bitcoin::PublicKey> for Remote {
+        fn lookup_sig<'a>(&'a self, pk: &bitcoin::PublicKey) -> BoxFuture<'a, Option<BitcoinSig>> {
+            self.lookups.set(self.lookups.get() + 1);
+            let sig = if *pk == self.pk { Some(self.sig) } else { None };
+            Box::pin(Delayed(Some(sig), false))
+        }
+    }
+
+    #[test]
+    fn satisfy_async() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let remote = Remote {
+            pk: pk,
+            sig: (secp.sign(&msg, &sk), bitcoin::SigHashType::All),
+            lookups: Cell::new(0),
+        };
+
+        let other = "020000000000000000000000000000000000000000000000000000000000000002";
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!(
+            "or_b(pk({}),s:pk({}))",
+            other, pk
+        ))
+        .unwrap();
+        let witness = block_on(ms.satisfy_async(&remote)).unwrap();
+        assert_eq!(witness.len(), 2);
+        assert!(witness[0].is_empty() || witness[1].is_empty());
+        // Each key is looked up once
+        assert_eq!(remote.lookups.get(), 2);
+
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!("wpkh({})", other)).unwrap();
+        assert!(block_on(desc.get_satisfaction_async(&remote)).is_err());
+    }
+}
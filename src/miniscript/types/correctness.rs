@@ -15,6 +15,7 @@
 //! Correctness/Soundness type properties
 
 use super::{ErrorKind, Property};
+use miniscript::ext::ExtFragment;
 
 /// Basic type representing where the fragment can go
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -191,6 +192,10 @@ impl Property for Correctness {
         }
     }
 
+    fn from_ext(ext: &ExtFragment) -> Self {
+        ext.corr_prop()
+    }
+
     fn cast_alt(self) -> Result<Self, ErrorKind> {
         Ok(Correctness {
             base: match self.base {
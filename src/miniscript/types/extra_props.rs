@@ -1,6 +1,7 @@
 //! Other miscellaneous type properties which are not related to
 //! correctness or malleability.
 
+use miniscript::ext::ExtFragment;
 use miniscript::limits::{HEIGHT_TIME_THRESHOLD, SEQUENCE_LOCKTIME_TYPE_FLAG};
 
 use super::{Error, ErrorKind, Property, ScriptContext};
@@ -318,6 +319,25 @@ impl Property for ExtData {
         }
     }
 
+    fn from_tx_template() -> Self {
+        ExtData {
+            pk_cost: 33 + 1,
+            has_free_verify: false,
+            ops_count_static: 1,
+            ops_count_sat: Some(1),
+            ops_count_nsat: None,
+            stack_elem_count_sat: Some(0),
+            stack_elem_count_dissat: None,
+            max_sat_size: Some((0, 0)),
+            max_dissat_size: None,
+            timelock_info: TimeLockInfo::default(),
+        }
+    }
+
+    fn from_ext(ext: &ExtFragment) -> Self {
+        ext.extra_prop()
+    }
+
     fn cast_alt(self) -> Result<Self, ErrorKind> {
         Ok(ExtData {
             pk_cost: self.pk_cost + 2,
@@ -873,6 +893,8 @@ impl Property for ExtData {
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
             Terminal::Hash160(..) => Ok(Self::from_hash160()),
+            Terminal::TxTemplate(..) => Ok(Self::from_tx_template()),
+            Terminal::Ext(ref e) => Ok(Self::from_ext(e)),
             Terminal::Alt(ref sub) => wrap_err(Self::cast_alt(sub.ext.clone())),
             Terminal::Swap(ref sub) => wrap_err(Self::cast_swap(sub.ext.clone())),
             Terminal::Check(ref sub) => wrap_err(Self::cast_check(sub.ext.clone())),
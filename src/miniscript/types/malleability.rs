@@ -15,6 +15,7 @@
 //! Malleability-related Type properties
 
 use super::{ErrorKind, Property};
+use miniscript::ext::ExtFragment;
 
 /// Whether the fragment has a dissatisfaction, and if so, whether
 /// it is unique. Affects both correctness and malleability-freeness,
@@ -138,6 +139,10 @@ impl Property for Malleability {
         }
     }
 
+    fn from_ext(ext: &ExtFragment) -> Self {
+        ext.mall_prop()
+    }
+
     fn cast_alt(self) -> Result<Self, ErrorKind> {
         Ok(self)
     }
@@ -25,6 +25,7 @@ use std::{error, fmt};
 pub use self::correctness::{Base, Correctness, Input};
 pub use self::extra_props::ExtData;
 pub use self::malleability::{Dissat, Malleability};
+use super::ext::ExtFragment;
 use super::ScriptContext;
 use MiniscriptKey;
 use Terminal;
@@ -311,6 +312,16 @@ pub trait Property: Sized {
         Self::from_time(t)
     }
 
+    /// Type property of a `TxTemplate` covenant. Default implementation
+    /// simply passes through to `from_time`, as a covenant, like a timelock,
+    /// takes no input and cannot be dissatisfied
+    fn from_tx_template() -> Self {
+        Self::from_time(0)
+    }
+
+    /// Type property of an extension fragment, as given by its extension
+    fn from_ext(ext: &ExtFragment) -> Self;
+
     /// Cast using the `Alt` wrapper
     fn cast_alt(self) -> Result<Self, ErrorKind>;
 
@@ -448,6 +459,8 @@ pub trait Property: Sized {
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
             Terminal::Hash160(..) => Ok(Self::from_hash160()),
+            Terminal::TxTemplate(..) => Ok(Self::from_tx_template()),
+            Terminal::Ext(ref e) => Ok(Self::from_ext(e)),
             Terminal::Alt(ref sub) => wrap_err(Self::cast_alt(get_child(&sub.node, 0)?)),
             Terminal::Swap(ref sub) => wrap_err(Self::cast_swap(get_child(&sub.node, 0)?)),
             Terminal::Check(ref sub) => wrap_err(Self::cast_check(get_child(&sub.node, 0)?)),
@@ -614,6 +627,13 @@ impl Property for Type {
         }
     }
 
+    fn from_ext(ext: &ExtFragment) -> Self {
+        Type {
+            corr: Property::from_ext(ext),
+            mall: Property::from_ext(ext),
+        }
+    }
+
     fn from_after(t: u32) -> Self {
         Type {
             corr: Property::from_after(t),
@@ -825,6 +845,8 @@ impl Property for Type {
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
             Terminal::Hash160(..) => Ok(Self::from_hash160()),
+            Terminal::TxTemplate(..) => Ok(Self::from_tx_template()),
+            Terminal::Ext(ref e) => Ok(Self::from_ext(e)),
             Terminal::Alt(ref sub) => wrap_err(Self::cast_alt(sub.ty.clone())),
             Terminal::Swap(ref sub) => wrap_err(Self::cast_swap(sub.ty.clone())),
             Terminal::Check(ref sub) => wrap_err(Self::cast_check(sub.ty.clone())),
@@ -20,19 +20,248 @@
 use std::collections::BTreeMap;
 use std::convert::From;
 use std::marker::PhantomData;
-use std::{cmp, error, f64, fmt, mem};
+use std::{cmp, error, f64, fmt, mem, usize};
 
+use miniscript::ext::ExtFragment;
 use miniscript::types::{self, ErrorKind, ExtData, Property, Type};
 use miniscript::ScriptContext;
 use policy::Concrete;
 use std::collections::vec_deque::VecDeque;
 use std::hash;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use {policy, Terminal};
 use {Miniscript, MiniscriptKey};
 
-type PolicyCache<Pk, Ctx> =
-    BTreeMap<(Concrete<Pk>, OrdF64, Option<OrdF64>), BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>>;
+/// Weighs the parts of the cost of spending from a compilation against each
+/// other, so that the compiler picks the cheapest compilation for a given
+/// context.
+///
+/// The cost of a compilation is the size of its script plus the expected
+/// size of its witness, given the probabilities of each branch, with each
+/// part multiplied by its weight here. Only the relative weights matter,
+/// scaling all of them by a fee rate picks the same compilation.
+pub trait CostModel {
+    /// Cost of a byte of script
+    fn script_byte_cost(&self) -> f64 {
+        1.0
+    }
+
+    /// Cost of a byte of witness
+    fn witness_byte_cost(&self) -> f64 {
+        1.0
+    }
+
+    /// Cost of a signature, on top of the cost of its bytes of witness
+    fn signature_cost(&self) -> f64 {
+        0.0
+    }
+}
+
+/// The cost model used by default: script and witness bytes weigh the same,
+/// and signatures have no extra cost
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct DefaultCostModel;
+
+impl CostModel for DefaultCostModel {}
+
+/// A cost model with fixed weights
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WeightedCostModel {
+    /// Cost of a byte of script
+    script_byte_cost: f64,
+    /// Cost of a byte of witness
+    witness_byte_cost: f64,
+    /// Cost of a signature, on top of the cost of its bytes of witness
+    signature_cost: f64,
+}
+
+impl WeightedCostModel {
+    /// Creates a cost model with the given weights, which must be finite and
+    /// non-negative
+    pub fn new(
+        script_byte_cost: f64,
+        witness_byte_cost: f64,
+        signature_cost: f64,
+    ) -> Result<WeightedCostModel, CompilerError> {
+        let model = WeightedCostModel {
+            script_byte_cost: script_byte_cost,
+            witness_byte_cost: witness_byte_cost,
+            signature_cost: signature_cost,
+        };
+        check_cost_model(&model)?;
+        Ok(model)
+    }
+}
+
+/// Checks that the weights of `cost_model` are finite and non-negative, as
+/// costs are compared assuming they are not NaN
+fn check_cost_model(cost_model: &dyn CostModel) -> Result<(), CompilerError> {
+    let weights = [
+        cost_model.script_byte_cost(),
+        cost_model.witness_byte_cost(),
+        cost_model.signature_cost(),
+    ];
+    if weights.iter().all(|w| w.is_finite() && *w >= 0.0) {
+        Ok(())
+    } else {
+        Err(CompilerError::InvalidCostModel)
+    }
+}
+
+impl CostModel for WeightedCostModel {
+    fn script_byte_cost(&self) -> f64 {
+        self.script_byte_cost
+    }
+
+    fn witness_byte_cost(&self) -> f64 {
+        self.witness_byte_cost
+    }
+
+    fn signature_cost(&self) -> f64 {
+        self.signature_cost
+    }
+}
+
+/// A version of the compiler's output.
+///
+/// Compiling a policy with an explicit version always gives the same
+/// Miniscript, whatever the version of this crate: improvements to the
+/// compiler which change its output are only made available under a new
+/// version. [Concrete::compile] uses [CompilerVersion::LATEST], so its
+/// output may change between releases.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum CompilerVersion {
+    /// The compiler minimizing the size of the script plus the expected size
+    /// of the witness, with [DefaultCostModel]
+    V1,
+}
+
+impl CompilerVersion {
+    /// The most recent version of the compiler
+    pub const LATEST: CompilerVersion = CompilerVersion::V1;
+}
+
+impl fmt::Display for CompilerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompilerVersion::V1 => f.write_str("v1"),
+        }
+    }
+}
+
+/// Budgets bounding the resources used by a compilation, see
+/// [best_compilation_with_options]. Large `thresh` policies can otherwise
+/// take a very long time and a lot of memory to compile.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct CompilerOptions {
+    /// Maximum number of sub-policies compiled, each for given satisfaction
+    /// and dissatisfaction probabilities
+    pub max_nodes: Option<usize>,
+    /// Maximum memory used by the compilations kept by the compiler, in
+    /// bytes. This is an estimate, which does not count the Miniscripts shared
+    /// between compilations.
+    pub max_memory: Option<usize>,
+    /// Maximum time spent compiling
+    pub max_duration: Option<Duration>,
+}
+
+/// A budget of [CompilerOptions]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CompilerLimit {
+    /// The maximum number of sub-policies compiled
+    Nodes(usize),
+    /// The maximum memory used, in bytes
+    Memory(usize),
+    /// The maximum time spent compiling
+    Duration(Duration),
+}
+
+impl fmt::Display for CompilerLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompilerLimit::Nodes(n) => write!(f, "{} compiled sub-policies", n),
+            CompilerLimit::Memory(n) => write!(f, "{} bytes of memory", n),
+            CompilerLimit::Duration(d) => write!(
+                f,
+                "{}.{:03}s of compilation",
+                d.as_secs(),
+                d.subsec_millis()
+            ),
+        }
+    }
+}
+
+/// State of a compilation: the best compilations found so far, the cost
+/// model used to compare them and the resources used so far
+struct PolicyCache<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    /// Best compilations of each policy, by satisfaction and dissatisfaction
+    /// probabilities
+    compilations: BTreeMap<
+        (Concrete<Pk>, OrdF64, Option<OrdF64>),
+        BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>,
+    >,
+    cost_model: &'a dyn CostModel,
+    options: CompilerOptions,
+    start: Instant,
+    nodes: usize,
+    memory: usize,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> PolicyCache<'a, Pk, Ctx> {
+    fn new(cost_model: &'a dyn CostModel) -> Self {
+        PolicyCache::with_options(cost_model, CompilerOptions::default())
+    }
+
+    fn with_options(cost_model: &'a dyn CostModel, options: CompilerOptions) -> Self {
+        PolicyCache {
+            compilations: BTreeMap::new(),
+            cost_model: cost_model,
+            options: options,
+            start: Instant::now(),
+            nodes: 0,
+            memory: 0,
+        }
+    }
+
+    /// Accounts for the compilation of a new sub-policy, failing if it goes
+    /// over the node or time budget
+    fn start_node(&mut self) -> Result<(), CompilerError> {
+        self.nodes += 1;
+        if let Some(max) = self.options.max_nodes {
+            if self.nodes > max {
+                return Err(CompilerError::BudgetExceeded(CompilerLimit::Nodes(max)));
+            }
+        }
+        if let Some(max) = self.options.max_duration {
+            if self.start.elapsed() > max {
+                return Err(CompilerError::BudgetExceeded(CompilerLimit::Duration(max)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the compilations of a sub-policy, failing if they go over the
+    /// memory budget
+    fn insert(
+        &mut self,
+        key: (Concrete<Pk>, OrdF64, Option<OrdF64>),
+        compilations: BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>,
+    ) -> Result<(), CompilerError> {
+        self.memory += mem::size_of_val(&key)
+            + compilations.len()
+                * (mem::size_of::<CompilationKey>()
+                    + mem::size_of::<AstElemExt<Pk, Ctx>>()
+                    + mem::size_of::<Miniscript<Pk, Ctx>>());
+        if let Some(max) = self.options.max_memory {
+            if self.memory > max {
+                return Err(CompilerError::BudgetExceeded(CompilerLimit::Memory(max)));
+            }
+        }
+        self.compilations.insert(key, compilations);
+        Ok(())
+    }
+}
 
 ///Ordered f64 for comparison
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
@@ -58,6 +287,11 @@ pub enum CompilerError {
     /// There may exist other miniscripts which are under these limits but the
     /// compiler currently does not find them.
     LimitsExceeded,
+    /// The compilation went over one of the budgets of its
+    /// [CompilerOptions]
+    BudgetExceeded(CompilerLimit),
+    /// A weight of the cost model is negative, infinite or NaN
+    InvalidCostModel,
     ///Policy related errors
     PolicyError(policy::concrete::PolicyError),
 }
@@ -76,6 +310,12 @@ impl fmt::Display for CompilerError {
             CompilerError::LimitsExceeded => f.write_str(
                 "At least one spending path has exceeded the standardness or consensus limits",
             ),
+            CompilerError::BudgetExceeded(ref limit) => {
+                write!(f, "Compilation exceeded its budget of {}", limit)
+            }
+            CompilerError::InvalidCostModel => {
+                f.write_str("Cost model weights must be finite and non-negative")
+            }
             CompilerError::PolicyError(ref e) => fmt::Display::fmt(e, f),
         }
     }
@@ -88,6 +328,97 @@ impl From<policy::concrete::PolicyError> for CompilerError {
     }
 }
 
+/// Upper bounds on the cost of spending from a compilation, see
+/// [best_compilation_bounded]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct CompilationBounds {
+    /// Maximum size of a satisfaction, in bytes, as computed by
+    /// [Miniscript::max_satisfaction_size]
+    pub max_satisfaction_size: Option<usize>,
+    /// Maximum size of the script, in bytes
+    pub max_script_size: Option<usize>,
+}
+
+/// A bound of [CompilationBounds]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Bound {
+    /// The maximum satisfaction size
+    SatisfactionSize(usize),
+    /// The maximum script size
+    ScriptSize(usize),
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bound::SatisfactionSize(n) => write!(f, "satisfaction size of {} bytes", n),
+            Bound::ScriptSize(n) => write!(f, "script size of {} bytes", n),
+        }
+    }
+}
+
+/// Error of a compilation under [CompilationBounds]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BoundedCompilationError<Pk: MiniscriptKey> {
+    /// The policy cannot be compiled at all
+    Compiler(CompilerError),
+    /// The compiler found no compilation within a bound
+    BoundExceeded {
+        /// The bound
+        bound: Bound,
+        /// The smallest value of the bounded quantity the compiler found
+        best: usize,
+        /// The smallest sub-policy which alone exceeds the bound, or which
+        /// exceeds it once its sub-policies are combined
+        sub_policy: Concrete<Pk>,
+        /// The smallest value of the bounded quantity the compiler found for
+        /// the sub-policy alone
+        sub_policy_best: usize,
+    },
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for BoundedCompilationError<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoundedCompilationError::Compiler(ref e) => fmt::Display::fmt(e, f),
+            BoundedCompilationError::BoundExceeded {
+                bound,
+                best,
+                ref sub_policy,
+                sub_policy_best,
+            } => write!(
+                f,
+                "No compilation within the {}, the best has {} bytes; \
+                 sub-policy {} alone takes {} bytes",
+                bound, best, sub_policy, sub_policy_best
+            ),
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> error::Error for BoundedCompilationError<Pk> {}
+
+#[doc(hidden)]
+impl<Pk: MiniscriptKey> From<CompilerError> for BoundedCompilationError<Pk> {
+    fn from(e: CompilerError) -> BoundedCompilationError<Pk> {
+        BoundedCompilationError::Compiler(e)
+    }
+}
+
+/// A compilation of a policy along with its costs, see [top_compilations]
+#[derive(Clone, PartialEq, Debug)]
+pub struct Compilation<Pk: MiniscriptKey, Ctx: ScriptContext> {
+    /// The compiled Miniscript
+    pub ms: Miniscript<Pk, Ctx>,
+    /// Size of the script plus expected size of the witness, given the
+    /// probabilities of the branches of the policy. This is the cost the
+    /// compiler minimizes by default.
+    pub expected_cost: f64,
+    /// Size of the script plus maximum size of a satisfaction, `None` if the
+    /// Miniscript cannot be satisfied
+    pub worst_case_cost: Option<usize>,
+}
+
 /// Hash required for using OrdF64 as key for hashmap
 impl hash::Hash for OrdF64 {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
@@ -147,6 +478,30 @@ struct CompilerExtData {
     /// (total length of all witness pushes, plus their own length prefixes)
     /// for fragments that can be dissatisfied without failing the script.
     dissat_cost: Option<f64>,
+    /// The expected number of signatures needed to satisfy the fragment
+    sat_sigs: f64,
+}
+
+impl CompilerExtData {
+    /// Like `threshold`, for sub-fragments each satisfied with the given
+    /// probability rather than `k/n`
+    fn weighted_threshold(subs: &[(f64, CompilerExtData)]) -> Self {
+        let mut sat_cost = 0.0;
+        let mut dissat_cost = 0.0;
+        let mut sat_sigs = 0.0;
+        for &(prob, ref sub) in subs {
+            let sub_dissat = sub.dissat_cost.expect("threshold subs are dissatisfiable");
+            sat_cost += prob * sub.sat_cost + (1.0 - prob) * sub_dissat;
+            dissat_cost += sub_dissat;
+            sat_sigs += prob * sub.sat_sigs;
+        }
+        CompilerExtData {
+            branch_prob: None,
+            sat_cost: sat_cost,
+            dissat_cost: Some(dissat_cost),
+            sat_sigs: sat_sigs,
+        }
+    }
 }
 
 impl Property for CompilerExtData {
@@ -155,6 +510,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 0.0,
             dissat_cost: None,
+            sat_sigs: 0.0,
         }
     }
 
@@ -163,6 +519,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: f64::MAX,
             dissat_cost: Some(0.0),
+            sat_sigs: 0.0,
         }
     }
 
@@ -171,6 +528,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 73.0,
             dissat_cost: Some(1.0),
+            sat_sigs: 1.0,
         }
     }
 
@@ -179,6 +537,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 73.0 + 34.0,
             dissat_cost: Some(1.0 + 34.0),
+            sat_sigs: 1.0,
         }
     }
 
@@ -187,6 +546,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 1.0 + 73.0 * k as f64,
             dissat_cost: Some(1.0 * (k + 1) as f64),
+            sat_sigs: k as f64,
         }
     }
 
@@ -195,6 +555,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 33.0,
             dissat_cost: Some(33.0),
+            sat_sigs: 0.0,
         }
     }
 
@@ -203,6 +564,17 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 0.0,
             dissat_cost: None,
+            sat_sigs: 0.0,
+        }
+    }
+
+    fn from_ext(ext: &ExtFragment) -> Self {
+        let extra = ext.extra_prop();
+        CompilerExtData {
+            branch_prob: None,
+            sat_cost: extra.max_sat_size.map_or(f64::MAX, |(w, _)| w as f64),
+            dissat_cost: extra.max_dissat_size.map(|(w, _)| w as f64),
+            sat_sigs: 0.0,
         }
     }
 
@@ -211,6 +583,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -219,6 +592,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -227,6 +601,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -235,6 +610,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 2.0 + self.sat_cost,
             dissat_cost: Some(1.0),
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -243,6 +619,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: None,
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -251,6 +628,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: Some(1.0),
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -259,6 +637,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: self.dissat_cost,
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -267,6 +646,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: self.sat_cost,
             dissat_cost: None,
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -280,6 +660,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 2.0 + self.sat_cost,
             dissat_cost: Some(1.0),
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -288,6 +669,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: 1.0 + self.sat_cost,
             dissat_cost: Some(2.0),
+            sat_sigs: self.sat_sigs,
         })
     }
 
@@ -299,6 +681,7 @@ impl Property for CompilerExtData {
                 (Some(l), Some(r)) => Some(l + r),
                 _ => None,
             },
+            sat_sigs: left.sat_sigs + right.sat_sigs,
         })
     }
 
@@ -307,6 +690,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: left.sat_cost + right.sat_cost,
             dissat_cost: None,
+            sat_sigs: left.sat_sigs + right.sat_sigs,
         })
     }
 
@@ -322,6 +706,7 @@ impl Property for CompilerExtData {
             sat_cost: lprob * (l.sat_cost + r.dissat_cost.unwrap())
                 + rprob * (r.sat_cost + l.dissat_cost.unwrap()),
             dissat_cost: Some(l.dissat_cost.unwrap() + r.dissat_cost.unwrap()),
+            sat_sigs: lprob * l.sat_sigs + rprob * r.sat_sigs,
         })
     }
 
@@ -336,6 +721,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: lprob * l.sat_cost + rprob * (r.sat_cost + l.dissat_cost.unwrap()),
             dissat_cost: r.dissat_cost.map(|rd| l.dissat_cost.unwrap() + rd),
+            sat_sigs: lprob * l.sat_sigs + rprob * r.sat_sigs,
         })
     }
 
@@ -350,6 +736,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: lprob * l.sat_cost + rprob * (r.sat_cost + l.dissat_cost.unwrap()),
             dissat_cost: None,
+            sat_sigs: lprob * l.sat_sigs + rprob * r.sat_sigs,
         })
     }
 
@@ -376,6 +763,7 @@ impl Property for CompilerExtData {
             } else {
                 None
             },
+            sat_sigs: lprob * l.sat_sigs + rprob * r.sat_sigs,
         })
     }
 
@@ -399,6 +787,7 @@ impl Property for CompilerExtData {
             } else {
                 None
             },
+            sat_sigs: aprob * (a.sat_sigs + b.sat_sigs) + cprob * c.sat_sigs,
         })
     }
 
@@ -407,6 +796,7 @@ impl Property for CompilerExtData {
             branch_prob: None,
             sat_cost: a.sat_cost + b.sat_cost,
             dissat_cost: a.dissat_cost,
+            sat_sigs: a.sat_sigs + b.sat_sigs,
         })
     }
 
@@ -417,15 +807,18 @@ impl Property for CompilerExtData {
         let k_over_n = k as f64 / n as f64;
         let mut sat_cost = 0.0;
         let mut dissat_cost = 0.0;
+        let mut sat_sigs = 0.0;
         for i in 0..n {
             let sub = sub_ck(i)?;
             sat_cost += sub.sat_cost;
             dissat_cost += sub.dissat_cost.unwrap();
+            sat_sigs += sub.sat_sigs;
         }
         Ok(CompilerExtData {
             branch_prob: None,
             sat_cost: sat_cost * k_over_n + dissat_cost * (1.0 - k_over_n),
             dissat_cost: Some(dissat_cost),
+            sat_sigs: sat_sigs * k_over_n,
         })
     }
 }
@@ -442,12 +835,16 @@ struct AstElemExt<Pk: MiniscriptKey, Ctx: ScriptContext> {
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> AstElemExt<Pk, Ctx> {
     /// Compute a 1-dimensional cost, given a probability of satisfaction
     /// and a probability of dissatisfaction; if `dissat_prob` is `None`
-    /// then it is assumed that dissatisfaction never occurs
-    fn cost_1d(&self, sat_prob: f64, dissat_prob: Option<f64>) -> f64 {
-        self.ms.ext.pk_cost as f64
-            + self.comp_ext_data.sat_cost * sat_prob
+    /// then it is assumed that dissatisfaction never occurs. Script, witness
+    /// and signatures are weighed by `cost_model`.
+    fn cost_1d(&self, cost_model: &dyn CostModel, sat_prob: f64, dissat_prob: Option<f64>) -> f64 {
+        let witness_byte_cost = cost_model.witness_byte_cost();
+        self.ms.ext.pk_cost as f64 * cost_model.script_byte_cost()
+            + (self.comp_ext_data.sat_cost * witness_byte_cost
+                + self.comp_ext_data.sat_sigs * cost_model.signature_cost())
+                * sat_prob
             + match (dissat_prob, self.comp_ext_data.dissat_cost) {
-                (Some(prob), Some(cost)) => prob * cost,
+                (Some(prob), Some(cost)) => prob * cost * witness_byte_cost,
                 (Some(_), None) => f64::INFINITY,
                 (None, Some(_)) => 0.0,
                 (None, None) => 0.0,
@@ -638,6 +1035,7 @@ fn all_casts<Pk: MiniscriptKey, Ctx: ScriptContext>() -> [Cast<Pk, Ctx>; 10] {
 fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
     map: &mut BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>,
     elem: AstElemExt<Pk, Ctx>,
+    cost_model: &dyn CostModel,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> bool {
@@ -651,7 +1049,7 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
         return false;
     }
 
-    let elem_cost = elem.cost_1d(sat_prob, dissat_prob);
+    let elem_cost = elem.cost_1d(cost_model, sat_prob, dissat_prob);
 
     let elem_key = CompilationKey::from_type(elem.ms.ty, elem.ms.ext.has_free_verify, dissat_prob);
 
@@ -661,7 +1059,7 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
     let is_worse = map
         .iter()
         .map(|(existing_key, existing_elem)| {
-            let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob);
+            let existing_elem_cost = existing_elem.cost_1d(cost_model, sat_prob, dissat_prob);
             existing_key.is_subtype(elem_key) && existing_elem_cost <= elem_cost
         })
         .fold(false, |acc, x| acc || x);
@@ -671,7 +1069,7 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
         *map = mem::replace(map, BTreeMap::new())
             .into_iter()
             .filter(|&(ref existing_key, ref existing_elem)| {
-                let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob);
+                let existing_elem_cost = existing_elem.cost_1d(cost_model, sat_prob, dissat_prob);
                 !(elem_key.is_subtype(*existing_key) && existing_elem_cost >= elem_cost)
             })
             .collect();
@@ -691,11 +1089,12 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
 fn insert_elem_closure<Pk: MiniscriptKey, Ctx: ScriptContext>(
     map: &mut BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>,
     astelem_ext: AstElemExt<Pk, Ctx>,
+    cost_model: &dyn CostModel,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) {
     let mut cast_stack: VecDeque<AstElemExt<Pk, Ctx>> = VecDeque::new();
-    if insert_elem(map, astelem_ext.clone(), sat_prob, dissat_prob) {
+    if insert_elem(map, astelem_ext.clone(), cost_model, sat_prob, dissat_prob) {
         cast_stack.push_back(astelem_ext);
     }
 
@@ -705,7 +1104,7 @@ fn insert_elem_closure<Pk: MiniscriptKey, Ctx: ScriptContext>(
 
         for i in 0..casts.len() {
             if let Ok(new_ext) = casts[i].cast(&current) {
-                if insert_elem(map, new_ext.clone(), sat_prob, dissat_prob) {
+                if insert_elem(map, new_ext.clone(), cost_model, sat_prob, dissat_prob) {
                     cast_stack.push_back(new_ext);
                 }
             }
@@ -730,7 +1129,8 @@ fn insert_best_wrapped<Pk: MiniscriptKey, Ctx: ScriptContext>(
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<(), CompilerError> {
-    insert_elem_closure(map, data, sat_prob, dissat_prob);
+    let cost_model = policy_cache.cost_model;
+    insert_elem_closure(map, data, cost_model, sat_prob, dissat_prob);
 
     if dissat_prob.is_some() {
         let casts: [Cast<Pk, Ctx>; 10] = all_casts::<Pk, Ctx>();
@@ -738,7 +1138,7 @@ fn insert_best_wrapped<Pk: MiniscriptKey, Ctx: ScriptContext>(
         for i in 0..casts.len() {
             for x in best_compilations(policy_cache, policy, sat_prob, None)?.values() {
                 if let Ok(new_ext) = casts[i].cast(x) {
-                    insert_elem_closure(map, new_ext, sat_prob, dissat_prob);
+                    insert_elem_closure(map, new_ext, cost_model, sat_prob, dissat_prob);
                 }
             }
         }
@@ -761,9 +1161,14 @@ where
     //Check the cache for hits
     let ord_sat_prob = OrdF64(sat_prob);
     let ord_dissat_prob = dissat_prob.and_then(|x| Some(OrdF64(x)));
-    if let Some(ret) = policy_cache.get(&(policy.clone(), ord_sat_prob, ord_dissat_prob)) {
+    if let Some(ret) =
+        policy_cache
+            .compilations
+            .get(&(policy.clone(), ord_sat_prob, ord_dissat_prob))
+    {
         return Ok(ret.clone());
     }
+    policy_cache.start_node()?;
 
     let mut ret = BTreeMap::new();
 
@@ -823,6 +1228,9 @@ where
         Concrete::Hash256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash256(hash))),
         Concrete::Ripemd160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Ripemd160(hash))),
         Concrete::Hash160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash160(hash))),
+        Concrete::TxTemplate(hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::TxTemplate(hash)))
+        }
         Concrete::And(ref subs) => {
             assert_eq!(subs.len(), 2, "and takes 2 args");
             let mut left = best_compilations(policy_cache, &subs[0], sat_prob, dissat_prob)?;
@@ -935,9 +1343,25 @@ where
             compile_binary!(&mut l_comp[3], &mut r_comp[2], [lw, rw], Terminal::OrI);
             compile_binary!(&mut r_comp[3], &mut l_comp[2], [rw, lw], Terminal::OrI);
         }
-        Concrete::Threshold(k, ref subs) => {
+        Concrete::Threshold(k, _) | Concrete::WeightedThreshold(k, _) => {
+            let subs = policy.children();
             let n = subs.len();
-            let k_over_n = k as f64 / n as f64;
+            // Probability of each sub-policy being among the satisfied ones
+            let sub_probs = match *policy {
+                Concrete::WeightedThreshold(_, ref weighted) => {
+                    let total = weighted.iter().map(|&(weight, _)| weight).sum::<usize>();
+                    if total == 0 {
+                        vec![k as f64 / n as f64; n]
+                    } else {
+                        weighted
+                            .iter()
+                            .map(|&(weight, _)| k as f64 * weight as f64 / total as f64)
+                            .map(|prob| prob.min(1.0))
+                            .collect()
+                    }
+                }
+                _ => vec![k as f64 / n as f64; n],
+            };
 
             let mut sub_ast = Vec::with_capacity(n);
             let mut sub_ext_data = Vec::with_capacity(n);
@@ -947,13 +1371,14 @@ where
 
             let mut min_value = (0 as usize, f64::INFINITY as f64);
             for (i, ast) in subs.iter().enumerate() {
-                let sp = sat_prob * k_over_n;
+                let sp = sat_prob * sub_probs[i];
                 //Expressions must be dissatisfiable
-                let dp = Some(dissat_prob.unwrap_or(0 as f64) + (1.0 - k_over_n) * sat_prob);
+                let dp = Some(dissat_prob.unwrap_or(0 as f64) + (1.0 - sub_probs[i]) * sat_prob);
                 let be = best(types::Base::B, policy_cache, ast, sp, dp)?;
                 let bw = best(types::Base::W, policy_cache, ast, sp, dp)?;
 
-                let diff = be.cost_1d(sp, dp) - bw.cost_1d(sp, dp);
+                let cost_model = policy_cache.cost_model;
+                let diff = be.cost_1d(cost_model, sp, dp) - bw.cost_1d(cost_model, sp, dp);
                 best_es.push((be.comp_ext_data, be));
                 best_ws.push((bw.comp_ext_data, bw));
 
@@ -962,14 +1387,28 @@ where
                     min_value.1 = diff;
                 }
             }
+            let mut ordered_probs = Vec::with_capacity(n);
             sub_ext_data.push(best_es[min_value.0].0);
             sub_ast.push(Arc::clone(&best_es[min_value.0].1.ms));
+            ordered_probs.push(sub_probs[min_value.0]);
             for (i, _ast) in subs.iter().enumerate() {
                 if i != min_value.0 {
                     sub_ext_data.push(best_ws[i].0);
                     sub_ast.push(Arc::clone(&best_ws[i].1.ms));
+                    ordered_probs.push(sub_probs[i]);
                 }
             }
+            let comp_ext_data = match *policy {
+                Concrete::WeightedThreshold(..) => {
+                    let weighted: Vec<_> = ordered_probs
+                        .into_iter()
+                        .zip(sub_ext_data.iter().cloned())
+                        .collect();
+                    CompilerExtData::weighted_threshold(&weighted)
+                }
+                _ => CompilerExtData::threshold(k, n, |i| Ok(sub_ext_data[i]))
+                    .expect("threshold subs, which we just compiled, typeck"),
+            };
 
             let ast = Terminal::Thresh(k, sub_ast);
             let ast_ext = AstElemExt {
@@ -977,15 +1416,14 @@ where
                     Miniscript::from_ast(ast)
                         .expect("threshold subs, which we just compiled, typeck"),
                 ),
-                comp_ext_data: CompilerExtData::threshold(k, n, |i| Ok(sub_ext_data[i]))
-                    .expect("threshold subs, which we just compiled, typeck"),
+                comp_ext_data: comp_ext_data,
             };
             insert_wrap!(ast_ext);
 
             let key_vec: Vec<Pk> = subs
                 .iter()
                 .filter_map(|s| {
-                    if let Concrete::Key(ref pk) = *s {
+                    if let Concrete::Key(ref pk) = **s {
                         Some(pk.clone())
                     } else {
                         None
@@ -999,9 +1437,9 @@ where
             // Not a threshold, it's always more optimal to translate it to and()s as we save the
             // resulting threshold check (N EQUAL) in any case.
             else if k == subs.len() {
-                let mut policy = subs.first().expect("No sub policy in thresh() ?").clone();
+                let mut policy = (*subs.first().expect("No sub policy in thresh() ?")).clone();
                 for sub in &subs[1..] {
-                    policy = Concrete::And(vec![sub.clone(), policy]);
+                    policy = Concrete::And(vec![(*sub).clone(), policy]);
                 }
 
                 ret = best_compilations(policy_cache, &policy, sat_prob, dissat_prob)?;
@@ -1022,7 +1460,7 @@ where
         // before calling this compile function
         Err(CompilerError::LimitsExceeded)
     } else {
-        policy_cache.insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone());
+        policy_cache.insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone())?;
         Ok(ret)
     }
 }
@@ -1098,7 +1536,28 @@ fn compile_tern<Pk: MiniscriptKey, Ctx: ScriptContext>(
 pub fn best_compilation<Pk: MiniscriptKey, Ctx: ScriptContext>(
     policy: &Concrete<Pk>,
 ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
-    let mut policy_cache = PolicyCache::<Pk, Ctx>::new();
+    best_compilation_with(policy, &DefaultCostModel)
+}
+
+/// Obtain the best compilation of for p=1.0 and q=0, comparing compilations
+/// with `cost_model`
+pub fn best_compilation_with<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+    cost_model: &dyn CostModel,
+) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+    best_compilation_with_options(policy, cost_model, CompilerOptions::default())
+}
+
+/// Obtain the best compilation of for p=1.0 and q=0, comparing compilations
+/// with `cost_model`, failing with [CompilerError::BudgetExceeded] as soon as
+/// the compilation goes over a budget of `options`
+pub fn best_compilation_with_options<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+    cost_model: &dyn CostModel,
+    options: CompilerOptions,
+) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+    check_cost_model(cost_model)?;
+    let mut policy_cache = PolicyCache::<Pk, Ctx>::with_options(cost_model, options);
     let x = &*best_t(&mut policy_cache, policy, 1.0, None)?.ms;
     if !x.ty.mall.safe {
         Err(CompilerError::TopLevelNonSafe)
@@ -1109,6 +1568,199 @@ pub fn best_compilation<Pk: MiniscriptKey, Ctx: ScriptContext>(
     }
 }
 
+/// Obtain the compilation of for p=1.0 and q=0 given by `version` of the
+/// compiler, see [CompilerVersion]
+pub fn versioned_compilation<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+    version: CompilerVersion,
+) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+    match version {
+        // V1 is the live compiler: changes to its output must go under a new
+        // version, which the golden outputs of V1 in the tests enforce
+        CompilerVersion::V1 => best_compilation_with(policy, &DefaultCostModel),
+    }
+}
+
+/// Cost models tried, in order, to find alternative compilations: the
+/// default one, then ones favouring smaller witnesses or smaller scripts,
+/// more and more strongly
+fn alternative_cost_models() -> Vec<WeightedCostModel> {
+    let mut models = vec![];
+    for &weight in &[1.0, 4.0, 64.0] {
+        models.push(WeightedCostModel {
+            script_byte_cost: 1.0,
+            witness_byte_cost: weight,
+            signature_cost: 0.0,
+        });
+        models.push(WeightedCostModel {
+            script_byte_cost: weight,
+            witness_byte_cost: 1.0,
+            signature_cost: 0.0,
+        });
+    }
+    models.dedup();
+    models
+}
+
+/// The bounded quantity of `ms`
+fn bounded_value<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+    bound: Bound,
+) -> usize {
+    match bound {
+        Bound::SatisfactionSize(_) => ms.max_satisfaction_size().unwrap_or(usize::MAX),
+        Bound::ScriptSize(_) => ms.script_size(),
+    }
+}
+
+/// Obtain a compilation for p=1.0 and q=0 within `bounds`, trying cost models
+/// favouring smaller witnesses or smaller scripts if the best compilation
+/// exceeds them. As the compiler only explores some of the compilations, one
+/// within bounds may exist even if none is found.
+///
+/// If none is found, the error reports the first bound exceeded and looks for
+/// the sub-policy responsible for it: the smallest sub-policy which exceeds
+/// the bound on its own, or whose sub-policies only exceed it once combined.
+pub fn best_compilation_bounded<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+    bounds: CompilationBounds,
+) -> Result<Miniscript<Pk, Ctx>, BoundedCompilationError<Pk>> {
+    let mut bound_list = vec![];
+    if let Some(n) = bounds.max_satisfaction_size {
+        bound_list.push(Bound::SatisfactionSize(n));
+    }
+    if let Some(n) = bounds.max_script_size {
+        bound_list.push(Bound::ScriptSize(n));
+    }
+    let within = |ms: &Miniscript<Pk, Ctx>, bound: Bound| match bound {
+        Bound::SatisfactionSize(n) | Bound::ScriptSize(n) => bounded_value(ms, bound) <= n,
+    };
+
+    let mut compilations = vec![];
+    for model in alternative_cost_models() {
+        let ms: Miniscript<Pk, Ctx> = match best_compilation_with(policy, &model) {
+            Ok(ms) => ms,
+            Err(e) => {
+                if compilations.is_empty() {
+                    return Err(BoundedCompilationError::Compiler(e));
+                }
+                continue;
+            }
+        };
+        if bound_list.iter().all(|&bound| within(&ms, bound)) {
+            return Ok(ms);
+        }
+        compilations.push(ms);
+    }
+
+    // The first bound which no compilation meets, if any; otherwise no
+    // compilation meets all the bounds at once, report the first one
+    let bound = bound_list
+        .iter()
+        .cloned()
+        .find(|&bound| !compilations.iter().any(|ms| within(ms, bound)))
+        .unwrap_or(bound_list[0]);
+    let best = compilations
+        .iter()
+        .map(|ms| bounded_value(ms, bound))
+        .min()
+        .expect("at least one compilation");
+
+    // Descend into the sub-policies exceeding the bound alone
+    let sub_best = |sub: &Concrete<Pk>| {
+        alternative_cost_models()
+            .iter()
+            .filter_map(|model| {
+                let mut policy_cache = PolicyCache::<Pk, Ctx>::new(model);
+                best_t(&mut policy_cache, sub, 1.0, None).ok()
+            })
+            .map(|ext| bounded_value(&ext.ms, bound))
+            .min()
+    };
+    let limit = match bound {
+        Bound::SatisfactionSize(n) | Bound::ScriptSize(n) => n,
+    };
+    let mut sub_policy = policy;
+    let mut sub_policy_best = best;
+    loop {
+        let worst = sub_policy
+            .children()
+            .into_iter()
+            .filter_map(|sub| sub_best(sub).map(|best| (sub, best)))
+            .filter(|&(_, best)| best > limit)
+            .max_by_key(|&(_, best)| best);
+        match worst {
+            Some((sub, best)) => {
+                sub_policy = sub;
+                sub_policy_best = best;
+            }
+            None => break,
+        }
+    }
+
+    Err(BoundedCompilationError::BoundExceeded {
+        bound: bound,
+        best: best,
+        sub_policy: sub_policy.clone(),
+        sub_policy_best: sub_policy_best,
+    })
+}
+
+/// Obtain the `k` best distinct compilations for p=1.0 and q=0, in order of
+/// expected cost, the first one being the compilation returned by
+/// [best_compilation].
+///
+/// Alternatives are the best compilations of each type the compiler keeps
+/// for the policy, under the default cost model and under cost models
+/// favouring smaller witnesses or smaller scripts. Fewer than `k` are
+/// returned if the compiler finds fewer distinct safe and non-malleable ones.
+pub fn top_compilations<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+    k: usize,
+) -> Result<Vec<Compilation<Pk, Ctx>>, CompilerError> {
+    let best = best_compilation::<Pk, Ctx>(policy)?;
+
+    let mut candidates: Vec<AstElemExt<Pk, Ctx>> = vec![];
+    for model in alternative_cost_models() {
+        let mut policy_cache = PolicyCache::<Pk, Ctx>::new(&model);
+        let compilations = match best_compilations(&mut policy_cache, policy, 1.0, None) {
+            Ok(compilations) => compilations,
+            Err(_) => continue,
+        };
+        for (key, ext) in compilations {
+            if key.ty.corr.base == types::Base::B
+                && key.dissat_prob.is_none()
+                && ext.ms.ty.mall.safe
+                && ext.ms.ty.mall.non_malleable
+                && !candidates.iter().any(|c| c.ms == ext.ms)
+            {
+                candidates.push(ext);
+            }
+        }
+    }
+
+    let cost = |ext: &AstElemExt<Pk, Ctx>| OrdF64(ext.cost_1d(&DefaultCostModel, 1.0, None));
+    // The best compilation first, even if another one ties with it
+    candidates.sort_by(|a, b| {
+        (*a.ms != best)
+            .cmp(&(*b.ms != best))
+            .then_with(|| cost(a).cmp(&cost(b)))
+    });
+    Ok(candidates
+        .into_iter()
+        .take(k)
+        .map(|ext| Compilation {
+            expected_cost: cost(&ext).0,
+            worst_case_cost: ext
+                .ms
+                .max_satisfaction_size()
+                .ok()
+                .map(|size| size + ext.ms.script_size()),
+            ms: Arc::try_unwrap(ext.ms).unwrap_or_else(|ms| (*ms).clone()),
+        })
+        .collect())
+}
+
 /// Obtain the best B expression with given sat and dissat
 fn best_t<Pk, Ctx>(
     policy_cache: &mut PolicyCache<Pk, Ctx>,
@@ -1120,6 +1772,7 @@ where
     Pk: MiniscriptKey,
     Ctx: ScriptContext,
 {
+    let cost_model = policy_cache.cost_model;
     best_compilations(policy_cache, policy, sat_prob, dissat_prob)?
         .into_iter()
         .filter(|&(key, _)| {
@@ -1127,7 +1780,7 @@ where
                 && key.dissat_prob == dissat_prob.and_then(|x| Some(OrdF64(x)))
         })
         .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .min_by_key(|ext| OrdF64(ext.cost_1d(cost_model, sat_prob, dissat_prob)))
         .ok_or(CompilerError::LimitsExceeded)
 }
 
@@ -1143,6 +1796,7 @@ where
     Pk: MiniscriptKey,
     Ctx: ScriptContext,
 {
+    let cost_model = policy_cache.cost_model;
     best_compilations(policy_cache, policy, sat_prob, dissat_prob)?
         .into_iter()
         .filter(|&(ref key, ref val)| {
@@ -1152,7 +1806,7 @@ where
                 && key.dissat_prob == dissat_prob.and_then(|x| Some(OrdF64(x)))
         })
         .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .min_by_key(|ext| OrdF64(ext.cost_1d(cost_model, sat_prob, dissat_prob)))
         .ok_or(CompilerError::LimitsExceeded)
 }
 
@@ -1262,9 +1916,12 @@ mod tests {
     fn compile_q() {
         let policy = SPolicy::from_str("or(1@and(pk(A),pk(B)),127@pk(C))").expect("parsing");
         let compilation: DummySegwitAstElemExt =
-            best_t(&mut BTreeMap::new(), &policy, 1.0, None).unwrap();
+            best_t(&mut PolicyCache::new(&DefaultCostModel), &policy, 1.0, None).unwrap();
 
-        assert_eq!(compilation.cost_1d(1.0, None), 88.0 + 74.109375);
+        assert_eq!(
+            compilation.cost_1d(&DefaultCostModel, 1.0, None),
+            88.0 + 74.109375
+        );
         assert_eq!(
             policy.lift().unwrap().sorted(),
             compilation.ms.lift().unwrap().sorted()
@@ -1274,15 +1931,298 @@ mod tests {
                 "and(and(and(or(127@thresh(2,pk(A),pk(B),thresh(2,or(127@pk(A),1@pk(B)),after(100),or(and(pk(C),after(200)),and(pk(D),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925))),pk(E))),1@pk(F)),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925)),or(127@pk(G),1@after(300))),or(127@after(400),pk(H)))"
             ).expect("parsing");
         let compilation: DummySegwitAstElemExt =
-            best_t(&mut BTreeMap::new(), &policy, 1.0, None).unwrap();
+            best_t(&mut PolicyCache::new(&DefaultCostModel), &policy, 1.0, None).unwrap();
 
-        assert_eq!(compilation.cost_1d(1.0, None), 437.0 + 299.4003295898438);
+        assert_eq!(
+            compilation.cost_1d(&DefaultCostModel, 1.0, None),
+            437.0 + 299.4003295898438
+        );
         assert_eq!(
             policy.lift().unwrap().sorted(),
             compilation.ms.lift().unwrap().sorted()
         );
     }
 
+    #[test]
+    fn cost_model() {
+        let policy = SPolicy::from_str("or(1@and(pk(A),pk(B)),127@pk(C))").expect("parsing");
+        let compilation: DummySegwitAstElemExt =
+            best_t(&mut PolicyCache::new(&DefaultCostModel), &policy, 1.0, None).unwrap();
+
+        // Script bytes count double, and each signature costs 10 more
+        let model = WeightedCostModel::new(2.0, 1.0, 10.0).unwrap();
+        // One signature with probability 127/128, two otherwise
+        assert_eq!(
+            compilation.cost_1d(&model, 1.0, None),
+            2.0 * 88.0 + 74.109375 + 10.0 * (127.0 + 2.0) / 128.0
+        );
+
+        // Only the relative weights matter
+        let scaled = WeightedCostModel::new(4.0, 4.0, 0.0).unwrap();
+        let policy = SPolicy::from_str("or(pk(A),and(pk(B),or(99@older(144),pk(C))))").unwrap();
+        let ms: Miniscript<String, Segwitv0> = policy.compile().unwrap();
+        assert_eq!(policy.compile_with_cost_model(&scaled), Ok(ms));
+
+        // Weights which cannot be compared are rejected
+        use std::f64;
+        for &weight in &[f64::NAN, f64::INFINITY, -1.0] {
+            assert_eq!(
+                WeightedCostModel::new(1.0, weight, 0.0),
+                Err(CompilerError::InvalidCostModel)
+            );
+        }
+        struct NanModel;
+        impl CostModel for NanModel {
+            fn signature_cost(&self) -> f64 {
+                f64::NAN
+            }
+        }
+        assert_eq!(
+            policy.compile_with_cost_model::<Segwitv0>(&NanModel),
+            Err(CompilerError::InvalidCostModel)
+        );
+    }
+
+    #[test]
+    fn compiler_options() {
+        let policy = SPolicy::from_str("thresh(3,pk(A),pk(B),pk(C),pk(D),pk(E))").unwrap();
+        let ms: Miniscript<String, Segwitv0> = policy.compile().unwrap();
+        assert_eq!(
+            policy.compile_with_options(CompilerOptions::default()),
+            Ok(ms.clone())
+        );
+        let options = CompilerOptions {
+            max_nodes: Some(1000),
+            max_memory: Some(1 << 30),
+            max_duration: Some(Duration::from_secs(3600)),
+        };
+        assert_eq!(policy.compile_with_options(options), Ok(ms));
+
+        let options = CompilerOptions {
+            max_nodes: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.compile_with_options::<Segwitv0>(options),
+            Err(CompilerError::BudgetExceeded(CompilerLimit::Nodes(1)))
+        );
+        let options = CompilerOptions {
+            max_memory: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.compile_with_options::<Segwitv0>(options),
+            Err(CompilerError::BudgetExceeded(CompilerLimit::Memory(1)))
+        );
+    }
+
+    #[test]
+    fn compiler_versions() {
+        // Outputs of each version, which must never change
+        let v1 = [
+            ("pk(A)", "pk(A)"),
+            ("and(pk(A),pk(B))", "and_v(v:pk(A),pk(B))"),
+            (
+                "or(10@and(sha256(1111111111111111111111111111111111111111111111111111111111111111),pk(A)),1@and(older(4444),pk(B)))",
+                "andor(pk(A),sha256(1111111111111111111111111111111111111111111111111111111111111111),and_v(v:pkh(B),older(4444)))",
+            ),
+        ];
+        for &(policy, expected) in &v1 {
+            let policy = SPolicy::from_str(policy).unwrap();
+            let ms: Miniscript<String, Segwitv0> =
+                policy.compile_with_version(CompilerVersion::V1).unwrap();
+            assert_eq!(ms.to_string(), expected);
+        }
+
+        // Multisigs, thresholds, timelocks and disjunctions with real keys
+        let (keys, _) = pubkeys_and_a_sig(21);
+        let k = |i: usize| keys[i].to_string();
+        let h = |i: usize| keys[i].to_pubkeyhash().to_string();
+        let mut v1 = vec![
+            (
+                format!("and(older(10000),thresh(2,pk({}),pk({}),pk({})))", k(5), k(6), k(7)),
+                format!("and_v(v:multi(2,{},{},{}),older(10000))", k(5), k(6), k(7)),
+            ),
+            (
+                format!(
+                    "or(127@thresh(3,pk({}),pk({}),pk({}),pk({}),pk({})),1@and(older(10000),thresh(2,pk({}),pk({}),pk({}))))",
+                    k(0), k(1), k(2), k(3), k(4), k(5), k(6), k(7)
+                ),
+                format!(
+                    "or_d(multi(3,{},{},{},{},{}),and_v(v:thresh(2,c:pk_h({}),ac:pk_h({}),ac:pk_h({})),older(10000)))",
+                    k(0), k(1), k(2), k(3), k(4), h(5), h(6), h(7)
+                ),
+            ),
+        ];
+        for n in 1..4 {
+            v1.push((
+                format!("thresh({},pk({}),pk({}),pk({}))", n, k(0), k(1), k(2)),
+                format!("multi({},{},{},{})", n, k(0), k(1), k(2)),
+            ));
+        }
+        let all: Vec<_> = (0..21).map(|i| format!("pk({})", k(i))).collect();
+        let swapped: Vec<_> = (1..21).map(|i| format!("s:pk({})", k(i))).collect();
+        v1.push((
+            format!("thresh(10,{})", all.join(",")),
+            format!("thresh(10,pk({}),{})", k(0), swapped.join(",")),
+        ));
+        for &(ref policy, ref expected) in &v1 {
+            let policy = BPolicy::from_str(policy).unwrap();
+            let ms: SegwitMiniScript = policy.compile_with_version(CompilerVersion::V1).unwrap();
+            assert_eq!(ms, SegwitMiniScript::from_str_insane(expected).unwrap());
+        }
+
+        let policy = SPolicy::from_str("or(pk(A),pk(B))").unwrap();
+        assert_eq!(
+            policy.compile::<Segwitv0>(),
+            policy.compile_with_version(CompilerVersion::LATEST)
+        );
+    }
+
+    #[test]
+    fn weighted_threshold() {
+        let policy = SPolicy::from_str("thresh(2,9@pk(A),9@pk(B),1@pk(C))").unwrap();
+        assert_eq!(policy.to_string(), "thresh(2,9@pk(A),9@pk(B),1@pk(C))");
+        assert_eq!(
+            policy.lift().unwrap(),
+            SPolicy::from_str("thresh(2,pk(A),pk(B),pk(C))")
+                .unwrap()
+                .lift()
+                .unwrap()
+        );
+        // Unweighted sub-policies have a weight of 1
+        assert_eq!(
+            SPolicy::from_str("thresh(2,9@pk(A),pk(B),pk(C))").unwrap(),
+            Concrete::WeightedThreshold(
+                2,
+                vec![
+                    (9, Concrete::Key("A".to_owned())),
+                    (1, Concrete::Key("B".to_owned())),
+                    (1, Concrete::Key("C".to_owned())),
+                ]
+            )
+        );
+
+        let ms: Miniscript<String, Segwitv0> =
+            SPolicy::from_str("thresh(2,9@pk(A),9@and(pk(B),older(144)),1@pk(C))")
+                .unwrap()
+                .compile()
+                .unwrap();
+        assert_eq!(
+            ms.lift().unwrap().sorted(),
+            SPolicy::from_str("thresh(2,pk(A),and(pk(B),older(144)),pk(C))")
+                .unwrap()
+                .lift()
+                .unwrap()
+                .sorted()
+        );
+    }
+
+    #[test]
+    fn cold_keys() {
+        let policy = SPolicy::from_str("or(pk(A),and(pk(B),older(144)))").unwrap();
+        let cold = policy.with_cold_keys(&["A".to_owned()]);
+        assert_eq!(
+            cold,
+            SPolicy::from_str("or(1@pk(A),64@and(pk(B),older(144)))").unwrap()
+        );
+        let ms: Miniscript<String, Segwitv0> = cold.compile().unwrap();
+        let hot: Miniscript<String, Segwitv0> = policy.compile().unwrap();
+        assert!(ms != hot);
+
+        // B and C can sign without A, so only the second branch is cold
+        let policy = SPolicy::from_str("or(thresh(2,pk(A),pk(B),pk(C)),and(pk(A),pk(D)))").unwrap();
+        assert_eq!(
+            policy.with_cold_keys(&["A".to_owned()]),
+            SPolicy::from_str("or(64@thresh(2,1@pk(A),64@pk(B),64@pk(C)),1@and(pk(A),pk(D)))")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn top_compilations() {
+        let policy = SPolicy::from_str("or(9@pk(A),1@and(pk(B),older(144)))").unwrap();
+        let best: Miniscript<String, Segwitv0> = policy.compile().unwrap();
+
+        let top = policy.compile_alternatives::<Segwitv0>(1).unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].ms, best);
+
+        let top = policy.compile_alternatives::<Segwitv0>(10).unwrap();
+        assert!(top.len() > 1);
+        assert_eq!(top[0].ms, best);
+        for (i, compilation) in top.iter().enumerate() {
+            assert!(top[i + 1..].iter().all(|other| other.ms != compilation.ms));
+            assert_eq!(
+                compilation.worst_case_cost,
+                Some(
+                    compilation.ms.script_size() + compilation.ms.max_satisfaction_size().unwrap()
+                )
+            );
+            assert_eq!(
+                policy.lift().unwrap().sorted(),
+                compilation.ms.lift().unwrap().sorted()
+            );
+        }
+        for pair in top[1..].windows(2) {
+            assert!(pair[0].expected_cost <= pair[1].expected_cost);
+        }
+    }
+
+    #[test]
+    fn compile_bounded() {
+        type SegwitDummyScript = Miniscript<String, Segwitv0>;
+
+        let policy = SPolicy::from_str("or(pk(A),pk(B))").unwrap();
+        let ms: SegwitDummyScript = policy.compile().unwrap();
+        assert_eq!(
+            policy.compile_bounded(CompilationBounds::default()),
+            Ok(ms.clone())
+        );
+        let bounds = CompilationBounds {
+            max_satisfaction_size: Some(ms.max_satisfaction_size().unwrap()),
+            max_script_size: Some(ms.script_size()),
+        };
+        assert_eq!(policy.compile_bounded(bounds), Ok(ms));
+
+        // Not even a single key fits
+        let bounds = CompilationBounds {
+            max_satisfaction_size: None,
+            max_script_size: Some(10),
+        };
+        match policy.compile_bounded::<Segwitv0>(bounds) {
+            Err(BoundedCompilationError::BoundExceeded {
+                bound,
+                sub_policy,
+                sub_policy_best,
+                ..
+            }) => {
+                assert_eq!(bound, Bound::ScriptSize(10));
+                assert_eq!(sub_policy, SPolicy::from_str("pk(B)").unwrap());
+                // c:pk_h(B)
+                assert_eq!(sub_policy_best, 25);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // Each signature fits, but not both of them
+        let policy = SPolicy::from_str("and(pk(A),pk(B))").unwrap();
+        let bounds = CompilationBounds {
+            max_satisfaction_size: Some(100),
+            max_script_size: None,
+        };
+        let err = policy.compile_bounded::<Segwitv0>(bounds).unwrap_err();
+        assert_eq!(
+            err,
+            BoundedCompilationError::BoundExceeded {
+                bound: Bound::SatisfactionSize(100),
+                best: 146,
+                sub_policy: policy.clone(),
+                sub_policy_best: 146,
+            }
+        );
+    }
+
     #[test]
     fn compile_misc() {
         let (keys, sig) = pubkeys_and_a_sig(10);
@@ -15,11 +15,15 @@
 //! Concrete Policies
 //!
 
+#[cfg(feature = "arbitrary")]
+use arbitrary::{self, Arbitrary, Unstructured};
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 use std::collections::HashSet;
 use std::{error, fmt, str};
 
+#[cfg(feature = "arbitrary")]
+use super::generator::{random_policy, GeneratorConfig};
 use super::ENTAILMENT_MAX_TERMINALS;
 use errstr;
 use expression::{self, FromTree};
@@ -30,10 +34,40 @@ use miniscript::ScriptContext;
 #[cfg(feature = "compiler")]
 use policy::compiler;
 #[cfg(feature = "compiler")]
-use policy::compiler::CompilerError;
+use policy::compiler::{
+    BoundedCompilationError, Compilation, CompilationBounds, CompilerError, CompilerOptions,
+    CompilerVersion, CostModel, DefaultCostModel,
+};
+use policy::semantic::{Assets, ChainState, Satisfiability};
+use policy::Liftable;
 #[cfg(feature = "compiler")]
 use Miniscript;
-use {Error, ForEach, ForEachKey, MiniscriptKey};
+use {util, Error, ForEach, ForEachKey, MiniscriptKey};
+
+/// How many times less likely than its other branches a disjunction branch
+/// requiring a cold key is taken to be, see [Policy::with_cold_keys]
+pub const COLD_BRANCH_ODDS: usize = 64;
+
+/// Multiplies the weights of the branches which do not require a cold key by
+/// [COLD_BRANCH_ODDS], if some branches do
+fn reweight_cold<Pk: MiniscriptKey>(
+    weights: Vec<usize>,
+    subs: Vec<(Policy<Pk>, bool)>,
+) -> Vec<(usize, Policy<Pk>)> {
+    let mixed = subs.iter().any(|&(_, cold)| cold) && subs.iter().any(|&(_, cold)| !cold);
+    weights
+        .into_iter()
+        .zip(subs)
+        .map(|(weight, (sub, cold))| {
+            if mixed && !cold {
+                (weight.saturating_mul(COLD_BRANCH_ODDS), sub)
+            } else {
+                (weight, sub)
+            }
+        })
+        .collect()
+}
+
 /// Concrete policy which corresponds directly to a Miniscript structure,
 /// and whose disjunctions are annotated with satisfaction probabilities
 /// to assist the compiler
@@ -57,6 +91,9 @@ pub enum Policy<Pk: MiniscriptKey> {
     Ripemd160(ripemd160::Hash),
     /// A HASH160 whose preimage must be provided to satisfy the descriptor
     Hash160(hash160::Hash),
+    /// A transaction template the spending transaction must match, only
+    /// parsed with the `ctv` feature
+    TxTemplate(sha256::Hash),
     /// A list of sub-policies, all of which must be satisfied
     And(Vec<Policy<Pk>>),
     /// A list of sub-policies, one of which must be satisfied, along with
@@ -64,6 +101,9 @@ pub enum Policy<Pk: MiniscriptKey> {
     Or(Vec<(usize, Policy<Pk>)>),
     /// A set of descriptors, satisfactions must be provided for `k` of them
     Threshold(usize, Vec<Policy<Pk>>),
+    /// A threshold whose sub-policies are annotated with relative
+    /// probabilities of being among the `k` satisfied ones
+    WeightedThreshold(usize, Vec<(usize, Policy<Pk>)>),
 }
 
 /// Detailed Error type for Policies
@@ -127,15 +167,140 @@ impl fmt::Display for PolicyError {
     }
 }
 
+/// A likely mistake in a policy, which is still valid, see [Policy::lint]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PolicyLint<Pk: MiniscriptKey> {
+    /// The key appears several times in the policy
+    DuplicateKey(Pk),
+    /// Some satisfaction of the policy requires both a height-based and a
+    /// time-based timelock, which no transaction can have
+    HeightTimeLockCombination,
+    /// The branch of a disjunction or threshold can never be satisfied, so
+    /// can never be taken
+    UnsatisfiableBranch(Policy<Pk>),
+    /// The sub-policy is `TRIVIAL` or `UNSATISFIABLE`, so its parent can be
+    /// simplified
+    ConstantSubPolicy(Policy<Pk>),
+    /// The threshold requires all of its sub-policies, so could be an `and`
+    ThresholdIsAnd(Policy<Pk>),
+    /// The threshold requires one of its sub-policies, so could be an `or`
+    ThresholdIsOr(Policy<Pk>),
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for PolicyLint<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PolicyLint::DuplicateKey(ref pk) => write!(f, "Key {} appears more than once", pk),
+            PolicyLint::HeightTimeLockCombination => {
+                f.write_str("A spending path combines a heightlock and a timelock")
+            }
+            PolicyLint::UnsatisfiableBranch(ref sub) => {
+                write!(f, "Branch {} can never be satisfied", sub)
+            }
+            PolicyLint::ConstantSubPolicy(ref sub) => {
+                write!(f, "Sub-policy {} is constant and can be simplified", sub)
+            }
+            PolicyLint::ThresholdIsAnd(ref sub) => {
+                write!(
+                    f,
+                    "Threshold {} requires all its sub-policies, use and",
+                    sub
+                )
+            }
+            PolicyLint::ThresholdIsOr(ref sub) => {
+                write!(
+                    f,
+                    "Threshold {} requires one of its sub-policies, use or",
+                    sub
+                )
+            }
+        }
+    }
+}
+
 impl<Pk: MiniscriptKey> Policy<Pk> {
-    /// Compile the descriptor into an optimized `Miniscript` representation
+    /// Compile the descriptor into an optimized `Miniscript` representation,
+    /// with the latest version of the compiler. The output may change between
+    /// releases, use [Policy::compile_with_version] to pin it.
     #[cfg(feature = "compiler")]
     pub fn compile<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+        self.compile_with_version(CompilerVersion::LATEST)
+    }
+
+    /// Compile the descriptor into the `Miniscript` representation given by
+    /// `version` of the compiler, which is the same across releases
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_version<Ctx: ScriptContext>(
+        &self,
+        version: CompilerVersion,
+    ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+        self.is_valid()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::versioned_compilation(self, version),
+        }
+    }
+
+    /// Compile the descriptor into an optimized `Miniscript` representation,
+    /// optimizing for the costs of `cost_model` rather than for the size of
+    /// the script plus witness
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_cost_model<Ctx: ScriptContext>(
+        &self,
+        cost_model: &dyn CostModel,
+    ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
         self.is_valid()?;
         match self.is_safe_nonmalleable() {
             (false, _) => Err(CompilerError::TopLevelNonSafe),
             (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
-            _ => compiler::best_compilation(self),
+            _ => compiler::best_compilation_with(self, cost_model),
+        }
+    }
+
+    /// Compile the descriptor into an optimized `Miniscript` representation,
+    /// as [Policy::compile] does, failing with [CompilerError::BudgetExceeded]
+    /// as soon as the compilation goes over a budget of `options`
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_options<Ctx: ScriptContext>(
+        &self,
+        options: CompilerOptions,
+    ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+        self.is_valid()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::best_compilation_with_options(self, &DefaultCostModel, options),
+        }
+    }
+
+    /// Compile the descriptor into its `k` best distinct `Miniscript`
+    /// representations, with their costs, see [compiler::top_compilations]
+    #[cfg(feature = "compiler")]
+    pub fn compile_alternatives<Ctx: ScriptContext>(
+        &self,
+        k: usize,
+    ) -> Result<Vec<Compilation<Pk, Ctx>>, CompilerError> {
+        self.is_valid()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::top_compilations(self, k),
+        }
+    }
+
+    /// Compile the descriptor into a `Miniscript` representation within
+    /// `bounds`, see [compiler::best_compilation_bounded]
+    #[cfg(feature = "compiler")]
+    pub fn compile_bounded<Ctx: ScriptContext>(
+        &self,
+        bounds: CompilationBounds,
+    ) -> Result<Miniscript<Pk, Ctx>, BoundedCompilationError<Pk>> {
+        self.is_valid().map_err(CompilerError::from)?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe.into()),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation.into()),
+            _ => compiler::best_compilation_bounded(self, bounds),
         }
     }
 }
@@ -155,14 +320,115 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Policy<Pk> {
             | Policy::Hash160(..)
             | Policy::After(..)
             | Policy::Older(..) => true,
+            Policy::TxTemplate(..) => true,
             Policy::Threshold(_, ref subs) | Policy::And(ref subs) => {
                 subs.iter().all(|sub| sub.for_each_key(&mut pred))
             }
-            Policy::Or(ref subs) => subs.iter().all(|(_, sub)| sub.for_each_key(&mut pred)),
+            Policy::Or(ref subs) | Policy::WeightedThreshold(_, ref subs) => {
+                subs.iter().all(|(_, sub)| sub.for_each_key(&mut pred))
+            }
         }
     }
 }
 
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// The sub-policies of the policy, in order
+    pub(super) fn children(&self) -> Vec<&Policy<Pk>> {
+        match *self {
+            Policy::Threshold(_, ref subs) | Policy::And(ref subs) => subs.iter().collect(),
+            Policy::Or(ref subs) | Policy::WeightedThreshold(_, ref subs) => {
+                subs.iter().map(|&(_, ref sub)| sub).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Folds the policy in post-order: `f` is called on every node with its
+    /// depth, 0 for `self`, and the results of its sub-policies in order.
+    /// Returns the result of `self`. The traversal does not recurse, so deep
+    /// policies cannot overflow the stack.
+    pub fn fold<T, F>(&self, f: F) -> T
+    where
+        F: FnMut(&Policy<Pk>, usize, Vec<T>) -> T,
+    {
+        util::fold_post_order(self, Policy::children, f)
+    }
+
+    /// Visits the policy in pre-order: `f` is called on every node with its
+    /// depth, 0 for `self`, and the sub-policies of a node are only visited if
+    /// it returns `true`. The traversal does not recurse, as for `fold`.
+    pub fn visit<F>(&self, f: F)
+    where
+        F: FnMut(&Policy<Pk>, usize) -> bool,
+    {
+        util::visit_pre_order(self, Policy::children, f)
+    }
+
+    /// Whether the policy can be satisfied with `assets`, given the state of
+    /// the chain, once lifted, see [Satisfiability]
+    pub fn satisfiability(
+        &self,
+        assets: &Assets<Pk>,
+        chain: ChainState,
+    ) -> Result<Satisfiability, Error> {
+        Ok(self.lift()?.satisfiability(assets, chain))
+    }
+
+    /// Returns the policy with the branches of its disjunctions and
+    /// thresholds which require one of `cold_keys` to sign made
+    /// [COLD_BRANCH_ODDS] times less likely than the other branches, on top
+    /// of their weights. The compiler then makes those rarely-used,
+    /// emergency, paths the expensive ones.
+    ///
+    /// A branch requires a cold key if it cannot be satisfied without one.
+    /// Disjunctions and thresholds whose branches all or none require a cold
+    /// key are left as is.
+    pub fn with_cold_keys(&self, cold_keys: &[Pk]) -> Policy<Pk> {
+        let (policy, _) = self.fold(|policy, _, subs: Vec<(Policy<Pk>, bool)>| match *policy {
+            Policy::Key(ref pk) => (policy.clone(), cold_keys.contains(pk)),
+            Policy::And(_) => {
+                let cold = subs.iter().any(|&(_, cold)| cold);
+                (
+                    Policy::And(subs.into_iter().map(|(sub, _)| sub).collect()),
+                    cold,
+                )
+            }
+            Policy::Threshold(k, _) | Policy::WeightedThreshold(k, _) => {
+                let cold = subs.iter().filter(|&&(_, cold)| !cold).count() < k;
+                let mixed =
+                    subs.iter().any(|&(_, cold)| cold) && subs.iter().any(|&(_, cold)| !cold);
+                match *policy {
+                    Policy::Threshold(..) if !mixed => {
+                        let subs = subs.into_iter().map(|(sub, _)| sub).collect();
+                        (Policy::Threshold(k, subs), cold)
+                    }
+                    Policy::WeightedThreshold(_, ref weighted) => {
+                        let weights = weighted.iter().map(|&(weight, _)| weight).collect();
+                        (
+                            Policy::WeightedThreshold(k, reweight_cold(weights, subs)),
+                            cold,
+                        )
+                    }
+                    _ => {
+                        let weights = vec![1; subs.len()];
+                        (
+                            Policy::WeightedThreshold(k, reweight_cold(weights, subs)),
+                            cold,
+                        )
+                    }
+                }
+            }
+            Policy::Or(ref weighted) => {
+                let cold = subs.iter().all(|&(_, cold)| cold);
+                let weights = weighted.iter().map(|&(weight, _)| weight).collect();
+                (Policy::Or(reweight_cold(weights, subs)), cold)
+            }
+            _ => (policy.clone(), false),
+        });
+        policy
+    }
+}
+
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Convert a policy using one kind of public key to another
     /// type of public key
@@ -206,6 +472,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::Hash256(ref h) => Ok(Policy::Hash256(h.clone())),
             Policy::Ripemd160(ref h) => Ok(Policy::Ripemd160(h.clone())),
             Policy::Hash160(ref h) => Ok(Policy::Hash160(h.clone())),
+            Policy::TxTemplate(ref h) => Ok(Policy::TxTemplate(h.clone())),
             Policy::After(n) => Ok(Policy::After(n)),
             Policy::Older(n) => Ok(Policy::Older(n)),
             Policy::Threshold(k, ref subs) => {
@@ -225,6 +492,12 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     .map(|&(ref prob, ref sub)| Ok((*prob, sub._translate_pk(translatefpk)?)))
                     .collect::<Result<Vec<(usize, Policy<Q>)>, E>>()?,
             )),
+            Policy::WeightedThreshold(k, ref subs) => Ok(Policy::WeightedThreshold(
+                k,
+                subs.iter()
+                    .map(|&(ref prob, ref sub)| Ok((*prob, sub._translate_pk(translatefpk)?)))
+                    .collect::<Result<Vec<(usize, Policy<Q>)>, E>>()?,
+            )),
         }
     }
 
@@ -242,7 +515,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                 .map(|sub| sub.keys())
                 .flatten()
                 .collect::<Vec<_>>(),
-            Policy::Or(ref subs) => subs
+            Policy::Or(ref subs) | Policy::WeightedThreshold(_, ref subs) => subs
                 .iter()
                 .map(|(ref _k, ref sub)| sub.keys())
                 .flatten()
@@ -265,6 +538,62 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
+    /// Looks for likely mistakes in the policy, which [Policy::is_valid]
+    /// does not reject: duplicate keys and heightlock and timelock
+    /// combinations, then branches which can never be taken, constant
+    /// sub-policies and thresholds which could be an `and` or an `or`, in
+    /// post-order.
+    pub fn lint(&self) -> Vec<PolicyLint<Pk>> {
+        let mut lints = vec![];
+        let mut seen = HashSet::new();
+        for pk in self.keys() {
+            if !seen.insert(pk) && !lints.contains(&PolicyLint::DuplicateKey(pk.clone())) {
+                lints.push(PolicyLint::DuplicateKey(pk.clone()));
+            }
+        }
+        if self.check_timelocks().is_err() {
+            lints.push(PolicyLint::HeightTimeLockCombination);
+        }
+
+        // Whether each sub-policy can be satisfied at all
+        self.fold(|policy, depth, subs: Vec<bool>| {
+            match *policy {
+                Policy::Trivial | Policy::Unsatisfiable if depth > 0 => {
+                    lints.push(PolicyLint::ConstantSubPolicy(policy.clone()));
+                }
+                Policy::Threshold(k, _) | Policy::WeightedThreshold(k, _) if subs.len() > 1 => {
+                    if k == subs.len() {
+                        lints.push(PolicyLint::ThresholdIsAnd(policy.clone()));
+                    } else if k == 1 {
+                        lints.push(PolicyLint::ThresholdIsOr(policy.clone()));
+                    }
+                }
+                _ => {}
+            }
+            let branches: Vec<&Policy<Pk>> = match *policy {
+                Policy::Or(..) | Policy::Threshold(..) | Policy::WeightedThreshold(..) => {
+                    policy.children()
+                }
+                _ => vec![],
+            };
+            for (branch, &satisfiable) in branches.into_iter().zip(subs.iter()) {
+                if !satisfiable && *branch != Policy::Unsatisfiable {
+                    lints.push(PolicyLint::UnsatisfiableBranch(branch.clone()));
+                }
+            }
+            match *policy {
+                Policy::Unsatisfiable => false,
+                Policy::And(_) => subs.iter().all(|&sat| sat),
+                Policy::Or(_) => subs.iter().any(|&sat| sat),
+                Policy::Threshold(k, _) | Policy::WeightedThreshold(k, _) => {
+                    subs.iter().filter(|&&sat| sat).count() >= k
+                }
+                _ => true,
+            }
+        });
+        lints
+    }
+
     /// Checks whether the given concrete policy contains a combination of
     /// timelocks and heightlocks.
     /// Returns an error if there is at least one satisfaction that contains
@@ -290,6 +619,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             | Policy::Hash256(_)
             | Policy::Ripemd160(_)
             | Policy::Hash160(_) => TimeLockInfo::default(),
+            Policy::TxTemplate(_) => TimeLockInfo::default(),
             Policy::After(t) => TimeLockInfo {
                 csv_with_height: false,
                 csv_with_time: false,
@@ -304,8 +634,11 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                 cltv_with_time: false,
                 contains_combination: false,
             },
-            Policy::Threshold(k, ref subs) => {
-                let iter = subs.iter().map(|sub| sub.check_timelocks_helper());
+            Policy::Threshold(k, _) | Policy::WeightedThreshold(k, _) => {
+                let iter = self
+                    .children()
+                    .into_iter()
+                    .map(|sub| sub.check_timelocks_helper());
                 TimeLockInfo::combine_thresh_timelocks(k, iter)
             }
             Policy::And(ref subs) => {
@@ -349,7 +682,8 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     Ok(())
                 }
             }
-            Policy::Threshold(k, ref subs) => {
+            Policy::Threshold(k, _) | Policy::WeightedThreshold(k, _) => {
+                let subs = self.children();
                 if k <= 0 || k > subs.len() {
                     Err(PolicyError::IncorrectThresh)
                 } else {
@@ -386,7 +720,9 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             | Policy::Hash160(_)
             | Policy::After(_)
             | Policy::Older(_) => (false, true),
-            Policy::Threshold(k, ref subs) => {
+            Policy::TxTemplate(_) => (false, true),
+            Policy::Threshold(k, _) | Policy::WeightedThreshold(k, _) => {
+                let subs = self.children();
                 let (safe_count, non_mall_count) = subs
                     .iter()
                     .map(|sub| sub.is_safe_nonmalleable())
@@ -434,6 +770,7 @@ impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
             Policy::Hash256(h) => write!(f, "hash256({})", h),
             Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
             Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::TxTemplate(h) => write!(f, "txtemplate({})", h),
             Policy::And(ref subs) => {
                 f.write_str("and(")?;
                 if !subs.is_empty() {
@@ -461,6 +798,13 @@ impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
                 }
                 f.write_str(")")
             }
+            Policy::WeightedThreshold(k, ref subs) => {
+                write!(f, "thresh({}", k)?;
+                for sub in subs {
+                    write!(f, ",{}@{:?}", sub.0, sub.1)?;
+                }
+                f.write_str(")")
+            }
         }
     }
 }
@@ -477,6 +821,7 @@ impl<Pk: MiniscriptKey> fmt::Display for Policy<Pk> {
             Policy::Hash256(h) => write!(f, "hash256({})", h),
             Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
             Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::TxTemplate(h) => write!(f, "txtemplate({})", h),
             Policy::And(ref subs) => {
                 f.write_str("and(")?;
                 if !subs.is_empty() {
@@ -504,6 +849,13 @@ impl<Pk: MiniscriptKey> fmt::Display for Policy<Pk> {
                 }
                 f.write_str(")")
             }
+            Policy::WeightedThreshold(k, ref subs) => {
+                write!(f, "thresh({}", k)?;
+                for sub in subs {
+                    write!(f, ",{}@{}", sub.0, sub.1)?;
+                }
+                f.write_str(")")
+            }
         }
     }
 }
@@ -533,6 +885,20 @@ where
 
 serde_string_impl_pk!(Policy, "a miniscript concrete policy");
 
+/// Valid policies drawn by the policy generator, with its default bounds, over
+/// arbitrary distinct keys
+#[cfg(feature = "arbitrary")]
+impl<'a, Pk: MiniscriptKey + Arbitrary<'a>> Arbitrary<'a> for Policy<Pk> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut keys: Vec<Pk> = u.arbitrary()?;
+        keys.sort();
+        keys.dedup();
+        Ok(random_policy(GeneratorConfig::default(), &keys, || {
+            u.arbitrary().unwrap_or(0)
+        }))
+    }
+}
+
 impl<Pk> Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
@@ -602,6 +968,9 @@ where
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
                 hash160::Hash::from_hex(x).map(Policy::Hash160)
             }),
+            ("txtemplate", 1) if cfg!(feature = "ctv") => expression::terminal(&top.args[0], |x| {
+                sha256::Hash::from_hex(x).map(Policy::TxTemplate)
+            }),
             ("and", _) => {
                 if top.args.len() != 2 {
                     return Err(Error::PolicyError(PolicyError::NonBinaryArgAnd));
@@ -634,9 +1003,16 @@ where
 
                 let mut subs = Vec::with_capacity(top.args.len() - 1);
                 for arg in &top.args[1..] {
-                    subs.push(Policy::from_tree(arg)?);
+                    subs.push(Policy::from_tree_prob(arg, true)?);
+                }
+                // Only a threshold with a probability on one of its
+                // sub-policies at least is weighted
+                if top.args[1..].iter().any(|arg| arg.name.contains('@')) {
+                    Ok(Policy::WeightedThreshold(thresh as usize, subs))
+                } else {
+                    let subs = subs.into_iter().map(|(_, sub)| sub).collect();
+                    Ok(Policy::Threshold(thresh as usize, subs))
                 }
-                Ok(Policy::Threshold(thresh as usize, subs))
             }
             _ => Err(errstr(top.name)),
         }
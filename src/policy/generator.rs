@@ -0,0 +1,340 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Policy Generator
+//!
+//! Generation of random valid concrete policies, and of Miniscripts compiled
+//! from them, for fuzzing and property testing. Randomness is drawn from a
+//! caller-supplied function, so that the generator can be driven by a
+//! random number generator or by the input of a fuzzer, see [ByteEntropy].
+//! With the `proptest` feature, [policy_strategy] and [miniscript_strategy]
+//! drive it from proptest.
+//!
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+#[cfg(feature = "proptest")]
+use proptest::prelude::*;
+use std::cmp;
+
+use super::Concrete;
+use miniscript::limits::HEIGHT_TIME_THRESHOLD;
+#[cfg(feature = "compiler")]
+use miniscript::ScriptContext;
+#[cfg(feature = "compiler")]
+use Miniscript;
+use MiniscriptKey;
+
+/// Bounds on the policies generated by [random_policy]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GeneratorConfig {
+    /// Maximum depth of a policy, a leaf having depth 0
+    pub max_depth: usize,
+    /// Maximum number of keys in a policy
+    pub max_keys: usize,
+    /// Maximum number of sub-policies of a threshold
+    pub max_thresh_subs: usize,
+    /// Maximum value of the timelocks, which are all height-based so that
+    /// they cannot conflict. No timelocks are generated if this is 0.
+    pub max_timelock: u32,
+    /// Whether to generate hash preimage conditions
+    pub hashes: bool,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {
+            max_depth: 4,
+            max_keys: 10,
+            max_thresh_subs: 4,
+            max_timelock: 1000,
+            hashes: true,
+        }
+    }
+}
+
+/// Randomness read from a byte slice, such as the input of a fuzzer, four
+/// bytes at a time. Once the bytes are exhausted, it only gives zeroes, so
+/// that the generation still terminates.
+#[derive(Clone, Debug)]
+pub struct ByteEntropy<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteEntropy<'a> {
+    /// Reads randomness from `bytes`
+    pub fn new(bytes: &'a [u8]) -> ByteEntropy<'a> {
+        ByteEntropy { bytes: bytes }
+    }
+
+    /// The next four bytes, as a little-endian integer
+    pub fn next_u32(&mut self) -> u32 {
+        let mut ret = 0;
+        for i in 0..4 {
+            if let Some((&byte, rest)) = self.bytes.split_first() {
+                ret |= (byte as u32) << (8 * i);
+                self.bytes = rest;
+            }
+        }
+        ret
+    }
+}
+
+/// State of a generation
+struct Generator<'k, Pk: MiniscriptKey + 'k, F: FnMut() -> u32> {
+    config: GeneratorConfig,
+    /// Keys not used yet
+    keys: &'k [Pk],
+    rng: F,
+}
+
+impl<'k, Pk: MiniscriptKey, F: FnMut() -> u32> Generator<'k, Pk, F> {
+    /// A random number in `0..n`, `n` being non-zero
+    fn below(&mut self, n: usize) -> usize {
+        (self.rng)() as usize % n
+    }
+
+    /// A random 32-byte array
+    fn bytes(&mut self) -> [u8; 32] {
+        let mut ret = [0; 32];
+        for chunk in ret.chunks_mut(4) {
+            let n = (self.rng)();
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (n >> (8 * i)) as u8;
+            }
+        }
+        ret
+    }
+
+    fn leaf(&mut self) -> Concrete<Pk> {
+        let mut kinds = 0;
+        let has_keys = !self.keys.is_empty();
+        let has_timelocks = self.config.max_timelock > 0;
+        if has_keys {
+            kinds += 1;
+        }
+        if has_timelocks {
+            kinds += 2;
+        }
+        if self.config.hashes {
+            kinds += 4;
+        }
+        if kinds == 0 {
+            return Concrete::Trivial;
+        }
+        let mut pick = self.below(kinds);
+        if has_keys {
+            if pick == 0 {
+                let (key, rest) = self.keys.split_first().expect("non-empty");
+                self.keys = rest;
+                return Concrete::Key(key.clone());
+            }
+            pick -= 1;
+        }
+        if has_timelocks {
+            if pick < 2 {
+                let max = cmp::min(self.config.max_timelock, HEIGHT_TIME_THRESHOLD - 1) as usize;
+                let n = 1 + self.below(max) as u32;
+                return if pick == 0 {
+                    Concrete::After(n)
+                } else {
+                    // Relative heightlocks are 16 bits
+                    Concrete::Older(1 + (n - 1) % 0xffff)
+                };
+            }
+            pick -= 2;
+        }
+        let bytes = self.bytes();
+        match pick {
+            0 => Concrete::Sha256(sha256::Hash::hash(&bytes)),
+            1 => Concrete::Hash256(sha256d::Hash::hash(&bytes)),
+            2 => Concrete::Ripemd160(ripemd160::Hash::hash(&bytes)),
+            _ => Concrete::Hash160(hash160::Hash::hash(&bytes)),
+        }
+    }
+
+    fn policy(&mut self, depth: usize) -> Concrete<Pk> {
+        if depth == 0 || self.below(4) == 0 {
+            return self.leaf();
+        }
+        match self.below(3) {
+            0 => Concrete::And(vec![self.policy(depth - 1), self.policy(depth - 1)]),
+            1 => {
+                let (a, b) = (1 + self.below(16), 1 + self.below(16));
+                Concrete::Or(vec![
+                    (a, self.policy(depth - 1)),
+                    (b, self.policy(depth - 1)),
+                ])
+            }
+            _ => {
+                let n = 2 + self.below(self.config.max_thresh_subs.saturating_sub(1).max(1));
+                let k = 1 + self.below(n);
+                let subs = (0..n).map(|_| self.policy(depth - 1)).collect();
+                Concrete::Threshold(k, subs)
+            }
+        }
+    }
+}
+
+/// Generates a random valid concrete policy within the bounds of `config`,
+/// drawing randomness from `rng`. The keys of the policy are taken, in order,
+/// from `keys`, so it has no duplicate keys if `keys` has none.
+pub fn random_policy<Pk, F>(config: GeneratorConfig, keys: &[Pk], rng: F) -> Concrete<Pk>
+where
+    Pk: MiniscriptKey,
+    F: FnMut() -> u32,
+{
+    let n_keys = if keys.len() < config.max_keys {
+        keys.len()
+    } else {
+        config.max_keys
+    };
+    let mut generator = Generator {
+        config: config,
+        keys: &keys[..n_keys],
+        rng: rng,
+    };
+    let depth = config.max_depth;
+    generator.policy(depth)
+}
+
+/// Generates a random Miniscript by compiling policies generated by
+/// [random_policy], until one compiles or `attempts` policies have been
+/// tried. Policies which do not compile are, for instance, those with a
+/// spending path requiring no signature.
+#[cfg(feature = "compiler")]
+pub fn random_miniscript<Pk, Ctx, F>(
+    config: GeneratorConfig,
+    keys: &[Pk],
+    mut rng: F,
+    attempts: usize,
+) -> Option<(Concrete<Pk>, Miniscript<Pk, Ctx>)>
+where
+    Pk: MiniscriptKey,
+    Ctx: ScriptContext,
+    F: FnMut() -> u32,
+{
+    for _ in 0..attempts {
+        let policy = random_policy(config, keys, &mut rng);
+        if let Ok(ms) = policy.compile() {
+            return Some((policy, ms));
+        }
+    }
+    None
+}
+
+/// A proptest strategy generating policies with [random_policy], over `keys`
+///
+/// The randomness is a vector of words, which proptest shrinks towards fewer
+/// and smaller words, so that failing policies shrink towards single leaves.
+#[cfg(feature = "proptest")]
+pub fn policy_strategy<Pk>(
+    config: GeneratorConfig,
+    keys: Vec<Pk>,
+) -> impl Strategy<Value = Concrete<Pk>>
+where
+    Pk: MiniscriptKey + 'static,
+{
+    proptest::collection::vec(any::<u32>(), 0..256).prop_map(move |words| {
+        let mut words = words.into_iter();
+        random_policy(config, &keys, || words.next().unwrap_or(0))
+    })
+}
+
+/// A proptest strategy generating Miniscripts compiled from the policies of
+/// [policy_strategy], rejecting those which do not compile
+#[cfg(all(feature = "proptest", feature = "compiler"))]
+pub fn miniscript_strategy<Pk, Ctx>(
+    config: GeneratorConfig,
+    keys: Vec<Pk>,
+) -> impl Strategy<Value = Miniscript<Pk, Ctx>>
+where
+    Pk: MiniscriptKey + 'static,
+    Ctx: ScriptContext + 'static,
+{
+    policy_strategy(config, keys)
+        .prop_filter_map("policy does not compile", |policy| policy.compile().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        (0..10).map(|i| format!("K{}", i)).collect()
+    }
+
+    #[test]
+    fn random_policies() {
+        let keys = keys();
+        let config = GeneratorConfig::default();
+        let mut state = 0x1234_5678u32;
+        for _ in 0..200 {
+            let policy = random_policy(config, &keys, || {
+                // xorshift32
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state
+            });
+            assert_eq!(policy.is_valid(), Ok(()));
+            assert!(policy.keys().len() <= config.max_keys);
+            let depth = policy.fold(|_, _, subs: Vec<usize>| {
+                subs.into_iter().max().map(|depth| depth + 1).unwrap_or(0)
+            });
+            assert!(depth <= config.max_depth);
+        }
+    }
+
+    #[test]
+    fn byte_entropy() {
+        let keys = keys();
+        let bytes = [7; 64];
+        let mut entropy = ByteEntropy::new(&bytes);
+        let policy = random_policy(GeneratorConfig::default(), &keys, || entropy.next_u32());
+        let mut entropy = ByteEntropy::new(&bytes);
+        let again = random_policy(GeneratorConfig::default(), &keys, || entropy.next_u32());
+        assert_eq!(policy, again);
+
+        let mut entropy = ByteEntropy::new(&[1, 2]);
+        assert_eq!(entropy.next_u32(), 0x0201);
+        assert_eq!(entropy.next_u32(), 0);
+    }
+
+    #[cfg(feature = "proptest")]
+    #[test]
+    fn strategies() {
+        use proptest::test_runner::TestRunner;
+
+        let config = GeneratorConfig::default();
+        let mut runner = TestRunner::default();
+        runner
+            .run(&policy_strategy(config, keys()), |policy| {
+                assert_eq!(policy.is_valid(), Ok(()));
+                Ok(())
+            })
+            .unwrap();
+
+        #[cfg(feature = "compiler")]
+        {
+            use miniscript::Segwitv0;
+
+            let strategy = miniscript_strategy::<_, Segwitv0>(config, keys());
+            runner
+                .run(&strategy, |ms| {
+                    assert!(ms.ty.mall.non_malleable);
+                    Ok(())
+                })
+                .unwrap();
+        }
+    }
+}
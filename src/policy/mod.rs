@@ -26,6 +26,8 @@ use {error, fmt};
 #[cfg(feature = "compiler")]
 pub mod compiler;
 pub mod concrete;
+#[cfg(any(feature = "generator", feature = "arbitrary", feature = "proptest"))]
+pub mod generator;
 pub mod semantic;
 
 use descriptor::Descriptor;
@@ -67,6 +69,8 @@ pub enum LiftError {
     HeightTimeLockCombination,
     /// Duplicate Public Keys
     BranchExceedResourceLimits,
+    /// Cannot lift extension fragments, whose semantics are unknown
+    ExtensionFragment,
 }
 
 impl error::Error for LiftError {
@@ -87,6 +91,9 @@ impl fmt::Display for LiftError {
             LiftError::BranchExceedResourceLimits => f.write_str(
                 "Cannot lift policies containing one branch that exceeds resource limits",
             ),
+            LiftError::ExtensionFragment => {
+                f.write_str("Cannot lift policies containing extension fragments")
+            }
         }
     }
 }
@@ -131,6 +138,8 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Liftable<Pk> for Terminal<Pk, Ctx> {
             Terminal::Hash256(h) => Semantic::Hash256(h),
             Terminal::Ripemd160(h) => Semantic::Ripemd160(h),
             Terminal::Hash160(h) => Semantic::Hash160(h),
+            Terminal::TxTemplate(h) => Semantic::TxTemplate(h),
+            Terminal::Ext(..) => return Err(LiftError::ExtensionFragment.into()),
             Terminal::True => Semantic::Trivial,
             Terminal::False => Semantic::Unsatisfiable,
             Terminal::Alt(ref sub)
@@ -181,6 +190,8 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => wpkh.lift(),
             Descriptor::Wsh(ref wsh) => wsh.lift(),
             Descriptor::Sh(ref sh) => sh.lift(),
+            Descriptor::Addr(ref addr) => addr.lift(),
+            Descriptor::Raw(ref raw) => raw.lift(),
         }
     }
 }
@@ -206,6 +217,7 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Concrete<Pk> {
             Concrete::Hash256(h) => Semantic::Hash256(h),
             Concrete::Ripemd160(h) => Semantic::Ripemd160(h),
             Concrete::Hash160(h) => Semantic::Hash160(h),
+            Concrete::TxTemplate(h) => Semantic::TxTemplate(h),
             Concrete::And(ref subs) => {
                 let semantic_subs: Result<_, Error> = subs.iter().map(Liftable::lift).collect();
                 Semantic::Threshold(2, semantic_subs?)
@@ -219,6 +231,11 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Concrete<Pk> {
                 let semantic_subs: Result<_, Error> = subs.iter().map(Liftable::lift).collect();
                 Semantic::Threshold(k, semantic_subs?)
             }
+            Concrete::WeightedThreshold(k, ref subs) => {
+                let semantic_subs: Result<_, Error> =
+                    subs.iter().map(|&(ref _p, ref sub)| sub.lift()).collect();
+                Semantic::Threshold(k, semantic_subs?)
+            }
         }
         .normalized();
         Ok(ret)
@@ -318,6 +335,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lint() {
+        use super::concrete::PolicyLint;
+        type StringPolicy = Concrete<String>;
+
+        let policy = StringPolicy::from_str(
+            "or(and(pk(A),thresh(2,pk(B),pk(C))),and(pk(A),thresh(1,pk(D),UNSATISFIABLE)))",
+        )
+        .unwrap();
+        assert_eq!(
+            policy.lint(),
+            vec![
+                PolicyLint::DuplicateKey("A".to_owned()),
+                PolicyLint::ThresholdIsAnd(
+                    StringPolicy::from_str("thresh(2,pk(B),pk(C))").unwrap()
+                ),
+                PolicyLint::ConstantSubPolicy(Concrete::Unsatisfiable),
+                PolicyLint::ThresholdIsOr(
+                    StringPolicy::from_str("thresh(1,pk(D),UNSATISFIABLE)").unwrap()
+                ),
+            ]
+        );
+
+        let policy = StringPolicy::from_str("or(pk(A),and(pk(B),UNSATISFIABLE))").unwrap();
+        assert_eq!(
+            policy.lint(),
+            vec![
+                PolicyLint::ConstantSubPolicy(Concrete::Unsatisfiable),
+                PolicyLint::UnsatisfiableBranch(
+                    StringPolicy::from_str("and(pk(B),UNSATISFIABLE)").unwrap()
+                ),
+            ]
+        );
+
+        let policy = StringPolicy::And(vec![Concrete::After(100), Concrete::After(1_000_000_000)]);
+        assert_eq!(policy.lint(), vec![PolicyLint::HeightTimeLockCombination]);
+        assert!(StringPolicy::from_str("or(pk(A),pk(B))")
+            .unwrap()
+            .lint()
+            .is_empty());
+    }
+
     //https://github.com/apoelstra/rust-miniscript/issues/41
     #[test]
     fn heavy_nest() {
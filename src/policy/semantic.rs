@@ -14,16 +14,22 @@
 
 //! Abstract Policies
 
+use std::collections::HashSet;
 use std::str::FromStr;
 use std::{fmt, str};
 
+#[cfg(feature = "arbitrary")]
+use arbitrary::{self, Arbitrary, Unstructured};
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 
 use super::concrete::PolicyError;
 use errstr;
+use miniscript::limits::{
+    HEIGHT_TIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
 use Error;
-use {expression, ForEach, ForEachKey, MiniscriptKey};
+use {expression, util, ForEach, ForEachKey, MiniscriptKey};
 
 use super::ENTAILMENT_MAX_TERMINALS;
 
@@ -53,6 +59,9 @@ pub enum Policy<Pk: MiniscriptKey> {
     Ripemd160(ripemd160::Hash),
     /// A HASH160 whose preimage must be provided to satisfy the descriptor
     Hash160(hash160::Hash),
+    /// A transaction template the spending transaction must match, only
+    /// parsed with the `ctv` feature
+    TxTemplate(sha256::Hash),
     /// A set of descriptors, satisfactions must be provided for `k` of them
     Threshold(usize, Vec<Policy<Pk>>),
 }
@@ -72,11 +81,43 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Policy<Pk> {
             | Policy::Hash160(..)
             | Policy::After(..)
             | Policy::Older(..) => true,
+            Policy::TxTemplate(..) => true,
             Policy::Threshold(_, ref subs) => subs.iter().all(|sub| sub.for_each_key(&mut pred)),
         }
     }
 }
 
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// The sub-policies of the policy, in order
+    fn children(&self) -> Vec<&Policy<Pk>> {
+        match *self {
+            Policy::Threshold(_, ref subs) => subs.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Folds the policy in post-order: `f` is called on every node with its
+    /// depth, 0 for `self`, and the results of its sub-policies in order.
+    /// Returns the result of `self`. The traversal does not recurse, so deep
+    /// policies cannot overflow the stack.
+    pub fn fold<T, F>(&self, f: F) -> T
+    where
+        F: FnMut(&Policy<Pk>, usize, Vec<T>) -> T,
+    {
+        util::fold_post_order(self, Policy::children, f)
+    }
+
+    /// Visits the policy in pre-order: `f` is called on every node with its
+    /// depth, 0 for `self`, and the sub-policies of a node are only visited if
+    /// it returns `true`. The traversal does not recurse, as for `fold`.
+    pub fn visit<F>(&self, f: F)
+    where
+        F: FnMut(&Policy<Pk>, usize) -> bool,
+    {
+        util::visit_pre_order(self, Policy::children, f)
+    }
+}
+
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Convert a policy using one kind of public key to another
     /// type of public key
@@ -120,6 +161,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::Hash256(ref h) => Ok(Policy::Hash256(h.clone())),
             Policy::Ripemd160(ref h) => Ok(Policy::Ripemd160(h.clone())),
             Policy::Hash160(ref h) => Ok(Policy::Hash160(h.clone())),
+            Policy::TxTemplate(ref h) => Ok(Policy::TxTemplate(h.clone())),
             Policy::After(n) => Ok(Policy::After(n)),
             Policy::Older(n) => Ok(Policy::Older(n)),
             Policy::Threshold(k, ref subs) => {
@@ -164,6 +206,13 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
+    /// Whether both policies entail each other, that is whether they are
+    /// satisfied by exactly the same witnesses. See [Policy::entails] for the
+    /// limits of the check.
+    pub fn is_equivalent(&self, other: &Policy<Pk>) -> Result<bool, PolicyError> {
+        Ok(self.clone().entails(other.clone())? && other.clone().entails(self.clone())?)
+    }
+
     // Helper function to compute the number of constraints in policy.
     fn n_terminals(&self) -> usize {
         match self {
@@ -229,6 +278,7 @@ impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
             Policy::Hash256(h) => write!(f, "hash256({})", h),
             Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
             Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::TxTemplate(h) => write!(f, "txtemplate({})", h),
             Policy::Threshold(k, ref subs) => {
                 if k == subs.len() {
                     write!(f, "and(")?;
@@ -262,6 +312,7 @@ impl<Pk: MiniscriptKey> fmt::Display for Policy<Pk> {
             Policy::Hash256(h) => write!(f, "hash256({})", h),
             Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
             Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::TxTemplate(h) => write!(f, "txtemplate({})", h),
             Policy::Threshold(k, ref subs) => {
                 if k == subs.len() {
                     write!(f, "and(")?;
@@ -306,6 +357,18 @@ where
 
 serde_string_impl_pk!(Policy, "a miniscript semantic policy");
 
+/// The lifts of arbitrary concrete policies
+#[cfg(feature = "arbitrary")]
+impl<'a, Pk: MiniscriptKey + Arbitrary<'a>> Arbitrary<'a> for Policy<Pk> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        use policy::{Concrete, Liftable};
+
+        Concrete::<Pk>::arbitrary(u)?
+            .lift()
+            .map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
+
 impl<Pk> expression::FromTree for Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
@@ -338,6 +401,9 @@ where
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
                 hash160::Hash::from_hex(x).map(Policy::Hash160)
             }),
+            ("txtemplate", 1) if cfg!(feature = "ctv") => expression::terminal(&top.args[0], |x| {
+                sha256::Hash::from_hex(x).map(Policy::TxTemplate)
+            }),
             ("and", nsubs) => {
                 if nsubs < 2 {
                     return Err(Error::PolicyError(PolicyError::InsufficientArgsforAnd));
@@ -481,6 +547,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             | Policy::Hash256(..)
             | Policy::Ripemd160(..)
             | Policy::Hash160(..) => vec![],
+            Policy::TxTemplate(..) => vec![],
             Policy::After(..) => vec![],
             Policy::Older(t) => vec![t],
             Policy::Threshold(_, ref subs) => subs.iter().fold(vec![], |mut acc, x| {
@@ -530,6 +597,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             | Policy::Hash256(..)
             | Policy::Ripemd160(..)
             | Policy::Hash160(..) => 0,
+            Policy::TxTemplate(..) => 0,
             Policy::Threshold(_, ref subs) => subs.iter().map(|sub| sub.n_keys()).sum::<usize>(),
         }
     }
@@ -546,6 +614,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             | Policy::Hash256(..)
             | Policy::Ripemd160(..)
             | Policy::Hash160(..) => 0,
+            Policy::TxTemplate(..) => 0,
             Policy::Threshold(k, ref subs) => {
                 let mut sublens: Vec<usize> = subs.iter().map(Policy::minimum_n_keys).collect();
                 sublens.sort();
@@ -572,6 +641,455 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     }
 }
 
+/// The spending conditions available to a spender: keys able to sign and
+/// known hash preimages, see [Policy::satisfiability]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Assets<Pk: MiniscriptKey> {
+    /// Hashes of the keys able to sign
+    pub keys: HashSet<Pk::Hash>,
+    /// SHA256 hashes whose preimage is known
+    pub sha256_preimages: HashSet<sha256::Hash>,
+    /// SHA256d hashes whose preimage is known
+    pub hash256_preimages: HashSet<sha256d::Hash>,
+    /// RIPEMD160 hashes whose preimage is known
+    pub ripemd160_preimages: HashSet<ripemd160::Hash>,
+    /// HASH160 hashes whose preimage is known
+    pub hash160_preimages: HashSet<hash160::Hash>,
+}
+
+impl<Pk: MiniscriptKey> Default for Assets<Pk> {
+    fn default() -> Self {
+        Assets {
+            keys: HashSet::new(),
+            sha256_preimages: HashSet::new(),
+            hash256_preimages: HashSet::new(),
+            ripemd160_preimages: HashSet::new(),
+            hash160_preimages: HashSet::new(),
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for Assets<Pk> {
+    /// Writes the assets as `assets(pkh(H),sha256(H),...)`, in the order of
+    /// the semantic policies they satisfy, so that equal assets are written
+    /// alike
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items: Vec<Policy<Pk>> = self.keys.iter().cloned().map(Policy::KeyHash).collect();
+        items.extend(self.sha256_preimages.iter().cloned().map(Policy::Sha256));
+        items.extend(self.hash256_preimages.iter().cloned().map(Policy::Hash256));
+        items.extend(
+            self.ripemd160_preimages
+                .iter()
+                .cloned()
+                .map(Policy::Ripemd160),
+        );
+        items.extend(self.hash160_preimages.iter().cloned().map(Policy::Hash160));
+        items.sort();
+
+        f.write_str("assets(")?;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl<Pk> str::FromStr for Assets<Pk>
+where
+    Pk: MiniscriptKey + str::FromStr,
+    Pk::Hash: str::FromStr,
+    <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+{
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Assets<Pk>, Error> {
+        for ch in s.as_bytes() {
+            if *ch < 20 || *ch > 127 {
+                return Err(Error::Unprintable(*ch));
+            }
+        }
+
+        let tree = expression::Tree::from_str(s)?;
+        if tree.name != "assets" {
+            return Err(errstr(tree.name));
+        }
+        let mut assets = Assets::default();
+        // `assets()` has a single empty argument
+        if tree.args.len() == 1 && tree.args[0].name.is_empty() && tree.args[0].args.is_empty() {
+            return Ok(assets);
+        }
+        for arg in &tree.args {
+            match <Policy<Pk> as expression::FromTree>::from_tree(arg)? {
+                Policy::KeyHash(pkh) => assets.keys.insert(pkh),
+                Policy::Sha256(h) => assets.sha256_preimages.insert(h),
+                Policy::Hash256(h) => assets.hash256_preimages.insert(h),
+                Policy::Ripemd160(h) => assets.ripemd160_preimages.insert(h),
+                Policy::Hash160(h) => assets.hash160_preimages.insert(h),
+                _ => return Err(errstr(arg.name)),
+            };
+        }
+        Ok(assets)
+    }
+}
+
+serde_string_impl_pk!(Assets, "spending assets");
+
+/// The state of the chain, and of the coin being spent, against which
+/// timelocks are checked, see [Policy::satisfiability]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ChainState {
+    /// Height of the tip of the chain
+    pub height: u32,
+    /// Median time past of the tip of the chain
+    pub time: u32,
+    /// Height of the block which confirmed the coin being spent
+    pub coin_height: u32,
+    /// Median time past of the block before the one which confirmed the coin
+    /// being spent
+    pub coin_time: u32,
+}
+
+/// Whether a policy can be satisfied, by a transaction included in the
+/// block after the tip of the chain
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Satisfiability {
+    /// The policy can be satisfied now
+    Now,
+    /// The policy can be satisfied once the tip of the chain has reached both
+    /// `height` and median time past `time`. When it can be satisfied at
+    /// several heights and times, none of which is reached before the others,
+    /// the lowest height is given, then the lowest time at that height.
+    Later {
+        /// Height the tip of the chain must reach
+        height: u32,
+        /// Median time past the tip of the chain must reach
+        time: u32,
+    },
+    /// The policy cannot be satisfied with the given assets
+    Never,
+}
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Whether the policy can be satisfied with `assets`, given the state of
+    /// the chain, and if not whether it can be later on, once its timelocks
+    /// have expired.
+    ///
+    /// The heights and times at which sub-policies can be satisfied are
+    /// traded off against each other: a threshold is satisfiable at a height
+    /// and time if any `k` of its sub-policies are.
+    pub fn satisfiability(&self, assets: &Assets<Pk>, chain: ChainState) -> Satisfiability {
+        // The pairs of the height and time the tip of the chain must reach,
+        // none of which is reached before another, sorted by height. Empty if
+        // the policy cannot be satisfied.
+        let required = self.fold(|policy, _, subs: Vec<Vec<(u32, u32)>>| match *policy {
+            Policy::Unsatisfiable => vec![],
+            Policy::Trivial => vec![(0, 0)],
+            Policy::KeyHash(ref pkh) => if_available(assets.keys.contains(pkh)),
+            Policy::Sha256(ref h) => if_available(assets.sha256_preimages.contains(h)),
+            Policy::Hash256(ref h) => if_available(assets.hash256_preimages.contains(h)),
+            Policy::Ripemd160(ref h) => if_available(assets.ripemd160_preimages.contains(h)),
+            Policy::Hash160(ref h) => if_available(assets.hash160_preimages.contains(h)),
+            // The spending transaction is assumed to match the template
+            Policy::TxTemplate(..) => vec![(0, 0)],
+            // The locktime must be below the height of the next block, or
+            // below the median time past of the tip
+            Policy::After(n) if n < HEIGHT_TIME_THRESHOLD => vec![(n, 0)],
+            Policy::After(n) => vec![(0, n.saturating_add(1))],
+            Policy::Older(n) if n & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 => vec![(0, 0)],
+            Policy::Older(n) if n & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 => {
+                let secs = (n & 0xffff) << 9;
+                vec![(0, chain.coin_time.saturating_add(secs))]
+            }
+            Policy::Older(n) => {
+                let blocks = n & 0xffff;
+                vec![(
+                    chain.coin_height.saturating_add(blocks).saturating_sub(1),
+                    0,
+                )]
+            }
+            Policy::Threshold(k, _) => threshold_requirements(k, &subs),
+        });
+        if required
+            .iter()
+            .any(|&(height, time)| height <= chain.height && time <= chain.time)
+        {
+            return Satisfiability::Now;
+        }
+        match required.first() {
+            None => Satisfiability::Never,
+            Some(&(height, time)) => Satisfiability::Later {
+                height: height,
+                time: time,
+            },
+        }
+    }
+}
+
+/// `[(0, 0)]`, satisfiable at any height and time, if `available`
+fn if_available(available: bool) -> Vec<(u32, u32)> {
+    if available {
+        vec![(0, 0)]
+    } else {
+        vec![]
+    }
+}
+
+/// The heights and times at which `k` of the sub-policies whose heights and
+/// times are `subs` can be satisfied, none of which is reached before
+/// another, sorted by height
+fn threshold_requirements(k: usize, subs: &[Vec<(u32, u32)>]) -> Vec<(u32, u32)> {
+    if k == 0 {
+        return vec![(0, 0)];
+    }
+    let mut heights: Vec<u32> = subs
+        .iter()
+        .flat_map(|sub| sub.iter().map(|&(h, _)| h))
+        .collect();
+    heights.sort();
+    heights.dedup();
+
+    let mut ret: Vec<(u32, u32)> = vec![];
+    for height in heights {
+        // The earliest time at which each sub-policy can be satisfied by
+        // this height
+        let mut times: Vec<u32> = subs
+            .iter()
+            .filter_map(|sub| {
+                sub.iter()
+                    .filter(|&&(h, _)| h <= height)
+                    .map(|&(_, t)| t)
+                    .min()
+            })
+            .collect();
+        if times.len() < k {
+            continue;
+        }
+        times.sort();
+        let time = times[k - 1];
+        // A greater height is only worth it for an earlier time
+        if ret.last().map_or(true, |&(_, t)| time < t) {
+            ret.push((height, time));
+        }
+    }
+    ret
+}
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Iterates over the sets of leaf conditions (keys, timelocks and hash
+    /// preimages) which suffice to satisfy the policy: the terms of its
+    /// disjunctive normal form. Each set is sorted and has no duplicates.
+    ///
+    /// Thresholds are expanded lazily, one combination of sub-policies at a
+    /// time, so that the sets can be inspected without computing all of
+    /// them. The same set may be given several times, and some sets may
+    /// contain others, see [Policy::minimal_authorization_sets].
+    pub fn authorization_sets(&self) -> AuthorizationSets<Pk> {
+        AuthorizationSets {
+            expansion: Expansion::new(self),
+        }
+    }
+
+    /// The sets of leaf conditions which suffice to satisfy the policy and
+    /// of which no subset does, ordered by size. The timelocks are taken as
+    /// independent conditions, so a set with both `after(100)` and
+    /// `after(200)` is not reduced.
+    pub fn minimal_authorization_sets(&self) -> Vec<Vec<Policy<Pk>>> {
+        let mut sets: Vec<_> = self.authorization_sets().collect();
+        sets.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+        sets.dedup();
+
+        let mut minimal: Vec<Vec<Policy<Pk>>> = vec![];
+        for set in sets {
+            let is_superset = minimal
+                .iter()
+                .any(|min| min.iter().all(|cond| set.binary_search(cond).is_ok()));
+            if !is_superset {
+                minimal.push(set);
+            }
+        }
+        minimal
+    }
+}
+
+/// Iterator over the sets of leaf conditions which suffice to satisfy a
+/// policy, see [Policy::authorization_sets]
+pub struct AuthorizationSets<'a, Pk: MiniscriptKey + 'a> {
+    expansion: Expansion<'a, Pk>,
+}
+
+impl<'a, Pk: MiniscriptKey> Iterator for AuthorizationSets<'a, Pk> {
+    type Item = Vec<Policy<Pk>>;
+
+    fn next(&mut self) -> Option<Vec<Policy<Pk>>> {
+        self.expansion.next().map(|mut set| {
+            set.sort();
+            set.dedup();
+            set.into_iter().cloned().collect()
+        })
+    }
+}
+
+/// Lazy expansion of a policy into the sets of leaves satisfying it
+enum Expansion<'a, Pk: MiniscriptKey + 'a> {
+    /// A leaf, satisfied by a single set, if any, not given yet
+    Once(Option<Vec<&'a Policy<Pk>>>),
+    /// A threshold, satisfied by the sets of each combination of `k` of its
+    /// sub-policies
+    Threshold {
+        k: usize,
+        subs: &'a [Policy<Pk>],
+        /// Indices of the sub-policies of the current combination, if any
+        combination: Option<Vec<usize>>,
+        /// The sets of the current combination
+        product: Option<Product<'a, Pk>>,
+    },
+}
+
+impl<'a, Pk: MiniscriptKey> Expansion<'a, Pk> {
+    fn new(policy: &'a Policy<Pk>) -> Expansion<'a, Pk> {
+        match *policy {
+            Policy::Unsatisfiable => Expansion::Once(None),
+            Policy::Trivial => Expansion::Once(Some(vec![])),
+            Policy::Threshold(k, ref subs) => Expansion::Threshold {
+                k: k,
+                subs: subs,
+                combination: None,
+                product: None,
+            },
+            ref leaf => Expansion::Once(Some(vec![leaf])),
+        }
+    }
+
+    fn next(&mut self) -> Option<Vec<&'a Policy<Pk>>> {
+        match *self {
+            Expansion::Once(ref mut set) => set.take(),
+            Expansion::Threshold {
+                k,
+                subs,
+                ref mut combination,
+                ref mut product,
+            } => loop {
+                if let Some(ref mut sets) = *product {
+                    if let Some(set) = sets.next() {
+                        return Some(set);
+                    }
+                }
+                if !next_combination(combination, k, subs.len()) {
+                    return None;
+                }
+                let selected = combination
+                    .as_ref()
+                    .expect("just set")
+                    .iter()
+                    .map(|&i| &subs[i])
+                    .collect();
+                *product = Some(Product::new(selected));
+            },
+        }
+    }
+}
+
+/// Advances `combination` to the next combination of `k` indices in `0..n`,
+/// in lexicographic order, or to the first one if it is `None`. Returns
+/// whether there was one.
+fn next_combination(combination: &mut Option<Vec<usize>>, k: usize, n: usize) -> bool {
+    match *combination {
+        None if k <= n => {
+            *combination = Some((0..k).collect());
+            return true;
+        }
+        None => return false,
+        Some(ref mut indices) => {
+            // The last index which can still be incremented
+            let mut i = k;
+            while i > 0 && indices[i - 1] == n - k + i - 1 {
+                i -= 1;
+            }
+            if i > 0 {
+                indices[i - 1] += 1;
+                for j in i..k {
+                    indices[j] = indices[j - 1] + 1;
+                }
+                return true;
+            }
+        }
+    }
+    *combination = Some(vec![]);
+    false
+}
+
+/// Lazy cartesian product of the sets of several sub-policies
+struct Product<'a, Pk: MiniscriptKey + 'a> {
+    subs: Vec<&'a Policy<Pk>>,
+    expansions: Vec<Expansion<'a, Pk>>,
+    /// The current set of each sub-policy, `None` before the first set
+    current: Option<Vec<Vec<&'a Policy<Pk>>>>,
+    done: bool,
+}
+
+impl<'a, Pk: MiniscriptKey> Product<'a, Pk> {
+    fn new(subs: Vec<&'a Policy<Pk>>) -> Product<'a, Pk> {
+        Product {
+            expansions: subs.iter().map(|&sub| Expansion::new(sub)).collect(),
+            subs: subs,
+            current: None,
+            done: false,
+        }
+    }
+
+    fn next(&mut self) -> Option<Vec<&'a Policy<Pk>>> {
+        if self.done {
+            return None;
+        }
+        if !self.advance() {
+            self.done = true;
+            return None;
+        }
+        let current = self.current.as_ref().expect("just advanced");
+        Some(current.iter().flat_map(|set| set.iter().cloned()).collect())
+    }
+
+    /// Moves to the next combination of the sets of the sub-policies.
+    /// Returns whether there was one.
+    fn advance(&mut self) -> bool {
+        if self.current.is_none() {
+            // The first set of each sub-policy
+            let mut first = Vec::with_capacity(self.expansions.len());
+            for expansion in &mut self.expansions {
+                match expansion.next() {
+                    Some(set) => first.push(set),
+                    None => return false,
+                }
+            }
+            self.current = Some(first);
+            return true;
+        }
+        let current = self.current.as_mut().expect("checked above");
+        // Advance the last sub-policy which has sets left, restarting the
+        // ones after it
+        let mut i = self.expansions.len();
+        loop {
+            if i == 0 {
+                return false;
+            }
+            i -= 1;
+            if let Some(set) = self.expansions[i].next() {
+                current[i] = set;
+                break;
+            }
+        }
+        for j in i + 1..self.expansions.len() {
+            self.expansions[j] = Expansion::new(self.subs[j]);
+            current[j] = self.expansions[j].next().expect("had a set before");
+        }
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bitcoin::PublicKey;
@@ -599,6 +1117,25 @@ mod tests {
         .is_ok());
     }
 
+    #[test]
+    fn fold_and_visit() {
+        let policy = StringPolicy::from_str("or(and(pkh(A),older(10)),pkh(B))").unwrap();
+        let height = policy.fold(|_, _, subs: Vec<usize>| subs.into_iter().max().unwrap_or(0) + 1);
+        assert_eq!(height, 3);
+        let keys = policy.fold(|node, _, subs: Vec<Vec<String>>| match *node {
+            Policy::KeyHash(ref pkh) => vec![pkh.clone()],
+            _ => subs.concat(),
+        });
+        assert_eq!(keys, vec!["A", "B"]);
+
+        let mut depths = vec![];
+        policy.visit(|_, depth| {
+            depths.push(depth);
+            depth < 1
+        });
+        assert_eq!(depths, vec![0, 1, 1]);
+    }
+
     #[test]
     fn semantic_analysis() {
         let policy = StringPolicy::from_str("pkh()").unwrap();
@@ -737,4 +1274,134 @@ mod tests {
         assert!(auth_alice.entails(htlc_pol.clone()).unwrap());
         assert!(htlc_pol.entails(control_alice).unwrap());
     }
+
+    #[test]
+    fn satisfiability() {
+        let policy =
+            StringPolicy::from_str("or(and(pkh(A),older(144)),and(pkh(B),after(700000)))").unwrap();
+        let chain = ChainState {
+            height: 700_100,
+            time: 1_600_000_000,
+            coin_height: 700_000,
+            coin_time: 1_599_999_500,
+        };
+
+        let mut assets = Assets::default();
+        assert_eq!(policy.satisfiability(&assets, chain), Satisfiability::Never);
+        assets.keys.insert("A".to_owned());
+        assert_eq!(
+            policy.satisfiability(&assets, chain),
+            Satisfiability::Later {
+                height: 700_143,
+                time: 0,
+            }
+        );
+        assets.keys.insert("B".to_owned());
+        assert_eq!(policy.satisfiability(&assets, chain), Satisfiability::Now);
+
+        // 2 * 512 seconds after the coin was confirmed
+        let policy = StringPolicy::from_str("and(pkh(A),older(4194306))").unwrap();
+        assert_eq!(
+            policy.satisfiability(&assets, chain),
+            Satisfiability::Later {
+                height: 0,
+                time: 1_600_000_524,
+            }
+        );
+
+        // The last two sub-policies are satisfiable before the first two
+        let policy = StringPolicy::from_str(
+            "thresh(2,after(1600000000),after(700005),and(after(700005),after(1500000000)))",
+        )
+        .unwrap();
+        let mut earlier = chain;
+        earlier.time = 1_550_000_000;
+        assert_eq!(policy.satisfiability(&assets, earlier), Satisfiability::Now);
+        earlier.time = 1_400_000_000;
+        assert_eq!(
+            policy.satisfiability(&assets, earlier),
+            Satisfiability::Later {
+                height: 700_005,
+                time: 1_500_000_001,
+            }
+        );
+    }
+
+    #[test]
+    fn assets_string() {
+        let mut assets = Assets::<String>::default();
+        assert_eq!(assets.to_string(), "assets()");
+        assert_eq!(Assets::from_str("assets()").unwrap(), assets);
+
+        assets.keys.insert("B".to_owned());
+        assets.keys.insert("A".to_owned());
+        assets
+            .sha256_preimages
+            .insert(sha256::Hash::from_hex(&"01".repeat(32)).unwrap());
+        let s = assets.to_string();
+        assert_eq!(
+            s,
+            format!("assets(pkh(A),pkh(B),sha256({}))", "01".repeat(32))
+        );
+        assert_eq!(Assets::from_str(&s).unwrap(), assets);
+
+        assert!(Assets::<String>::from_str("assets(pkh(A),older(10))").is_err());
+        assert!(Assets::<String>::from_str("and(pkh(A))").is_err());
+    }
+
+    #[test]
+    fn equivalence() {
+        let escrow_pol =
+            StringPolicy::from_str("thresh(2,pkh(Alice),pkh(Bob),pkh(Judge))").unwrap();
+        let expanded = StringPolicy::from_str(
+            "or(and(pkh(Alice),pkh(Bob)),or(and(pkh(Alice),pkh(Judge)),and(pkh(Bob),pkh(Judge))))",
+        )
+        .unwrap();
+        assert!(escrow_pol.is_equivalent(&expanded).unwrap());
+        assert!(expanded.is_equivalent(&escrow_pol).unwrap());
+
+        // Migrating to a 2-of-2 is less permissive, but not equivalent
+        let migrated = StringPolicy::from_str("and(pkh(Alice),pkh(Bob))").unwrap();
+        assert!(migrated.clone().entails(escrow_pol.clone()).unwrap());
+        assert!(!migrated.is_equivalent(&escrow_pol).unwrap());
+    }
+
+    #[test]
+    fn authorization_sets() {
+        let policy =
+            StringPolicy::from_str("or(pkh(A),thresh(2,pkh(A),pkh(B),after(10)))").unwrap();
+        let a = StringPolicy::KeyHash("A".to_owned());
+        let b = StringPolicy::KeyHash("B".to_owned());
+        let after = StringPolicy::After(10);
+        assert_eq!(
+            policy.authorization_sets().collect::<Vec<_>>(),
+            vec![
+                vec![a.clone()],
+                vec![a.clone(), b.clone()],
+                vec![a.clone(), after.clone()],
+                vec![b.clone(), after.clone()],
+            ]
+        );
+        assert_eq!(
+            policy.minimal_authorization_sets(),
+            vec![vec![a.clone()], vec![b.clone(), after.clone()]]
+        );
+
+        // Sets are lazily expanded
+        let policy = StringPolicy::Threshold(
+            10,
+            (0..20)
+                .map(|i| StringPolicy::KeyHash(i.to_string()))
+                .collect(),
+        );
+        assert_eq!(policy.authorization_sets().nth(2).unwrap().len(), 10);
+
+        assert_eq!(
+            StringPolicy::Trivial.minimal_authorization_sets(),
+            vec![vec![]]
+        );
+        assert!(StringPolicy::Unsatisfiable
+            .minimal_authorization_sets()
+            .is_empty());
+    }
 }
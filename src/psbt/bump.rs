@@ -0,0 +1,335 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Fee Bumping
+//!
+//! Reverting the inputs of a PSBT spending known descriptors to their
+//! unsigned state, so that the transaction can be modified to pay a higher
+//! fee, as allowed by BIP 125, then signed and finalized again along the
+//! same spend paths.
+//!
+
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::{PublicKey, Script};
+
+use super::finalizer::{finalize_input_with_spend_path, get_scriptpubkey};
+use super::{sanity_check, set_scripts, Error, InputError, Psbt};
+use descriptor::{Descriptor, DescriptorTrait, SpendPathWeight};
+use interpreter::{HashLockType, Interpreter, SatisfiedConstraint};
+use policy::{semantic, Liftable};
+use {MiniscriptKey, ToPublicKey, TranslatePk};
+
+/// Highest sequence number signaling replaceability, as defined in BIP 125
+pub const MAX_RBF_SEQUENCE: u32 = 0xfffffffd;
+
+/// Prepares the inputs of `psbt` spending one of `descriptors` for a fee
+/// bump: their final scriptSig and witness, and their partial signatures,
+/// which the modified transaction would invalidate, are cleared, and their
+/// redeem and witness scripts are set back from the descriptors. Their
+/// sequence numbers are lowered to [MAX_RBF_SEQUENCE] when above it, so that
+/// the transaction signals replaceability; relative timelocks are kept.
+///
+/// Returns, indexed by input, the spend path along which each input was
+/// satisfied, or None for the inputs left untouched, which do not give the
+/// output they spend. The spend path of a finalized input is the lightest
+/// one whose conditions are met by the signatures, preimages and timelocks
+/// of its satisfaction; that of an input which was not finalized allows all
+/// the conditions of its descriptor. Once the outputs are modified and the
+/// inputs signed again, such as with `sign`, `finalize_fee_bump` finalizes
+/// them along these spend paths.
+pub fn prepare_fee_bump<Pk>(
+    psbt: &mut Psbt,
+    descriptors: &[Descriptor<Pk>],
+) -> Result<Vec<Option<SpendPathWeight<PublicKey>>>, Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+{
+    sanity_check(psbt)?;
+
+    let script_pubkeys: Vec<_> = descriptors.iter().map(|d| d.script_pubkey()).collect();
+    let mut paths = Vec::with_capacity(psbt.inputs.len());
+    let mut owned = Vec::with_capacity(psbt.inputs.len());
+    for index in 0..psbt.inputs.len() {
+        let descriptor = match get_scriptpubkey(psbt, index)
+            .ok()
+            .and_then(|spk| script_pubkeys.iter().position(|s| s == spk))
+        {
+            Some(i) => descriptors[i]
+                .translate_pk_infallible(|pk| pk.to_public_key(), |pkh| Pk::hash_to_hash160(pkh)),
+            None => {
+                paths.push(None);
+                owned.push(None);
+                continue;
+            }
+        };
+        let path = spend_path(psbt, index, &descriptor).map_err(|e| Error::InputError(e, index))?;
+        paths.push(Some(path));
+        owned.push(Some(descriptor));
+    }
+
+    for (index, descriptor) in owned.into_iter().enumerate() {
+        let descriptor = match descriptor {
+            Some(descriptor) => descriptor,
+            None => continue,
+        };
+        let input = &mut psbt.inputs[index];
+        input.final_script_sig = None;
+        input.final_script_witness = None;
+        input.partial_sigs.clear();
+
+        set_scripts(input, &descriptor);
+
+        let txin = &mut psbt.global.unsigned_tx.input[index];
+        if txin.sequence > MAX_RBF_SEQUENCE {
+            txin.sequence = MAX_RBF_SEQUENCE;
+        }
+    }
+    Ok(paths)
+}
+
+/// Finalizes the inputs of `psbt` prepared by `prepare_fee_bump` along the
+/// spend paths it returned, `paths`, with `finalize_input_with_spend_path`.
+/// Inputs without a spend path are left untouched. On error, no input is
+/// finalized.
+pub fn finalize_fee_bump<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    paths: &[Option<SpendPathWeight<PublicKey>>],
+    secp: &Secp256k1<C>,
+) -> Result<(), Error> {
+    if paths.len() != psbt.inputs.len() {
+        return Err(Error::WrongInputCount {
+            in_tx: psbt.inputs.len(),
+            in_map: paths.len(),
+        });
+    }
+    let mut finalized = psbt.clone();
+    for (index, path) in paths.iter().enumerate() {
+        if let Some(ref path) = *path {
+            finalize_input_with_spend_path(&mut finalized, index, path, secp)?;
+        }
+    }
+    *psbt = finalized;
+    Ok(())
+}
+
+// The spend path of `descriptor` along which the input at `index` is
+// satisfied, see `prepare_fee_bump`
+fn spend_path(
+    psbt: &Psbt,
+    index: usize,
+    descriptor: &Descriptor<PublicKey>,
+) -> Result<SpendPathWeight<PublicKey>, InputError> {
+    let input = &psbt.inputs[index];
+    if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+        return Ok(SpendPathWeight {
+            conditions: vec![descriptor.lift()?],
+            weight: descriptor.max_satisfaction_weight()?,
+        });
+    }
+
+    // The conditions met by the satisfaction, as leaves of semantic policies
+    let spk = get_scriptpubkey(psbt, index)?;
+    let empty_script_sig = Script::new();
+    let empty_witness = Vec::new();
+    let script_sig = input.final_script_sig.as_ref().unwrap_or(&empty_script_sig);
+    let witness = input
+        .final_script_witness
+        .as_ref()
+        .unwrap_or(&empty_witness);
+    let tx = &psbt.global.unsigned_tx;
+    let mut interpreter = Interpreter::from_txdata(
+        spk,
+        script_sig,
+        witness,
+        tx.lock_time,
+        tx.input[index].sequence,
+    )
+    .map_err(InputError::Interpreter)?;
+    let mut met = vec![];
+    for constraint in interpreter.iter_assume_sigs() {
+        met.push(match constraint.map_err(InputError::Interpreter)? {
+            SatisfiedConstraint::PublicKey { key, .. } => {
+                semantic::Policy::KeyHash(key.to_pubkeyhash())
+            }
+            SatisfiedConstraint::PublicKeyHash { keyhash, .. } => {
+                semantic::Policy::KeyHash(*keyhash)
+            }
+            SatisfiedConstraint::HashLock { hash, .. } => match hash {
+                HashLockType::Sha256(h) => semantic::Policy::Sha256(*h),
+                HashLockType::Hash256(h) => semantic::Policy::Hash256(*h),
+                HashLockType::Hash160(h) => semantic::Policy::Hash160(*h),
+                HashLockType::Ripemd160(h) => semantic::Policy::Ripemd160(*h),
+            },
+            SatisfiedConstraint::RelativeTimeLock { time } => semantic::Policy::Older(*time),
+            SatisfiedConstraint::AbsoluteTimeLock { time } => semantic::Policy::After(*time),
+            SatisfiedConstraint::TxTemplate { hash } => semantic::Policy::TxTemplate(*hash),
+        });
+    }
+
+    let is_met = |condition: &semantic::Policy<PublicKey>| {
+        condition.fold(|policy, _, subs| match *policy {
+            semantic::Policy::Threshold(k, _) => subs.into_iter().filter(|&sub| sub).count() >= k,
+            semantic::Policy::Trivial => true,
+            semantic::Policy::Unsatisfiable => false,
+            ref leaf => met.contains(leaf),
+        })
+    };
+    let mut found: Option<SpendPathWeight<PublicKey>> = None;
+    for path in descriptor.satisfaction_weights()? {
+        if path.conditions.iter().all(|c| is_met(c))
+            && found.as_ref().map_or(true, |f| path.weight < f.weight)
+        {
+            found = Some(path);
+        }
+    }
+    found.ok_or(InputError::MiniscriptError(::Error::CouldNotSatisfy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin;
+    use bitcoin::util::bip143;
+    use psbt::{finalize, finalize_owned_inputs};
+    use std::str::FromStr;
+
+    #[test]
+    fn prepare_fee_bump() {
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..3)
+            .map(|i| secp256k1::SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<_> = sks
+            .iter()
+            .map(|sk| PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(&secp, sk),
+                compressed: true,
+            })
+            .collect();
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "sh(wsh(or_d(pk({}),and_v(v:pk({}),older(6)))))",
+            pks[0], pks[1]
+        ))
+        .unwrap();
+        let sign = |psbt: &mut Psbt, signers: &[usize]| {
+            let sighash = bip143::SigHashCache::new(&psbt.global.unsigned_tx).signature_hash(
+                0,
+                &desc.explicit_script(),
+                100_000,
+                bitcoin::SigHashType::All,
+            );
+            let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+            for &i in signers {
+                let mut sig = secp.sign(&msg, &sks[i]).serialize_der().to_vec();
+                sig.push(0x01);
+                psbt.inputs[0].partial_sigs.insert(pks[i], sig);
+            }
+        };
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: Default::default(),
+                script_sig: Script::new(),
+                sequence: 6,
+                witness: vec![],
+            }],
+            output: vec![bitcoin::TxOut {
+                value: 99_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: desc.script_pubkey(),
+        });
+        // Spent along the recovery path
+        sign(&mut psbt, &[1]);
+        finalize_owned_inputs(&mut psbt, &[desc.clone()], &secp).unwrap();
+        assert!(psbt.inputs[0].final_script_witness.is_some());
+
+        let paths = super::prepare_fee_bump(&mut psbt, &[desc.clone()]).unwrap();
+        assert_eq!(paths.len(), 1);
+        let path = paths[0].as_ref().unwrap();
+        assert!(path.conditions.contains(&semantic::Policy::Older(6)));
+        let input = &psbt.inputs[0];
+        assert_eq!(input.final_script_sig, None);
+        assert_eq!(input.final_script_witness, None);
+        assert!(input.partial_sigs.is_empty());
+        assert_eq!(input.witness_script, Some(desc.explicit_script()));
+        // The relative timelock is kept
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+
+        // With both signatures, the finalizer would pick the cheaper path
+        psbt.global.unsigned_tx.output[0].value = 98_000;
+        sign(&mut psbt, &[0, 1]);
+        let mut cheapest = psbt.clone();
+        finalize(&mut cheapest, &secp).unwrap();
+        assert_eq!(
+            cheapest.inputs[0]
+                .final_script_witness
+                .as_ref()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // The bumped input is finalized along the recovery path again,
+        // dissatisfying the first key
+        finalize_fee_bump(&mut psbt, &paths, &secp).unwrap();
+        let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 3);
+        assert!(witness[1].is_empty());
+    }
+
+    #[test]
+    fn rbf_sequence() {
+        let desc = Descriptor::<PublicKey>::from_str(
+            "wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)",
+        )
+        .unwrap();
+        let txin = bitcoin::TxIn {
+            previous_output: Default::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: desc.script_pubkey(),
+        });
+
+        // The second input does not give the output it spends
+        assert_eq!(
+            super::prepare_fee_bump(&mut psbt, &[desc])
+                .unwrap()
+                .iter()
+                .map(Option::is_some)
+                .collect::<Vec<_>>(),
+            vec![true, false]
+        );
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input[0].sequence, MAX_RBF_SEQUENCE);
+        assert_eq!(tx.input[1].sequence, 0xffffffff);
+    }
+}
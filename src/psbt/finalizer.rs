@@ -21,15 +21,18 @@
 
 use super::{sanity_check, Psbt};
 use super::{Error, InputError, PsbtInputSatisfier};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::psbt;
 use bitcoin::{self, PublicKey, Script};
-use descriptor::DescriptorTrait;
+use descriptor::{DescriptorTrait, SpendPathWeight};
 use interpreter;
+use policy::semantic;
 use Descriptor;
 use Miniscript;
-use {BareCtx, Legacy, Segwitv0};
+use {BareCtx, BitcoinSig, Legacy, MiniscriptKey, Preimage32, Satisfier, Segwitv0, ToPublicKey};
 // Get the scriptpubkey for the psbt input
-fn get_scriptpubkey(psbt: &Psbt, index: usize) -> Result<&Script, InputError> {
+pub(super) fn get_scriptpubkey(psbt: &Psbt, index: usize) -> Result<&Script, InputError> {
     let script_pubkey;
     let inp = &psbt.inputs[index];
     if let Some(ref witness_utxo) = inp.witness_utxo {
@@ -44,7 +47,7 @@ fn get_scriptpubkey(psbt: &Psbt, index: usize) -> Result<&Script, InputError> {
 }
 
 // Get the amount being spent for the psbt input
-fn get_amt(psbt: &Psbt, index: usize) -> Result<u64, InputError> {
+pub(super) fn get_amt(psbt: &Psbt, index: usize) -> Result<u64, InputError> {
     let amt;
     let inp = &psbt.inputs[index];
     if let Some(ref witness_utxo) = inp.witness_utxo {
@@ -206,31 +209,42 @@ pub fn interpreter_check<C: secp256k1::Verification>(
     psbt: &Psbt,
     secp: &Secp256k1<C>,
 ) -> Result<(), Error> {
-    for (index, input) in psbt.inputs.iter().enumerate() {
-        let spk = get_scriptpubkey(psbt, index).map_err(|e| Error::InputError(e, index))?;
-        let empty_script_sig = Script::new();
-        let empty_witness = Vec::new();
-        let script_sig = input.final_script_sig.as_ref().unwrap_or(&empty_script_sig);
-        let witness = input
-            .final_script_witness
-            .as_ref()
-            .unwrap_or(&empty_witness);
-
-        // Now look at all the satisfied constraints. If everything is filled in
-        // corrected, there should be no errors
-
-        let cltv = psbt.global.unsigned_tx.lock_time;
-        let csv = psbt.global.unsigned_tx.input[index].sequence;
-        let amt = get_amt(psbt, index).map_err(|e| Error::InputError(e, index))?;
-
-        let mut interpreter =
-            interpreter::Interpreter::from_txdata(spk, &script_sig, &witness, cltv, csv)
-                .map_err(|e| Error::InputError(InputError::Interpreter(e), index))?;
-
-        let vfyfn = interpreter.sighash_verify(&secp, &psbt.global.unsigned_tx, index, amt);
-        if let Some(error) = interpreter.iter(vfyfn).filter_map(Result::err).next() {
-            return Err(Error::InputError(InputError::Interpreter(error), index));
-        }
+    for index in 0..psbt.inputs.len() {
+        interpreter_check_input(psbt, index, secp)?;
+    }
+    Ok(())
+}
+
+// Interprets the finalized psbt input at `index`
+fn interpreter_check_input<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    index: usize,
+    secp: &Secp256k1<C>,
+) -> Result<(), Error> {
+    let input = &psbt.inputs[index];
+    let spk = get_scriptpubkey(psbt, index).map_err(|e| Error::InputError(e, index))?;
+    let empty_script_sig = Script::new();
+    let empty_witness = Vec::new();
+    let script_sig = input.final_script_sig.as_ref().unwrap_or(&empty_script_sig);
+    let witness = input
+        .final_script_witness
+        .as_ref()
+        .unwrap_or(&empty_witness);
+
+    // Now look at all the satisfied constraints. If everything is filled in
+    // corrected, there should be no errors
+
+    let cltv = psbt.global.unsigned_tx.lock_time;
+    let csv = psbt.global.unsigned_tx.input[index].sequence;
+    let amt = get_amt(psbt, index).map_err(|e| Error::InputError(e, index))?;
+
+    let mut interpreter =
+        interpreter::Interpreter::from_txdata(spk, &script_sig, &witness, cltv, csv)
+            .map_err(|e| Error::InputError(InputError::Interpreter(e), index))?;
+
+    let vfyfn = interpreter.sighash_verify(&secp, &psbt.global.unsigned_tx, index, amt);
+    if let Some(error) = interpreter.iter(vfyfn).filter_map(Result::err).next() {
+        return Err(Error::InputError(InputError::Interpreter(error), index));
     }
     Ok(())
 }
@@ -242,6 +256,10 @@ pub fn interpreter_check<C: secp256k1::Verification>(
 /// For satisfaction of individual inputs, use the satisfy API.
 /// This function also performs a sanity interpreter check on the
 /// finalized psbt which involves checking the signatures/ preimages/timelocks.
+///
+/// The signatures of an input must all have its sighash type, `ALL` if it
+/// has none: to finalize an input signed with another sighash type, such as
+/// `ALL|ANYONECANPAY` or `SINGLE`, set it in the `sighash_type` of the input.
 pub fn finalize<C: secp256k1::Verification>(
     psbt: &mut Psbt,
     secp: &Secp256k1<C>,
@@ -250,51 +268,7 @@ pub fn finalize<C: secp256k1::Verification>(
 
     // Check well-formedness of input data
     for (n, input) in psbt.inputs.iter().enumerate() {
-        let target = input.sighash_type.unwrap_or(bitcoin::SigHashType::All);
-        for (key, rawsig) in &input.partial_sigs {
-            if rawsig.is_empty() {
-                return Err(Error::InputError(
-                    InputError::InvalidSignature {
-                        pubkey: *key,
-                        sig: rawsig.clone(),
-                    },
-                    n,
-                ));
-            }
-            let (flag, sig) = rawsig.split_last().unwrap();
-            let flag = bitcoin::SigHashType::from_u32_standard(*flag as u32).map_err(|_| {
-                super::Error::InputError(
-                    InputError::Interpreter(interpreter::Error::NonStandardSigHash(
-                        [sig, &[*flag]].concat().to_vec(),
-                    )),
-                    n,
-                )
-            })?;
-            if target != flag {
-                return Err(Error::InputError(
-                    InputError::WrongSigHashFlag {
-                        required: target,
-                        got: flag,
-                        pubkey: *key,
-                    },
-                    n,
-                ));
-            }
-            match secp256k1::Signature::from_der(sig) {
-                Err(..) => {
-                    return Err(Error::InputError(
-                        InputError::InvalidSignature {
-                            pubkey: *key,
-                            sig: Vec::from(sig),
-                        },
-                        n,
-                    ));
-                }
-                Ok(_sig) => {
-                    // Interpreter will check all the sigs later.
-                }
-            }
-        }
+        check_partial_sigs(input, n)?;
     }
 
     // Actually construct the witnesses
@@ -307,25 +281,7 @@ pub fn finalize<C: secp256k1::Verification>(
             .get_satisfaction(PsbtInputSatisfier::new(&psbt, index))
             .map_err(|e| Error::InputError(InputError::MiniscriptError(e), index))?;
 
-        let input = &mut psbt.inputs[index];
-        //Fill in the satisfactions
-        input.final_script_sig = if script_sig.is_empty() {
-            None
-        } else {
-            Some(script_sig)
-        };
-        input.final_script_witness = if witness.is_empty() {
-            None
-        } else {
-            Some(witness)
-        };
-        //reset everything
-        input.redeem_script = None;
-        input.partial_sigs.clear();
-        input.sighash_type = None;
-        input.redeem_script = None;
-        input.bip32_derivation.clear();
-        input.witness_script = None;
+        set_satisfaction(&mut psbt.inputs[index], witness, script_sig);
     }
     // Double check everything with the interpreter
     // This only checks whether the script will be executed
@@ -335,6 +291,259 @@ pub fn finalize<C: secp256k1::Verification>(
     Ok(())
 }
 
+/// Finalizes the inputs of the psbt spending one of `descriptors`, leaving
+/// the other inputs untouched, such as the inputs of the other participants
+/// of a coinjoin or a payjoin. Inputs already finalized, and inputs which do
+/// not give the output they spend, are left untouched as well.
+///
+/// Returns, indexed by input, whether each input was finalized. As
+/// `finalize` does, this checks the partial signatures of the inputs it
+/// finalizes and interprets them. On error, no input is finalized.
+pub fn finalize_owned_inputs<Pk, C>(
+    psbt: &mut Psbt,
+    descriptors: &[Descriptor<Pk>],
+    secp: &Secp256k1<C>,
+) -> Result<Vec<bool>, super::Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    C: secp256k1::Verification,
+{
+    sanity_check(psbt)?;
+
+    let script_pubkeys: Vec<_> = descriptors.iter().map(|d| d.script_pubkey()).collect();
+    let mut satisfactions = vec![];
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let desc = match get_scriptpubkey(psbt, index)
+            .ok()
+            .and_then(|spk| script_pubkeys.iter().position(|s| s == spk))
+        {
+            Some(i) => &descriptors[i],
+            None => continue,
+        };
+        check_partial_sigs(input, index)?;
+        let (witness, script_sig) = desc
+            .get_satisfaction(PsbtInputSatisfier::new(&psbt, index))
+            .map_err(|e| Error::InputError(InputError::MiniscriptError(e), index))?;
+        satisfactions.push((index, witness, script_sig));
+    }
+
+    let mut finalized = psbt.clone();
+    let mut ret = vec![false; psbt.inputs.len()];
+    for (index, witness, script_sig) in satisfactions {
+        set_satisfaction(&mut finalized.inputs[index], witness, script_sig);
+        interpreter_check_input(&finalized, index, secp)?;
+        ret[index] = true;
+    }
+    *psbt = finalized;
+    Ok(ret)
+}
+
+// Check that the partial signatures of the input at index `n` are
+// well-formed, and have its sighash type (`ALL` if unset)
+fn check_partial_sigs(input: &psbt::Input, n: usize) -> Result<(), Error> {
+    let target = input.sighash_type.unwrap_or(bitcoin::SigHashType::All);
+    for (key, rawsig) in &input.partial_sigs {
+        if rawsig.is_empty() {
+            return Err(Error::InputError(
+                InputError::InvalidSignature {
+                    pubkey: *key,
+                    sig: rawsig.clone(),
+                },
+                n,
+            ));
+        }
+        let (flag, sig) = rawsig.split_last().unwrap();
+        let flag = bitcoin::SigHashType::from_u32_standard(*flag as u32).map_err(|_| {
+            super::Error::InputError(
+                InputError::Interpreter(interpreter::Error::NonStandardSigHash(
+                    [sig, &[*flag]].concat().to_vec(),
+                )),
+                n,
+            )
+        })?;
+        if target != flag {
+            return Err(Error::InputError(
+                InputError::WrongSigHashFlag {
+                    required: target,
+                    got: flag,
+                    pubkey: *key,
+                },
+                n,
+            ));
+        }
+        match secp256k1::Signature::from_der(sig) {
+            Err(..) => {
+                return Err(Error::InputError(
+                    InputError::InvalidSignature {
+                        pubkey: *key,
+                        sig: Vec::from(sig),
+                    },
+                    n,
+                ));
+            }
+            Ok(_sig) => {
+                // Interpreter will check all the sigs later.
+            }
+        }
+    }
+    Ok(())
+}
+
+// Fill in the satisfaction of a psbt input, resetting the fields it makes
+// redundant
+fn set_satisfaction(input: &mut psbt::Input, witness: Vec<Vec<u8>>, script_sig: Script) {
+    //Fill in the satisfactions
+    input.final_script_sig = if script_sig.is_empty() {
+        None
+    } else {
+        Some(script_sig)
+    };
+    input.final_script_witness = if witness.is_empty() {
+        None
+    } else {
+        Some(witness)
+    };
+    //reset everything
+    input.redeem_script = None;
+    input.partial_sigs.clear();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.bip32_derivation.clear();
+    input.witness_script = None;
+}
+
+/// Finalizes the input at `index` of the psbt along the spend `path` of its
+/// descriptor, such as the one picked by `Descriptor::cheapest_spend_path`
+/// to estimate the fee, rather than along its cheapest satisfaction: only
+/// the signatures, preimages and timelocks among the conditions of the path
+/// are used, so that the witness weighs at most the weight of the path.
+///
+/// As `finalize` does, this checks the partial signatures of the input and
+/// interprets the finalized input. Panics if `index` is out of range.
+pub fn finalize_input_with_spend_path<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    index: usize,
+    path: &SpendPathWeight<PublicKey>,
+    secp: &Secp256k1<C>,
+) -> Result<(), super::Error> {
+    sanity_check(psbt)?;
+    check_partial_sigs(&psbt.inputs[index], index)?;
+
+    let desc = get_descriptor(&psbt, index).map_err(|e| Error::InputError(e, index))?;
+    let (witness, script_sig) = desc
+        .get_satisfaction(SpendPathSatisfier::new(
+            PsbtInputSatisfier::new(&psbt, index),
+            &path.conditions,
+        ))
+        .map_err(|e| Error::InputError(InputError::MiniscriptError(e), index))?;
+    set_satisfaction(&mut psbt.inputs[index], witness, script_sig);
+
+    interpreter_check_input(&psbt, index, secp)
+}
+
+// Satisfier only giving the items among the conditions of a spend path
+struct SpendPathSatisfier<S> {
+    satisfier: S,
+    // The leaves of the conditions
+    conditions: Vec<semantic::Policy<PublicKey>>,
+}
+
+impl<S: Satisfier<PublicKey>> SpendPathSatisfier<S> {
+    fn new(satisfier: S, conditions: &[semantic::Policy<PublicKey>]) -> Self {
+        let mut leaves = vec![];
+        for condition in conditions {
+            condition.visit(|policy, _| match *policy {
+                semantic::Policy::Threshold(..) => true,
+                _ => {
+                    leaves.push(policy.clone());
+                    false
+                }
+            });
+        }
+        SpendPathSatisfier {
+            satisfier: satisfier,
+            conditions: leaves,
+        }
+    }
+
+    fn allows(&self, condition: semantic::Policy<PublicKey>) -> bool {
+        self.conditions.contains(&condition)
+    }
+}
+
+impl<S: Satisfier<PublicKey>> Satisfier<PublicKey> for SpendPathSatisfier<S> {
+    fn lookup_sig(&self, pk: &PublicKey) -> Option<BitcoinSig> {
+        if self.allows(semantic::Policy::KeyHash(pk.to_pubkeyhash())) {
+            self.satisfier.lookup_sig(pk)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &hash160::Hash) -> Option<PublicKey> {
+        if self.allows(semantic::Policy::KeyHash(*pkh)) {
+            self.satisfier.lookup_pkh_pk(pkh)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &hash160::Hash) -> Option<(PublicKey, BitcoinSig)> {
+        if self.allows(semantic::Policy::KeyHash(*pkh)) {
+            self.satisfier.lookup_pkh_sig(pkh)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+        if self.allows(semantic::Policy::Sha256(h)) {
+            self.satisfier.lookup_sha256(h)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        if self.allows(semantic::Policy::Hash256(h)) {
+            self.satisfier.lookup_hash256(h)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        if self.allows(semantic::Policy::Ripemd160(h)) {
+            self.satisfier.lookup_ripemd160(h)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        if self.allows(semantic::Policy::Hash160(h)) {
+            self.satisfier.lookup_hash160(h)
+        } else {
+            None
+        }
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.allows(semantic::Policy::Older(n)) && self.satisfier.check_older(n)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.allows(semantic::Policy::After(n)) && self.satisfier.check_after(n)
+    }
+
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        self.allows(semantic::Policy::TxTemplate(h)) && self.satisfier.check_tx_template(h)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -352,4 +561,141 @@ mod tests {
         let expected: bitcoin::util::psbt::PartiallySignedTransaction = deserialize(&Vec::<u8>::from_hex("70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000000107da00473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae0001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e8870107232200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b20289030108da0400473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f01473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d20147522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae00220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000").unwrap()).unwrap();
         assert_eq!(psbt, expected);
     }
+
+    #[test]
+    fn finalize_with_spend_path() {
+        use bitcoin::util::bip143;
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let keys: Vec<_> = (1..3)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                let pk = PublicKey {
+                    key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                    compressed: true,
+                };
+                (sk, pk)
+            })
+            .collect();
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(or_d(pk({}),and_v(v:pk({}),older(10))))",
+            keys[0].1, keys[1].1
+        ))
+        .unwrap();
+        let witness_script = desc.explicit_script();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: Default::default(),
+                script_sig: Script::new(),
+                sequence: 10,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let sighash = bip143::SigHashCache::new(&tx).signature_hash(
+            0,
+            &witness_script,
+            100_000,
+            bitcoin::SigHashType::All,
+        );
+        let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: desc.script_pubkey(),
+        });
+        psbt.inputs[0].witness_script = Some(witness_script);
+        for &(ref sk, ref pk) in &keys {
+            let mut sig = secp.sign(&msg, sk).serialize_der().to_vec();
+            sig.push(0x01);
+            psbt.inputs[0].partial_sigs.insert(*pk, sig);
+        }
+
+        // Both keys signed: the cheapest satisfaction uses the first one
+        let mut cheapest = psbt.clone();
+        finalize(&mut cheapest, &secp).unwrap();
+        let witness = cheapest.inputs[0].final_script_witness.clone().unwrap();
+        assert_eq!(witness.len(), 2);
+
+        let paths = desc.satisfaction_weights().unwrap();
+        finalize_input_with_spend_path(&mut psbt, 0, &paths[1], &secp).unwrap();
+        let witness = psbt.inputs[0].final_script_witness.clone().unwrap();
+        // The signature of the second key, the dissatisfaction of the first
+        // one and the witness script
+        assert_eq!(witness.len(), 3);
+        assert!(witness[1].is_empty());
+        let weight = 1 + witness.iter().map(|w| 1 + w.len()).sum::<usize>();
+        assert!(weight <= paths[1].weight);
+    }
+
+    #[test]
+    fn finalize_owned_inputs() {
+        use bitcoin::util::bip143;
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let ours = Descriptor::<PublicKey>::from_str(&format!("wpkh({})", pk)).unwrap();
+        let theirs = Descriptor::<PublicKey>::from_str(
+            "wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)",
+        )
+        .unwrap();
+
+        let txin = bitcoin::TxIn {
+            previous_output: Default::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+        let sighash = bip143::SigHashCache::new(&tx).signature_hash(
+            1,
+            &ours.script_code(),
+            100_000,
+            bitcoin::SigHashType::All,
+        );
+        let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: theirs.script_pubkey(),
+        });
+        psbt.inputs[1].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: ours.script_pubkey(),
+        });
+
+        // Without a signature, our input cannot be finalized
+        let unsigned = psbt.clone();
+        assert!(super::finalize_owned_inputs(&mut psbt, &[ours.clone()], &secp).is_err());
+        assert_eq!(psbt, unsigned);
+
+        let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01);
+        psbt.inputs[1].partial_sigs.insert(pk, sig);
+        let finalized = super::finalize_owned_inputs(&mut psbt, &[ours.clone()], &secp).unwrap();
+        assert_eq!(finalized, vec![false, true]);
+        assert_eq!(psbt.inputs[0], unsigned.inputs[0]);
+        assert_eq!(
+            psbt.inputs[1].final_script_witness.as_ref().map(Vec::len),
+            Some(2)
+        );
+
+        // Finalized inputs are left untouched
+        let finalized = super::finalize_owned_inputs(&mut psbt, &[ours], &secp).unwrap();
+        assert_eq!(finalized, vec![false, false]);
+    }
 }
@@ -14,8 +14,8 @@
 
 //! # Partially-Signed Bitcoin Transactions
 //!
-//! This module implements the Finalizer and Extractor roles defined in
-//! BIP 174, PSBT, described at
+//! This module implements the Signer, Finalizer and Extractor roles defined
+//! in BIP 174, PSBT, described at
 //! `https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki`
 //!
 
@@ -24,9 +24,11 @@ use std::{error, fmt};
 use bitcoin;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::psbt;
 use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
 use bitcoin::Script;
 
+use descriptor::{Descriptor, DescriptorTrait, DescriptorType};
 use interpreter;
 use miniscript::limits::SEQUENCE_LOCKTIME_DISABLE_FLAG;
 use miniscript::satisfy::{bitcoinsig_from_rawsig, After, Older};
@@ -34,8 +36,25 @@ use Satisfier;
 use {BitcoinSig, Preimage32};
 use {MiniscriptKey, ToPublicKey};
 
+mod bump;
+pub use self::bump::{finalize_fee_bump, prepare_fee_bump, MAX_RBF_SEQUENCE};
+
 mod finalizer;
-pub use self::finalizer::{finalize, interpreter_check};
+pub use self::finalizer::{
+    finalize, finalize_input_with_spend_path, finalize_owned_inputs, interpreter_check,
+};
+
+mod reserves;
+pub use self::reserves::{create_reserves_proof, reserves_commitment, verify_reserves_proof};
+
+mod signer;
+pub use self::signer::{sign, SigningKeys};
+
+mod status;
+pub use self::status::{signing_status, InputSigningStatus};
+
+mod weight;
+pub use self::weight::{estimate_weight, WeightEstimate};
 
 /// Error type for Pbst Input
 #[derive(Debug)]
@@ -69,6 +88,8 @@ pub enum InputError {
     },
     /// Pass through the underlying errors in miniscript
     MiniscriptError(super::Error),
+    /// The key source failed to look up a signing key
+    KeyLookup(String),
     /// Missing redeem script for p2sh
     MissingRedeemScript,
     /// Missing witness
@@ -79,6 +100,8 @@ pub enum InputError {
     MissingWitnessScript,
     ///Missing both the witness and non-witness utxo
     MissingUtxo,
+    /// None of the given descriptors has the script pubkey of the output spent
+    MissingDescriptor,
     /// Non empty Witness script for p2sh
     NonEmptyWitnessScript,
     /// Non empty Redeem script
@@ -106,6 +129,8 @@ pub enum Error {
         /// Input count in psbt
         in_map: usize,
     },
+    /// The PSBT is not a valid proof of reserves
+    InvalidReservesProof(&'static str),
 }
 
 impl fmt::Display for InputError {
@@ -135,12 +160,16 @@ impl fmt::Display for InputError {
                 witness_script, p2wsh_expected
             ),
             InputError::MiniscriptError(ref e) => write!(f, "Miniscript Error: {}", e),
+            InputError::KeyLookup(ref e) => write!(f, "Key lookup error: {}", e),
             InputError::MissingWitness => write!(f, "PSBT is missing witness"),
             InputError::MissingRedeemScript => write!(f, "PSBT is Redeem script"),
             InputError::MissingUtxo => {
                 write!(f, "PSBT is missing both witness and non-witness UTXO")
             }
             InputError::MissingWitnessScript => write!(f, "PSBT is missing witness script"),
+            InputError::MissingDescriptor => {
+                write!(f, "No descriptor for the output spent by the input")
+            }
             InputError::MissingPubkey => write!(f, "Missing pubkey for a pkh/wpkh"),
             InputError::NonEmptyRedeemScript => write!(
                 f,
@@ -195,6 +224,7 @@ impl fmt::Display for Error {
                 "PSBT had {} inputs in transaction but {} inputs in map",
                 in_tx, in_map
             ),
+            Error::InvalidReservesProof(ref e) => write!(f, "invalid proof of reserves: {}", e),
         }
     }
 }
@@ -281,6 +311,12 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
         }
     }
 
+    // The scriptSigs committed to are those of the unsigned transaction, so
+    // templates committing to non-empty ones are not recognized
+    fn check_tx_template(&self, h: sha256::Hash) -> bool {
+        interpreter::template_hash(&self.psbt.global.unsigned_tx, self.index) == h
+    }
+
     fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
         self.psbt.inputs[self.index]
             .hash160_preimages
@@ -332,6 +368,31 @@ fn sanity_check(psbt: &Psbt) -> Result<(), Error> {
     Ok(())
 }
 
+/// Sets the redeem script and the witness script of `input`, which spends an
+/// output of `descriptor`
+pub(super) fn set_scripts<Pk: MiniscriptKey + ToPublicKey>(
+    input: &mut psbt::Input,
+    descriptor: &Descriptor<Pk>,
+) {
+    let script = descriptor.explicit_script();
+    let (redeem_script, witness_script) = match descriptor.desc_type() {
+        DescriptorType::Sh | DescriptorType::ShSortedMulti | DescriptorType::ShWpkh => {
+            (Some(script), None)
+        }
+        DescriptorType::ShWsh | DescriptorType::ShWshSortedMulti => {
+            (Some(script.to_v0_p2wsh()), Some(script))
+        }
+        DescriptorType::Wsh | DescriptorType::WshSortedMulti => (None, Some(script)),
+        DescriptorType::Bare
+        | DescriptorType::Pkh
+        | DescriptorType::Wpkh
+        | DescriptorType::Addr
+        | DescriptorType::Raw => (None, None),
+    };
+    input.redeem_script = redeem_script;
+    input.witness_script = witness_script;
+}
+
 /// Psbt extractor as defined in BIP174 that takes in a psbt reference
 /// and outputs a extracted bitcoin::Transaction
 /// Also does the interpreter sanity check
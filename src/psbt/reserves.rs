@@ -0,0 +1,283 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Proof of Reserves
+//!
+//! Proofs of reserves as defined in BIP 127: a PSBT which cannot be
+//! broadcast, as its first input spends a nonexistent output committing to
+//! a message, and whose other inputs spend the outputs claimed, signed as if
+//! they were spent. The claimed outputs are checked against the set of
+//! unspent outputs of the verifier.
+//!
+
+use std::collections::HashMap;
+
+use bitcoin::blockdata::{opcodes, script};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+
+use super::finalizer::{get_amt, get_scriptpubkey};
+use super::{extract, sanity_check, set_scripts, Error, InputError, Psbt};
+use descriptor::{Descriptor, DescriptorPublicKey, DescriptorTrait};
+use {ForEachKey, TranslatePk2};
+
+/// The prefix of the messages committed to by proofs of reserves
+const MESSAGE_PREFIX: &'static [u8] = b"Proof-of-Reserves: ";
+
+/// The nonexistent output spent by the first input of a proof of reserves
+/// for `message`, which commits to it
+pub fn reserves_commitment(message: &str) -> OutPoint {
+    let hash = sha256d::Hash::hash(&[MESSAGE_PREFIX, message.as_bytes()].concat());
+    OutPoint::new(Txid::from_hash(hash), 0)
+}
+
+/// Builds the unsigned PSBT of a proof of reserves for `message` of the
+/// outputs `utxos`, each spending one of `descriptors`
+///
+/// Descriptors with wildcards must be derived at the indices of the outputs
+/// first. The inputs are given the redeem and witness scripts of
+/// their descriptor, and the derivations of its keys, so that they can be
+/// signed with `sign` and finalized with `finalize_owned_inputs`. The first
+/// input, committing to the message, is signed as if it spent the first
+/// output. The single output of the PSBT is worth all the outputs claimed and
+/// pays to `OP_TRUE`.
+///
+/// Returns Error when an output does not pay to any of the descriptors, or
+/// when `utxos` is empty.
+pub fn create_reserves_proof<C: secp256k1::Verification>(
+    message: &str,
+    utxos: &[(OutPoint, TxOut)],
+    descriptors: &[Descriptor<DescriptorPublicKey>],
+    secp: &Secp256k1<C>,
+) -> Result<Psbt, Error> {
+    if utxos.is_empty() {
+        return Err(Error::InvalidReservesProof("no output claimed"));
+    }
+
+    // Descriptors which cannot be derived pay to none of the outputs
+    let derived: Vec<_> = descriptors
+        .iter()
+        .filter_map(|desc| {
+            desc.translate_pk2(|pk| pk.derive_public_key(secp))
+                .ok()
+                .map(|derived| (desc, derived))
+        })
+        .collect();
+    let script_pubkeys: Vec<_> = derived
+        .iter()
+        .map(|&(_, ref d)| d.script_pubkey())
+        .collect();
+
+    // The commitment input spends a copy of the first output worth nothing
+    let commitment = TxOut {
+        value: 0,
+        script_pubkey: utxos[0].1.script_pubkey.clone(),
+    };
+    let spent: Vec<_> = Some((reserves_commitment(message), &commitment))
+        .into_iter()
+        .chain(utxos.iter().map(|&(outpoint, ref txout)| (outpoint, txout)))
+        .collect();
+
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: spent
+            .iter()
+            .map(|&(outpoint, _)| TxIn {
+                previous_output: outpoint,
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: utxos.iter().map(|&(_, ref txout)| txout.value).sum(),
+            script_pubkey: script::Builder::new()
+                .push_opcode(opcodes::OP_TRUE)
+                .into_script(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("unsigned transaction");
+
+    for (index, &(_, txout)) in spent.iter().enumerate() {
+        let i = match script_pubkeys
+            .iter()
+            .position(|s| *s == txout.script_pubkey)
+        {
+            Some(i) => i,
+            None => return Err(Error::InputError(InputError::MissingDescriptor, index)),
+        };
+        let input = &mut psbt.inputs[index];
+        input.witness_utxo = Some(txout.clone());
+
+        let (descriptor, ref derived_desc) = derived[i];
+        set_scripts(input, derived_desc);
+
+        descriptor.for_each_key(|key| {
+            let key = key.as_key();
+            if let Ok(pk) = key.derive_public_key(secp) {
+                let source = (key.master_fingerprint(), key.full_derivation_path());
+                input.bip32_derivation.insert(pk, source);
+            }
+            true
+        });
+    }
+    Ok(psbt)
+}
+
+/// Verifies the finalized PSBT of a proof of reserves for `message` against
+/// the unspent outputs `utxos` of the verifier, returning the total value of
+/// the outputs it claims
+///
+/// Every input is interpreted, including the one committing to the message.
+/// Each claimed output must be in `utxos`, with the value and script pubkey
+/// the PSBT gives it: legacy signatures do not commit to the values of the
+/// outputs they spend, so these are only trusted from `utxos`.
+pub fn verify_reserves_proof<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    message: &str,
+    utxos: &HashMap<OutPoint, TxOut>,
+    secp: &Secp256k1<C>,
+) -> Result<u64, Error> {
+    sanity_check(psbt)?;
+
+    let tx = &psbt.global.unsigned_tx;
+    if tx.input.len() < 2 {
+        return Err(Error::InvalidReservesProof("no output claimed"));
+    }
+    if tx.input[0].previous_output != reserves_commitment(message) {
+        return Err(Error::InvalidReservesProof(
+            "first input does not commit to the message",
+        ));
+    }
+    if tx.input[1..]
+        .iter()
+        .any(|txin| txin.previous_output == tx.input[0].previous_output)
+    {
+        return Err(Error::InvalidReservesProof("commitment spent twice"));
+    }
+
+    let mut total = 0;
+    for index in 1..tx.input.len() {
+        let utxo = match utxos.get(&tx.input[index].previous_output) {
+            Some(utxo) => utxo,
+            None => return Err(Error::InvalidReservesProof("claimed output is not unspent")),
+        };
+        let amount = get_amt(psbt, index).map_err(|e| Error::InputError(e, index))?;
+        let script_pubkey =
+            get_scriptpubkey(psbt, index).map_err(|e| Error::InputError(e, index))?;
+        if amount != utxo.value || *script_pubkey != utxo.script_pubkey {
+            return Err(Error::InvalidReservesProof(
+                "claimed output differs from the unspent output",
+            ));
+        }
+        total += utxo.value;
+    }
+    if tx.output.len() != 1 || tx.output[0].value != total {
+        return Err(Error::InvalidReservesProof(
+            "output is not worth the outputs claimed",
+        ));
+    }
+
+    extract(psbt, secp)?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use descriptor::KeyMap;
+    use psbt::{finalize_owned_inputs, sign};
+
+    // Two outputs of the descriptor, and the signed proof of reserves of
+    // `message` for them
+    fn proof(message: &str) -> (Psbt, HashMap<OutPoint, TxOut>) {
+        let secp = Secp256k1::new();
+        let (descriptor, key_map): (_, KeyMap) = Descriptor::parse_descriptor(&secp, "wsh(multi(1,tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/7,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))").unwrap();
+        let spk = descriptor
+            .translate_pk2(|pk| pk.derive_public_key(&secp))
+            .unwrap()
+            .script_pubkey();
+        let utxo = |vout, value| {
+            (
+                OutPoint::new(Txid::from_hash(sha256d::Hash::hash(&[0x42])), vout),
+                TxOut {
+                    value: value,
+                    script_pubkey: spk.clone(),
+                },
+            )
+        };
+
+        let utxos = [utxo(0, 50_000), utxo(1, 70_000)];
+        let mut psbt =
+            create_reserves_proof(message, &utxos, &[descriptor.clone()], &secp).unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 3);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 120_000);
+
+        sign(&mut psbt, &key_map, &secp).unwrap();
+        let derived = descriptor
+            .translate_pk2(|pk| pk.derive_public_key(&secp))
+            .unwrap();
+        assert_eq!(
+            finalize_owned_inputs(&mut psbt, &[derived], &secp).unwrap(),
+            vec![true; 3]
+        );
+        (psbt, utxos.iter().cloned().collect())
+    }
+
+    #[test]
+    fn reserves_proof() {
+        let secp = Secp256k1::verification_only();
+        let (psbt, mut utxos) = proof("Stored on-chain");
+        assert_eq!(
+            verify_reserves_proof(&psbt, "Stored on-chain", &utxos, &secp).unwrap(),
+            120_000
+        );
+        match verify_reserves_proof(&psbt, "Stored off-chain", &utxos, &secp) {
+            Err(Error::InvalidReservesProof(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // The commitment input is signed for the message
+        let (mut forged, _) = proof("Stored off-chain");
+        forged.inputs[0] = psbt.inputs[0].clone();
+        assert!(verify_reserves_proof(&forged, "Stored off-chain", &utxos, &secp).is_err());
+
+        // The claimed outputs must be unspent, and worth what the PSBT claims
+        let outpoint = psbt.global.unsigned_tx.input[1].previous_output;
+        utxos.get_mut(&outpoint).unwrap().value += 1;
+        match verify_reserves_proof(&psbt, "Stored on-chain", &utxos, &secp) {
+            Err(Error::InvalidReservesProof(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        utxos.remove(&outpoint);
+        match verify_reserves_proof(&psbt, "Stored on-chain", &utxos, &secp) {
+            Err(Error::InvalidReservesProof(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let unspendable = Descriptor::<DescriptorPublicKey>::from_str(
+            "wpkh(02e96fe52ef0e22d2f131dd425ce1893073a3c6ad20e8cac36726393dfb4856a4c)",
+        )
+        .unwrap();
+        let utxo = (OutPoint::default(), TxOut::default());
+        match create_reserves_proof("", &[utxo], &[unspendable], &secp) {
+            Err(Error::InputError(InputError::MissingDescriptor, 0)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
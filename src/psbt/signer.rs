@@ -0,0 +1,266 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Signer
+//!
+//! This module implements the Signer role defined in BIP 174 for inputs
+//! spending legacy and segwit v0 outputs, using keys looked up from a
+//! `GetKey` key source.
+//!
+
+use std::collections::BTreeSet;
+
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip143;
+use bitcoin::{self, PublicKey, Script, SigHashType};
+
+use super::finalizer::{get_amt, get_scriptpubkey};
+use super::{sanity_check, Error, InputError, Psbt};
+use descriptor::{GetKey, KeyRequest};
+use MiniscriptKey;
+
+/// The public keys which produced signatures for each input of a PSBT,
+/// indexed by input
+pub type SigningKeys = Vec<BTreeSet<PublicKey>>;
+
+/// Psbt signer as defined in BIP174
+///
+/// Signs every input of `psbt` with the keys listed in its bip32 derivations
+/// that are known to `keys`. Keys are requested by their origin first, and by
+/// public key if the origin is unknown. Signatures are added to the partial
+/// signatures of the inputs, using the sighash type of the input (`ALL` if
+/// unset): to sign an input with another sighash type, such as
+/// `ALL|ANYONECANPAY`, set it in the `sighash_type` of the input first.
+///
+/// Returns the keys which produced a signature for each input. Inputs are only
+/// modified if all of them could be signed without errors.
+pub fn sign<C, K>(psbt: &mut Psbt, keys: &K, secp: &Secp256k1<C>) -> Result<SigningKeys, Error>
+where
+    C: secp256k1::Signing,
+    K: GetKey,
+    K::Error: ToString,
+{
+    sanity_check(psbt)?;
+
+    let mut sigs = Vec::with_capacity(psbt.inputs.len());
+    {
+        let mut sighash_cache = bip143::SigHashCache::new(&psbt.global.unsigned_tx);
+        for index in 0..psbt.inputs.len() {
+            let input_sigs = sign_input(psbt, &mut sighash_cache, index, keys, secp)
+                .map_err(|e| Error::InputError(e, index))?;
+            sigs.push(input_sigs);
+        }
+    }
+
+    let mut ret = Vec::with_capacity(sigs.len());
+    for (input, input_sigs) in psbt.inputs.iter_mut().zip(sigs) {
+        let mut signing_keys = BTreeSet::new();
+        for (pk, sig) in input_sigs {
+            input.partial_sigs.insert(pk, sig);
+            signing_keys.insert(pk);
+        }
+        ret.push(signing_keys);
+    }
+    Ok(ret)
+}
+
+// Produce the signatures of the keys known to `keys` for the psbt input at
+// `index`, without modifying the psbt
+fn sign_input<C, K>(
+    psbt: &Psbt,
+    sighash_cache: &mut bip143::SigHashCache<&bitcoin::Transaction>,
+    index: usize,
+    keys: &K,
+    secp: &Secp256k1<C>,
+) -> Result<Vec<(PublicKey, Vec<u8>)>, InputError>
+where
+    C: secp256k1::Signing,
+    K: GetKey,
+    K::Error: ToString,
+{
+    let inp = &psbt.inputs[index];
+    let sighash_type = inp.sighash_type.unwrap_or(SigHashType::All);
+
+    let mut ret = vec![];
+    for (pk, source) in &inp.bip32_derivation {
+        // The key source may map the origin to an unrelated key
+        let sk = match lookup_key(keys, &KeyRequest::Bip32(source.clone()), secp)? {
+            Some(sk) if sk.public_key(secp) == *pk => sk,
+            _ => match lookup_key(keys, &KeyRequest::Pubkey(*pk), secp)? {
+                Some(sk) if sk.public_key(secp) == *pk => sk,
+                _ => continue,
+            },
+        };
+
+        let sighash = signature_hash(psbt, sighash_cache, index, pk, sighash_type)?;
+        let msg = secp256k1::Message::from_slice(&sighash[..]).expect("32-byte sighash");
+        let mut sig = secp.sign(&msg, &sk.key).serialize_der().to_vec();
+        sig.push(sighash_type.as_u32() as u8);
+        ret.push((*pk, sig));
+    }
+    Ok(ret)
+}
+
+fn lookup_key<C, K>(
+    keys: &K,
+    key_request: &KeyRequest,
+    secp: &Secp256k1<C>,
+) -> Result<Option<bitcoin::PrivateKey>, InputError>
+where
+    C: secp256k1::Signing,
+    K: GetKey,
+    K::Error: ToString,
+{
+    keys.get_key(key_request, secp)
+        .map_err(|e| InputError::KeyLookup(e.to_string()))
+}
+
+// Compute the sighash of the psbt input at `index` for a signature by `pk`
+fn signature_hash(
+    psbt: &Psbt,
+    sighash_cache: &mut bip143::SigHashCache<&bitcoin::Transaction>,
+    index: usize,
+    pk: &PublicKey,
+    sighash_type: SigHashType,
+) -> Result<bitcoin::SigHash, InputError> {
+    let inp = &psbt.inputs[index];
+    let script_pubkey = get_scriptpubkey(psbt, index)?;
+    let inner_script = match inp.redeem_script {
+        Some(ref redeem_script) => redeem_script,
+        None => script_pubkey,
+    };
+
+    if let Some(ref witness_script) = inp.witness_script {
+        let amt = get_amt(psbt, index)?;
+        Ok(sighash_cache.signature_hash(index, witness_script, amt, sighash_type))
+    } else if inner_script.is_v0_p2wpkh() {
+        // bip143: the scriptCode of a P2WPKH output is the P2PKH script of the key
+        let script_code = Script::new_p2pkh(&pk.to_pubkeyhash().into());
+        let amt = get_amt(psbt, index)?;
+        Ok(sighash_cache.signature_hash(index, &script_code, amt, sighash_type))
+    } else {
+        Ok(psbt
+            .global
+            .unsigned_tx
+            .signature_hash(index, inner_script, sighash_type.as_u32()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::util::psbt::PartiallySignedTransaction;
+    use descriptor::{Descriptor, DescriptorTrait, KeyMap};
+    use psbt::finalize;
+    use TranslatePk2;
+
+    // A psbt with two inputs spending a wpkh output, listing the key in the
+    // bip32 derivations of the inputs in `listed`
+    fn wpkh_psbt(
+        secp: &Secp256k1<secp256k1::All>,
+        listed: &[usize],
+    ) -> (PartiallySignedTransaction, KeyMap, PublicKey) {
+        let (descriptor, key_map) = Descriptor::parse_descriptor(secp, "wpkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/*)").unwrap();
+        let derived = descriptor.derive(3);
+        let (pk, source) = match derived {
+            Descriptor::Wpkh(ref wpkh) => (
+                wpkh.as_inner().derive_public_key(secp).unwrap(),
+                (
+                    wpkh.as_inner().master_fingerprint(),
+                    wpkh.as_inner().full_derivation_path(),
+                ),
+            ),
+            _ => unreachable!(),
+        };
+        let script_pubkey = derived
+            .translate_pk2(|k| k.derive_public_key(secp))
+            .unwrap()
+            .script_pubkey();
+
+        let txin = bitcoin::TxIn {
+            previous_output: Default::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        for input in psbt.inputs.iter_mut() {
+            input.witness_utxo = Some(bitcoin::TxOut {
+                value: 100_000,
+                script_pubkey: script_pubkey.clone(),
+            });
+        }
+        for &index in listed {
+            psbt.inputs[index]
+                .bip32_derivation
+                .insert(pk, source.clone());
+        }
+        (psbt, key_map, pk)
+    }
+
+    #[test]
+    fn sign_wpkh() {
+        let secp = Secp256k1::new();
+        // Only the first input lists the key
+        let (mut psbt, key_map, pk) = wpkh_psbt(&secp, &[0]);
+
+        let signing_keys = sign(&mut psbt, &key_map, &secp).unwrap();
+        assert_eq!(signing_keys.len(), 2);
+        assert_eq!(signing_keys[0].iter().collect::<Vec<_>>(), vec![&pk]);
+        assert!(signing_keys[1].is_empty());
+        assert!(psbt.inputs[1].partial_sigs.is_empty());
+
+        let sig = &psbt.inputs[0].partial_sigs[&pk];
+        assert_eq!(*sig.last().unwrap(), SigHashType::All.as_u32() as u8);
+        let script_code = Script::new_p2pkh(&pk.to_pubkeyhash().into());
+        let sighash = bip143::SigHashCache::new(&psbt.global.unsigned_tx).signature_hash(
+            0,
+            &script_code,
+            100_000,
+            SigHashType::All,
+        );
+        let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+        let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
+        secp.verify(&msg, &sig, &pk.key).unwrap();
+    }
+
+    #[test]
+    fn sign_anyonecanpay() {
+        let secp = Secp256k1::new();
+        let (mut psbt, key_map, pk) = wpkh_psbt(&secp, &[0, 1]);
+        psbt.inputs[0].sighash_type = Some(SigHashType::AllPlusAnyoneCanPay);
+        sign(&mut psbt, &key_map, &secp).unwrap();
+        assert_eq!(*psbt.inputs[0].partial_sigs[&pk].last().unwrap(), 0x81);
+        assert_eq!(*psbt.inputs[1].partial_sigs[&pk].last().unwrap(), 0x01);
+
+        // The signatures must have the sighash type of their input, `ALL` if
+        // it has none
+        let mut wrong = psbt.clone();
+        wrong.inputs[1].sighash_type = Some(SigHashType::AllPlusAnyoneCanPay);
+        assert!(finalize(&mut wrong, &secp).is_err());
+        let mut unset = psbt.clone();
+        unset.inputs[0].sighash_type = None;
+        assert!(finalize(&mut unset, &secp).is_err());
+
+        finalize(&mut psbt, &secp).unwrap();
+        let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(*witness[0].last().unwrap(), 0x81);
+    }
+}
@@ -0,0 +1,180 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Signing Status
+//!
+//! Progress of the signing of the inputs of a PSBT spending a descriptor:
+//! which of its keys signed, which did not yet, and whether the signatures
+//! gathered already suffice to finalize an input.
+//!
+
+use std::collections::BTreeSet;
+
+use bitcoin::PublicKey;
+
+use super::finalizer::get_scriptpubkey;
+use super::{sanity_check, Error, Psbt, PsbtInputSatisfier};
+use descriptor::{Descriptor, DescriptorTrait};
+use {ForEach, ForEachKey, MiniscriptKey, ToPublicKey};
+
+/// Signing progress of a PSBT input spending a descriptor, see
+/// [signing_status]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputSigningStatus {
+    /// Keys of the descriptor with a partial signature for the input
+    pub signed: BTreeSet<PublicKey>,
+    /// Keys of the descriptor without a partial signature for the input.
+    /// Keys only present in the descriptor as hashes are not listed.
+    pub missing: BTreeSet<PublicKey>,
+    /// Whether a satisfaction can be built from the partial signatures,
+    /// preimages and timelocks of the input, so that it can be finalized.
+    /// Inputs already finalized are complete.
+    pub complete: bool,
+}
+
+/// Reports the signing progress of each input of `psbt` spending
+/// `descriptor`, indexed by input. Inputs spending other outputs are None.
+///
+/// Returns Error when an input does not give the output it spends.
+pub fn signing_status<Pk>(
+    psbt: &Psbt,
+    descriptor: &Descriptor<Pk>,
+) -> Result<Vec<Option<InputSigningStatus>>, Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+{
+    sanity_check(psbt)?;
+
+    let script_pubkey = descriptor.script_pubkey();
+    let mut ret = Vec::with_capacity(psbt.inputs.len());
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let spk = get_scriptpubkey(psbt, index).map_err(|e| Error::InputError(e, index))?;
+        if *spk != script_pubkey {
+            ret.push(None);
+            continue;
+        }
+
+        let mut status = InputSigningStatus {
+            signed: BTreeSet::new(),
+            missing: BTreeSet::new(),
+            complete: input.final_script_sig.is_some() || input.final_script_witness.is_some(),
+        };
+        descriptor.for_each_key(|key| {
+            match key {
+                ForEach::Key(pk) => {
+                    let pk = pk.to_public_key();
+                    if input.partial_sigs.contains_key(&pk) {
+                        status.signed.insert(pk);
+                    } else {
+                        status.missing.insert(pk);
+                    }
+                }
+                ForEach::Hash(hash) => {
+                    let hash = Pk::hash_to_hash160(hash);
+                    for pk in input.partial_sigs.keys() {
+                        if pk.to_pubkeyhash() == hash {
+                            status.signed.insert(*pk);
+                        }
+                    }
+                }
+            }
+            true
+        });
+        if !status.complete {
+            status.complete = descriptor
+                .get_satisfaction(PsbtInputSatisfier::new(psbt, index))
+                .is_ok();
+        }
+        ret.push(Some(status));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::secp256k1::{self, Secp256k1};
+    use bitcoin::util::psbt::PartiallySignedTransaction;
+    use bitcoin::{self, Script};
+    use std::str::FromStr;
+
+    #[test]
+    fn signing_status() {
+        let secp = Secp256k1::new();
+        let keys: Vec<_> = (1..4)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                let pk = PublicKey {
+                    key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                    compressed: true,
+                };
+                (sk, pk)
+            })
+            .collect();
+        let descriptor = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(multi(2,{},{},{}))",
+            keys[0].1, keys[1].1, keys[2].1
+        ))
+        .unwrap();
+        // Signatures over any message will do, as they are not verified
+        let msg = secp256k1::Message::from_slice(&[0x42; 32]).unwrap();
+        let sig = |i: usize| {
+            let mut sig = secp.sign(&msg, &keys[i].0).serialize_der().to_vec();
+            sig.push(0x01);
+            sig
+        };
+
+        let txin = bitcoin::TxIn {
+            previous_output: Default::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: descriptor.script_pubkey(),
+        });
+        psbt.inputs[1].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: Script::new(),
+        });
+        psbt.inputs[0].partial_sigs.insert(keys[0].1, sig(0));
+
+        let status = super::signing_status(&psbt, &descriptor).unwrap();
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[1], None);
+        let first = status[0].clone().unwrap();
+        assert_eq!(first.signed.iter().collect::<Vec<_>>(), vec![&keys[0].1]);
+        assert_eq!(first.missing.len(), 2);
+        assert!(!first.complete);
+
+        psbt.inputs[0].partial_sigs.insert(keys[2].1, sig(2));
+        let first = super::signing_status(&psbt, &descriptor).unwrap()[0]
+            .clone()
+            .unwrap();
+        assert_eq!(first.signed.len(), 2);
+        assert_eq!(first.missing.iter().collect::<Vec<_>>(), vec![&keys[1].1]);
+        assert!(first.complete);
+
+        psbt.inputs[1].witness_utxo = None;
+        assert!(super::signing_status(&psbt, &descriptor).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// Miniscript
+// Written in 2021 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Weight Estimation
+//!
+//! Weight and fee of the transaction of a PSBT once all its inputs are
+//! finalized, computed from the descriptors of the outputs they spend,
+//! before any signature exists.
+//!
+
+use super::finalizer::{get_amt, get_scriptpubkey};
+use super::{sanity_check, Error, InputError, Psbt};
+use descriptor::{Descriptor, DescriptorTrait, DescriptorType};
+use {MiniscriptKey, ToPublicKey};
+
+/// Estimated weight and fee of the transaction of a PSBT, see
+/// [estimate_weight]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WeightEstimate {
+    /// Upper bound on the weight of the finalized transaction
+    pub weight: usize,
+    /// Fee paid by the transaction, None if its outputs are worth more than
+    /// the outputs it spends
+    pub fee: Option<u64>,
+}
+
+impl WeightEstimate {
+    /// Upper bound on the virtual size of the finalized transaction
+    pub fn vsize(&self) -> usize {
+        (self.weight + 3) / 4
+    }
+}
+
+/// Whether the satisfaction of a descriptor is in the witness
+fn is_segwit(desc_type: DescriptorType) -> bool {
+    match desc_type {
+        DescriptorType::Wpkh
+        | DescriptorType::Wsh
+        | DescriptorType::ShWsh
+        | DescriptorType::ShWpkh
+        | DescriptorType::WshSortedMulti
+        | DescriptorType::ShWshSortedMulti => true,
+        DescriptorType::Bare
+        | DescriptorType::Sh
+        | DescriptorType::Pkh
+        | DescriptorType::ShSortedMulti
+        | DescriptorType::Addr
+        | DescriptorType::Raw => false,
+    }
+}
+
+/// Estimates the weight of the transaction of `psbt` once its inputs are
+/// finalized, and the fee it pays. Each input is matched with the descriptor
+/// of `descriptors` having the script pubkey of the output it spends, and is
+/// assumed to have its maximum satisfaction weight, see
+/// [DescriptorTrait::max_satisfaction_weight].
+///
+/// Returns Error when an input does not give the output it spends, or when
+/// none of the descriptors matches it.
+pub fn estimate_weight<Pk>(
+    psbt: &Psbt,
+    descriptors: &[Descriptor<Pk>],
+) -> Result<WeightEstimate, Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+{
+    sanity_check(psbt)?;
+
+    let script_pubkeys: Vec<_> = descriptors.iter().map(|d| d.script_pubkey()).collect();
+    let mut satisfaction_weight = 0;
+    let mut legacy_inputs = 0;
+    let mut has_segwit = false;
+    let mut input_value = 0u64;
+    for index in 0..psbt.inputs.len() {
+        let spk = get_scriptpubkey(psbt, index).map_err(|e| Error::InputError(e, index))?;
+        let descriptor = match script_pubkeys.iter().position(|s| s == spk) {
+            Some(i) => &descriptors[i],
+            None => return Err(Error::InputError(InputError::MissingDescriptor, index)),
+        };
+        satisfaction_weight += descriptor
+            .max_satisfaction_weight()
+            .map_err(|e| Error::InputError(InputError::MiniscriptError(e), index))?;
+        if is_segwit(descriptor.desc_type()) {
+            has_segwit = true;
+        } else {
+            legacy_inputs += 1;
+        }
+        input_value += get_amt(psbt, index).map_err(|e| Error::InputError(e, index))?;
+    }
+
+    let tx = &psbt.global.unsigned_tx;
+    // The satisfaction weights include the scriptSig length bytes, which the
+    // unsigned transaction already has
+    let mut weight = tx.get_weight() - 4 * tx.input.len() + satisfaction_weight;
+    if has_segwit {
+        // Segwit marker and flag, and empty witnesses of legacy inputs
+        weight += 2 + legacy_inputs;
+    }
+    let output_value = tx.output.iter().map(|o| o.value).sum::<u64>();
+    Ok(WeightEstimate {
+        weight: weight,
+        fee: input_value.checked_sub(output_value),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::util::psbt::PartiallySignedTransaction;
+    use bitcoin::{self, PublicKey, Script};
+    use std::str::FromStr;
+
+    #[test]
+    fn estimate_weight() {
+        let wpkh = Descriptor::<PublicKey>::from_str(
+            "wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556)",
+        )
+        .unwrap();
+        let pkh = Descriptor::<PublicKey>::from_str(
+            "pkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap();
+        let txin = bitcoin::TxIn {
+            previous_output: Default::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone()],
+            output: vec![bitcoin::TxOut {
+                value: 90_000,
+                script_pubkey: wpkh.script_pubkey(),
+            }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: wpkh.script_pubkey(),
+        });
+
+        // A p2wpkh spend to a p2wpkh output: 82 bytes with an empty
+        // scriptSig, whose length byte is part of the satisfaction weight,
+        // then a marker, a flag and the witness
+        let estimate = super::estimate_weight(&psbt, &[pkh.clone(), wpkh.clone()]).unwrap();
+        assert_eq!(
+            estimate.weight,
+            4 * 82 - 4 + 2 + wpkh.max_satisfaction_weight().unwrap()
+        );
+        assert_eq!(estimate.vsize(), 110);
+        assert_eq!(estimate.fee, Some(10_000));
+
+        // A legacy input adds its scriptSig, and an empty witness
+        let mut tx = tx;
+        tx.input.push(txin);
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: wpkh.script_pubkey(),
+        });
+        psbt.inputs[1].witness_utxo = Some(bitcoin::TxOut {
+            value: 100_000,
+            script_pubkey: pkh.script_pubkey(),
+        });
+        let both = super::estimate_weight(&psbt, &[pkh.clone(), wpkh.clone()]).unwrap();
+        assert_eq!(
+            both.weight,
+            estimate.weight + 4 * 40 + pkh.max_satisfaction_weight().unwrap() + 1
+        );
+        assert_eq!(both.fee, Some(110_000));
+
+        match super::estimate_weight(&psbt, &[wpkh]) {
+            Err(Error::InputError(InputError::MissingDescriptor, 1)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
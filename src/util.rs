@@ -21,3 +21,50 @@ pub(crate) fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
     }
     b.into_script()
 }
+
+/// Folds the tree below `root` in post-order, without recursion: `f` is called
+/// on every node with its depth and the results of its children, in order.
+pub(crate) fn fold_post_order<'a, N, T, C, F>(root: &'a N, children: C, mut f: F) -> T
+where
+    C: Fn(&'a N) -> Vec<&'a N>,
+    F: FnMut(&'a N, usize, Vec<T>) -> T,
+{
+    // Nodes still to visit, with their depth and, once their children have
+    // been pushed, the number of them
+    let mut stack = vec![(root, 0, None)];
+    let mut results = vec![];
+    while let Some((node, depth, n_children)) = stack.pop() {
+        match n_children {
+            Some(n) => {
+                let child_results = results.split_off(results.len() - n);
+                results.push(f(node, depth, child_results));
+            }
+            None => {
+                let node_children = children(node);
+                stack.push((node, depth, Some(node_children.len())));
+                for child in node_children.into_iter().rev() {
+                    stack.push((child, depth + 1, None));
+                }
+            }
+        }
+    }
+    results.pop().expect("the root has a result")
+}
+
+/// Visits the tree below `root` in pre-order, without recursion: `f` is called
+/// on every node with its depth, and the children of a node are only visited
+/// if it returns `true`.
+pub(crate) fn visit_pre_order<'a, N, C, F>(root: &'a N, children: C, mut f: F)
+where
+    C: Fn(&'a N) -> Vec<&'a N>,
+    F: FnMut(&'a N, usize) -> bool,
+{
+    let mut stack = vec![(root, 0)];
+    while let Some((node, depth)) = stack.pop() {
+        if f(node, depth) {
+            for child in children(node).into_iter().rev() {
+                stack.push((child, depth + 1));
+            }
+        }
+    }
+}